@@ -0,0 +1,343 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly_core::{BitReader, BitWriter};
+
+use crate::error::Error;
+
+pub const OBJECT_TYPE_AAC_MAIN: u8 = 1;
+pub const OBJECT_TYPE_AAC_LC: u8 = 2;
+pub const OBJECT_TYPE_AAC_SSR: u8 = 3;
+pub const OBJECT_TYPE_AAC_LTP: u8 = 4;
+pub const OBJECT_TYPE_SBR: u8 = 5;
+pub const OBJECT_TYPE_PS: u8 = 29;
+
+/// Samples per channel in the AAC access unit
+pub const SAMPLES_PER_FRAME: u32 = 1024;
+
+pub const ADTS_HEADER_SIZE: usize = 7;
+pub const ADTS_CRC_SIZE: usize = 2;
+
+/// Bits of `raw_data_block` covered by the ADTS CRC (ISO/IEC 13818-7 8.1.2.1)
+const ADTS_CRC_DATA_BYTES: usize = 192 / 8;
+
+/// `samplingFrequencyIndex` values (ISO/IEC 14496-3 1.6.3.4)
+pub const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Index of the sample rate in [`SAMPLE_RATES`]
+#[inline]
+pub fn sample_rate_index(sample_rate: u32) -> Option<u8> {
+    SAMPLE_RATES
+        .iter()
+        .position(|&x| x == sample_rate)
+        .map(|x| x as u8)
+}
+
+/// AudioSpecificConfig (ISO/IEC 14496-3 1.6.2.1), the AAC decoding parameters
+///
+/// Only the leading fields are kept, `to_bytes` writes the plain
+/// `GASpecificConfig` without extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioSpecificConfig {
+    pub object_type: u8,
+    pub sample_rate: u32,
+    pub channel_config: u8,
+}
+
+impl AudioSpecificConfig {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut r = BitReader::new(data);
+
+        let object_type = match r.read_u8(5)? {
+            31 => 32 + r.read_u8(6)?,
+            x => x,
+        };
+
+        let sample_rate = match r.read_u8(4)? {
+            15 => match r.read_u32(24)? {
+                0 => return Err(Error::InvalidData("aac: zero sampling frequency")),
+                x => x,
+            },
+            idx => *SAMPLE_RATES
+                .get(idx as usize)
+                .ok_or(Error::InvalidData("aac: invalid sampling frequency index"))?,
+        };
+
+        Ok(Self {
+            object_type,
+            sample_rate,
+            channel_config: r.read_u8(4)?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut w = BitWriter::new(BytesMut::with_capacity(6));
+
+        if self.object_type >= 31 {
+            w.put_bits(5, 31);
+            w.put_bits(6, (self.object_type - 32) as u64);
+        } else {
+            w.put_bits(5, self.object_type as u64);
+        }
+
+        match sample_rate_index(self.sample_rate) {
+            Some(idx) => w.put_bits(4, idx as u64),
+            None => {
+                w.put_bits(4, 15);
+                w.put_bits(24, self.sample_rate as u64);
+            }
+        }
+
+        w.put_bits(4, self.channel_config as u64);
+
+        // frameLengthFlag, dependsOnCoreCoder, extensionFlag
+        w.put_bits(3, 0);
+
+        w.finish().freeze()
+    }
+
+    /// Number of channels, 0 if defined by the program config element
+    pub fn channels(&self) -> u16 {
+        match self.channel_config {
+            7 => 8,
+            x @ 1..=6 => x as u16,
+            _ => 0,
+        }
+    }
+}
+
+/// ADTS frame header (ISO/IEC 13818-7 6.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdtsHeader {
+    /// MPEG-2 identifier instead of MPEG-4
+    pub mpeg2: bool,
+    pub object_type: u8,
+    pub sample_rate_index: u8,
+    pub private: bool,
+    pub channel_config: u8,
+    pub original: bool,
+    pub home: bool,
+
+    /// Frame length including the header
+    pub frame_length: u16,
+    pub buffer_fullness: u16,
+
+    /// Number of raw data blocks in the frame
+    pub raw_blocks: u8,
+    pub crc: Option<u16>,
+}
+
+impl AdtsHeader {
+    /// Parses the header at the beginning of the frame
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut r = BitReader::new(data);
+
+        if r.read_u16(12)? != 0xFFF {
+            return Err(Error::InvalidData("adts: invalid syncword"));
+        }
+
+        let mpeg2 = r.read_flag()?;
+        if r.read_u8(2)? != 0 {
+            return Err(Error::InvalidData("adts: invalid layer"));
+        }
+
+        let protection_absent = r.read_flag()?;
+        let object_type = r.read_u8(2)? + 1;
+        let sample_rate_index = r.read_u8(4)?;
+
+        if sample_rate_index as usize >= SAMPLE_RATES.len() {
+            return Err(Error::InvalidData("aac: invalid sampling frequency index"));
+        }
+
+        let private = r.read_flag()?;
+        let channel_config = r.read_u8(3)?;
+        let original = r.read_flag()?;
+        let home = r.read_flag()?;
+
+        // copyright identification bit and start
+        r.skip_bits(2)?;
+
+        let frame_length = r.read_u16(13)?;
+        let buffer_fullness = r.read_u16(11)?;
+        let raw_blocks = r.read_u8(2)? + 1;
+        let crc = if protection_absent {
+            None
+        } else {
+            Some(r.read_u16(16)?)
+        };
+
+        let header = Self {
+            mpeg2,
+            object_type,
+            sample_rate_index,
+            private,
+            channel_config,
+            original,
+            home,
+            frame_length,
+            buffer_fullness,
+            raw_blocks,
+            crc,
+        };
+
+        if (frame_length as usize) < header.size() {
+            return Err(Error::InvalidData("adts: invalid frame length"));
+        }
+
+        Ok(header)
+    }
+
+    /// Header of the single raw data block frame without CRC
+    pub fn from_asc(asc: &AudioSpecificConfig, payload_len: usize) -> Result<Self, Error> {
+        if !(OBJECT_TYPE_AAC_MAIN..=OBJECT_TYPE_AAC_LTP).contains(&asc.object_type) {
+            return Err(Error::Unsupported(
+                "aac: ADTS supports object types 1 to 4 only",
+            ));
+        }
+
+        let sample_rate_index = sample_rate_index(asc.sample_rate)
+            .ok_or(Error::Unsupported("aac: explicit sampling frequency"))?;
+
+        let frame_length = ADTS_HEADER_SIZE + payload_len;
+        if frame_length >= 1 << 13 {
+            return Err(Error::InvalidData("adts: frame is too long"));
+        }
+
+        Ok(Self {
+            mpeg2: false,
+            object_type: asc.object_type,
+            sample_rate_index,
+            private: false,
+            channel_config: asc.channel_config,
+            original: false,
+            home: false,
+            frame_length: frame_length as u16,
+            buffer_fullness: 0x7FF,
+            raw_blocks: 1,
+            crc: None,
+        })
+    }
+
+    /// Writes the header (followed by `crc` if set)
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        let mut w = BitWriter::new(buf);
+        w.put_bits(12, 0xFFF);
+        w.put_flag(self.mpeg2);
+        w.put_bits(2, 0);
+        w.put_flag(self.crc.is_none());
+        w.put_bits(2, self.object_type.saturating_sub(1) as u64);
+        w.put_bits(4, self.sample_rate_index as u64);
+        w.put_flag(self.private);
+        w.put_bits(3, self.channel_config as u64);
+        w.put_flag(self.original);
+        w.put_flag(self.home);
+        w.put_bits(2, 0);
+        w.put_bits(13, self.frame_length as u64);
+        w.put_bits(11, self.buffer_fullness as u64);
+        w.put_bits(2, self.raw_blocks.saturating_sub(1) as u64);
+
+        if let Some(crc) = self.crc {
+            w.put_bits(16, crc as u64);
+        }
+
+        w.finish();
+    }
+
+    /// Header size in bytes
+    #[inline]
+    pub fn size(&self) -> usize {
+        ADTS_HEADER_SIZE + if self.crc.is_some() { ADTS_CRC_SIZE } else { 0 }
+    }
+
+    #[inline]
+    pub fn sample_rate(&self) -> u32 {
+        SAMPLE_RATES[self.sample_rate_index as usize]
+    }
+
+    pub fn to_asc(&self) -> AudioSpecificConfig {
+        AudioSpecificConfig {
+            object_type: self.object_type,
+            sample_rate: self.sample_rate(),
+            channel_config: self.channel_config,
+        }
+    }
+
+    /// CRC of the single raw data block frame, the header fields and up to
+    /// 192 bits of the payload are protected
+    pub fn compute_crc(frame: &[u8]) -> u16 {
+        let payload = frame
+            .get(ADTS_HEADER_SIZE + ADTS_CRC_SIZE..)
+            .unwrap_or_default();
+        let payload = &payload[..payload.len().min(ADTS_CRC_DATA_BYTES)];
+
+        crc16(
+            crc16(0xFFFF, &frame[..ADTS_HEADER_SIZE.min(frame.len())]),
+            payload,
+        )
+    }
+}
+
+/// CRC-16 of ISO/IEC 11172-3 (x^16 + x^15 + x^2 + 1)
+fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::{AdtsHeader, AudioSpecificConfig};
+
+    #[test]
+    fn test_adts_asc_roundtrip() {
+        let asc = AudioSpecificConfig::parse(&[0x12, 0x10]).unwrap();
+        assert_eq!(
+            (asc.object_type, asc.sample_rate, asc.channels()),
+            (2, 44100, 2)
+        );
+        assert_eq!(&asc.to_bytes()[..], &[0x12, 0x10]);
+
+        let header = AdtsHeader::from_asc(&asc, 100).unwrap();
+        let mut buf = BytesMut::new();
+        header.write(&mut buf);
+        assert_eq!(&buf[..], &[0xFF, 0xF1, 0x50, 0x80, 0x0D, 0x7F, 0xFC]);
+
+        let parsed = AdtsHeader::parse(&buf).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(parsed.to_asc(), asc);
+
+        // explicit sample rate escape
+        let asc = AudioSpecificConfig {
+            object_type: 2,
+            sample_rate: 37800,
+            channel_config: 1,
+        };
+        assert_eq!(AudioSpecificConfig::parse(&asc.to_bytes()).unwrap(), asc);
+        assert!(AdtsHeader::from_asc(&asc, 10).is_err());
+    }
+
+    #[test]
+    fn test_asc_malformed() {
+        // explicit zero sampling frequency, reserved index 13, truncated
+        for data in [&[0x17, 0x80, 0x00, 0x00, 0x10][..], &[0x16, 0x90], &[0x12]] {
+            assert!(AudioSpecificConfig::parse(data).is_err(), "{data:02X?}");
+        }
+
+        assert!(matches!(
+            AudioSpecificConfig::parse(&[0x17, 0x80, 0x00, 0x00, 0x10]),
+            Err(crate::Error::InvalidData("aac: zero sampling frequency"))
+        ));
+    }
+}
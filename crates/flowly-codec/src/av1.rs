@@ -0,0 +1,809 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly_core::BitReader;
+
+use crate::{
+    error::Error,
+    info::{ChromaFormat, Timing},
+};
+
+pub const OBU_SEQUENCE_HEADER: u8 = 1;
+pub const OBU_TEMPORAL_DELIMITER: u8 = 2;
+pub const OBU_FRAME_HEADER: u8 = 3;
+pub const OBU_TILE_GROUP: u8 = 4;
+pub const OBU_METADATA: u8 = 5;
+pub const OBU_FRAME: u8 = 6;
+pub const OBU_REDUNDANT_FRAME_HEADER: u8 = 7;
+pub const OBU_TILE_LIST: u8 = 8;
+pub const OBU_PADDING: u8 = 15;
+
+pub const FRAME_KEY: u8 = 0;
+pub const FRAME_INTER: u8 = 1;
+pub const FRAME_INTRA_ONLY: u8 = 2;
+pub const FRAME_SWITCH: u8 = 3;
+
+/// Open Bitstream Unit (AV1 5.3) borrowed from the frame payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Obu<'a> {
+    pub obu_type: u8,
+
+    /// Raw `obu_extension_header` byte
+    pub extension: Option<u8>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Obu<'a> {
+    /// Parses the OBU at the beginning of `data` returning it with its size,
+    /// an OBU without the size field extends to the end of `data`
+    pub fn parse(data: &'a [u8]) -> Result<(Self, usize), Error> {
+        let &[header, ..] = data else {
+            return Err(Error::InvalidData("av1: empty OBU"));
+        };
+
+        if header & 0x80 != 0 {
+            return Err(Error::InvalidData("av1: forbidden bit"));
+        }
+
+        let mut pos = 1;
+        let extension = if header & 0x04 != 0 {
+            pos += 1;
+            Some(
+                *data
+                    .get(1)
+                    .ok_or(Error::InvalidData("av1: truncated OBU header"))?,
+            )
+        } else {
+            None
+        };
+
+        let size = if header & 0x02 != 0 {
+            let (size, len) = read_leb128(&data[pos..])?;
+            pos += len;
+            size as usize
+        } else {
+            data.len() - pos
+        };
+
+        let payload = data
+            .get(pos..pos + size)
+            .ok_or(Error::InvalidData("av1: truncated OBU"))?;
+
+        let obu = Self {
+            obu_type: (header >> 3) & 0x0F,
+            extension,
+            payload,
+        };
+
+        Ok((obu, pos + size))
+    }
+
+    #[inline]
+    pub fn temporal_id(&self) -> u8 {
+        self.extension.map_or(0, |x| x >> 5)
+    }
+
+    #[inline]
+    pub fn spatial_id(&self) -> u8 {
+        self.extension.map_or(0, |x| (x >> 3) & 0x03)
+    }
+
+    /// Writes the OBU in the low overhead format (with `obu_size`)
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        self.write_header(buf, true);
+        put_leb128(buf, self.payload.len() as u64);
+        buf.put_slice(self.payload);
+    }
+
+    /// Size of the OBU written by [`Obu::write`]
+    pub fn size(&self) -> usize {
+        1 + self.extension.is_some() as usize
+            + leb128_size(self.payload.len() as u64)
+            + self.payload.len()
+    }
+
+    fn write_header<B: BufMut>(&self, buf: &mut B, has_size: bool) {
+        let ext = if self.extension.is_some() { 0x04 } else { 0 };
+        let size = if has_size { 0x02 } else { 0 };
+
+        buf.put_u8(self.obu_type << 3 | ext | size);
+        if let Some(extension) = self.extension {
+            buf.put_u8(extension);
+        }
+    }
+}
+
+/// Iterator over OBUs of the low overhead bitstream (AV1 Section 5)
+#[derive(Debug, Clone)]
+pub struct ObuIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ObuIter<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for ObuIter<'a> {
+    type Item = Result<Obu<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        match Obu::parse(self.data) {
+            Ok((obu, len)) => {
+                self.data = &self.data[len..];
+                Some(Ok(obu))
+            }
+            Err(err) => {
+                self.data = &[];
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Reads `leb128()` returning the value and its size in bytes
+pub fn read_leb128(data: &[u8]) -> Result<(u64, usize), Error> {
+    let mut value = 0u64;
+
+    for (i, &byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7F) as u64) << (i * 7);
+
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+
+    Err(Error::InvalidData("av1: invalid leb128"))
+}
+
+pub fn put_leb128<B: BufMut>(buf: &mut B, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8(value as u8 & 0x7F | 0x80);
+        value >>= 7;
+    }
+
+    buf.put_u8(value as u8);
+}
+
+#[inline]
+pub fn leb128_size(value: u64) -> usize {
+    (64 - value.leading_zeros() as usize).max(1).div_ceil(7)
+}
+
+/// Sequence header OBU payload (AV1 5.5)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SequenceHeader {
+    pub profile: u8,
+    pub still_picture: bool,
+    pub reduced_still_picture_header: bool,
+    pub timing: Option<Timing>,
+
+    /// Level and tier of the first operating point
+    pub level_idx: u8,
+    pub tier: bool,
+    pub max_frame_width: u32,
+    pub max_frame_height: u32,
+    pub bit_depth: u8,
+    pub mono_chrome: bool,
+    pub subsampling_x: bool,
+    pub subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub color_description: Option<(u8, u8, u8)>,
+    pub color_range: bool,
+    pub film_grain_params_present: bool,
+}
+
+impl SequenceHeader {
+    pub fn parse(payload: &[u8]) -> Result<Self, Error> {
+        let mut r = BitReader::new(payload);
+
+        let mut seq = SequenceHeader {
+            profile: r.read_u8(3)?,
+            still_picture: r.read_flag()?,
+            reduced_still_picture_header: r.read_flag()?,
+            ..Default::default()
+        };
+
+        if seq.profile > 2 {
+            return Err(Error::InvalidData("av1: seq_profile"));
+        }
+
+        if seq.reduced_still_picture_header {
+            seq.level_idx = r.read_u8(5)?;
+        } else {
+            let mut decoder_model_info_present = false;
+            let mut buffer_delay_length = 0;
+
+            if r.read_flag()? {
+                let num_units_in_display_tick = r.read_u32(32)?;
+                let time_scale = r.read_u32(32)?;
+                let equal_picture_interval = r.read_flag()?;
+                let ticks_per_picture = if equal_picture_interval {
+                    r.read_ue()? + 1
+                } else {
+                    1
+                };
+
+                seq.timing = Some(Timing {
+                    num_units_in_tick: num_units_in_display_tick.saturating_mul(ticks_per_picture),
+                    time_scale,
+                    fixed_frame_rate: equal_picture_interval,
+                });
+
+                decoder_model_info_present = r.read_flag()?;
+                if decoder_model_info_present {
+                    buffer_delay_length = r.read_u8(5)? as usize + 1;
+
+                    // num_units_in_decoding_tick, removal and presentation time lengths
+                    r.skip_bits(32 + 5 + 5)?;
+                }
+            }
+
+            let initial_display_delay_present = r.read_flag()?;
+            let operating_points = r.read_u8(5)? + 1;
+
+            for i in 0..operating_points {
+                let _operating_point_idc = r.read_u16(12)?;
+                let level_idx = r.read_u8(5)?;
+                let tier = level_idx > 7 && r.read_flag()?;
+
+                if i == 0 {
+                    seq.level_idx = level_idx;
+                    seq.tier = tier;
+                }
+
+                // decoder and encoder buffer delays, low_delay_mode_flag
+                if decoder_model_info_present && r.read_flag()? {
+                    r.skip_bits(buffer_delay_length * 2 + 1)?;
+                }
+
+                if initial_display_delay_present && r.read_flag()? {
+                    r.skip_bits(4)?;
+                }
+            }
+        }
+
+        let width_bits = r.read_u8(4)? + 1;
+        let height_bits = r.read_u8(4)? + 1;
+        seq.max_frame_width = r.read_u32(width_bits)? + 1;
+        seq.max_frame_height = r.read_u32(height_bits)? + 1;
+
+        // delta_frame_id_length_minus_2, additional_frame_id_length_minus_1
+        if !seq.reduced_still_picture_header && r.read_flag()? {
+            r.skip_bits(7)?;
+        }
+
+        // use_128x128_superblock, enable_filter_intra, enable_intra_edge_filter
+        r.skip_bits(3)?;
+
+        if !seq.reduced_still_picture_header {
+            // interintra and masked compound, warped motion, dual filter
+            r.skip_bits(4)?;
+
+            let enable_order_hint = r.read_flag()?;
+            if enable_order_hint {
+                // enable_jnt_comp, enable_ref_frame_mvs
+                r.skip_bits(2)?;
+            }
+
+            let force_screen_content_tools = if r.read_flag()? { 2 } else { r.read_u8(1)? };
+
+            // seq_choose_integer_mv, seq_force_integer_mv
+            if force_screen_content_tools > 0 && !r.read_flag()? {
+                r.skip_bits(1)?;
+            }
+
+            if enable_order_hint {
+                r.skip_bits(3)?;
+            }
+        }
+
+        // enable_superres, enable_cdef, enable_restoration
+        r.skip_bits(3)?;
+
+        seq.parse_color_config(&mut r)?;
+        seq.film_grain_params_present = r.read_flag()?;
+
+        Ok(seq)
+    }
+
+    /// `color_config()` (AV1 5.5.2)
+    fn parse_color_config(&mut self, r: &mut BitReader<&[u8]>) -> Result<(), Error> {
+        let high_bitdepth = r.read_flag()?;
+        self.bit_depth = match (self.profile, high_bitdepth) {
+            (2, true) if r.read_flag()? => 12,
+            (_, true) => 10,
+            _ => 8,
+        };
+
+        self.mono_chrome = self.profile != 1 && r.read_flag()?;
+
+        if r.read_flag()? {
+            self.color_description = Some((r.read_u8(8)?, r.read_u8(8)?, r.read_u8(8)?));
+        }
+
+        if self.mono_chrome {
+            self.color_range = r.read_flag()?;
+            self.subsampling_x = true;
+            self.subsampling_y = true;
+            return Ok(());
+        }
+
+        // sRGB: BT.709 primaries, sRGB transfer, identity matrix
+        if self.color_description == Some((1, 13, 0)) {
+            self.color_range = true;
+        } else {
+            self.color_range = r.read_flag()?;
+
+            (self.subsampling_x, self.subsampling_y) = match self.profile {
+                0 => (true, true),
+                1 => (false, false),
+                _ if self.bit_depth == 12 => {
+                    let x = r.read_flag()?;
+                    (x, x && r.read_flag()?)
+                }
+                _ => (true, false),
+            };
+
+            if self.subsampling_x && self.subsampling_y {
+                self.chroma_sample_position = r.read_u8(2)?;
+            }
+        }
+
+        let _separate_uv_delta_q = r.read_flag()?;
+
+        Ok(())
+    }
+
+    pub fn chroma_format(&self) -> ChromaFormat {
+        match (self.mono_chrome, self.subsampling_x, self.subsampling_y) {
+            (true, ..) => ChromaFormat::Monochrome,
+            (_, true, true) => ChromaFormat::Yuv420,
+            (_, true, false) => ChromaFormat::Yuv422,
+            _ => ChromaFormat::Yuv444,
+        }
+    }
+}
+
+/// Leading fields of the uncompressed frame header (AV1 5.9.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub show_existing_frame: bool,
+    pub frame_type: u8,
+    pub show_frame: bool,
+}
+
+impl FrameHeader {
+    /// Parses the payload of `OBU_FRAME_HEADER` or `OBU_FRAME`
+    pub fn parse(payload: &[u8], seq: Option<&SequenceHeader>) -> Result<Self, Error> {
+        if seq.is_some_and(|x| x.reduced_still_picture_header) {
+            return Ok(Self {
+                show_existing_frame: false,
+                frame_type: FRAME_KEY,
+                show_frame: true,
+            });
+        }
+
+        let mut r = BitReader::new(payload);
+        let show_existing_frame = r.read_flag()?;
+
+        // the type of the shown frame is only known to the decoder
+        if show_existing_frame {
+            return Ok(Self {
+                show_existing_frame,
+                frame_type: FRAME_INTER,
+                show_frame: true,
+            });
+        }
+
+        Ok(Self {
+            show_existing_frame,
+            frame_type: r.read_u8(2)?,
+            show_frame: r.read_flag()?,
+        })
+    }
+
+    /// Shown key frame, a random access point
+    #[inline]
+    pub fn is_keyframe(&self) -> bool {
+        self.frame_type == FRAME_KEY && self.show_frame
+    }
+}
+
+/// Checks the first frame header of the temporal unit (low overhead format),
+/// the in-band sequence header takes precedence over `seq`
+pub fn is_keyframe(data: &[u8], seq: Option<&SequenceHeader>) -> Result<bool, Error> {
+    let mut inband = None;
+
+    for obu in ObuIter::new(data) {
+        let obu = obu?;
+
+        match obu.obu_type {
+            OBU_SEQUENCE_HEADER => inband = Some(SequenceHeader::parse(obu.payload)?),
+            OBU_FRAME_HEADER | OBU_FRAME => {
+                let header = FrameHeader::parse(obu.payload, inband.as_ref().or(seq))?;
+                return Ok(header.is_keyframe());
+            }
+            _ => (),
+        }
+    }
+
+    Ok(false)
+}
+
+/// Converts the length delimited bitstream (AV1 Annex B) into the low overhead format
+pub fn annexb_to_section5(mut data: &[u8]) -> Result<Bytes, Error> {
+    let mut buf = BytesMut::with_capacity(data.len());
+
+    while !data.is_empty() {
+        let temporal_unit = read_sized(&mut data)?;
+        let mut frames = temporal_unit;
+
+        while !frames.is_empty() {
+            let mut obus = read_sized(&mut frames)?;
+
+            while !obus.is_empty() {
+                let (obu, _) = Obu::parse(read_sized(&mut obus)?)?;
+                obu.write(&mut buf);
+            }
+        }
+    }
+
+    Ok(buf.freeze())
+}
+
+/// Converts the temporal unit in the low overhead format into AV1 Annex B,
+/// OBUs are grouped into frame units by their frame headers
+pub fn section5_to_annexb(data: &[u8]) -> Result<Bytes, Error> {
+    let mut frame_units = vec![Vec::new()];
+    let mut has_frame = false;
+
+    for obu in ObuIter::new(data) {
+        let obu = obu?;
+
+        if matches!(obu.obu_type, OBU_FRAME_HEADER | OBU_FRAME) {
+            if has_frame {
+                frame_units.push(Vec::new());
+            }
+
+            has_frame = true;
+        }
+
+        frame_units.last_mut().unwrap().push(obu);
+    }
+
+    let obu_size = |obu: &Obu| 1 + obu.extension.is_some() as usize + obu.payload.len();
+    let unit_size = |unit: &[Obu]| {
+        unit.iter()
+            .map(obu_size)
+            .map(|x| leb128_size(x as u64) + x)
+            .sum::<usize>()
+    };
+
+    let temporal_unit_size: usize = frame_units
+        .iter()
+        .map(|x| unit_size(x))
+        .map(|x| leb128_size(x as u64) + x)
+        .sum();
+
+    let mut buf = BytesMut::with_capacity(temporal_unit_size + 8);
+    put_leb128(&mut buf, temporal_unit_size as u64);
+
+    for unit in &frame_units {
+        put_leb128(&mut buf, unit_size(unit) as u64);
+
+        for obu in unit {
+            put_leb128(&mut buf, obu_size(obu) as u64);
+            obu.write_header(&mut buf, false);
+            buf.put_slice(obu.payload);
+        }
+    }
+
+    Ok(buf.freeze())
+}
+
+/// Splits the `leb128()` size prefixed block off the beginning of `data`
+fn read_sized<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], Error> {
+    let (size, len) = read_leb128(data)?;
+    let block = data
+        .get(len..len + size as usize)
+        .ok_or(Error::InvalidData("av1: truncated Annex B unit"))?;
+
+    *data = &data[len + size as usize..];
+    Ok(block)
+}
+
+/// AV1CodecConfigurationRecord, `av1C` box payload
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Av1Config {
+    pub profile: u8,
+    pub level_idx: u8,
+    pub tier: bool,
+    pub high_bitdepth: bool,
+    pub twelve_bit: bool,
+    pub mono_chrome: bool,
+    pub subsampling_x: bool,
+    pub subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub initial_presentation_delay: Option<u8>,
+
+    /// Sequence header and metadata OBUs in the low overhead format
+    pub config_obus: Bytes,
+}
+
+impl Av1Config {
+    /// Builds the record from the first sequence header OBU of `data`
+    pub fn from_obus(data: &[u8]) -> Result<Self, Error> {
+        for obu in ObuIter::new(data) {
+            let obu = obu?;
+
+            if obu.obu_type == OBU_SEQUENCE_HEADER {
+                let seq = SequenceHeader::parse(obu.payload)?;
+                let mut config_obus = BytesMut::with_capacity(obu.size());
+                obu.write(&mut config_obus);
+
+                return Ok(Self {
+                    profile: seq.profile,
+                    level_idx: seq.level_idx,
+                    tier: seq.tier,
+                    high_bitdepth: seq.bit_depth > 8,
+                    twelve_bit: seq.bit_depth == 12,
+                    mono_chrome: seq.mono_chrome,
+                    subsampling_x: seq.subsampling_x,
+                    subsampling_y: seq.subsampling_y,
+                    chroma_sample_position: seq.chroma_sample_position,
+                    initial_presentation_delay: None,
+                    config_obus: config_obus.freeze(),
+                });
+            }
+        }
+
+        Err(Error::MissingParams("av1: sequence header"))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let &[marker, b1, b2, b3, ..] = data else {
+            return Err(Error::InvalidData("av1C: truncated record"));
+        };
+
+        if marker != 0x81 {
+            return Err(Error::InvalidData("av1C: marker or version"));
+        }
+
+        Ok(Self {
+            profile: b1 >> 5,
+            level_idx: b1 & 0x1F,
+            tier: b2 & 0x80 != 0,
+            high_bitdepth: b2 & 0x40 != 0,
+            twelve_bit: b2 & 0x20 != 0,
+            mono_chrome: b2 & 0x10 != 0,
+            subsampling_x: b2 & 0x08 != 0,
+            subsampling_y: b2 & 0x04 != 0,
+            chroma_sample_position: b2 & 0x03,
+            initial_presentation_delay: (b3 & 0x10 != 0).then_some((b3 & 0x0F) + 1),
+            config_obus: Bytes::copy_from_slice(&data[4..]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(4 + self.config_obus.len());
+
+        buf.put_u8(0x81);
+        buf.put_u8(self.profile << 5 | self.level_idx & 0x1F);
+        buf.put_u8(
+            (self.tier as u8) << 7
+                | (self.high_bitdepth as u8) << 6
+                | (self.twelve_bit as u8) << 5
+                | (self.mono_chrome as u8) << 4
+                | (self.subsampling_x as u8) << 3
+                | (self.subsampling_y as u8) << 2
+                | self.chroma_sample_position & 0x03,
+        );
+        buf.put_u8(match self.initial_presentation_delay {
+            Some(delay) => 0x10 | (delay - 1) & 0x0F,
+            None => 0,
+        });
+        buf.put_slice(&self.config_obus);
+
+        buf.freeze()
+    }
+
+    /// Sequence header of the configuration OBUs
+    pub fn sequence_header(&self) -> Result<SequenceHeader, Error> {
+        for obu in ObuIter::new(&self.config_obus) {
+            let obu = obu?;
+
+            if obu.obu_type == OBU_SEQUENCE_HEADER {
+                return SequenceHeader::parse(obu.payload);
+            }
+        }
+
+        Err(Error::MissingParams("av1: sequence header"))
+    }
+}
+
+/// Sequence header of the `av1C` record or the sequence header OBU(s)
+pub fn sequence_header_of_param(param: &[u8]) -> Result<SequenceHeader, Error> {
+    // the marker bit of `av1C` is the forbidden bit of the OBU header
+    if param.first().is_some_and(|x| x & 0x80 != 0) {
+        return Av1Config::parse(param)?.sequence_header();
+    }
+
+    for obu in ObuIter::new(param) {
+        let obu = obu?;
+
+        if obu.obu_type == OBU_SEQUENCE_HEADER {
+            return SequenceHeader::parse(obu.payload);
+        }
+    }
+
+    Err(Error::MissingParams("av1: sequence header"))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use flowly_core::BitWriter;
+
+    use super::{
+        Av1Config, OBU_FRAME, OBU_FRAME_HEADER, OBU_SEQUENCE_HEADER, OBU_TEMPORAL_DELIMITER, Obu,
+        ObuIter, SequenceHeader, annexb_to_section5, is_keyframe, read_leb128, section5_to_annexb,
+    };
+    use crate::info::ChromaFormat;
+
+    /// Main profile 1280x720 10 bit 4:2:0 at 30 fps
+    fn sequence_header() -> Bytes {
+        let mut w = BitWriter::new(BytesMut::new());
+
+        w.put_bits(3, 0);
+        w.put_bits(2, 0);
+
+        // timing info, 1 tick per picture
+        w.put_flag(true);
+        w.put_bits(32, 1);
+        w.put_bits(32, 30);
+        w.put_flag(true);
+        w.put_ue(0);
+        w.put_flag(false);
+
+        // one operating point at level 4.0
+        w.put_flag(false);
+        w.put_bits(5, 0);
+        w.put_bits(12, 0);
+        w.put_bits(5, 8);
+        w.put_flag(false);
+
+        w.put_bits(4, 10);
+        w.put_bits(4, 10);
+        w.put_bits(11, 1279);
+        w.put_bits(11, 719);
+        w.put_flag(false);
+        w.put_bits(3, 0b111);
+
+        // tools, order hint with 7 bits, screen content tools chosen per frame
+        w.put_bits(4, 0);
+        w.put_flag(true);
+        w.put_bits(2, 0b11);
+        w.put_flag(true);
+        w.put_flag(true);
+        w.put_bits(3, 6);
+        w.put_bits(3, 0b011);
+
+        // color config: 10 bit, no description, limited range
+        w.put_flag(true);
+        w.put_flag(false);
+        w.put_flag(false);
+        w.put_flag(false);
+        w.put_bits(2, 0);
+        w.put_flag(false);
+
+        w.put_flag(false);
+        w.put_trailing_bits();
+        w.finish().freeze()
+    }
+
+    fn temporal_unit(frames: &[&[u8]]) -> Bytes {
+        let seq = sequence_header();
+        let mut buf = BytesMut::new();
+
+        for (obu_type, payload) in [
+            (OBU_TEMPORAL_DELIMITER, &[][..]),
+            (OBU_SEQUENCE_HEADER, &seq[..]),
+        ] {
+            Obu {
+                obu_type,
+                extension: None,
+                payload,
+            }
+            .write(&mut buf);
+        }
+
+        for (i, payload) in frames.iter().enumerate() {
+            let obu_type = if i == 0 { OBU_FRAME } else { OBU_FRAME_HEADER };
+            Obu {
+                obu_type,
+                extension: Some(0x20),
+                payload,
+            }
+            .write(&mut buf);
+        }
+
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_av1_sequence_header() {
+        let seq = SequenceHeader::parse(&sequence_header()).unwrap();
+
+        assert_eq!((seq.max_frame_width, seq.max_frame_height), (1280, 720));
+        assert_eq!((seq.profile, seq.level_idx, seq.bit_depth), (0, 8, 10));
+        assert_eq!(seq.chroma_format(), ChromaFormat::Yuv420);
+        assert_eq!(seq.timing.unwrap().frame_rate(), Some((30, 1)));
+
+        // shown key frame and inter frame
+        let key = temporal_unit(&[&[0x10, 0xAA]]);
+        let inter = temporal_unit(&[&[0x30, 0xAA]]);
+        assert!(is_keyframe(&key, None).unwrap());
+        assert!(!is_keyframe(&inter, Some(&seq)).unwrap());
+
+        let config = Av1Config::from_obus(&key).unwrap();
+        let bytes = config.to_bytes();
+        assert_eq!(bytes[..4], [0x81, 0x08, 0x4C, 0x00]);
+        assert_eq!(Av1Config::parse(&bytes).unwrap(), config);
+        assert_eq!(config.sequence_header().unwrap(), seq);
+
+        let info = crate::VideoInfo::from_params(flowly_core::Fourcc::VIDEO_AV1, [bytes]).unwrap();
+        assert_eq!((info.width, info.height, info.bit_depth), (1280, 720, 10));
+        assert_eq!(info.timing.unwrap().frame_duration(), Some(33_333));
+    }
+
+    #[test]
+    fn test_av1_annexb_roundtrip() {
+        let section5 = temporal_unit(&[&[0x10, 1, 2, 3], &[0x80]]);
+        let annexb = section5_to_annexb(&section5).unwrap();
+
+        // temporal unit of two frame units, the first one holds TD and sequence header
+        let seq_len = sequence_header().len();
+        assert_eq!(annexb[0] as usize, annexb.len() - 1);
+        assert_eq!(annexb[1] as usize, 2 + (2 + seq_len) + 7);
+        assert_eq!(annexb[2..4], [1, OBU_TEMPORAL_DELIMITER << 3]);
+        assert_eq!(
+            annexb[annexb.len() - 5..],
+            [4, 3, OBU_FRAME_HEADER << 3 | 0x04, 0x20, 0x80]
+        );
+
+        let obus: Vec<_> = ObuIter::new(&section5).map(Result::unwrap).collect();
+        assert_eq!(obus[2].temporal_id(), 1);
+        assert_eq!(annexb_to_section5(&annexb).unwrap(), section5);
+    }
+
+    #[test]
+    fn test_av1_malformed() {
+        // empty, forbidden bit, missing extension byte, truncated payload
+        for data in [&[][..], &[0x80], &[0x0C], &[0x0A, 0x05, 0x00]] {
+            assert!(Obu::parse(data).is_err(), "{data:02X?}");
+        }
+
+        assert!(read_leb128(&[0xFF; 8]).is_err());
+        assert!(read_leb128(&[0x80]).is_err());
+
+        // seq_profile 7, truncated
+        assert!(SequenceHeader::parse(&[0xE0]).is_err());
+        assert!(SequenceHeader::parse(&[]).is_err());
+
+        assert!(Av1Config::parse(&[0x81, 0, 0]).is_err());
+        assert!(Av1Config::parse(&[0x80, 0, 0, 0]).is_err());
+        assert!(
+            Av1Config::parse(&[0x81, 0, 0, 0])
+                .unwrap()
+                .sequence_header()
+                .is_err()
+        );
+
+        // temporal unit size beyond the data
+        assert!(annexb_to_section5(&[0x05, 0x01, 0x00]).is_err());
+    }
+}
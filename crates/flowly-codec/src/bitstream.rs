@@ -0,0 +1,394 @@
+use bytes::{Bytes, BytesMut};
+use flowly_core::{
+    EncodedFrame, Fourcc, Frame, FrameFlags, FrameSource, Multichannel, Packet, VideoFrame,
+};
+use flowly_service::{Context, Service};
+
+use crate::{
+    av1,
+    error::Error,
+    h264, h265,
+    nal::{self, AnnexBIter, LengthPrefixedIter},
+};
+
+/// Layout of H.264/H.265 NAL units (or AV1 OBUs) inside the frame payload
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NalFormat {
+    /// Start code prefixed (MPEG-TS, RTP, raw `.h264` files),
+    /// length delimited AV1 Annex B
+    #[default]
+    AnnexB,
+
+    /// Length prefixed (AVCC/HVCC), used by MP4, FLV and Matroska,
+    /// low overhead AV1 bitstream (Section 5)
+    LengthPrefixed,
+}
+
+impl NalFormat {
+    #[inline]
+    pub fn of<F: EncodedFrame>(frame: &F) -> Self {
+        if frame.has_flag(FrameFlags::ANNEXB) {
+            Self::AnnexB
+        } else {
+            Self::LengthPrefixed
+        }
+    }
+}
+
+/// Converts H.264/H.265 and AV1 frames into requested [`NalFormat`].
+///
+/// Converting to AnnexB puts the parameter sets in-band in front of keyframes,
+/// converting to length prefixed moves in-band parameter sets to `params()`
+/// (replacing the previous ones, as the in-band sets are the most recent).
+/// AV1 sequence headers are kept in-band, `av1C` built from the in-band one
+/// is attached if the frame has no params. Frames of other codecs are passed
+/// unchanged. Length prefixed input has 4-byte lengths unless `params()` is an
+/// `avcC`/`hvcC` record with another `lengthSizeMinusOne`.
+#[derive(Debug, Clone, Copy)]
+pub struct BitstreamConverter {
+    format: NalFormat,
+    length_size: u8,
+}
+
+impl BitstreamConverter {
+    pub fn new(format: NalFormat, length_size: u8) -> Self {
+        assert!(
+            matches!(length_size, 1 | 2 | 4),
+            "NAL length size must be 1, 2 or 4"
+        );
+
+        Self {
+            format,
+            length_size,
+        }
+    }
+
+    pub fn annexb() -> Self {
+        Self::new(NalFormat::AnnexB, 4)
+    }
+
+    pub fn length_prefixed() -> Self {
+        Self::new(NalFormat::LengthPrefixed, 4)
+    }
+
+    pub fn convert<F>(&self, frame: F) -> Result<Packet<F::Source>, Error>
+    where
+        F: EncodedFrame + Multichannel + VideoFrame,
+    {
+        let format = NalFormat::of(&frame);
+        let mut packet = Packet::from_frame(frame);
+
+        if packet.codec == Fourcc::VIDEO_AV1 {
+            return self.convert_av1(packet, format);
+        }
+
+        if !matches!(packet.codec, Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC) {
+            return Ok(packet);
+        }
+
+        let codec = packet.codec;
+        let (params, input_length_size) = split_params(codec, &packet.params)?;
+
+        let data = packet.data.clone();
+        let nals = match format {
+            NalFormat::AnnexB => AnnexBIter::new(&data).collect::<Vec<_>>(),
+            NalFormat::LengthPrefixed => {
+                LengthPrefixedIter::new(&data, input_length_size).collect::<Result<Vec<_>, _>>()?
+            }
+        };
+
+        let mut buf = BytesMut::with_capacity(packet.data.len() + 64);
+
+        match self.format {
+            NalFormat::AnnexB => {
+                let has_inband = nals.iter().any(|x| is_param(codec, x));
+                let mut nals = nals.into_iter().peekable();
+
+                // the access unit delimiter stays the first NAL unit
+                if let Some(aud) = nals.next_if(|x| is_aud(codec, x)) {
+                    nal::put_annexb(&mut buf, aud);
+                }
+
+                if packet.is_keyframe() && !has_inband {
+                    for param in &params {
+                        nal::put_annexb(&mut buf, param);
+                    }
+                }
+
+                for nal in nals {
+                    nal::put_annexb(&mut buf, nal);
+                }
+
+                packet.flags.insert(FrameFlags::ANNEXB);
+            }
+
+            NalFormat::LengthPrefixed => {
+                let mut inband = Vec::new();
+
+                for nal in nals {
+                    if is_aud(codec, nal) {
+                        continue;
+                    }
+
+                    if is_param(codec, nal) {
+                        inband.push(Bytes::copy_from_slice(nal));
+                    } else {
+                        nal::put_length_prefixed(&mut buf, nal, self.length_size);
+                    }
+                }
+
+                packet = packet.with_params(if inband.is_empty() { params } else { inband });
+                packet.flags.remove(FrameFlags::ANNEXB);
+            }
+        }
+
+        packet.data = buf.freeze();
+
+        Ok(packet)
+    }
+}
+
+impl BitstreamConverter {
+    fn convert_av1<S: FrameSource>(
+        &self,
+        mut packet: Packet<S>,
+        format: NalFormat,
+    ) -> Result<Packet<S>, Error> {
+        let section5 = match format {
+            NalFormat::AnnexB => av1::annexb_to_section5(&packet.data)?,
+            NalFormat::LengthPrefixed => packet.data.clone(),
+        };
+
+        let has_inband = av1::ObuIter::new(&section5)
+            .any(|x| x.is_ok_and(|x| x.obu_type == av1::OBU_SEQUENCE_HEADER));
+
+        match self.format {
+            NalFormat::AnnexB => {
+                let mut data = section5;
+
+                if packet.is_keyframe() && !has_inband {
+                    let config = packet.params.first().map(|x| av1::Av1Config::parse(x));
+
+                    if let Some(Ok(config)) = config {
+                        data = prepend_config_obus(&data, &config.config_obus)?;
+                    }
+                }
+
+                packet.data = av1::section5_to_annexb(&data)?;
+                packet.flags.insert(FrameFlags::ANNEXB);
+            }
+
+            NalFormat::LengthPrefixed => {
+                if packet.params.is_empty() && has_inband {
+                    let config = av1::Av1Config::from_obus(&section5)?;
+                    packet = packet.with_params(vec![config.to_bytes()]);
+                }
+
+                packet.data = section5;
+                packet.flags.remove(FrameFlags::ANNEXB);
+            }
+        }
+
+        Ok(packet)
+    }
+}
+
+/// Inserts configuration OBUs after the temporal delimiter
+fn prepend_config_obus(data: &[u8], config_obus: &[u8]) -> Result<Bytes, Error> {
+    let mut buf = BytesMut::with_capacity(data.len() + config_obus.len());
+    let mut inserted = false;
+
+    for obu in av1::ObuIter::new(data) {
+        let obu = obu?;
+
+        if !inserted && obu.obu_type != av1::OBU_TEMPORAL_DELIMITER {
+            buf.extend_from_slice(config_obus);
+            inserted = true;
+        }
+
+        obu.write(&mut buf);
+    }
+
+    if !inserted {
+        buf.extend_from_slice(config_obus);
+    }
+
+    Ok(buf.freeze())
+}
+
+impl Default for BitstreamConverter {
+    fn default() -> Self {
+        Self::annexb()
+    }
+}
+
+/// Parameter set NAL units (start codes stripped) and the NAL length size of
+/// the length prefixed payload. `params` are either NAL units or a single
+/// `avcC`/`hvcC` record, the length size is 4 unless the record tells otherwise
+fn split_params(codec: Fourcc, params: &[Bytes]) -> Result<(Vec<Bytes>, u8), Error> {
+    match params {
+        // configurationVersion 1, never the first byte of a parameter set NAL unit
+        [record] if record.first() == Some(&1) => Ok(match codec {
+            Fourcc::VIDEO_AVC => {
+                let config = h264::AvcConfig::parse(record)?;
+                (config.params().cloned().collect(), config.length_size)
+            }
+            _ => {
+                let config = h265::HevcConfig::parse(record)?;
+                (config.params().cloned().collect(), config.length_size)
+            }
+        }),
+
+        _ => Ok((
+            params
+                .iter()
+                .map(|x| match nal::strip_start_code(x).len() {
+                    len if len == x.len() => x.clone(),
+                    len => x.slice(x.len() - len..),
+                })
+                .collect(),
+            4,
+        )),
+    }
+}
+
+/// VPS/SPS/PPS
+fn is_param(codec: Fourcc, nal: &[u8]) -> bool {
+    match codec {
+        Fourcc::VIDEO_AVC => matches!(h264::nal_type(nal), Some(h264::NAL_SPS | h264::NAL_PPS)),
+        _ => matches!(
+            h265::nal_type(nal),
+            Some(h265::NAL_VPS | h265::NAL_SPS | h265::NAL_PPS)
+        ),
+    }
+}
+
+fn is_aud(codec: Fourcc, nal: &[u8]) -> bool {
+    match codec {
+        Fourcc::VIDEO_AVC => h264::nal_type(nal) == Some(h264::NAL_AUD),
+        _ => h265::nal_type(nal) == Some(h265::NAL_AUD),
+    }
+}
+
+impl<F: EncodedFrame + Multichannel + VideoFrame> Service<F> for BitstreamConverter {
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        futures::stream::once(futures::future::ready(self.convert(frame)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use flowly_core::{Fourcc, Frame, FrameFlags, Packet};
+
+    use super::BitstreamConverter;
+    use crate::{
+        h264::AvcConfig,
+        test_fixtures::{PPS, SPS},
+    };
+
+    #[test]
+    fn test_annexb_roundtrip() {
+        let annexb = Packet::new(
+            (),
+            Fourcc::VIDEO_AVC,
+            FrameFlags::KEYFRAME | FrameFlags::ANNEXB,
+            Bytes::from_static(&[
+                0, 0, 0, 1, 0x09, 0xF0, 0, 0, 0, 1, 0x67, 0x42, 0, 0, 1, 0x68, 0xCE, 0, 0, 1, 0x65,
+                0x88, 0x80,
+            ]),
+        );
+
+        let avcc = BitstreamConverter::length_prefixed()
+            .convert(annexb)
+            .unwrap();
+
+        assert!(!avcc.has_flag(FrameFlags::ANNEXB));
+        assert!(avcc.has_params());
+        assert_eq!(avcc.params, [&[0x67, 0x42][..], &[0x68, 0xCE][..]]);
+        assert_eq!(&avcc.data[..], &[0, 0, 0, 3, 0x65, 0x88, 0x80]);
+
+        let annexb = BitstreamConverter::annexb().convert(avcc).unwrap();
+
+        assert!(annexb.has_flag(FrameFlags::ANNEXB));
+        assert_eq!(
+            &annexb.data[..],
+            &[
+                0, 0, 0, 1, 0x67, 0x42, 0, 0, 0, 1, 0x68, 0xCE, 0, 0, 0, 1, 0x65, 0x88, 0x80
+            ]
+        );
+    }
+
+    #[test]
+    fn test_annexb_params() {
+        let config = AvcConfig {
+            length_size: 2,
+            ..AvcConfig::from_params([SPS, PPS]).unwrap()
+        };
+
+        // 2-byte NAL lengths of the `avcC` record, an AUD but no in-band SPS/PPS
+        let avcc = Packet::new(
+            (),
+            Fourcc::VIDEO_AVC,
+            FrameFlags::KEYFRAME,
+            Bytes::from_static(&[0, 2, 0x09, 0xF0, 0, 3, 0x65, 0x88, 0x80]),
+        )
+        .with_params(vec![config.to_bytes()]);
+
+        let annexb = BitstreamConverter::annexb().convert(avcc).unwrap();
+
+        let mut expected = vec![0, 0, 0, 1, 0x09, 0xF0];
+        for nal in [SPS, PPS, &[0x65, 0x88, 0x80]] {
+            expected.extend_from_slice(&[0, 0, 0, 1]);
+            expected.extend_from_slice(nal);
+        }
+
+        assert_eq!(&annexb.data[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_annexb_inband_params() {
+        // in-band SPS/PPS take precedence over the stale params
+        let mut annexb = Packet::new(
+            (),
+            Fourcc::VIDEO_AVC,
+            FrameFlags::KEYFRAME | FrameFlags::ANNEXB,
+            Bytes::from_static(&[
+                0, 0, 1, 0x67, 0x42, 0, 0, 1, 0x68, 0xCE, 0, 0, 1, 0x65, 0x88,
+            ]),
+        )
+        .with_params(vec![Bytes::from_static(SPS), Bytes::from_static(PPS)])
+        .with_track(3);
+        annexb.dimensions = (1920, 1080);
+        annexb.bit_depth = 8;
+
+        let avcc = BitstreamConverter::length_prefixed()
+            .convert(annexb)
+            .unwrap();
+
+        assert_eq!(avcc.params, [&[0x67, 0x42][..], &[0x68, 0xCE][..]]);
+        assert_eq!(&avcc.data[..], &[0, 0, 0, 2, 0x65, 0x88]);
+        assert_eq!(
+            (avcc.track, avcc.dimensions, avcc.bit_depth),
+            (3, (1920, 1080), 8)
+        );
+    }
+
+    #[test]
+    fn test_convert_malformed() {
+        // NAL unit shorter than its length, AV1 OBU with the forbidden bit
+        for (codec, data) in [
+            (Fourcc::VIDEO_AVC, &[0, 0, 0, 9, 0x65, 0x88][..]),
+            (Fourcc::VIDEO_HEVC, &[0, 0, 0][..]),
+            (Fourcc::VIDEO_AV1, &[0x80, 0x00][..]),
+        ] {
+            let packet = Packet::new((), codec, FrameFlags::KEYFRAME, Bytes::from_static(data));
+            assert!(
+                BitstreamConverter::annexb().convert(packet).is_err(),
+                "{codec:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use bytes::TryGetError;
+use flowly_core::Fourcc;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Unexpected end of data: {0}")]
+    UnexpectedEof(#[from] TryGetError),
+
+    #[error("Unexpected NAL unit type: {0}")]
+    UnexpectedNalType(u8),
+
+    #[error("Missing parameter set: {0}")]
+    MissingParams(&'static str),
+
+    #[error("Invalid data: {0}")]
+    InvalidData(&'static str),
+
+    #[error("Unsupported: {0}")]
+    Unsupported(&'static str),
+
+    #[error("Unsupported codec: {0}")]
+    UnsupportedCodec(Fourcc),
+}
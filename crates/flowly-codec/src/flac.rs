@@ -0,0 +1,336 @@
+use crate::error::Error;
+
+pub const MARKER: &[u8; 4] = b"fLaC";
+
+pub const BLOCK_STREAMINFO: u8 = 0;
+pub const STREAMINFO_SIZE: usize = 34;
+
+/// Shortest frame header: sync, codes, one byte of number and CRC-8
+pub const MIN_HEADER_SIZE: usize = 6;
+
+/// Longest frame header: 7 bytes of number and both 16-bit extensions
+pub const MAX_HEADER_SIZE: usize = 16;
+
+const SAMPLE_RATES: [u32; 12] = [
+    0, 88200, 176400, 192000, 8000, 16000, 22050, 24000, 32000, 44100, 48000, 96000,
+];
+
+const SAMPLE_SIZES: [u8; 8] = [0, 8, 12, 0, 16, 20, 24, 32];
+
+/// STREAMINFO metadata block (RFC 9639 8.2)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub min_block_size: u16,
+    pub max_block_size: u16,
+    pub min_frame_size: u32,
+    pub max_frame_size: u32,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u8,
+
+    /// Total samples per channel, zero if unknown
+    pub total_samples: u64,
+    pub md5: [u8; 16],
+}
+
+impl StreamInfo {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let Some(data) = data.get(..STREAMINFO_SIZE) else {
+            return Err(Error::InvalidData("flac: truncated STREAMINFO"));
+        };
+
+        let u24_at = |pos: usize| u32::from_be_bytes([0, data[pos], data[pos + 1], data[pos + 2]]);
+        let packed = u64::from_be_bytes(data[10..18].try_into().unwrap());
+
+        let info = Self {
+            min_block_size: u16::from_be_bytes([data[0], data[1]]),
+            max_block_size: u16::from_be_bytes([data[2], data[3]]),
+            min_frame_size: u24_at(4),
+            max_frame_size: u24_at(7),
+            sample_rate: (packed >> 44) as u32,
+            channels: ((packed >> 41) & 0x07) as u8 + 1,
+            bits_per_sample: ((packed >> 36) & 0x1F) as u8 + 1,
+            total_samples: packed & 0x0F_FFFF_FFFF,
+            md5: data[18..34].try_into().unwrap(),
+        };
+
+        if info.sample_rate == 0 || info.min_block_size < 16 {
+            return Err(Error::InvalidData("flac: invalid STREAMINFO"));
+        }
+
+        Ok(info)
+    }
+
+    pub fn to_bytes(&self) -> [u8; STREAMINFO_SIZE] {
+        let mut out = [0u8; STREAMINFO_SIZE];
+        let packed = (self.sample_rate as u64) << 44
+            | ((self.channels as u64 - 1) & 0x07) << 41
+            | ((self.bits_per_sample as u64 - 1) & 0x1F) << 36
+            | self.total_samples & 0x0F_FFFF_FFFF;
+
+        out[0..2].copy_from_slice(&self.min_block_size.to_be_bytes());
+        out[2..4].copy_from_slice(&self.max_block_size.to_be_bytes());
+        out[4..7].copy_from_slice(&self.min_frame_size.to_be_bytes()[1..]);
+        out[7..10].copy_from_slice(&self.max_frame_size.to_be_bytes()[1..]);
+        out[10..18].copy_from_slice(&packed.to_be_bytes());
+        out[18..34].copy_from_slice(&self.md5);
+        out
+    }
+
+    #[inline]
+    pub fn is_fixed_blocksize(&self) -> bool {
+        self.min_block_size == self.max_block_size
+    }
+}
+
+/// Frame header (RFC 9639 9.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub variable_blocksize: bool,
+    pub block_size: u16,
+
+    /// Sample rate, `None` if it is taken from STREAMINFO
+    pub sample_rate: Option<u32>,
+    pub channel_assignment: u8,
+
+    /// Bits per sample, `None` if it is taken from STREAMINFO
+    pub bits_per_sample: Option<u8>,
+
+    /// Frame number for fixed block size streams, first sample number otherwise
+    pub number: u64,
+
+    /// Header size including CRC-8
+    pub size: usize,
+}
+
+impl FrameHeader {
+    /// Parses and verifies the header, `UnexpectedEof` asks for more data
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let eof = || {
+            Error::UnexpectedEof(bytes::TryGetError {
+                requested: MIN_HEADER_SIZE,
+                available: data.len(),
+            })
+        };
+
+        let &[b0, b1, b2, b3, ..] = data else {
+            return Err(eof());
+        };
+
+        if b0 != 0xFF || b1 & 0xFE != 0xF8 {
+            return Err(Error::InvalidData("flac: invalid sync"));
+        }
+
+        let block_code = b2 >> 4;
+        let rate_code = b2 & 0x0F;
+        let channel_assignment = b3 >> 4;
+        let size_code = (b3 >> 1) & 0x07;
+
+        if block_code == 0
+            || rate_code == 15
+            || channel_assignment > 10
+            || size_code == 3
+            || b3 & 0x01 != 0
+        {
+            return Err(Error::InvalidData("flac: reserved header value"));
+        }
+
+        let mut pos = 4;
+        let number = read_utf8(data, &mut pos)?;
+
+        let mut read_ext = |len: usize| match data.get(pos..pos + len) {
+            Some(ext) => {
+                pos += len;
+                Ok(ext.iter().fold(0u32, |acc, &x| acc << 8 | x as u32))
+            }
+            None => Err(eof()),
+        };
+
+        let block_size = match block_code {
+            1 => 192,
+            2..=5 => 576 << (block_code - 2),
+            6 => read_ext(1)? + 1,
+            7 => read_ext(2)? + 1,
+            _ => 256 << (block_code - 8),
+        };
+
+        let sample_rate = match rate_code {
+            0 => None,
+            12 => Some(read_ext(1)? * 1000),
+            13 => Some(read_ext(2)?),
+            14 => Some(read_ext(2)? * 10),
+            _ => Some(SAMPLE_RATES[rate_code as usize]),
+        };
+
+        let Some(&crc) = data.get(pos) else {
+            return Err(eof());
+        };
+
+        if crc8(&data[..pos]) != crc {
+            return Err(Error::InvalidData("flac: header CRC mismatch"));
+        }
+
+        if block_size > u16::MAX as u32 {
+            return Err(Error::InvalidData("flac: invalid block size"));
+        }
+
+        if sample_rate == Some(0) {
+            return Err(Error::InvalidData("flac: invalid sample rate"));
+        }
+
+        Ok(Self {
+            variable_blocksize: b1 & 0x01 != 0,
+            block_size: block_size as u16,
+            sample_rate,
+            channel_assignment,
+            bits_per_sample: (size_code != 0).then_some(SAMPLE_SIZES[size_code as usize]),
+            number,
+            size: pos + 1,
+        })
+    }
+
+    #[inline]
+    pub fn channels(&self) -> u8 {
+        if self.channel_assignment < 8 {
+            self.channel_assignment + 1
+        } else {
+            2
+        }
+    }
+
+    /// Number of the first sample of the frame
+    pub fn first_sample(&self, info: &StreamInfo) -> u64 {
+        if self.variable_blocksize {
+            self.number
+        } else {
+            self.number * info.max_block_size as u64
+        }
+    }
+}
+
+/// Reads the UTF-8 like coded frame or sample number
+fn read_utf8(data: &[u8], pos: &mut usize) -> Result<u64, Error> {
+    let eof = || {
+        Error::UnexpectedEof(bytes::TryGetError {
+            requested: *pos + 1,
+            available: data.len(),
+        })
+    };
+    let &first = data.get(*pos).ok_or_else(eof)?;
+
+    let extra = match first.leading_ones() {
+        0 => 0,
+        n @ 2..=7 => n as usize - 1,
+        _ => return Err(Error::InvalidData("flac: invalid coded number")),
+    };
+
+    let bytes = data.get(*pos + 1..*pos + 1 + extra).ok_or_else(eof)?;
+    let mut value = (first & (0x7F >> extra)) as u64;
+
+    for &byte in bytes {
+        if byte & 0xC0 != 0x80 {
+            return Err(Error::InvalidData("flac: invalid coded number"));
+        }
+
+        value = value << 6 | (byte & 0x3F) as u64;
+    }
+
+    *pos += 1 + extra;
+    Ok(value)
+}
+
+/// CRC-8 of the frame header, polynomial 0x07
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+
+    for &byte in data {
+        crc ^= byte;
+
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// CRC-16 of the frame, polynomial 0x8005; zero over the frame with its footer
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameHeader, StreamInfo, crc8};
+
+    #[test]
+    fn test_flac_headers() {
+        let info = StreamInfo {
+            min_block_size: 4096,
+            max_block_size: 4096,
+            min_frame_size: 14,
+            max_frame_size: 9000,
+            sample_rate: 44100,
+            channels: 2,
+            bits_per_sample: 16,
+            total_samples: 1_000_000,
+            md5: [7; 16],
+        };
+        assert_eq!(StreamInfo::parse(&info.to_bytes()).unwrap(), info);
+
+        // fixed block size 4096, 44.1 kHz, stereo, 16 bit, frame 300 (two bytes coded)
+        let mut data = vec![0xFF, 0xF8, 0xC9, 0x18, 0xC4, 0xAC];
+        data.push(crc8(&data));
+
+        let header = FrameHeader::parse(&data).unwrap();
+        assert_eq!(header.block_size, 4096);
+        assert_eq!(
+            (header.sample_rate, header.bits_per_sample),
+            (Some(44100), Some(16))
+        );
+        assert_eq!((header.number, header.size), (300, 7));
+        assert_eq!(header.first_sample(&info), 300 * 4096);
+
+        // uncommon block size 1000 in the 16-bit extension, bad CRC
+        let mut data = vec![0xFF, 0xF9, 0x79, 0x18, 0x00, 0x03, 0xE7];
+        data.push(crc8(&data));
+        assert_eq!(FrameHeader::parse(&data).unwrap().block_size, 1000);
+
+        data[7] ^= 1;
+        assert!(FrameHeader::parse(&data).is_err());
+        assert!(matches!(
+            FrameHeader::parse(&data[..5]),
+            Err(crate::Error::UnexpectedEof(_))
+        ));
+
+        // zero sample rate in the 8 and 16-bit extensions
+        for (rate_code, ext) in [(0xCC, &[0][..]), (0xCD, &[0, 0][..])] {
+            let mut data = vec![0xFF, 0xF8, rate_code, 0x18, 0x00];
+            data.extend_from_slice(ext);
+            data.push(crc8(&data));
+
+            assert!(matches!(
+                FrameHeader::parse(&data),
+                Err(crate::Error::InvalidData("flac: invalid sample rate"))
+            ));
+        }
+    }
+}
@@ -0,0 +1,621 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly_core::{BitReader, Reader, ReaderExt, WriterExt};
+
+use crate::{
+    error::Error,
+    info::{ChromaFormat, Crop, Timing},
+    nal::{self, read_se_range, read_ue_max},
+};
+
+pub const NAL_SLICE: u8 = 1;
+pub const NAL_IDR: u8 = 5;
+pub const NAL_SEI: u8 = 6;
+pub const NAL_SPS: u8 = 7;
+pub const NAL_PPS: u8 = 8;
+pub const NAL_AUD: u8 = 9;
+pub const NAL_STAP_A: u8 = 24;
+pub const NAL_FU_A: u8 = 28;
+
+/// Upper limit of the picture width/height in macroblocks (minus 1), far
+/// beyond the largest level
+const MAX_SIZE_IN_MBS: u32 = 4095;
+
+/// NAL unit type of the H.264 NAL unit (without start code)
+#[inline]
+pub fn nal_type(nal: &[u8]) -> Option<u8> {
+    nal.first().map(|x| x & 0x1F)
+}
+
+/// Sequence Parameter Set (H.264 7.3.2.1.1)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Sps {
+    pub profile_idc: u8,
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub id: u32,
+    pub chroma_format: ChromaFormat,
+    pub separate_colour_plane: bool,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    pub log2_max_frame_num: u8,
+    pub pic_order_cnt_type: u32,
+    pub log2_max_pic_order_cnt_lsb: u8,
+    pub max_num_ref_frames: u32,
+    pub pic_width_in_mbs: u32,
+    pub pic_height_in_map_units: u32,
+    pub frame_mbs_only: bool,
+    pub direct_8x8_inference: bool,
+    pub crop: Crop,
+    pub vui: Option<Vui>,
+}
+
+/// Subset of the VUI parameters (H.264 E.1.1)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Vui {
+    pub sar: Option<(u16, u16)>,
+    pub video_full_range: bool,
+    pub colour: Option<(u8, u8, u8)>,
+    pub timing: Option<Timing>,
+}
+
+impl Sps {
+    pub fn parse(nal: &[u8]) -> Result<Self, Error> {
+        let mut r = BitReader::new_rbsp(nal);
+
+        let nal_type = r.read_u8(8)? & 0x1F;
+        if nal_type != NAL_SPS {
+            return Err(Error::UnexpectedNalType(nal_type));
+        }
+
+        let mut sps = Sps {
+            profile_idc: r.read_u8(8)?,
+            constraint_flags: r.read_u8(8)?,
+            level_idc: r.read_u8(8)?,
+            id: read_ue_max(&mut r, 31, "h264: seq_parameter_set_id")?,
+            chroma_format: ChromaFormat::Yuv420,
+            bit_depth_luma: 8,
+            bit_depth_chroma: 8,
+            ..Default::default()
+        };
+
+        if matches!(
+            sps.profile_idc,
+            100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+        ) {
+            let chroma_format_idc = r.read_ue()?;
+            sps.chroma_format = ChromaFormat::from_idc(chroma_format_idc)
+                .ok_or(Error::InvalidData("h264: chroma_format_idc"))?;
+
+            if chroma_format_idc == 3 {
+                sps.separate_colour_plane = r.read_flag()?;
+            }
+
+            sps.bit_depth_luma = read_ue_max(&mut r, 6, "h264: bit_depth_luma_minus8")? as u8 + 8;
+            sps.bit_depth_chroma =
+                read_ue_max(&mut r, 6, "h264: bit_depth_chroma_minus8")? as u8 + 8;
+            let _qpprime_y_zero_transform_bypass = r.read_flag()?;
+
+            if r.read_flag()? {
+                let count = if chroma_format_idc != 3 { 8 } else { 12 };
+
+                for i in 0..count {
+                    if r.read_flag()? {
+                        skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+
+        sps.log2_max_frame_num =
+            read_ue_max(&mut r, 12, "h264: log2_max_frame_num_minus4")? as u8 + 4;
+        sps.pic_order_cnt_type = read_ue_max(&mut r, 2, "h264: pic_order_cnt_type")?;
+
+        match sps.pic_order_cnt_type {
+            0 => {
+                sps.log2_max_pic_order_cnt_lsb =
+                    read_ue_max(&mut r, 12, "h264: log2_max_pic_order_cnt_lsb_minus4")? as u8 + 4;
+            }
+            1 => {
+                let _delta_pic_order_always_zero = r.read_flag()?;
+                let _offset_for_non_ref_pic = r.read_se()?;
+                let _offset_for_top_to_bottom_field = r.read_se()?;
+
+                for _ in 0..read_ue_max(&mut r, 255, "h264: num_ref_frames_in_pic_order_cnt_cycle")?
+                {
+                    let _offset_for_ref_frame = r.read_se()?;
+                }
+            }
+            _ => (),
+        }
+
+        sps.max_num_ref_frames = read_ue_max(&mut r, 16, "h264: max_num_ref_frames")?;
+        let _gaps_in_frame_num_value_allowed = r.read_flag()?;
+        sps.pic_width_in_mbs =
+            read_ue_max(&mut r, MAX_SIZE_IN_MBS, "h264: pic_width_in_mbs_minus1")? + 1;
+        sps.pic_height_in_map_units = read_ue_max(
+            &mut r,
+            MAX_SIZE_IN_MBS,
+            "h264: pic_height_in_map_units_minus1",
+        )? + 1;
+        sps.frame_mbs_only = r.read_flag()?;
+
+        if !sps.frame_mbs_only {
+            let _mb_adaptive_frame_field = r.read_flag()?;
+        }
+
+        sps.direct_8x8_inference = r.read_flag()?;
+
+        if r.read_flag()? {
+            // offsets are in chroma units, never more than the coded size
+            let (max_x, max_y) = sps.coded_size();
+
+            sps.crop = Crop {
+                left: read_ue_max(&mut r, max_x, "h264: frame_crop_left_offset")?,
+                right: read_ue_max(&mut r, max_x, "h264: frame_crop_right_offset")?,
+                top: read_ue_max(&mut r, max_y, "h264: frame_crop_top_offset")?,
+                bottom: read_ue_max(&mut r, max_y, "h264: frame_crop_bottom_offset")?,
+            };
+        }
+
+        if r.read_flag()? {
+            sps.vui = Some(Vui::parse(&mut r)?);
+        }
+
+        Ok(sps)
+    }
+
+    /// ChromaArrayType
+    #[inline]
+    pub fn chroma_array_type(&self) -> ChromaFormat {
+        if self.separate_colour_plane {
+            ChromaFormat::Monochrome
+        } else {
+            self.chroma_format
+        }
+    }
+
+    /// Frame width and height in luma samples, before cropping
+    pub fn coded_size(&self) -> (u32, u32) {
+        (
+            self.pic_width_in_mbs * 16,
+            self.pic_height_in_map_units * 16 * (2 - self.frame_mbs_only as u32),
+        )
+    }
+
+    /// Cropping rectangle in luma samples
+    pub fn crop_rect(&self) -> Crop {
+        let (unit_x, unit_y) = match self.chroma_array_type() {
+            ChromaFormat::Monochrome => (1, 2 - self.frame_mbs_only as u32),
+            format => {
+                let (sub_width, sub_height) = format.subsampling();
+                (sub_width, sub_height * (2 - self.frame_mbs_only as u32))
+            }
+        };
+
+        Crop {
+            left: self.crop.left * unit_x,
+            right: self.crop.right * unit_x,
+            top: self.crop.top * unit_y,
+            bottom: self.crop.bottom * unit_y,
+        }
+    }
+
+    /// Display width and height
+    pub fn dimensions(&self) -> (u32, u32) {
+        let (width, height) = self.coded_size();
+        let crop = self.crop_rect();
+
+        (
+            width.saturating_sub(crop.left + crop.right),
+            height.saturating_sub(crop.top + crop.bottom),
+        )
+    }
+}
+
+impl Vui {
+    fn parse<B: bytes::Buf>(r: &mut BitReader<B>) -> Result<Self, Error> {
+        let mut vui = Vui::default();
+
+        if r.read_flag()? {
+            vui.sar = match r.read_u8(8)? {
+                255 => Some((r.read_u16(16)?, r.read_u16(16)?)),
+                idc => sar_from_idc(idc),
+            };
+        }
+
+        if r.read_flag()? {
+            let _overscan_appropriate = r.read_flag()?;
+        }
+
+        if r.read_flag()? {
+            let _video_format = r.read_u8(3)?;
+            vui.video_full_range = r.read_flag()?;
+
+            if r.read_flag()? {
+                vui.colour = Some((r.read_u8(8)?, r.read_u8(8)?, r.read_u8(8)?));
+            }
+        }
+
+        if r.read_flag()? {
+            let _chroma_sample_loc_type_top_field = r.read_ue()?;
+            let _chroma_sample_loc_type_bottom_field = r.read_ue()?;
+        }
+
+        if r.read_flag()? {
+            let num_units_in_tick = r.read_u32(32)?;
+            let time_scale = r.read_u32(32)?;
+
+            vui.timing = Some(Timing {
+                // H.264 time_scale counts fields, two ticks per frame
+                num_units_in_tick: num_units_in_tick.saturating_mul(2),
+                time_scale,
+                fixed_frame_rate: r.read_flag()?,
+            });
+        }
+
+        Ok(vui)
+    }
+}
+
+/// Picture Parameter Set (H.264 7.3.2.2)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Pps {
+    pub id: u32,
+    pub sps_id: u32,
+    pub entropy_coding_mode: bool,
+    pub bottom_field_pic_order_in_frame_present: bool,
+    pub num_slice_groups: u32,
+    pub num_ref_idx_l0_default_active: u32,
+    pub num_ref_idx_l1_default_active: u32,
+    pub weighted_pred: bool,
+    pub weighted_bipred_idc: u8,
+    pub pic_init_qp: i32,
+    pub pic_init_qs: i32,
+    pub chroma_qp_index_offset: i32,
+    pub deblocking_filter_control_present: bool,
+    pub constrained_intra_pred: bool,
+    pub redundant_pic_cnt_present: bool,
+    pub transform_8x8_mode: bool,
+}
+
+impl Pps {
+    pub fn parse(nal: &[u8]) -> Result<Self, Error> {
+        let mut r = BitReader::new_rbsp(nal);
+
+        let nal_type = r.read_u8(8)? & 0x1F;
+        if nal_type != NAL_PPS {
+            return Err(Error::UnexpectedNalType(nal_type));
+        }
+
+        let mut pps = Pps {
+            id: read_ue_max(&mut r, 255, "h264: pic_parameter_set_id")?,
+            sps_id: read_ue_max(&mut r, 31, "h264: seq_parameter_set_id")?,
+            entropy_coding_mode: r.read_flag()?,
+            bottom_field_pic_order_in_frame_present: r.read_flag()?,
+            num_slice_groups: read_ue_max(&mut r, 7, "h264: num_slice_groups_minus1")? + 1,
+            ..Default::default()
+        };
+
+        if pps.num_slice_groups > 1 {
+            // FMO is only allowed in Baseline/Extended profiles and is not used in practice
+            return Err(Error::Unsupported("h264: slice groups"));
+        }
+
+        pps.num_ref_idx_l0_default_active =
+            read_ue_max(&mut r, 31, "h264: num_ref_idx_l0_default_active_minus1")? + 1;
+        pps.num_ref_idx_l1_default_active =
+            read_ue_max(&mut r, 31, "h264: num_ref_idx_l1_default_active_minus1")? + 1;
+        pps.weighted_pred = r.read_flag()?;
+        pps.weighted_bipred_idc = r.read_u8(2)?;
+        // the lower bound depends on the bit depth of the SPS, 14 bits is the widest
+        pps.pic_init_qp = read_se_range(&mut r, -62, 25, "h264: pic_init_qp_minus26")? + 26;
+        pps.pic_init_qs = read_se_range(&mut r, -26, 25, "h264: pic_init_qs_minus26")? + 26;
+        pps.chroma_qp_index_offset =
+            read_se_range(&mut r, -12, 12, "h264: chroma_qp_index_offset")?;
+        pps.deblocking_filter_control_present = r.read_flag()?;
+        pps.constrained_intra_pred = r.read_flag()?;
+        pps.redundant_pic_cnt_present = r.read_flag()?;
+
+        if r.more_rbsp_data() {
+            pps.transform_8x8_mode = r.read_flag()?;
+        }
+
+        Ok(pps)
+    }
+}
+
+/// AVCDecoderConfigurationRecord (ISO/IEC 14496-15 5.3.3.1), `avcC` box payload
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AvcConfig {
+    pub profile_idc: u8,
+    pub profile_compatibility: u8,
+    pub level_idc: u8,
+    pub length_size: u8,
+    pub sps: Vec<Bytes>,
+    pub pps: Vec<Bytes>,
+    pub chroma_format: ChromaFormat,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    pub sps_ext: Vec<Bytes>,
+}
+
+impl AvcConfig {
+    /// Builds the record from SPS/PPS NAL units (start codes are allowed)
+    pub fn from_params<P: AsRef<[u8]>>(params: impl IntoIterator<Item = P>) -> Result<Self, Error> {
+        let mut config = AvcConfig {
+            length_size: 4,
+            ..Default::default()
+        };
+
+        for param in params {
+            let param = nal::strip_start_code(param.as_ref());
+
+            match nal_type(param) {
+                Some(NAL_SPS) => config.sps.push(Bytes::copy_from_slice(param)),
+                Some(NAL_PPS) => config.pps.push(Bytes::copy_from_slice(param)),
+                Some(13) => config.sps_ext.push(Bytes::copy_from_slice(param)),
+                _ => (),
+            }
+        }
+
+        let sps = Sps::parse(
+            config
+                .sps
+                .first()
+                .ok_or(Error::MissingParams("h264: sps"))?,
+        )?;
+
+        config.profile_idc = sps.profile_idc;
+        config.profile_compatibility = sps.constraint_flags;
+        config.level_idc = sps.level_idc;
+        config.chroma_format = sps.chroma_format;
+        config.bit_depth_luma = sps.bit_depth_luma;
+        config.bit_depth_chroma = sps.bit_depth_chroma;
+
+        Ok(config)
+    }
+
+    pub fn parse(mut data: &[u8]) -> Result<Self, Error> {
+        let r = &mut data;
+
+        if r.read_u8()? != 1 {
+            return Err(Error::InvalidData("avcC: configuration version"));
+        }
+
+        let mut config = AvcConfig {
+            profile_idc: r.read_u8()?,
+            profile_compatibility: r.read_u8()?,
+            level_idc: r.read_u8()?,
+            length_size: r.read_u8p2::<6, 2>()?.1 + 1,
+            chroma_format: ChromaFormat::Yuv420,
+            bit_depth_luma: 8,
+            bit_depth_chroma: 8,
+            ..Default::default()
+        };
+
+        for _ in 0..r.read_u8p2::<3, 5>()?.1 {
+            let len = r.read_u16()? as usize;
+            config.sps.push(r.read_bytes(len)?);
+        }
+
+        for _ in 0..r.read_u8()? {
+            let len = r.read_u16()? as usize;
+            config.pps.push(r.read_bytes(len)?);
+        }
+
+        // the extension is often missing even for high profiles
+        if matches!(config.profile_idc, 100 | 110 | 122 | 144) && r.len() >= 4 {
+            config.chroma_format =
+                ChromaFormat::from_idc(r.read_u8p2::<6, 2>()?.1 as u32).unwrap_or_default();
+            config.bit_depth_luma = r.read_u8p2::<5, 3>()?.1 + 8;
+            config.bit_depth_chroma = r.read_u8p2::<5, 3>()?.1 + 8;
+
+            for _ in 0..r.read_u8()? {
+                let len = r.read_u16()? as usize;
+                config.sps_ext.push(r.read_bytes(len)?);
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(64);
+
+        buf.put_u8(1);
+        buf.put_u8(self.profile_idc);
+        buf.put_u8(self.profile_compatibility);
+        buf.put_u8(self.level_idc);
+        buf.put_u8p2::<6, 2>(0x3F, self.length_size.saturating_sub(1));
+        buf.put_u8p2::<3, 5>(0x07, self.sps.len() as u8);
+
+        for sps in &self.sps {
+            buf.put_u16(sps.len() as u16);
+            buf.put_slice(sps);
+        }
+
+        buf.put_u8(self.pps.len() as u8);
+        for pps in &self.pps {
+            buf.put_u16(pps.len() as u16);
+            buf.put_slice(pps);
+        }
+
+        if matches!(self.profile_idc, 100 | 110 | 122 | 144) {
+            buf.put_u8p2::<6, 2>(0x3F, self.chroma_format.idc());
+            buf.put_u8p2::<5, 3>(0x1F, self.bit_depth_luma.saturating_sub(8));
+            buf.put_u8p2::<5, 3>(0x1F, self.bit_depth_chroma.saturating_sub(8));
+            buf.put_u8(self.sps_ext.len() as u8);
+
+            for ext in &self.sps_ext {
+                buf.put_u16(ext.len() as u16);
+                buf.put_slice(ext);
+            }
+        }
+
+        buf.freeze()
+    }
+
+    /// Parameter sets in decoding order (SPS, SPS extensions, PPS)
+    pub fn params(&self) -> impl Iterator<Item = &Bytes> {
+        self.sps
+            .iter()
+            .chain(self.sps_ext.iter())
+            .chain(self.pps.iter())
+    }
+}
+
+fn skip_scaling_list<B: bytes::Buf>(r: &mut BitReader<B>, size: usize) -> Result<(), Error> {
+    let mut last_scale = 8i32;
+    let mut next_scale = 8i32;
+
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta_scale = read_se_range(r, -128, 127, "h264: delta_scale")?;
+            next_scale = (last_scale + delta_scale + 256) % 256;
+        }
+
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sample aspect ratio from `aspect_ratio_idc` (Table E-1)
+pub(crate) fn sar_from_idc(idc: u8) -> Option<(u16, u16)> {
+    const SAR: [(u16, u16); 17] = [
+        (0, 0),
+        (1, 1),
+        (12, 11),
+        (10, 11),
+        (16, 11),
+        (40, 33),
+        (24, 11),
+        (20, 11),
+        (32, 11),
+        (80, 33),
+        (18, 11),
+        (15, 11),
+        (64, 33),
+        (160, 99),
+        (4, 3),
+        (3, 2),
+        (2, 1),
+    ];
+
+    SAR.get(idc as usize).copied().filter(|x| x.0 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use flowly_core::BitWriter;
+
+    use super::{AvcConfig, Pps, Sps};
+    use crate::{
+        error::Error,
+        info::ChromaFormat,
+        test_fixtures::{PPS, SPS},
+    };
+
+    #[test]
+    fn test_parse_sps() {
+        let sps = Sps::parse(SPS).unwrap();
+
+        assert_eq!(sps.profile_idc, 100);
+        assert_eq!(sps.level_idc, 40);
+        assert_eq!(sps.chroma_format, ChromaFormat::Yuv420);
+        assert_eq!(sps.bit_depth_luma, 8);
+        assert_eq!(sps.coded_size(), (1920, 1088));
+        assert_eq!(sps.dimensions(), (1920, 1080));
+
+        let timing = sps.vui.unwrap().timing.unwrap();
+        assert_eq!(timing.frame_rate(), Some((50, 2)));
+    }
+
+    #[test]
+    fn test_parse_sps_out_of_range() {
+        // High profile SPS with bit_depth_luma_minus8 = 300
+        let mut w = BitWriter::new(Vec::new());
+        w.put_bits(32, 0x6764_0028);
+        w.put_ue(0);
+        w.put_ue(1);
+        w.put_ue(300);
+        w.put_trailing_bits();
+
+        assert!(matches!(
+            Sps::parse(&w.finish()),
+            Err(Error::InvalidData("h264: bit_depth_luma_minus8"))
+        ));
+
+        // log2_max_frame_num_minus4 = 252 used to wrap around to 0
+        let mut w = BitWriter::new(Vec::new());
+        w.put_bits(32, 0x6742_001E);
+        w.put_ue(0);
+        w.put_ue(252);
+        w.put_trailing_bits();
+
+        assert!(matches!(
+            Sps::parse(&w.finish()),
+            Err(Error::InvalidData("h264: log2_max_frame_num_minus4"))
+        ));
+
+        // scaling list with delta_scale = i32::MAX used to overflow
+        let mut w = BitWriter::new(Vec::new());
+        w.put_bits(32, 0x6764_0028);
+        w.put_ue(0);
+        w.put_ue(1);
+        w.put_ue(0);
+        w.put_ue(0);
+        w.put_bits(3, 0b011);
+        w.put_se(i32::MAX);
+        w.put_trailing_bits();
+
+        assert!(matches!(
+            Sps::parse(&w.finish()),
+            Err(Error::InvalidData("h264: delta_scale"))
+        ));
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert!(matches!(Sps::parse(PPS), Err(Error::UnexpectedNalType(_))));
+        assert!(matches!(Pps::parse(SPS), Err(Error::UnexpectedNalType(_))));
+        assert!(Sps::parse(&SPS[..6]).is_err());
+        assert!(Pps::parse(&PPS[..1]).is_err());
+
+        let mut config = AvcConfig::from_params([SPS, PPS])
+            .unwrap()
+            .to_bytes()
+            .to_vec();
+        assert!(AvcConfig::parse(&config[..8]).is_err());
+
+        config[0] = 0;
+        assert!(matches!(
+            AvcConfig::parse(&config),
+            Err(Error::InvalidData("avcC: configuration version"))
+        ));
+    }
+
+    #[test]
+    fn test_parse_pps() {
+        let pps = Pps::parse(PPS).unwrap();
+
+        assert_eq!(pps.id, 0);
+        assert_eq!(pps.sps_id, 0);
+        assert!(pps.entropy_coding_mode);
+        assert!(pps.transform_8x8_mode);
+    }
+
+    #[test]
+    fn test_avc_config_roundtrip() {
+        let config = AvcConfig::from_params([SPS, PPS]).unwrap();
+
+        assert_eq!(config.profile_idc, 100);
+        assert_eq!(config.level_idc, 40);
+
+        let parsed = AvcConfig::parse(&config.to_bytes()).unwrap();
+        assert_eq!(parsed, config);
+        assert_eq!(parsed.params().count(), 2);
+    }
+}
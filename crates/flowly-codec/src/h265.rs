@@ -0,0 +1,849 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flowly_core::{BitReader, Reader, ReaderExt, Writer, WriterExt};
+
+use crate::{
+    error::Error,
+    h264::sar_from_idc,
+    info::{ChromaFormat, Crop, Timing},
+    nal::{self, read_se_range, read_ue_max},
+};
+
+pub const NAL_TRAIL_R: u8 = 1;
+pub const NAL_BLA_W_LP: u8 = 16;
+pub const NAL_IDR_W_RADL: u8 = 19;
+pub const NAL_IDR_N_LP: u8 = 20;
+pub const NAL_CRA: u8 = 21;
+pub const NAL_RSV_IRAP_23: u8 = 23;
+pub const NAL_VPS: u8 = 32;
+pub const NAL_SPS: u8 = 33;
+pub const NAL_PPS: u8 = 34;
+pub const NAL_AUD: u8 = 35;
+pub const NAL_PREFIX_SEI: u8 = 39;
+pub const NAL_SUFFIX_SEI: u8 = 40;
+pub const NAL_AP: u8 = 48;
+pub const NAL_FU: u8 = 49;
+
+/// Upper limit of the picture width/height, far beyond the largest level
+const MAX_PIC_SIZE: u32 = 65535;
+
+/// NAL unit type of the H.265 NAL unit (without start code)
+#[inline]
+pub fn nal_type(nal: &[u8]) -> Option<u8> {
+    nal.first().map(|x| (x >> 1) & 0x3F)
+}
+
+/// IRAP pictures (BLA, IDR, CRA) can be decoded independently
+#[inline]
+pub fn is_irap(nal_type: u8) -> bool {
+    (NAL_BLA_W_LP..=NAL_RSV_IRAP_23).contains(&nal_type)
+}
+
+/// General profile, tier and level (H.265 7.3.3)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProfileTierLevel {
+    pub profile_space: u8,
+    pub tier: bool,
+    pub profile_idc: u8,
+    pub profile_compatibility_flags: u32,
+    /// progressive, interlaced, non-packed, frame-only and reserved flags (48 bits)
+    pub constraint_indicator_flags: u64,
+    pub level_idc: u8,
+}
+
+impl ProfileTierLevel {
+    fn parse<B: Buf>(r: &mut BitReader<B>, max_sub_layers_minus1: u8) -> Result<Self, Error> {
+        let ptl = Self {
+            profile_space: r.read_u8(2)?,
+            tier: r.read_flag()?,
+            profile_idc: r.read_u8(5)?,
+            profile_compatibility_flags: r.read_u32(32)?,
+            constraint_indicator_flags: r.read_bits(48)?,
+            level_idc: r.read_u8(8)?,
+        };
+
+        let mut sub_layers = [(false, false); 8];
+        for layer in sub_layers.iter_mut().take(max_sub_layers_minus1 as usize) {
+            *layer = (r.read_flag()?, r.read_flag()?);
+        }
+
+        if max_sub_layers_minus1 > 0 {
+            r.skip_bits(2 * (8 - max_sub_layers_minus1 as usize))?;
+        }
+
+        for (profile_present, level_present) in
+            sub_layers.into_iter().take(max_sub_layers_minus1 as usize)
+        {
+            if profile_present {
+                r.skip_bits(88)?;
+            }
+
+            if level_present {
+                r.skip_bits(8)?;
+            }
+        }
+
+        Ok(ptl)
+    }
+}
+
+/// Video Parameter Set (H.265 7.3.2.1)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Vps {
+    pub id: u8,
+    pub max_sub_layers: u8,
+    pub temporal_id_nesting: bool,
+    pub profile_tier_level: ProfileTierLevel,
+    pub timing: Option<Timing>,
+}
+
+impl Vps {
+    pub fn parse(nal: &[u8]) -> Result<Self, Error> {
+        let mut r = BitReader::new_rbsp(nal);
+
+        let nal_type = (r.read_u16(16)? >> 9) as u8;
+        if nal_type != NAL_VPS {
+            return Err(Error::UnexpectedNalType(nal_type));
+        }
+
+        let id = r.read_u8(4)?;
+        let _base_layer_internal = r.read_flag()?;
+        let _base_layer_available = r.read_flag()?;
+        let _max_layers_minus1 = r.read_u8(6)?;
+        let max_sub_layers_minus1 = r.read_u8(3)?;
+        let temporal_id_nesting = r.read_flag()?;
+        let _reserved = r.read_u16(16)?;
+        let profile_tier_level = ProfileTierLevel::parse(&mut r, max_sub_layers_minus1)?;
+
+        let ordering_info_present = r.read_flag()?;
+        let first = if ordering_info_present {
+            0
+        } else {
+            max_sub_layers_minus1
+        };
+
+        for _ in first..=max_sub_layers_minus1 {
+            let _max_dec_pic_buffering_minus1 = r.read_ue()?;
+            let _max_num_reorder_pics = r.read_ue()?;
+            let _max_latency_increase_plus1 = r.read_ue()?;
+        }
+
+        let max_layer_id = r.read_u8(6)?;
+        let num_layer_sets_minus1 = r.read_ue()?;
+        r.skip_bits(num_layer_sets_minus1 as usize * (max_layer_id as usize + 1))?;
+
+        let timing = if r.read_flag()? {
+            Some(Timing {
+                num_units_in_tick: r.read_u32(32)?,
+                time_scale: r.read_u32(32)?,
+                fixed_frame_rate: r.read_flag()?,
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            id,
+            max_sub_layers: max_sub_layers_minus1 + 1,
+            temporal_id_nesting,
+            profile_tier_level,
+            timing,
+        })
+    }
+}
+
+/// Sequence Parameter Set (H.265 7.3.2.2)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Sps {
+    pub vps_id: u8,
+    pub max_sub_layers: u8,
+    pub temporal_id_nesting: bool,
+    pub profile_tier_level: ProfileTierLevel,
+    pub id: u32,
+    pub chroma_format: ChromaFormat,
+    pub separate_colour_plane: bool,
+    pub pic_width: u32,
+    pub pic_height: u32,
+    pub conformance_window: Crop,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    pub log2_max_pic_order_cnt_lsb: u8,
+    pub log2_min_luma_coding_block_size: u8,
+    pub log2_diff_max_min_luma_coding_block_size: u8,
+    pub amp_enabled: bool,
+    pub sample_adaptive_offset_enabled: bool,
+    pub num_short_term_ref_pic_sets: u32,
+    pub long_term_ref_pics_present: bool,
+    pub temporal_mvp_enabled: bool,
+    pub strong_intra_smoothing_enabled: bool,
+    pub vui: Option<Vui>,
+}
+
+/// Subset of the VUI parameters (H.265 E.2.1)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Vui {
+    pub sar: Option<(u16, u16)>,
+    pub video_full_range: bool,
+    pub colour: Option<(u8, u8, u8)>,
+    pub field_seq: bool,
+    pub timing: Option<Timing>,
+    pub min_spatial_segmentation: u32,
+}
+
+impl Sps {
+    pub fn parse(nal: &[u8]) -> Result<Self, Error> {
+        let mut r = BitReader::new_rbsp(nal);
+
+        let nal_type = (r.read_u16(16)? >> 9) as u8;
+        if nal_type != NAL_SPS {
+            return Err(Error::UnexpectedNalType(nal_type));
+        }
+
+        let vps_id = r.read_u8(4)?;
+        let max_sub_layers_minus1 = r.read_u8(3)?;
+        let temporal_id_nesting = r.read_flag()?;
+        let profile_tier_level = ProfileTierLevel::parse(&mut r, max_sub_layers_minus1)?;
+
+        let mut sps = Sps {
+            vps_id,
+            max_sub_layers: max_sub_layers_minus1 + 1,
+            temporal_id_nesting,
+            profile_tier_level,
+            id: read_ue_max(&mut r, 15, "h265: sps_seq_parameter_set_id")?,
+            ..Default::default()
+        };
+
+        let chroma_format_idc = r.read_ue()?;
+        sps.chroma_format = ChromaFormat::from_idc(chroma_format_idc)
+            .ok_or(Error::InvalidData("h265: chroma_format_idc"))?;
+
+        if chroma_format_idc == 3 {
+            sps.separate_colour_plane = r.read_flag()?;
+        }
+
+        sps.pic_width = read_ue_max(&mut r, MAX_PIC_SIZE, "h265: pic_width_in_luma_samples")?;
+        sps.pic_height = read_ue_max(&mut r, MAX_PIC_SIZE, "h265: pic_height_in_luma_samples")?;
+
+        if r.read_flag()? {
+            // offsets are in chroma units, never more than the picture size
+            sps.conformance_window = Crop {
+                left: read_ue_max(&mut r, sps.pic_width, "h265: conf_win_left_offset")?,
+                right: read_ue_max(&mut r, sps.pic_width, "h265: conf_win_right_offset")?,
+                top: read_ue_max(&mut r, sps.pic_height, "h265: conf_win_top_offset")?,
+                bottom: read_ue_max(&mut r, sps.pic_height, "h265: conf_win_bottom_offset")?,
+            };
+        }
+
+        sps.bit_depth_luma = read_ue_max(&mut r, 8, "h265: bit_depth_luma_minus8")? as u8 + 8;
+        sps.bit_depth_chroma = read_ue_max(&mut r, 8, "h265: bit_depth_chroma_minus8")? as u8 + 8;
+        sps.log2_max_pic_order_cnt_lsb =
+            read_ue_max(&mut r, 12, "h265: log2_max_pic_order_cnt_lsb_minus4")? as u8 + 4;
+
+        let ordering_info_present = r.read_flag()?;
+        let first = if ordering_info_present {
+            0
+        } else {
+            max_sub_layers_minus1
+        };
+
+        for _ in first..=max_sub_layers_minus1 {
+            let _max_dec_pic_buffering_minus1 = r.read_ue()?;
+            let _max_num_reorder_pics = r.read_ue()?;
+            let _max_latency_increase_plus1 = r.read_ue()?;
+        }
+
+        // CtbLog2SizeY is 4..=6
+        sps.log2_min_luma_coding_block_size =
+            read_ue_max(&mut r, 3, "h265: log2_min_luma_coding_block_size_minus3")? as u8 + 3;
+        sps.log2_diff_max_min_luma_coding_block_size = read_ue_max(
+            &mut r,
+            6 - sps.log2_min_luma_coding_block_size as u32,
+            "h265: log2_diff_max_min_luma_coding_block_size",
+        )? as u8;
+        let _log2_min_luma_transform_block_size_minus2 = r.read_ue()?;
+        let _log2_diff_max_min_luma_transform_block_size = r.read_ue()?;
+        let _max_transform_hierarchy_depth_inter = r.read_ue()?;
+        let _max_transform_hierarchy_depth_intra = r.read_ue()?;
+
+        if r.read_flag()? && r.read_flag()? {
+            skip_scaling_list_data(&mut r)?;
+        }
+
+        sps.amp_enabled = r.read_flag()?;
+        sps.sample_adaptive_offset_enabled = r.read_flag()?;
+
+        if r.read_flag()? {
+            let _pcm_sample_bit_depth_luma_minus1 = r.read_u8(4)?;
+            let _pcm_sample_bit_depth_chroma_minus1 = r.read_u8(4)?;
+            let _log2_min_pcm_luma_coding_block_size_minus3 = r.read_ue()?;
+            let _log2_diff_max_min_pcm_luma_coding_block_size = r.read_ue()?;
+            let _pcm_loop_filter_disabled = r.read_flag()?;
+        }
+
+        sps.num_short_term_ref_pic_sets = r.read_ue()?;
+        if sps.num_short_term_ref_pic_sets > 64 {
+            return Err(Error::InvalidData("h265: num_short_term_ref_pic_sets"));
+        }
+
+        let mut num_delta_pocs = Vec::with_capacity(sps.num_short_term_ref_pic_sets as usize);
+        for idx in 0..sps.num_short_term_ref_pic_sets as usize {
+            let count = skip_st_ref_pic_set(&mut r, idx, &num_delta_pocs)?;
+            num_delta_pocs.push(count);
+        }
+
+        sps.long_term_ref_pics_present = r.read_flag()?;
+        if sps.long_term_ref_pics_present {
+            for _ in 0..read_ue_max(&mut r, 32, "h265: num_long_term_ref_pics_sps")? {
+                r.skip_bits(sps.log2_max_pic_order_cnt_lsb as usize + 1)?;
+            }
+        }
+
+        sps.temporal_mvp_enabled = r.read_flag()?;
+        sps.strong_intra_smoothing_enabled = r.read_flag()?;
+
+        if r.read_flag()? {
+            sps.vui = Some(Vui::parse(&mut r)?);
+        }
+
+        Ok(sps)
+    }
+
+    /// ChromaArrayType
+    #[inline]
+    pub fn chroma_array_type(&self) -> ChromaFormat {
+        if self.separate_colour_plane {
+            ChromaFormat::Monochrome
+        } else {
+            self.chroma_format
+        }
+    }
+
+    /// Conformance window in luma samples
+    pub fn crop_rect(&self) -> Crop {
+        let (unit_x, unit_y) = self.chroma_array_type().subsampling();
+
+        Crop {
+            left: self.conformance_window.left * unit_x,
+            right: self.conformance_window.right * unit_x,
+            top: self.conformance_window.top * unit_y,
+            bottom: self.conformance_window.bottom * unit_y,
+        }
+    }
+
+    /// Display width and height
+    pub fn dimensions(&self) -> (u32, u32) {
+        let crop = self.crop_rect();
+
+        (
+            self.pic_width.saturating_sub(crop.left + crop.right),
+            self.pic_height.saturating_sub(crop.top + crop.bottom),
+        )
+    }
+}
+
+impl Vui {
+    fn parse<B: Buf>(r: &mut BitReader<B>) -> Result<Self, Error> {
+        let mut vui = Vui::default();
+
+        if r.read_flag()? {
+            vui.sar = match r.read_u8(8)? {
+                255 => Some((r.read_u16(16)?, r.read_u16(16)?)),
+                idc => sar_from_idc(idc),
+            };
+        }
+
+        if r.read_flag()? {
+            let _overscan_appropriate = r.read_flag()?;
+        }
+
+        if r.read_flag()? {
+            let _video_format = r.read_u8(3)?;
+            vui.video_full_range = r.read_flag()?;
+
+            if r.read_flag()? {
+                vui.colour = Some((r.read_u8(8)?, r.read_u8(8)?, r.read_u8(8)?));
+            }
+        }
+
+        if r.read_flag()? {
+            let _chroma_sample_loc_type_top_field = r.read_ue()?;
+            let _chroma_sample_loc_type_bottom_field = r.read_ue()?;
+        }
+
+        let _neutral_chroma_indication = r.read_flag()?;
+        vui.field_seq = r.read_flag()?;
+        let _frame_field_info_present = r.read_flag()?;
+
+        if r.read_flag()? {
+            let _def_disp_win_left_offset = r.read_ue()?;
+            let _def_disp_win_right_offset = r.read_ue()?;
+            let _def_disp_win_top_offset = r.read_ue()?;
+            let _def_disp_win_bottom_offset = r.read_ue()?;
+        }
+
+        if r.read_flag()? {
+            vui.timing = Some(Timing {
+                num_units_in_tick: r.read_u32(32)?,
+                time_scale: r.read_u32(32)?,
+                fixed_frame_rate: false,
+            });
+
+            if r.read_flag()? {
+                let _num_ticks_poc_diff_one_minus1 = r.read_ue()?;
+            }
+
+            if r.read_flag()? {
+                // hrd_parameters() are not needed, the rest of the VUI is skipped
+                return Ok(vui);
+            }
+        }
+
+        if r.read_flag()? {
+            let _tiles_fixed_structure = r.read_flag()?;
+            let _motion_vectors_over_pic_boundaries = r.read_flag()?;
+            let _restricted_ref_pic_lists = r.read_flag()?;
+            vui.min_spatial_segmentation = r.read_ue()?;
+        }
+
+        Ok(vui)
+    }
+}
+
+/// Picture Parameter Set (H.265 7.3.2.3), fields up to `entropy_coding_sync_enabled_flag`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Pps {
+    pub id: u32,
+    pub sps_id: u32,
+    pub dependent_slice_segments_enabled: bool,
+    pub output_flag_present: bool,
+    pub num_extra_slice_header_bits: u8,
+    pub sign_data_hiding_enabled: bool,
+    pub cabac_init_present: bool,
+    pub num_ref_idx_l0_default_active: u32,
+    pub num_ref_idx_l1_default_active: u32,
+    pub init_qp: i32,
+    pub constrained_intra_pred: bool,
+    pub transform_skip_enabled: bool,
+    pub cu_qp_delta_enabled: bool,
+    pub diff_cu_qp_delta_depth: u32,
+    pub cb_qp_offset: i32,
+    pub cr_qp_offset: i32,
+    pub slice_chroma_qp_offsets_present: bool,
+    pub weighted_pred: bool,
+    pub weighted_bipred: bool,
+    pub transquant_bypass_enabled: bool,
+    pub tiles_enabled: bool,
+    pub entropy_coding_sync_enabled: bool,
+}
+
+impl Pps {
+    pub fn parse(nal: &[u8]) -> Result<Self, Error> {
+        let mut r = BitReader::new_rbsp(nal);
+
+        let nal_type = (r.read_u16(16)? >> 9) as u8;
+        if nal_type != NAL_PPS {
+            return Err(Error::UnexpectedNalType(nal_type));
+        }
+
+        let mut pps = Pps {
+            id: read_ue_max(&mut r, 63, "h265: pps_pic_parameter_set_id")?,
+            sps_id: read_ue_max(&mut r, 15, "h265: pps_seq_parameter_set_id")?,
+            dependent_slice_segments_enabled: r.read_flag()?,
+            output_flag_present: r.read_flag()?,
+            num_extra_slice_header_bits: r.read_u8(3)?,
+            sign_data_hiding_enabled: r.read_flag()?,
+            cabac_init_present: r.read_flag()?,
+            num_ref_idx_l0_default_active: read_ue_max(
+                &mut r,
+                14,
+                "h265: num_ref_idx_l0_default_active_minus1",
+            )? + 1,
+            num_ref_idx_l1_default_active: read_ue_max(
+                &mut r,
+                14,
+                "h265: num_ref_idx_l1_default_active_minus1",
+            )? + 1,
+            // the lower bound depends on the bit depth of the SPS, 16 bits is the widest
+            init_qp: read_se_range(&mut r, -74, 25, "h265: init_qp_minus26")? + 26,
+            constrained_intra_pred: r.read_flag()?,
+            transform_skip_enabled: r.read_flag()?,
+            cu_qp_delta_enabled: r.read_flag()?,
+            ..Default::default()
+        };
+
+        if pps.cu_qp_delta_enabled {
+            pps.diff_cu_qp_delta_depth = read_ue_max(&mut r, 3, "h265: diff_cu_qp_delta_depth")?;
+        }
+
+        pps.cb_qp_offset = read_se_range(&mut r, -12, 12, "h265: pps_cb_qp_offset")?;
+        pps.cr_qp_offset = read_se_range(&mut r, -12, 12, "h265: pps_cr_qp_offset")?;
+        pps.slice_chroma_qp_offsets_present = r.read_flag()?;
+        pps.weighted_pred = r.read_flag()?;
+        pps.weighted_bipred = r.read_flag()?;
+        pps.transquant_bypass_enabled = r.read_flag()?;
+        pps.tiles_enabled = r.read_flag()?;
+        pps.entropy_coding_sync_enabled = r.read_flag()?;
+
+        Ok(pps)
+    }
+}
+
+/// Array of the NAL units of the same type in [`HevcConfig`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NalArray {
+    pub completeness: bool,
+    pub nal_type: u8,
+    pub nals: Vec<Bytes>,
+}
+
+/// HEVCDecoderConfigurationRecord (ISO/IEC 14496-15 8.3.3.1), `hvcC` box payload
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HevcConfig {
+    pub profile_tier_level: ProfileTierLevel,
+    pub min_spatial_segmentation: u16,
+    pub parallelism_type: u8,
+    pub chroma_format: ChromaFormat,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    pub avg_frame_rate: u16,
+    pub constant_frame_rate: u8,
+    pub num_temporal_layers: u8,
+    pub temporal_id_nested: bool,
+    pub length_size: u8,
+    pub arrays: Vec<NalArray>,
+}
+
+impl HevcConfig {
+    /// Builds the record from VPS/SPS/PPS (and SEI) NAL units, start codes are allowed
+    pub fn from_params<P: AsRef<[u8]>>(params: impl IntoIterator<Item = P>) -> Result<Self, Error> {
+        let mut config = HevcConfig {
+            length_size: 4,
+            ..Default::default()
+        };
+
+        let mut sps = None;
+        let mut pps = None;
+
+        for param in params {
+            let param = nal::strip_start_code(param.as_ref());
+            let Some(nal_type) = nal_type(param) else {
+                continue;
+            };
+
+            match nal_type {
+                NAL_SPS if sps.is_none() => sps = Some(Sps::parse(param)?),
+                NAL_PPS if pps.is_none() => pps = Some(Pps::parse(param)?),
+                NAL_VPS | NAL_SPS | NAL_PPS | NAL_PREFIX_SEI | NAL_SUFFIX_SEI => (),
+                _ => continue,
+            }
+
+            config.push_nal(nal_type, Bytes::copy_from_slice(param));
+        }
+
+        let sps = sps.ok_or(Error::MissingParams("h265: sps"))?;
+
+        config.profile_tier_level = sps.profile_tier_level;
+        config.min_spatial_segmentation = sps
+            .vui
+            .as_ref()
+            .map(|x| x.min_spatial_segmentation as u16)
+            .unwrap_or(0);
+        config.parallelism_type = match pps {
+            Some(pps) if pps.tiles_enabled && pps.entropy_coding_sync_enabled => 0,
+            Some(pps) if pps.entropy_coding_sync_enabled => 3,
+            Some(pps) if pps.tiles_enabled => 2,
+            _ => 0,
+        };
+        config.chroma_format = sps.chroma_format;
+        config.bit_depth_luma = sps.bit_depth_luma;
+        config.bit_depth_chroma = sps.bit_depth_chroma;
+        config.num_temporal_layers = sps.max_sub_layers;
+        config.temporal_id_nested = sps.temporal_id_nesting;
+
+        // parameter sets must be in VPS, SPS, PPS order
+        config.arrays.sort_by_key(|x| match x.nal_type {
+            NAL_VPS => 0,
+            NAL_SPS => 1,
+            NAL_PPS => 2,
+            _ => 3,
+        });
+
+        Ok(config)
+    }
+
+    fn push_nal(&mut self, nal_type: u8, nal: Bytes) {
+        if let Some(arr) = self.arrays.iter_mut().find(|x| x.nal_type == nal_type) {
+            arr.nals.push(nal);
+        } else {
+            self.arrays.push(NalArray {
+                completeness: true,
+                nal_type,
+                nals: vec![nal],
+            });
+        }
+    }
+
+    pub fn parse(mut data: &[u8]) -> Result<Self, Error> {
+        let r = &mut data;
+
+        if r.read_u8()? != 1 {
+            return Err(Error::InvalidData("hvcC: configuration version"));
+        }
+
+        let (profile_space, tier, profile_idc) = r.read_u8p3::<2, 1, 5>()?;
+        let profile_tier_level = ProfileTierLevel {
+            profile_space,
+            tier: tier == 1,
+            profile_idc,
+            profile_compatibility_flags: r.read_u32()?,
+            constraint_indicator_flags: r.read_u48()?,
+            level_idc: r.read_u8()?,
+        };
+
+        let mut config = HevcConfig {
+            profile_tier_level,
+            min_spatial_segmentation: r.read_u16p2::<4, 12>()?.1,
+            parallelism_type: r.read_u8p2::<6, 2>()?.1,
+            chroma_format: ChromaFormat::from_idc(r.read_u8p2::<6, 2>()?.1 as u32)
+                .unwrap_or_default(),
+            bit_depth_luma: r.read_u8p2::<5, 3>()?.1 + 8,
+            bit_depth_chroma: r.read_u8p2::<5, 3>()?.1 + 8,
+            avg_frame_rate: r.read_u16()?,
+            ..Default::default()
+        };
+
+        let (constant_frame_rate, num_temporal_layers, temporal_id_nested, length_size_minus1) =
+            r.read_u8p4::<2, 3, 1, 2>()?;
+
+        config.constant_frame_rate = constant_frame_rate;
+        config.num_temporal_layers = num_temporal_layers;
+        config.temporal_id_nested = temporal_id_nested == 1;
+        config.length_size = length_size_minus1 + 1;
+
+        for _ in 0..r.read_u8()? {
+            let (completeness, _, nal_type) = r.read_u8p3::<1, 1, 6>()?;
+            let mut arr = NalArray {
+                completeness: completeness == 1,
+                nal_type,
+                nals: Vec::new(),
+            };
+
+            for _ in 0..r.read_u16()? {
+                let len = r.read_u16()? as usize;
+                arr.nals.push(r.read_bytes(len)?);
+            }
+
+            config.arrays.push(arr);
+        }
+
+        Ok(config)
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let ptl = &self.profile_tier_level;
+        let mut buf = BytesMut::with_capacity(128);
+
+        buf.put_u8(1);
+        buf.put_u8p3::<2, 1, 5>(ptl.profile_space, ptl.tier as u8, ptl.profile_idc);
+        buf.put_u32(ptl.profile_compatibility_flags);
+        buf.put_u48(ptl.constraint_indicator_flags);
+        buf.put_u8(ptl.level_idc);
+        buf.put_u16p2::<4, 12>(0x0F, self.min_spatial_segmentation);
+        buf.put_u8p2::<6, 2>(0x3F, self.parallelism_type);
+        buf.put_u8p2::<6, 2>(0x3F, self.chroma_format.idc());
+        buf.put_u8p2::<5, 3>(0x1F, self.bit_depth_luma.saturating_sub(8));
+        buf.put_u8p2::<5, 3>(0x1F, self.bit_depth_chroma.saturating_sub(8));
+        buf.put_u16(self.avg_frame_rate);
+        buf.put_u8p4::<2, 3, 1, 2>(
+            self.constant_frame_rate,
+            self.num_temporal_layers,
+            self.temporal_id_nested as u8,
+            self.length_size.saturating_sub(1),
+        );
+
+        buf.put_u8(self.arrays.len() as u8);
+        for arr in &self.arrays {
+            buf.put_u8p3::<1, 1, 6>(arr.completeness as u8, 0, arr.nal_type);
+            buf.put_u16(arr.nals.len() as u16);
+
+            for nal in &arr.nals {
+                buf.put_u16(nal.len() as u16);
+                buf.put_slice(nal);
+            }
+        }
+
+        buf.freeze()
+    }
+
+    /// Parameter sets in the record order
+    pub fn params(&self) -> impl Iterator<Item = &Bytes> {
+        self.arrays.iter().flat_map(|x| x.nals.iter())
+    }
+}
+
+fn skip_scaling_list_data<B: Buf>(r: &mut BitReader<B>) -> Result<(), Error> {
+    for size_id in 0..4 {
+        let step = if size_id == 3 { 3 } else { 1 };
+
+        for _ in (0..6).step_by(step) {
+            if !r.read_flag()? {
+                let _scaling_list_pred_matrix_id_delta = r.read_ue()?;
+            } else {
+                let coef_num = usize::min(64, 1 << (4 + (size_id << 1)));
+
+                if size_id > 1 {
+                    let _scaling_list_dc_coef_minus8 = r.read_se()?;
+                }
+
+                for _ in 0..coef_num {
+                    let _scaling_list_delta_coef = r.read_se()?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Skips `st_ref_pic_set(idx)` returning its `NumDeltaPocs`
+fn skip_st_ref_pic_set<B: Buf>(
+    r: &mut BitReader<B>,
+    idx: usize,
+    num_delta_pocs: &[u32],
+) -> Result<u32, Error> {
+    let inter_ref_pic_set_prediction = idx != 0 && r.read_flag()?;
+
+    if inter_ref_pic_set_prediction {
+        let _delta_rps_sign = r.read_flag()?;
+        let _abs_delta_rps_minus1 = r.read_ue()?;
+
+        let ref_idx = idx - 1;
+        let mut count = 0;
+
+        for _ in 0..=num_delta_pocs[ref_idx] {
+            let used_by_curr_pic = r.read_flag()?;
+            let use_delta = used_by_curr_pic || r.read_flag()?;
+
+            if use_delta {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    } else {
+        let num_negative_pics = r.read_ue()?;
+        let num_positive_pics = r.read_ue()?;
+
+        if num_negative_pics > 16 || num_positive_pics > 16 {
+            return Err(Error::InvalidData("h265: st_ref_pic_set"));
+        }
+
+        for _ in 0..num_negative_pics + num_positive_pics {
+            let _delta_poc_minus1 = r.read_ue()?;
+            let _used_by_curr_pic = r.read_flag()?;
+        }
+
+        Ok(num_negative_pics + num_positive_pics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flowly_core::BitWriter;
+
+    use super::{HevcConfig, NAL_PPS, NAL_SPS, NAL_VPS, Pps, Sps, Vps};
+    use crate::error::Error;
+
+    const VPS: &[u8] = &[
+        0x40, 0x01, 0x0C, 0x01, 0xFF, 0xFF, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00,
+        0x03, 0x00, 0x00, 0x03, 0x00, 0x5D, 0x95, 0x98, 0x09,
+    ];
+
+    const SPS: &[u8] = &[
+        0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00,
+        0x03, 0x00, 0x5D, 0xA0, 0x02, 0x80, 0x80, 0x2D, 0x16, 0x59, 0x59, 0xA4, 0x93, 0x2B, 0xC0,
+        0x5A, 0x70, 0x80, 0x00, 0x00, 0x03, 0x00, 0x80, 0x00, 0x00, 0x0C, 0x84,
+    ];
+
+    const PPS: &[u8] = &[0x44, 0x01, 0xC1, 0x72, 0xB4, 0x62, 0x40];
+
+    #[test]
+    fn test_parse_vps() {
+        let vps = Vps::parse(VPS).unwrap();
+
+        assert_eq!(vps.max_sub_layers, 1);
+        assert_eq!(vps.profile_tier_level.profile_idc, 1);
+        assert_eq!(vps.profile_tier_level.level_idc, 93);
+    }
+
+    #[test]
+    fn test_parse_sps() {
+        let sps = Sps::parse(SPS).unwrap();
+
+        assert_eq!(sps.profile_tier_level.profile_idc, 1);
+        assert_eq!(sps.bit_depth_luma, 8);
+        assert_eq!(sps.dimensions(), (1280, 720));
+
+        let timing = sps.vui.unwrap().timing.unwrap();
+        assert_eq!(timing.frame_rate(), Some((25, 1)));
+        assert_eq!(timing.frame_duration(), Some(40_000));
+    }
+
+    #[test]
+    fn test_parse_pps() {
+        let pps = Pps::parse(PPS).unwrap();
+
+        assert_eq!(pps.id, 0);
+        assert!(pps.cu_qp_delta_enabled);
+        assert!(pps.entropy_coding_sync_enabled);
+        assert!(!pps.tiles_enabled);
+    }
+
+    #[test]
+    fn test_hevc_config_roundtrip() {
+        let config = HevcConfig::from_params([PPS, SPS, VPS]).unwrap();
+
+        assert_eq!(config.profile_tier_level.level_idc, 93);
+        assert_eq!(config.parallelism_type, 3);
+        assert_eq!(
+            config.arrays.iter().map(|x| x.nal_type).collect::<Vec<_>>(),
+            [NAL_VPS, NAL_SPS, NAL_PPS]
+        );
+
+        let parsed = HevcConfig::parse(&config.to_bytes()).unwrap();
+        assert_eq!(parsed, config);
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert!(matches!(Vps::parse(SPS), Err(Error::UnexpectedNalType(_))));
+        assert!(matches!(Sps::parse(PPS), Err(Error::UnexpectedNalType(_))));
+        assert!(matches!(Pps::parse(VPS), Err(Error::UnexpectedNalType(_))));
+
+        assert!(Vps::parse(&VPS[..8]).is_err());
+        assert!(Sps::parse(&SPS[..20]).is_err());
+        assert!(Pps::parse(&PPS[..2]).is_err());
+
+        // pps_pic_parameter_set_id = 64
+        let mut w = BitWriter::new(Vec::new());
+        w.put_bits(16, 0x4401);
+        w.put_ue(64);
+        w.put_trailing_bits();
+
+        assert!(matches!(
+            Pps::parse(&w.finish()),
+            Err(Error::InvalidData("h265: pps_pic_parameter_set_id"))
+        ));
+
+        let mut config = HevcConfig::from_params([VPS, SPS, PPS])
+            .unwrap()
+            .to_bytes()
+            .to_vec();
+        assert!(HevcConfig::parse(&config[..10]).is_err());
+
+        config[0] = 0;
+        assert!(matches!(
+            HevcConfig::parse(&config),
+            Err(Error::InvalidData("hvcC: configuration version"))
+        ));
+    }
+}
@@ -0,0 +1,367 @@
+use std::ops::{Deref, DerefMut};
+
+use flowly_core::{
+    DataFrame, EncodedFrame, Fourcc, Frame, FrameFlags, MemBlock, Multichannel, VideoFrame,
+};
+use flowly_service::{Context, Service};
+
+use crate::{av1, error::Error, h264, h265, nal};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChromaFormat {
+    Monochrome,
+    #[default]
+    Yuv420,
+    Yuv422,
+    Yuv444,
+}
+
+impl ChromaFormat {
+    /// `chroma_format_idc` as used by H.264/H.265
+    pub fn from_idc(idc: u32) -> Option<Self> {
+        Some(match idc {
+            0 => Self::Monochrome,
+            1 => Self::Yuv420,
+            2 => Self::Yuv422,
+            3 => Self::Yuv444,
+            _ => return None,
+        })
+    }
+
+    pub fn idc(&self) -> u8 {
+        match self {
+            Self::Monochrome => 0,
+            Self::Yuv420 => 1,
+            Self::Yuv422 => 2,
+            Self::Yuv444 => 3,
+        }
+    }
+
+    /// Horizontal and vertical chroma subsampling factors (SubWidthC, SubHeightC)
+    pub fn subsampling(&self) -> (u32, u32) {
+        match self {
+            Self::Monochrome => (1, 1),
+            Self::Yuv420 => (2, 2),
+            Self::Yuv422 => (2, 1),
+            Self::Yuv444 => (1, 1),
+        }
+    }
+}
+
+/// Cropping offsets
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Crop {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+/// Stream timing, one frame lasts `num_units_in_tick / time_scale` seconds
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Timing {
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    pub fixed_frame_rate: bool,
+}
+
+impl Timing {
+    /// Frame rate as (numerator, denominator)
+    pub fn frame_rate(&self) -> Option<(u32, u32)> {
+        (self.num_units_in_tick != 0 && self.time_scale != 0)
+            .then_some((self.time_scale, self.num_units_in_tick))
+    }
+
+    /// Frame duration in microseconds
+    pub fn frame_duration(&self) -> Option<u64> {
+        let (num, den) = self.frame_rate()?;
+        Some(den as u64 * 1_000_000 / num as u64)
+    }
+}
+
+/// Video stream description decoded from the codec parameter sets
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VideoInfo {
+    pub codec: Fourcc,
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub bit_depth_chroma: u8,
+    pub chroma_format: ChromaFormat,
+    pub profile: u8,
+    pub level: u8,
+    pub crop: Crop,
+    pub sar: Option<(u16, u16)>,
+    pub timing: Option<Timing>,
+}
+
+impl VideoInfo {
+    /// Decodes info from parameter sets NAL units (start codes are allowed),
+    /// AV1 takes `av1C` or the sequence header OBU
+    pub fn from_params<P: AsRef<[u8]>>(
+        codec: Fourcc,
+        params: impl IntoIterator<Item = P>,
+    ) -> Result<Self, Error> {
+        match codec {
+            Fourcc::VIDEO_AVC => {
+                for param in params {
+                    let param = nal::strip_start_code(param.as_ref());
+
+                    if h264::nal_type(param) == Some(h264::NAL_SPS) {
+                        return Ok(Self::from(&h264::Sps::parse(param)?));
+                    }
+                }
+
+                Err(Error::MissingParams("h264: sps"))
+            }
+
+            Fourcc::VIDEO_HEVC => {
+                let mut vps_timing = None;
+                let mut info = None;
+
+                for param in params {
+                    let param = nal::strip_start_code(param.as_ref());
+
+                    match h265::nal_type(param) {
+                        Some(h265::NAL_VPS) => vps_timing = h265::Vps::parse(param)?.timing,
+                        Some(h265::NAL_SPS) => info = Some(Self::from(&h265::Sps::parse(param)?)),
+                        _ => (),
+                    }
+                }
+
+                let mut info = info.ok_or(Error::MissingParams("h265: sps"))?;
+                info.timing = info.timing.or(vps_timing);
+
+                Ok(info)
+            }
+
+            Fourcc::VIDEO_AV1 => {
+                let mut err = Error::MissingParams("av1: sequence header");
+
+                for param in params {
+                    match av1::sequence_header_of_param(param.as_ref()) {
+                        Ok(seq) => return Ok(Self::from(&seq)),
+                        Err(e) => err = e,
+                    }
+                }
+
+                Err(err)
+            }
+
+            codec => Err(Error::UnsupportedCodec(codec)),
+        }
+    }
+
+    /// Decodes info from the frame parameters
+    pub fn from_frame<F: EncodedFrame>(frame: &F) -> Result<Self, Error> {
+        Self::from_params(frame.codec(), frame.params())
+    }
+
+    /// Display dimensions clamped to the `VideoFrame::dimensions` range
+    #[inline]
+    pub fn dimensions(&self) -> (u16, u16) {
+        (
+            self.width.min(u16::MAX as u32) as u16,
+            self.height.min(u16::MAX as u32) as u16,
+        )
+    }
+}
+
+impl From<&h264::Sps> for VideoInfo {
+    fn from(sps: &h264::Sps) -> Self {
+        let (width, height) = sps.dimensions();
+        let vui = sps.vui.as_ref();
+
+        Self {
+            codec: Fourcc::VIDEO_AVC,
+            width,
+            height,
+            bit_depth: sps.bit_depth_luma,
+            bit_depth_chroma: sps.bit_depth_chroma,
+            chroma_format: sps.chroma_format,
+            profile: sps.profile_idc,
+            level: sps.level_idc,
+            crop: sps.crop_rect(),
+            sar: vui.and_then(|x| x.sar),
+            timing: vui.and_then(|x| x.timing),
+        }
+    }
+}
+
+impl From<&h265::Sps> for VideoInfo {
+    fn from(sps: &h265::Sps) -> Self {
+        let (width, height) = sps.dimensions();
+        let vui = sps.vui.as_ref();
+
+        Self {
+            codec: Fourcc::VIDEO_HEVC,
+            width,
+            height,
+            bit_depth: sps.bit_depth_luma,
+            bit_depth_chroma: sps.bit_depth_chroma,
+            chroma_format: sps.chroma_format,
+            profile: sps.profile_tier_level.profile_idc,
+            level: sps.profile_tier_level.level_idc,
+            crop: sps.crop_rect(),
+            sar: vui.and_then(|x| x.sar),
+            timing: vui.and_then(|x| x.timing),
+        }
+    }
+}
+
+impl From<&av1::SequenceHeader> for VideoInfo {
+    fn from(seq: &av1::SequenceHeader) -> Self {
+        Self {
+            codec: Fourcc::VIDEO_AV1,
+            width: seq.max_frame_width,
+            height: seq.max_frame_height,
+            bit_depth: seq.bit_depth,
+            bit_depth_chroma: seq.bit_depth,
+            chroma_format: seq.chroma_format(),
+            profile: seq.profile,
+            level: seq.level_idx,
+            crop: Crop::default(),
+            sar: None,
+            timing: seq.timing,
+        }
+    }
+}
+
+/// Frame bundled with the [`VideoInfo`] of its stream
+#[derive(Debug, Clone)]
+pub struct WithVideoInfo<F> {
+    pub inner: F,
+    pub info: VideoInfo,
+}
+
+impl<F> WithVideoInfo<F> {
+    pub fn new(inner: F, info: VideoInfo) -> Self {
+        Self { inner, info }
+    }
+}
+
+impl<F> Deref for WithVideoInfo<F> {
+    type Target = F;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<F> DerefMut for WithVideoInfo<F> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<F: DataFrame> DataFrame for WithVideoInfo<F> {
+    type Source = F::Source;
+    type Chunk = F::Chunk;
+
+    #[inline]
+    fn source(&self) -> &Self::Source {
+        self.inner.source()
+    }
+
+    #[inline]
+    fn chunks(&self) -> impl Send + Iterator<Item = <Self::Chunk as MemBlock>::Ref<'_>> {
+        self.inner.chunks()
+    }
+
+    #[inline]
+    fn into_chunks(self) -> impl Send + Iterator<Item = Self::Chunk> {
+        self.inner.into_chunks()
+    }
+}
+
+impl<F: Frame> Frame for WithVideoInfo<F> {
+    #[inline]
+    fn timestamp(&self) -> u64 {
+        self.inner.timestamp()
+    }
+
+    #[inline]
+    fn codec(&self) -> Fourcc {
+        self.inner.codec()
+    }
+
+    #[inline]
+    fn flags(&self) -> FrameFlags {
+        self.inner.flags()
+    }
+}
+
+impl<F: EncodedFrame> EncodedFrame for WithVideoInfo<F> {
+    type Param = F::Param;
+
+    #[inline]
+    fn dts(&self) -> u64 {
+        self.inner.dts()
+    }
+
+    #[inline]
+    fn pts(&self) -> i64 {
+        self.inner.pts()
+    }
+
+    #[inline]
+    fn params(&self) -> impl Iterator<Item = &Self::Param> {
+        self.inner.params()
+    }
+}
+
+impl<F: Multichannel> Multichannel for WithVideoInfo<F> {
+    #[inline]
+    fn track(&self) -> u32 {
+        self.inner.track()
+    }
+}
+
+impl<F: Frame> VideoFrame for WithVideoInfo<F> {
+    #[inline]
+    fn dimensions(&self) -> (u16, u16) {
+        self.info.dimensions()
+    }
+
+    #[inline]
+    fn bit_depth(&self) -> u8 {
+        self.info.bit_depth
+    }
+}
+
+/// Attaches [`VideoInfo`] to every frame of the stream.
+///
+/// The info is refreshed on every frame flagged with `HAS_PARAMS`, frames
+/// received before the first parameter sets carry the default (empty) info.
+#[derive(Debug, Default, Clone)]
+pub struct VideoInfoParser {
+    info: VideoInfo,
+}
+
+impl VideoInfoParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Last decoded info
+    #[inline]
+    pub fn info(&self) -> &VideoInfo {
+        &self.info
+    }
+}
+
+impl<F: EncodedFrame + Send> Service<F> for VideoInfoParser {
+    type Out = Result<WithVideoInfo<F>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let res = if frame.has_params() {
+            VideoInfo::from_frame(&frame).map(|info| self.info = info)
+        } else {
+            Ok(())
+        };
+
+        futures::stream::once(
+            async move { res.map(|_| WithVideoInfo::new(frame, self.info.clone())) },
+        )
+    }
+}
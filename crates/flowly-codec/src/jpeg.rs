@@ -0,0 +1,136 @@
+use crate::error::Error;
+
+pub const MARKER_SOI: u8 = 0xD8;
+pub const MARKER_EOI: u8 = 0xD9;
+pub const MARKER_SOS: u8 = 0xDA;
+pub const MARKER_SOF0: u8 = 0xC0;
+pub const MARKER_SOF1: u8 = 0xC1;
+pub const MARKER_SOF2: u8 = 0xC2;
+
+/// Frame header of the baseline, extended or progressive DCT JPEG (T.81 B.2.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub marker: u8,
+
+    /// Sample precision in bits
+    pub precision: u8,
+    pub width: u16,
+    pub height: u16,
+    pub components: u8,
+}
+
+impl FrameHeader {
+    /// Walks the markers of the JPEG image up to the first SOF0/SOF1/SOF2
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if !data.starts_with(&[0xFF, MARKER_SOI]) {
+            return Err(Error::InvalidData("jpeg: missing SOI"));
+        }
+
+        let mut pos = 2;
+
+        loop {
+            let Some(&[prefix, marker]) = data.get(pos..pos + 2) else {
+                return Err(Error::InvalidData("jpeg: truncated image"));
+            };
+
+            if prefix != 0xFF {
+                return Err(Error::InvalidData("jpeg: invalid marker"));
+            }
+
+            pos += 2;
+
+            match marker {
+                // fill bytes
+                0xFF => pos -= 1,
+
+                // standalone markers
+                0x01 | 0xD0..=0xD7 => (),
+
+                MARKER_SOS | MARKER_EOI => {
+                    return Err(Error::InvalidData("jpeg: missing SOF"));
+                }
+
+                _ => {
+                    let Some(&[hi, lo]) = data.get(pos..pos + 2) else {
+                        return Err(Error::InvalidData("jpeg: truncated image"));
+                    };
+
+                    let len = u16::from_be_bytes([hi, lo]) as usize;
+                    if len < 2 {
+                        return Err(Error::InvalidData("jpeg: invalid segment length"));
+                    }
+
+                    if matches!(marker, MARKER_SOF0 | MARKER_SOF1 | MARKER_SOF2) {
+                        let Some(&[precision, h0, h1, w0, w1, components]) =
+                            data.get(pos + 2..pos + 8)
+                        else {
+                            return Err(Error::InvalidData("jpeg: truncated SOF"));
+                        };
+
+                        return Ok(Self {
+                            marker,
+                            precision,
+                            width: u16::from_be_bytes([w0, w1]),
+                            height: u16::from_be_bytes([h0, h1]),
+                            components,
+                        });
+                    }
+
+                    // other SOFn are lossless, hierarchical or arithmetic coded
+                    if (0xC3..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+                        return Err(Error::Unsupported("jpeg: SOF type"));
+                    }
+
+                    pos += len;
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_progressive(&self) -> bool {
+        self.marker == MARKER_SOF2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameHeader, MARKER_SOF0, MARKER_SOF2};
+
+    #[test]
+    fn test_jpeg_header() {
+        let mut data = vec![0xFF, 0xD8];
+
+        // APP0 JFIF and fill byte
+        data.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x10]);
+        data.extend_from_slice(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        data.push(0xFF);
+
+        // SOF0: 8 bit, 480x640, 3 components
+        data.extend_from_slice(&[
+            0xFF,
+            MARKER_SOF0,
+            0x00,
+            0x11,
+            0x08,
+            0x01,
+            0xE0,
+            0x02,
+            0x80,
+            0x03,
+        ]);
+        data.extend_from_slice(&[1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+
+        let header = FrameHeader::parse(&data).unwrap();
+        assert_eq!((header.width, header.height), (640, 480));
+        assert_eq!((header.precision, header.components), (8, 3));
+        assert!(!header.is_progressive());
+
+        let sof = data.len() - 19 + 1;
+        data[sof] = MARKER_SOF2;
+        assert!(FrameHeader::parse(&data).unwrap().is_progressive());
+
+        assert!(FrameHeader::parse(&data[..sof + 5]).is_err());
+        assert!(FrameHeader::parse(&[0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x02]).is_err());
+    }
+}
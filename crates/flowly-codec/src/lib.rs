@@ -0,0 +1,19 @@
+pub mod aac;
+pub mod av1;
+pub mod bitstream;
+pub mod error;
+pub mod flac;
+pub mod h264;
+pub mod h265;
+pub mod info;
+pub mod jpeg;
+pub mod mpa;
+pub mod nal;
+pub mod opus;
+
+#[cfg(test)]
+mod test_fixtures;
+
+pub use bitstream::{BitstreamConverter, NalFormat};
+pub use error::Error;
+pub use info::{ChromaFormat, Crop, Timing, VideoInfo, VideoInfoParser, WithVideoInfo};
@@ -0,0 +1,225 @@
+use crate::error::Error;
+
+pub const HEADER_SIZE: usize = 4;
+
+/// Layer III bitrates in kbit/s of MPEG-1 and MPEG-2/2.5
+const BITRATES: [[u16; 15]; 2] = [
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MpegVersion {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+/// MPEG audio layer III frame header (ISO/IEC 11172-3 2.4.1.3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: MpegVersion,
+    pub protected: bool,
+
+    /// Bitrate in kbit/s
+    pub bitrate: u16,
+    pub sample_rate: u32,
+    pub padding: bool,
+
+    /// 0 stereo, 1 joint stereo, 2 dual channel, 3 mono
+    pub channel_mode: u8,
+}
+
+impl FrameHeader {
+    /// Parses the layer III header, free format streams are not supported
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let &[b0, b1, b2, b3, ..] = data else {
+            return Err(Error::InvalidData("mp3: truncated header"));
+        };
+
+        if b0 != 0xFF || b1 & 0xE0 != 0xE0 {
+            return Err(Error::InvalidData("mp3: invalid sync"));
+        }
+
+        let version = match (b1 >> 3) & 0x03 {
+            0 => MpegVersion::Mpeg25,
+            2 => MpegVersion::Mpeg2,
+            3 => MpegVersion::Mpeg1,
+            _ => return Err(Error::InvalidData("mp3: reserved version")),
+        };
+
+        if (b1 >> 1) & 0x03 != 1 {
+            return Err(Error::Unsupported("mp3: layer III only"));
+        }
+
+        let bitrate_index = (b2 >> 4) as usize;
+        let rate_index = ((b2 >> 2) & 0x03) as usize;
+
+        if bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+            return Err(Error::InvalidData("mp3: invalid bitrate or sample rate"));
+        }
+
+        let bitrate = BITRATES[(version != MpegVersion::Mpeg1) as usize][bitrate_index];
+        let sample_rate = match version {
+            MpegVersion::Mpeg1 => SAMPLE_RATES[rate_index],
+            MpegVersion::Mpeg2 => SAMPLE_RATES[rate_index] / 2,
+            MpegVersion::Mpeg25 => SAMPLE_RATES[rate_index] / 4,
+        };
+
+        Ok(Self {
+            version,
+            protected: b1 & 0x01 == 0,
+            bitrate,
+            sample_rate,
+            padding: b2 & 0x02 != 0,
+            channel_mode: b3 >> 6,
+        })
+    }
+
+    /// Samples per channel in the frame
+    #[inline]
+    pub fn samples(&self) -> u32 {
+        if self.version == MpegVersion::Mpeg1 {
+            1152
+        } else {
+            576
+        }
+    }
+
+    /// Frame size in bytes including the header
+    #[inline]
+    pub fn frame_size(&self) -> usize {
+        (self.samples() / 8 * self.bitrate as u32 * 1000 / self.sample_rate) as usize
+            + self.padding as usize
+    }
+
+    #[inline]
+    pub fn channels(&self) -> u16 {
+        if self.channel_mode == 3 { 1 } else { 2 }
+    }
+
+    /// Offset of the data following the side information (Xing header position)
+    pub fn side_info_end(&self) -> usize {
+        let side_info = match (self.version == MpegVersion::Mpeg1, self.channels()) {
+            (true, 1) => 17,
+            (true, _) => 32,
+            (false, 1) => 9,
+            (false, _) => 17,
+        };
+
+        HEADER_SIZE + if self.protected { 2 } else { 0 } + side_info
+    }
+
+    /// Checks that the other header belongs to the same stream
+    #[inline]
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.version == other.version && self.sample_rate == other.sample_rate
+    }
+}
+
+/// Stream information of the Xing/Info or VBRI header frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VbrInfo {
+    /// Number of audio frames
+    pub frames: Option<u32>,
+
+    /// Size of the audio stream in bytes
+    pub bytes: Option<u32>,
+
+    /// Encoder delay and padding in samples (LAME extension)
+    pub delay: u16,
+    pub padding: u16,
+}
+
+impl VbrInfo {
+    /// Parses the header frame, `None` if the frame carries audio
+    pub fn parse(header: &FrameHeader, frame: &[u8]) -> Option<Self> {
+        let xing = header.side_info_end();
+
+        if let Some(b"Xing" | b"Info") = frame.get(xing..xing + 4) {
+            return Some(parse_xing(&frame[xing + 4..]));
+        }
+
+        // VBRI always follows 32 bytes of side information
+        let vbri = HEADER_SIZE + 32;
+        match frame.get(vbri..vbri + 18) {
+            Some(data) if data.starts_with(b"VBRI") => Some(Self {
+                bytes: Some(u32::from_be_bytes(data[10..14].try_into().unwrap())),
+                frames: Some(u32::from_be_bytes(data[14..18].try_into().unwrap())),
+                delay: u16::from_be_bytes([data[6], data[7]]),
+                padding: 0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn parse_xing(data: &[u8]) -> VbrInfo {
+    let mut info = VbrInfo::default();
+    let u32_at = |pos: usize| {
+        data.get(pos..pos + 4)
+            .map(|x| u32::from_be_bytes(x.try_into().unwrap()))
+    };
+
+    let Some(flags) = u32_at(0) else {
+        return info;
+    };
+
+    let mut pos = 4;
+    if flags & 0x01 != 0 {
+        info.frames = u32_at(pos);
+        pos += 4;
+    }
+
+    if flags & 0x02 != 0 {
+        info.bytes = u32_at(pos);
+        pos += 4;
+    }
+
+    // TOC and quality indicator
+    if flags & 0x04 != 0 {
+        pos += 100;
+    }
+
+    if flags & 0x08 != 0 {
+        pos += 4;
+    }
+
+    // LAME tag: 9 bytes of version, 12 bytes of fields, then delay and padding
+    if let Some(tag) = data.get(pos..pos + 24)
+        && (tag.starts_with(b"LAME") || tag.starts_with(b"Lavc") || tag.starts_with(b"Lavf"))
+    {
+        info.delay = (tag[21] as u16) << 4 | (tag[22] as u16) >> 4;
+        info.padding = ((tag[22] & 0x0F) as u16) << 8 | tag[23] as u16;
+    }
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameHeader, MpegVersion};
+
+    #[test]
+    fn test_mpa_header() {
+        // MPEG-1 layer III, 128 kbit/s, 44.1 kHz, padded, joint stereo
+        let header = FrameHeader::parse(&[0xFF, 0xFB, 0x92, 0x64]).unwrap();
+        assert_eq!(header.version, MpegVersion::Mpeg1);
+        assert_eq!((header.bitrate, header.sample_rate), (128, 44100));
+        assert_eq!(header.frame_size(), 418);
+        assert_eq!(header.side_info_end(), 36);
+
+        // MPEG-2 layer III, 64 kbit/s, 22.05 kHz, mono
+        let header = FrameHeader::parse(&[0xFF, 0xF3, 0x80, 0xC4]).unwrap();
+        assert_eq!((header.samples(), header.channels()), (576, 1));
+        assert_eq!(header.frame_size(), 208);
+
+        // layer II and free format
+        assert!(FrameHeader::parse(&[0xFF, 0xFD, 0x92, 0x64]).is_err());
+        assert!(FrameHeader::parse(&[0xFF, 0xFB, 0x02, 0x64]).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flowly_core::BitReader;
+
+use crate::error::Error;
+
+pub const START_CODE: &[u8] = &[0, 0, 0, 1];
+
+/// Strips AnnexB start code (`00 00 01` or `00 00 00 01`) if present
+#[inline]
+pub fn strip_start_code(data: &[u8]) -> &[u8] {
+    match data {
+        [0, 0, 1, rest @ ..] | [0, 0, 0, 1, rest @ ..] => rest,
+        _ => data,
+    }
+}
+
+/// true if data starts with AnnexB start code
+#[inline]
+pub fn is_annexb(data: &[u8]) -> bool {
+    matches!(data, [0, 0, 1, ..] | [0, 0, 0, 1, ..])
+}
+
+/// Iterator over NAL units of the AnnexB byte stream (start codes are stripped)
+#[derive(Debug, Clone)]
+pub struct AnnexBIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> AnnexBIter<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        let data = match find_start_code(data) {
+            Some((pos, len)) => &data[pos + len..],
+            None => data,
+        };
+
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for AnnexBIter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let (nal, rest) = match find_start_code(self.data) {
+            Some((pos, len)) => (&self.data[..pos], &self.data[pos + len..]),
+            None => (self.data, &[][..]),
+        };
+
+        self.data = rest;
+
+        // trailing_zero_8bits belong to the byte stream, not to the NAL unit
+        let mut nal = nal;
+        while let [head @ .., 0] = nal {
+            nal = head;
+        }
+
+        if nal.is_empty() {
+            self.next()
+        } else {
+            Some(nal)
+        }
+    }
+}
+
+/// Position and length of the first start code
+fn find_start_code(data: &[u8]) -> Option<(usize, usize)> {
+    let mut zeros = 0;
+
+    for (i, &byte) in data.iter().enumerate() {
+        match byte {
+            0 => zeros += 1,
+            1 if zeros >= 2 => {
+                let len = usize::min(zeros, 3) + 1;
+                return Some((i + 1 - len, len));
+            }
+            _ => zeros = 0,
+        }
+    }
+
+    None
+}
+
+/// Iterator over NAL units of the length prefixed (AVCC/HVCC) payload
+#[derive(Debug, Clone)]
+pub struct LengthPrefixedIter<'a> {
+    data: &'a [u8],
+    length_size: usize,
+}
+
+impl<'a> LengthPrefixedIter<'a> {
+    pub fn new(data: &'a [u8], length_size: u8) -> Self {
+        Self {
+            data,
+            length_size: length_size as usize,
+        }
+    }
+}
+
+impl<'a> Iterator for LengthPrefixedIter<'a> {
+    type Item = Result<&'a [u8], Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        if self.data.len() < self.length_size {
+            self.data = &[];
+            return Some(Err(Error::InvalidData("nal: truncated length prefix")));
+        }
+
+        let (prefix, rest) = self.data.split_at(self.length_size);
+        let len = prefix
+            .iter()
+            .fold(0usize, |acc, &x| (acc << 8) | x as usize);
+
+        if rest.len() < len {
+            self.data = &[];
+            return Some(Err(Error::InvalidData("nal: truncated NAL unit")));
+        }
+
+        let (nal, rest) = rest.split_at(len);
+        self.data = rest;
+
+        Some(Ok(nal))
+    }
+}
+
+/// Writes NAL unit with 4-byte start code
+#[inline]
+pub fn put_annexb<B: BufMut>(buf: &mut B, nal: &[u8]) {
+    buf.put_slice(START_CODE);
+    buf.put_slice(nal);
+}
+
+/// Writes NAL unit with big-endian length prefix
+#[inline]
+pub fn put_length_prefixed<B: BufMut>(buf: &mut B, nal: &[u8], length_size: u8) {
+    buf.put_uint(nal.len() as u64, length_size as usize);
+    buf.put_slice(nal);
+}
+
+pub fn annexb_to_length_prefixed(data: &[u8], length_size: u8) -> Bytes {
+    let mut buf = BytesMut::with_capacity(data.len() + 8);
+
+    for nal in AnnexBIter::new(data) {
+        put_length_prefixed(&mut buf, nal, length_size);
+    }
+
+    buf.freeze()
+}
+
+pub fn length_prefixed_to_annexb(data: &[u8], length_size: u8) -> Result<Bytes, Error> {
+    let mut buf = BytesMut::with_capacity(data.len() + 8);
+
+    for nal in LengthPrefixedIter::new(data, length_size) {
+        put_annexb(&mut buf, nal?);
+    }
+
+    Ok(buf.freeze())
+}
+
+/// Rewrites the NAL length prefixes to `to` bytes, `data` is returned as is if
+/// the sizes match
+pub fn resize_length_prefix(data: Bytes, from: u8, to: u8) -> Result<Bytes, Error> {
+    if from == to {
+        return Ok(data);
+    }
+
+    let mut buf = BytesMut::with_capacity(data.len() + 8);
+
+    for nal in LengthPrefixedIter::new(&data, from) {
+        put_length_prefixed(&mut buf, nal?, to);
+    }
+
+    Ok(buf.freeze())
+}
+
+/// Reads `ue(v)` syntax element limited to `max` (its range in the spec)
+pub fn read_ue_max<B: Buf>(
+    r: &mut BitReader<B>,
+    max: u32,
+    name: &'static str,
+) -> Result<u32, Error> {
+    let val = r.read_ue()?;
+
+    if val > max {
+        return Err(Error::InvalidData(name));
+    }
+
+    Ok(val)
+}
+
+/// Reads `se(v)` syntax element limited to `min..=max`
+pub fn read_se_range<B: Buf>(
+    r: &mut BitReader<B>,
+    min: i32,
+    max: i32,
+    name: &'static str,
+) -> Result<i32, Error> {
+    let val = r.read_se()?;
+
+    if !(min..=max).contains(&val) {
+        return Err(Error::InvalidData(name));
+    }
+
+    Ok(val)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{
+        AnnexBIter, LengthPrefixedIter, annexb_to_length_prefixed, length_prefixed_to_annexb,
+        resize_length_prefix,
+    };
+    use crate::error::Error;
+
+    #[test]
+    fn test_annexb_split() {
+        let data = [
+            0, 0, 0, 1, 0x67, 1, 2, 0, 0, 1, 0x68, 3, 0, 0, 0, 0, 1, 0x65, 0, 0, 2, 4, 0,
+        ];
+
+        let nals: Vec<_> = AnnexBIter::new(&data).collect();
+        assert_eq!(
+            nals,
+            [&[0x67, 1, 2][..], &[0x68, 3][..], &[0x65, 0, 0, 2, 4][..]]
+        );
+
+        let avcc = annexb_to_length_prefixed(&data, 4);
+        let nals2: Vec<_> = LengthPrefixedIter::new(&avcc, 4)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(nals, nals2);
+    }
+
+    #[test]
+    fn test_length_prefixed_malformed() {
+        // truncated prefix, NAL unit shorter than its length
+        for data in [&[0, 0, 1][..], &[0, 0, 0, 3, 0x65, 0x88]] {
+            let nals: Vec<_> = LengthPrefixedIter::new(data, 4).collect();
+            assert!(
+                matches!(nals[..], [Err(Error::InvalidData(_))]),
+                "{data:02X?}"
+            );
+            assert!(length_prefixed_to_annexb(data, 4).is_err());
+        }
+
+        let data = Bytes::from_static(&[0, 2, 0x65, 0x88, 0, 9, 0x41]);
+        assert!(resize_length_prefix(data, 2, 4).is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::error::Error;
+
+pub const OPUS_HEAD: &[u8; 8] = b"OpusHead";
+pub const OPUS_TAGS: &[u8; 8] = b"OpusTags";
+
+/// Opus always runs at 48 kHz as far as timestamps are concerned
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// Longest packet duration (120 ms) in samples
+pub const MAX_PACKET_SAMPLES: u32 = 5760;
+
+/// Table-of-contents byte of the Opus packet (RFC 6716 3.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Toc {
+    pub config: u8,
+    pub stereo: bool,
+
+    /// Frame count code, 0 to 3
+    pub code: u8,
+}
+
+impl Toc {
+    #[inline]
+    pub fn parse(toc: u8) -> Self {
+        Self {
+            config: toc >> 3,
+            stereo: toc & 0x04 != 0,
+            code: toc & 0x03,
+        }
+    }
+
+    /// Duration of the single frame in 48 kHz samples
+    pub fn frame_samples(&self) -> u32 {
+        match self.config {
+            // SILK-only: 10, 20, 40, 60 ms
+            0..=11 => [480, 960, 1920, 2880][self.config as usize % 4],
+
+            // Hybrid: 10, 20 ms
+            12..=15 => [480, 960][self.config as usize % 2],
+
+            // CELT-only: 2.5, 5, 10, 20 ms
+            _ => [120, 240, 480, 960][self.config as usize % 4],
+        }
+    }
+}
+
+/// Number of frames in the Opus packet
+pub fn packet_frames(packet: &[u8]) -> Result<u32, Error> {
+    let toc = Toc::parse(
+        *packet
+            .first()
+            .ok_or(Error::InvalidData("opus: empty packet"))?,
+    );
+
+    match toc.code {
+        0 => Ok(1),
+        1 | 2 => Ok(2),
+        _ => match packet.get(1) {
+            Some(&count) if count & 0x3F != 0 => Ok((count & 0x3F) as u32),
+            Some(_) => Err(Error::InvalidData("opus: zero frame count")),
+            None => Err(Error::InvalidData("opus: missing frame count")),
+        },
+    }
+}
+
+/// Duration of the Opus packet in 48 kHz samples
+pub fn packet_samples(packet: &[u8]) -> Result<u32, Error> {
+    let frames = packet_frames(packet)?;
+    let samples = frames * Toc::parse(packet[0]).frame_samples();
+
+    if samples > MAX_PACKET_SAMPLES {
+        return Err(Error::InvalidData("opus: packet is longer than 120 ms"));
+    }
+
+    Ok(samples)
+}
+
+/// Identification header (RFC 7845 5.1), the Opus decoding parameters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpusHead {
+    pub version: u8,
+    pub channels: u8,
+
+    /// Samples (at 48 kHz) to discard from the decoder output at the start
+    pub pre_skip: u16,
+    pub input_sample_rate: u32,
+
+    /// Output gain in Q7.8 dB
+    pub output_gain: i16,
+    pub mapping_family: u8,
+
+    /// Channel mapping table, present for the non-zero mapping family only
+    pub stream_count: u8,
+    pub coupled_count: u8,
+    pub channel_mapping: Vec<u8>,
+}
+
+impl OpusHead {
+    pub fn parse(mut data: &[u8]) -> Result<Self, Error> {
+        if !data.starts_with(OPUS_HEAD) {
+            return Err(Error::InvalidData("opus: invalid OpusHead magic"));
+        }
+
+        data.advance(OPUS_HEAD.len());
+
+        let version = data.try_get_u8()?;
+        if version >> 4 != 0 {
+            return Err(Error::Unsupported("opus: unsupported OpusHead version"));
+        }
+
+        let mut head = Self {
+            version,
+            channels: data.try_get_u8()?,
+            pre_skip: data.try_get_u16_le()?,
+            input_sample_rate: data.try_get_u32_le()?,
+            output_gain: data.try_get_i16_le()?,
+            mapping_family: data.try_get_u8()?,
+            ..Default::default()
+        };
+
+        if head.channels == 0 {
+            return Err(Error::InvalidData("opus: zero channel count"));
+        }
+
+        if head.mapping_family == 0 {
+            head.stream_count = 1;
+            head.coupled_count = (head.channels > 1) as u8;
+        } else {
+            head.stream_count = data.try_get_u8()?;
+            head.coupled_count = data.try_get_u8()?;
+
+            if data.remaining() < head.channels as usize {
+                return Err(Error::InvalidData("opus: truncated channel mapping"));
+            }
+
+            head.channel_mapping = data[..head.channels as usize].to_vec();
+        }
+
+        Ok(head)
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(21 + self.channel_mapping.len());
+
+        buf.put_slice(OPUS_HEAD);
+        buf.put_u8(self.version);
+        buf.put_u8(self.channels);
+        buf.put_u16_le(self.pre_skip);
+        buf.put_u32_le(self.input_sample_rate);
+        buf.put_i16_le(self.output_gain);
+        buf.put_u8(self.mapping_family);
+
+        if self.mapping_family != 0 {
+            buf.put_u8(self.stream_count);
+            buf.put_u8(self.coupled_count);
+            buf.put_slice(&self.channel_mapping);
+        }
+
+        buf.freeze()
+    }
+}
+
+impl Default for OpusHead {
+    /// Stereo with the usual 3840 samples (80 ms) of pre-skip
+    fn default() -> Self {
+        Self {
+            version: 1,
+            channels: 2,
+            pre_skip: 3840,
+            input_sample_rate: SAMPLE_RATE,
+            output_gain: 0,
+            mapping_family: 0,
+            stream_count: 1,
+            coupled_count: 1,
+            channel_mapping: Vec::new(),
+        }
+    }
+}
+
+/// Comment header (RFC 7845 5.2)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OpusTags {
+    pub vendor: String,
+
+    /// User comments in the `NAME=value` form
+    pub comments: Vec<String>,
+}
+
+impl OpusTags {
+    pub fn parse(mut data: &[u8]) -> Result<Self, Error> {
+        if !data.starts_with(OPUS_TAGS) {
+            return Err(Error::InvalidData("opus: invalid OpusTags magic"));
+        }
+
+        data.advance(OPUS_TAGS.len());
+
+        let vendor = read_string(&mut data)?;
+        let count = data.try_get_u32_le()?;
+        let comments = (0..count)
+            .map(|_| read_string(&mut data))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { vendor, comments })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let len = self.comments.iter().map(|x| x.len() + 4).sum::<usize>();
+        let mut buf = BytesMut::with_capacity(16 + self.vendor.len() + len);
+
+        buf.put_slice(OPUS_TAGS);
+        buf.put_u32_le(self.vendor.len() as u32);
+        buf.put_slice(self.vendor.as_bytes());
+        buf.put_u32_le(self.comments.len() as u32);
+
+        for comment in &self.comments {
+            buf.put_u32_le(comment.len() as u32);
+            buf.put_slice(comment.as_bytes());
+        }
+
+        buf.freeze()
+    }
+}
+
+fn read_string(data: &mut &[u8]) -> Result<String, Error> {
+    let len = data.try_get_u32_le()? as usize;
+
+    if data.remaining() < len {
+        return Err(Error::InvalidData("opus: truncated OpusTags"));
+    }
+
+    let value = String::from_utf8_lossy(&data[..len]).into_owned();
+    data.advance(len);
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OpusHead, OpusTags, packet_samples};
+
+    #[test]
+    fn test_opus_toc_and_headers() {
+        // SILK 20 ms, CELT 2.5 ms x2, CELT 20 ms x3 (code 3), hybrid 10 ms
+        assert_eq!(packet_samples(&[0x08]).unwrap(), 960);
+        assert_eq!(packet_samples(&[0x81]).unwrap(), 240);
+        assert_eq!(packet_samples(&[0xFB, 0x03]).unwrap(), 2880);
+        assert_eq!(packet_samples(&[0x60, 0x00]).unwrap(), 480);
+        assert!(packet_samples(&[0xFB, 0x07]).is_err());
+        assert!(packet_samples(&[0x0B]).is_err());
+
+        let head = OpusHead {
+            channels: 1,
+            pre_skip: 312,
+            coupled_count: 0,
+            ..Default::default()
+        };
+        let bytes = head.to_bytes();
+        assert_eq!(bytes.len(), 19);
+        assert_eq!(
+            OpusHead::parse(&bytes).unwrap(),
+            OpusHead {
+                coupled_count: 0,
+                ..head
+            }
+        );
+
+        let tags = OpusTags {
+            vendor: "flowly".into(),
+            comments: vec!["TITLE=test".into()],
+        };
+        assert_eq!(OpusTags::parse(&tags.to_bytes()).unwrap(), tags);
+    }
+}
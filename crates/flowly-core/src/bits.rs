@@ -0,0 +1,437 @@
+use bytes::{Buf, Bytes, BytesMut, TryGetError};
+
+use crate::Writer;
+
+/// MSB-first bit reader over any [`Buf`].
+///
+/// In RBSP mode (see [`BitReader::new_rbsp`]) emulation prevention bytes
+/// (`0x00 0x00 0x03`) are stripped on the fly, so H.264/H.265 parameter sets
+/// can be parsed straight from the NAL unit payload.
+#[derive(Debug, Clone)]
+pub struct BitReader<B> {
+    inner: B,
+    byte: u8,
+    left: u8,
+    rbsp: bool,
+    zeros: u8,
+}
+
+impl<B: Buf> BitReader<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            byte: 0,
+            left: 0,
+            rbsp: false,
+            zeros: 0,
+        }
+    }
+
+    /// Reader which skips emulation prevention bytes
+    pub fn new_rbsp(inner: B) -> Self {
+        Self {
+            rbsp: true,
+            ..Self::new(inner)
+        }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &B {
+        &self.inner
+    }
+
+    /// Returns the inner buffer, bits left in the current byte are dropped
+    #[inline]
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    /// Number of bits which can still be read (including emulation prevention bytes)
+    #[inline]
+    pub fn bits_remaining(&self) -> usize {
+        self.left as usize + self.inner.remaining() * 8
+    }
+
+    #[inline]
+    pub fn is_aligned(&self) -> bool {
+        self.left == 0
+    }
+
+    /// Drops the rest of the current byte
+    #[inline]
+    pub fn byte_align(&mut self) {
+        self.left = 0;
+    }
+
+    fn next_byte(&mut self) -> Result<u8, TryGetError> {
+        let mut byte = self.inner.try_get_u8()?;
+
+        if self.rbsp {
+            if self.zeros >= 2 && byte == 0x03 {
+                self.zeros = 0;
+                byte = self.inner.try_get_u8()?;
+            }
+
+            if byte == 0 {
+                self.zeros = self.zeros.saturating_add(1);
+            } else {
+                self.zeros = 0;
+            }
+        }
+
+        Ok(byte)
+    }
+
+    /// Reads up to 64 bits MSB first
+    pub fn read_bits(&mut self, n: u8) -> Result<u64, TryGetError> {
+        assert!(n <= 64, "read_bits: can not read more than 64 bits at once");
+
+        let mut val = 0u64;
+        let mut n = n;
+
+        while n > 0 {
+            if self.left == 0 {
+                self.byte = self.next_byte().map_err(|_| TryGetError {
+                    requested: n.div_ceil(8) as usize,
+                    available: 0,
+                })?;
+                self.left = 8;
+            }
+
+            let take = u8::min(n, self.left);
+            let shift = self.left - take;
+            let bits = (self.byte >> shift) as u64 & ((1u64 << take) - 1);
+
+            val = (val << take) | bits;
+            self.left -= take;
+            n -= take;
+        }
+
+        Ok(val)
+    }
+
+    #[inline]
+    pub fn read_flag(&mut self) -> Result<bool, TryGetError> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    #[inline]
+    pub fn read_u8(&mut self, n: u8) -> Result<u8, TryGetError> {
+        Ok(self.read_bits(u8::min(n, 8))? as u8)
+    }
+
+    #[inline]
+    pub fn read_u16(&mut self, n: u8) -> Result<u16, TryGetError> {
+        Ok(self.read_bits(u8::min(n, 16))? as u16)
+    }
+
+    #[inline]
+    pub fn read_u32(&mut self, n: u8) -> Result<u32, TryGetError> {
+        Ok(self.read_bits(u8::min(n, 32))? as u32)
+    }
+
+    pub fn skip_bits(&mut self, mut n: usize) -> Result<(), TryGetError> {
+        while n > 0 {
+            let take = usize::min(n, 64);
+            self.read_bits(take as u8)?;
+            n -= take;
+        }
+
+        Ok(())
+    }
+
+    /// Unsigned Exp-Golomb code `ue(v)`
+    pub fn read_ue(&mut self) -> Result<u32, TryGetError> {
+        let mut zeros = 0u8;
+
+        while !self.read_flag()? {
+            zeros += 1;
+
+            if zeros > 31 {
+                return Err(TryGetError {
+                    requested: 32,
+                    available: 0,
+                });
+            }
+        }
+
+        Ok(((1u64 << zeros) - 1 + self.read_bits(zeros)?) as u32)
+    }
+
+    /// Signed Exp-Golomb code `se(v)`
+    pub fn read_se(&mut self) -> Result<i32, TryGetError> {
+        let val = self.read_ue()? as i64;
+
+        Ok(if val & 1 == 1 {
+            ((val + 1) / 2) as i32
+        } else {
+            -(val / 2) as i32
+        })
+    }
+
+    /// `more_rbsp_data()` as defined in H.264 7.2
+    pub fn more_rbsp_data(&self) -> bool {
+        let mut rest = self.inner.chunk();
+        let total = self.inner.remaining();
+
+        if total > rest.len() {
+            // data spans several chunks, only the last one matters for the trailing bits
+            return true;
+        }
+
+        while let [head @ .., 0] = rest {
+            rest = head;
+        }
+
+        match rest {
+            [] if self.left > 0 => {
+                let bits = self.byte & ((1u16 << self.left) - 1) as u8;
+                bits != 0 && bits != 1 << (self.left - 1)
+            }
+            [] => false,
+            [last] => *last != 0x80 || self.left > 0,
+            _ => true,
+        }
+    }
+}
+
+/// MSB-first bit writer on top of any [`Writer`].
+///
+/// In RBSP mode (see [`BitWriter::new_rbsp`]) emulation prevention bytes are
+/// inserted when needed.
+#[derive(Debug, Clone)]
+pub struct BitWriter<W> {
+    inner: W,
+    byte: u8,
+    used: u8,
+    rbsp: bool,
+    zeros: u8,
+}
+
+impl<W: Writer> BitWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            byte: 0,
+            used: 0,
+            rbsp: false,
+            zeros: 0,
+        }
+    }
+
+    /// Writer which inserts emulation prevention bytes
+    pub fn new_rbsp(inner: W) -> Self {
+        Self {
+            rbsp: true,
+            ..Self::new(inner)
+        }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn is_aligned(&self) -> bool {
+        self.used == 0
+    }
+
+    fn emit(&mut self, byte: u8) {
+        if self.rbsp {
+            if self.zeros >= 2 && byte <= 0x03 {
+                self.inner.put_u8(0x03);
+                self.zeros = 0;
+            }
+
+            if byte == 0 {
+                self.zeros = self.zeros.saturating_add(1);
+            } else {
+                self.zeros = 0;
+            }
+        }
+
+        self.inner.put_u8(byte);
+    }
+
+    /// Writes `n` (up to 64) least significant bits of `val` MSB first
+    pub fn put_bits(&mut self, n: u8, val: u64) {
+        assert!(n <= 64, "put_bits: can not write more than 64 bits at once");
+
+        let mut n = n;
+        while n > 0 {
+            let take = u8::min(n, 8 - self.used);
+            let bits = ((val >> (n - take)) & ((1u64 << take) - 1)) as u8;
+
+            self.byte |= bits << (8 - self.used - take);
+            self.used += take;
+            n -= take;
+
+            if self.used == 8 {
+                let byte = self.byte;
+                self.emit(byte);
+                self.byte = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    #[inline]
+    pub fn put_flag(&mut self, flag: bool) {
+        self.put_bits(1, flag as u64)
+    }
+
+    /// Unsigned Exp-Golomb code `ue(v)`
+    pub fn put_ue(&mut self, val: u32) {
+        let val = val as u64 + 1;
+        let len = 64 - val.leading_zeros() as u8;
+
+        self.put_bits(len - 1, 0);
+        self.put_bits(len, val);
+    }
+
+    /// Signed Exp-Golomb code `se(v)`
+    pub fn put_se(&mut self, val: i32) {
+        let val = val as i64;
+
+        self.put_ue(if val > 0 {
+            (val * 2 - 1) as u32
+        } else {
+            (-val * 2) as u32
+        })
+    }
+
+    /// Pads current byte with zero bits
+    pub fn byte_align(&mut self) {
+        if self.used > 0 {
+            self.put_bits(8 - self.used, 0);
+        }
+    }
+
+    /// Writes `rbsp_trailing_bits()` (stop bit followed by alignment zeros)
+    pub fn put_trailing_bits(&mut self) {
+        self.put_flag(true);
+        self.byte_align();
+    }
+
+    /// Aligns the stream and returns inner writer
+    pub fn finish(mut self) -> W {
+        self.byte_align();
+        self.inner
+    }
+}
+
+/// Removes emulation prevention bytes (`0x00 0x00 0x03` -> `0x00 0x00`)
+pub fn rbsp_unescape(data: &[u8]) -> Bytes {
+    let mut out = BytesMut::with_capacity(data.len());
+    let mut zeros = 0;
+
+    for &byte in data {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.extend_from_slice(&[byte]);
+    }
+
+    out.freeze()
+}
+
+/// Inserts emulation prevention bytes where start code could be emulated
+pub fn rbsp_escape(data: &[u8]) -> Bytes {
+    let mut writer = BitWriter::new_rbsp(BytesMut::with_capacity(data.len() + data.len() / 64));
+
+    for &byte in data {
+        writer.put_bits(8, byte as u64);
+    }
+
+    writer.finish().freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::{BitReader, BitWriter, rbsp_escape, rbsp_unescape};
+    use crate::Chunked;
+
+    #[test]
+    fn test_read_bits() {
+        let mut reader = BitReader::new(&[0b1010_0011, 0b1100_0000, 0xFF][..]);
+
+        assert!(reader.read_flag().unwrap());
+        assert_eq!(reader.read_bits(3).unwrap(), 0b010);
+        assert_eq!(reader.read_bits(6).unwrap(), 0b00_1111);
+        assert!(!reader.is_aligned());
+        reader.byte_align();
+        assert_eq!(reader.read_bits(8).unwrap(), 0xFF);
+        assert!(reader.read_flag().is_err());
+    }
+
+    #[test]
+    fn test_exp_golomb() {
+        // 1 | 010 | 011 | 00100 | 00101 | 00110 | 00111
+        let mut reader = BitReader::new(&[0b1010_0110, 0b0100_0010, 0b1001_1000, 0b1110_0000][..]);
+
+        assert_eq!(reader.read_ue().unwrap(), 0);
+        assert_eq!(reader.read_ue().unwrap(), 1);
+        assert_eq!(reader.read_ue().unwrap(), 2);
+        assert_eq!(reader.read_se().unwrap(), 2);
+        assert_eq!(reader.read_se().unwrap(), -2);
+        assert_eq!(reader.read_se().unwrap(), 3);
+        assert_eq!(reader.read_se().unwrap(), -3);
+    }
+
+    #[test]
+    fn test_bit_writer_roundtrip() {
+        let mut writer = BitWriter::new(BytesMut::new());
+        writer.put_flag(true);
+        writer.put_bits(5, 0b10110);
+        writer.put_ue(0);
+        writer.put_ue(1234);
+        writer.put_se(-77);
+        writer.put_se(42);
+        writer.put_bits(33, 0x1_2345_6789);
+        writer.put_trailing_bits();
+
+        let data = writer.finish().freeze();
+
+        let mut chunked = Chunked::new();
+        chunked.put(data.slice(0..3));
+        chunked.put(data.slice(3..));
+
+        let mut reader = BitReader::new(chunked);
+        assert!(reader.read_flag().unwrap());
+        assert_eq!(reader.read_bits(5).unwrap(), 0b10110);
+        assert_eq!(reader.read_ue().unwrap(), 0);
+        assert_eq!(reader.read_ue().unwrap(), 1234);
+        assert_eq!(reader.read_se().unwrap(), -77);
+        assert_eq!(reader.read_se().unwrap(), 42);
+        assert_eq!(reader.read_bits(33).unwrap(), 0x1_2345_6789);
+        assert!(!reader.more_rbsp_data());
+    }
+
+    #[test]
+    fn test_emulation_prevention() {
+        let raw = [0x67, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x03, 0x10];
+        let escaped = rbsp_escape(&raw);
+
+        assert_eq!(
+            &escaped[..],
+            &[
+                0x67, 0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x03, 0x10
+            ]
+        );
+        assert_eq!(&rbsp_unescape(&escaped)[..], &raw[..]);
+
+        let mut reader = BitReader::new_rbsp(escaped);
+        let mut out = Vec::new();
+        while let Ok(byte) = reader.read_bits(8) {
+            out.push(byte as u8);
+        }
+
+        assert_eq!(out, raw);
+    }
+}
@@ -32,6 +32,32 @@ impl<T> Chunked<T> {
     }
 }
 
+impl<T: AsRef<[u8]>> Chunked<T> {
+    /// Copies the bytes starting at `offset` into `dst` without consuming them,
+    /// returns the number of bytes copied (less than `dst.len()` at the end of data)
+    pub fn peek(&self, mut offset: usize, dst: &mut [u8]) -> usize {
+        let mut pos = 0;
+
+        for chunk in self.chunks.iter().map(AsRef::as_ref) {
+            if pos == dst.len() {
+                break;
+            }
+
+            if offset >= chunk.len() {
+                offset -= chunk.len();
+                continue;
+            }
+
+            let len = (chunk.len() - offset).min(dst.len() - pos);
+            dst[pos..pos + len].copy_from_slice(&chunk[offset..offset + len]);
+            pos += len;
+            offset = 0;
+        }
+
+        pos
+    }
+}
+
 impl<T: Buf> Chunked<T> {
     #[inline]
     pub fn put(&mut self, chunk: T) {
@@ -242,6 +268,22 @@ mod tests {
         assert_eq!(bytes.remaining(), 0);
     }
 
+    #[test]
+    fn test_chunked_peek() {
+        let mut bytes = Chunked::new();
+        bytes.put(Bytes::from_static(b"hello"));
+        bytes.put(Bytes::from_static(b", "));
+        bytes.put(Bytes::from_static(b"world"));
+
+        let mut dst = [0u8; 6];
+        assert_eq!(bytes.peek(3, &mut dst), 6);
+        assert_eq!(&dst, b"lo, wo");
+
+        assert_eq!(bytes.peek(10, &mut dst), 2);
+        assert_eq!(&dst[..2], b"ld");
+        assert_eq!(bytes.remaining(), 12);
+    }
+
     #[test]
     fn test_chunked_bytes_vectored() {
         let mut dst = Vec::new();
@@ -277,27 +277,36 @@ pub trait Writer: BufMut {
 impl<T: BufMut> Writer for T {}
 
 pub trait WriterExt: Writer {
+    #[inline]
     fn put_u8p2<const A: u8, const B: u8>(&mut self, a: u8, b: u8) {
-        let _ = b;
-        let _ = a;
         const {
             assert!(A + B == 8);
         }
 
-        todo!()
+        let a_mask = ((1u16 << A) - 1) as u8;
+        let b_mask = ((1u16 << B) - 1) as u8;
+
+        self.put_u8((((a & a_mask) as u16) << B) as u8 | (b & b_mask))
     }
 
+    #[inline]
     fn put_u8p3<const A: u8, const B: u8, const C: u8>(&mut self, a: u8, b: u8, c: u8) {
-        let _ = c;
-        let _ = b;
-        let _ = a;
         const {
             assert!(A + B + C == 8);
         }
 
-        todo!()
+        let a_mask = ((1u16 << A) - 1) as u8;
+        let b_mask = ((1u16 << B) - 1) as u8;
+        let c_mask = ((1u16 << C) - 1) as u8;
+
+        self.put_u8(
+            (((a & a_mask) as u16) << (B + C)) as u8
+                | (((b & b_mask) as u16) << C) as u8
+                | (c & c_mask),
+        )
     }
 
+    #[inline]
     fn put_u8p4<const A: u8, const B: u8, const C: u8, const D: u8>(
         &mut self,
         a: u8,
@@ -305,38 +314,53 @@ pub trait WriterExt: Writer {
         c: u8,
         d: u8,
     ) {
-        let _ = d;
-        let _ = c;
-        let _ = b;
-        let _ = a;
         const {
             assert!(A + B + C + D == 8);
         }
 
-        todo!()
+        let a_mask = ((1u16 << A) - 1) as u8;
+        let b_mask = ((1u16 << B) - 1) as u8;
+        let c_mask = ((1u16 << C) - 1) as u8;
+        let d_mask = ((1u16 << D) - 1) as u8;
+
+        self.put_u8(
+            (((a & a_mask) as u16) << (B + C + D)) as u8
+                | (((b & b_mask) as u16) << (C + D)) as u8
+                | (((c & c_mask) as u16) << D) as u8
+                | (d & d_mask),
+        )
     }
 
+    #[inline]
     fn put_u16p2<const A: u8, const B: u8>(&mut self, a: u16, b: u16) {
-        let _ = b;
-        let _ = a;
         const {
             assert!(A + B == 16);
         }
 
-        todo!()
+        let a_mask = ((1u32 << A) - 1) as u16;
+        let b_mask = ((1u32 << B) - 1) as u16;
+
+        self.put_u16((((a & a_mask) as u32) << B) as u16 | (b & b_mask))
     }
 
+    #[inline]
     fn put_u16p3<const A: u8, const B: u8, const C: u8>(&mut self, a: u16, b: u16, c: u16) {
-        let _ = c;
-        let _ = b;
-        let _ = a;
         const {
             assert!(A + B + C == 16);
         }
 
-        todo!()
+        let a_mask = ((1u32 << A) - 1) as u16;
+        let b_mask = ((1u32 << B) - 1) as u16;
+        let c_mask = ((1u32 << C) - 1) as u16;
+
+        self.put_u16(
+            (((a & a_mask) as u32) << (B + C)) as u16
+                | (((b & b_mask) as u32) << C) as u16
+                | (c & c_mask),
+        )
     }
 
+    #[inline]
     fn put_u16p4<const A: u8, const B: u8, const C: u8, const D: u8>(
         &mut self,
         a: u16,
@@ -344,15 +368,21 @@ pub trait WriterExt: Writer {
         c: u16,
         d: u16,
     ) {
-        let _ = d;
-        let _ = c;
-        let _ = b;
-        let _ = a;
         const {
             assert!(A + B + C + D == 16);
         }
 
-        todo!()
+        let a_mask = ((1u32 << A) - 1) as u16;
+        let b_mask = ((1u32 << B) - 1) as u16;
+        let c_mask = ((1u32 << C) - 1) as u16;
+        let d_mask = ((1u32 << D) - 1) as u16;
+
+        self.put_u16(
+            (((a & a_mask) as u32) << (B + C + D)) as u16
+                | (((b & b_mask) as u32) << (C + D)) as u16
+                | (((c & c_mask) as u32) << D) as u16
+                | (d & d_mask),
+        )
     }
 }
 
@@ -386,3 +416,56 @@ impl Decoder<Bytes> for BytesDecoder {
         Ok(reader.read_bytes(reader.remaining()).unwrap())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Buf, BytesMut};
+
+    use super::{ReaderExt, WriterExt};
+
+    #[test]
+    fn test_u8_packed_roundtrip() {
+        let mut buf = BytesMut::new();
+        buf.put_u8p2::<4, 4>(0xA, 0x5);
+        buf.put_u8p2::<1, 7>(1, 0x7F);
+        buf.put_u8p3::<2, 3, 3>(0b10, 0b101, 0b011);
+        buf.put_u8p4::<1, 2, 1, 4>(1, 0b01, 0, 0b1110);
+
+        assert_eq!(&buf[..], &[0xA5, 0xFF, 0b1010_1011, 0b1010_1110]);
+
+        let mut buf = buf.freeze();
+        assert_eq!(buf.read_u8p2::<4, 4>().unwrap(), (0xA, 0x5));
+        assert_eq!(buf.read_u8p2::<1, 7>().unwrap(), (1, 0x7F));
+        assert_eq!(buf.read_u8p3::<2, 3, 3>().unwrap(), (0b10, 0b101, 0b011));
+        assert_eq!(buf.read_u8p4::<1, 2, 1, 4>().unwrap(), (1, 0b01, 0, 0b1110));
+        assert!(!buf.has_remaining());
+    }
+
+    #[test]
+    fn test_u16_packed_roundtrip() {
+        let mut buf = BytesMut::new();
+        buf.put_u16p2::<3, 13>(0b010, 0x1FFF);
+        buf.put_u16p3::<1, 2, 13>(0, 0b11, 0x0100);
+        buf.put_u16p4::<4, 4, 4, 4>(0x1, 0x2, 0x3, 0x4);
+
+        assert_eq!(&buf[..], &[0x5F, 0xFF, 0x61, 0x00, 0x12, 0x34]);
+
+        let mut buf = buf.freeze();
+        assert_eq!(buf.read_u16p2::<3, 13>().unwrap(), (0b010, 0x1FFF));
+        assert_eq!(buf.read_u16p3::<1, 2, 13>().unwrap(), (0, 0b11, 0x0100));
+        assert_eq!(
+            buf.read_u16p4::<4, 4, 4, 4>().unwrap(),
+            (0x1, 0x2, 0x3, 0x4)
+        );
+        assert!(!buf.has_remaining());
+    }
+
+    #[test]
+    fn test_packed_values_are_masked() {
+        let mut buf = BytesMut::new();
+        buf.put_u8p2::<4, 4>(0xFA, 0xF5);
+        buf.put_u16p2::<4, 12>(0xFFF1, 0xF234);
+
+        assert_eq!(&buf[..], &[0xA5, 0x12, 0x34]);
+    }
+}
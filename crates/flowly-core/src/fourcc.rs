@@ -29,6 +29,9 @@ impl Fourcc {
     pub const AUDIO_PCM_ALAW: Fourcc = Fourcc(*b"ALAW");
     pub const AUDIO_PCM_ULAW: Fourcc = Fourcc(*b"ULAW");
 
+    /// IEEE float PCM
+    pub const AUDIO_PCM_FLOAT: Fourcc = Fourcc(*b"PCMF");
+
     ///
     /// Video Codecs
     ///
@@ -53,6 +56,12 @@ impl Fourcc {
     /// JPEG Codec
     pub const VIDEO_JPEG: Fourcc = Fourcc(*b"JPEG");
 
+    ///
+    /// Metadata
+    ///
+    /// AMF0 encoded script data (FLV `onMetaData`, RTMP data messages)
+    pub const METADATA_AMF0: Fourcc = Fourcc(*b"amf0");
+
     /// Pixel formats
     ///
     /// | C1 C2 C3 C4 C5 C6 C7 C8 |
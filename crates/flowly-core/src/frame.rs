@@ -244,3 +244,14 @@ pub trait VideoFrame: Frame {
     /// Bits per pixel (8, 10, 12)
     fn bit_depth(&self) -> u8;
 }
+
+pub trait AudioFrame: Frame {
+    /// Sampling rate in Hz
+    fn sample_rate(&self) -> u32;
+
+    /// Number of interleaved channels
+    fn channels(&self) -> u16;
+
+    /// Bits per sample of a single channel (8, 16, 24, 32)
+    fn sample_size(&self) -> u8;
+}
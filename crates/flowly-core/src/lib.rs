@@ -1,3 +1,4 @@
+mod bits;
 mod chunked;
 mod codec;
 mod either;
@@ -5,8 +6,11 @@ mod error;
 mod fourcc;
 mod frame;
 mod memory;
+mod packet;
+mod raw;
 mod void;
 
+pub use bits::{BitReader, BitWriter, rbsp_escape, rbsp_unescape};
 pub use chunked::Chunked;
 pub use codec::{BytesDecoder, Decoder, Encoder, Reader, ReaderExt, Writer, WriterExt};
 pub use either::Either;
@@ -14,4 +18,6 @@ pub use error::Error;
 pub use fourcc::Fourcc;
 pub use frame::*;
 pub use memory::{CpuAllocator, MemAlloc, MemBlock, MemDevice, MemError};
+pub use packet::{Packet, concat_chunks};
+pub use raw::{RawAudioFrame, RawFrame};
 pub use void::Void;
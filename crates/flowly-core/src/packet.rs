@@ -0,0 +1,179 @@
+use bytes::{Bytes, BytesMut};
+
+use crate::{
+    DataFrame, EncodedFrame, Fourcc, Frame, FrameFlags, FrameSource, MemBlock, Multichannel,
+    VideoFrame,
+};
+
+/// Owned encoded frame, produced by demuxers and bitstream filters
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Packet<S = ()> {
+    pub source: S,
+    pub codec: Fourcc,
+    pub flags: FrameFlags,
+    pub track: u32,
+
+    /// Decoding timestamp in microseconds
+    pub dts: u64,
+
+    /// Presentation timestamp in microseconds
+    pub pts: i64,
+
+    /// Duration in microseconds (0 if unknown)
+    pub duration: u64,
+
+    /// Decoding parameters (SPS/PPS, AudioSpecificConfig etc.)
+    pub params: Vec<Bytes>,
+    pub data: Bytes,
+
+    /// Video dimensions, (0, 0) if unknown or not a video
+    pub dimensions: (u16, u16),
+    pub bit_depth: u8,
+}
+
+impl<S: FrameSource> Packet<S> {
+    pub fn new(source: S, codec: Fourcc, flags: FrameFlags, data: Bytes) -> Self {
+        Self {
+            source,
+            codec,
+            flags: flags | FrameFlags::ENCODED,
+            data,
+            ..Default::default()
+        }
+    }
+
+    /// Copies all the frame attributes into a packet, chunks are concatenated
+    pub fn from_frame<F>(frame: F) -> Self
+    where
+        F: EncodedFrame<Source = S> + Multichannel + VideoFrame,
+    {
+        let params = frame
+            .params()
+            .map(|x| Bytes::copy_from_slice(x.as_ref()))
+            .collect();
+
+        Self {
+            source: frame.source().clone(),
+            codec: frame.codec(),
+            flags: frame.flags(),
+            track: frame.track(),
+            dts: frame.dts(),
+            pts: frame.pts(),
+            duration: 0,
+            params,
+            dimensions: frame.dimensions(),
+            bit_depth: frame.bit_depth(),
+            data: concat_chunks(frame.into_chunks()),
+        }
+    }
+
+    #[inline]
+    pub fn with_timestamps(mut self, dts: u64, pts: i64) -> Self {
+        self.dts = dts;
+        self.pts = pts;
+        self
+    }
+
+    #[inline]
+    pub fn with_params(mut self, params: Vec<Bytes>) -> Self {
+        self.flags.set(FrameFlags::HAS_PARAMS, !params.is_empty());
+        self.params = params;
+        self
+    }
+
+    #[inline]
+    pub fn with_track(mut self, track: u32) -> Self {
+        self.track = track;
+        self
+    }
+}
+
+/// Joins memory blocks into continuous `Bytes`, single block is not copied
+pub fn concat_chunks<C: MemBlock>(mut chunks: impl Iterator<Item = C>) -> Bytes {
+    let Some(first) = chunks.next() else {
+        return Bytes::new();
+    };
+
+    let Some(second) = chunks.next() else {
+        return first.into_cpu_bytes();
+    };
+
+    let mut buf = BytesMut::from(first.map_to_cpu());
+    buf.extend_from_slice(second.map_to_cpu());
+
+    for chunk in chunks {
+        buf.extend_from_slice(chunk.map_to_cpu());
+    }
+
+    buf.freeze()
+}
+
+impl<S: FrameSource> DataFrame for Packet<S> {
+    type Source = S;
+    type Chunk = Bytes;
+
+    #[inline]
+    fn source(&self) -> &Self::Source {
+        &self.source
+    }
+
+    #[inline]
+    fn chunks(&self) -> impl Send + Iterator<Item = <Self::Chunk as MemBlock>::Ref<'_>> {
+        std::iter::once(&self.data)
+    }
+
+    #[inline]
+    fn into_chunks(self) -> impl Send + Iterator<Item = Self::Chunk> {
+        std::iter::once(self.data)
+    }
+}
+
+impl<S: FrameSource> Frame for Packet<S> {
+    #[inline]
+    fn timestamp(&self) -> u64 {
+        self.dts
+    }
+
+    #[inline]
+    fn codec(&self) -> Fourcc {
+        self.codec
+    }
+
+    #[inline]
+    fn flags(&self) -> FrameFlags {
+        self.flags
+    }
+}
+
+impl<S: FrameSource> EncodedFrame for Packet<S> {
+    type Param = Bytes;
+
+    #[inline]
+    fn pts(&self) -> i64 {
+        self.pts
+    }
+
+    #[inline]
+    fn params(&self) -> impl Iterator<Item = &Self::Param> {
+        self.params.iter()
+    }
+}
+
+impl<S: FrameSource> Multichannel for Packet<S> {
+    #[inline]
+    fn track(&self) -> u32 {
+        self.track
+    }
+}
+
+impl<S: FrameSource> VideoFrame for Packet<S> {
+    #[inline]
+    fn dimensions(&self) -> (u16, u16) {
+        self.dimensions
+    }
+
+    #[inline]
+    fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+}
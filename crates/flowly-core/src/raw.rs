@@ -0,0 +1,211 @@
+use bytes::Bytes;
+
+use crate::{AudioFrame, DataFrame, Fourcc, Frame, FrameFlags, FrameSource, MemBlock, VideoFrame};
+
+/// Owned uncompressed video frame, one chunk per plane
+///
+/// `format` is one of the `Fourcc::PIXEL_FORMAT_*` values, samples wider than
+/// 8 bits (see `bit_depth`) are stored as 16-bit little endian words.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RawFrame<S = ()> {
+    pub source: S,
+    pub format: Fourcc,
+    pub flags: FrameFlags,
+
+    /// Timestamp in microseconds
+    pub timestamp: u64,
+
+    /// Duration in microseconds (0 if unknown)
+    pub duration: u64,
+    pub dimensions: (u16, u16),
+    pub bit_depth: u8,
+    pub planes: Vec<Bytes>,
+}
+
+impl<S: FrameSource> RawFrame<S> {
+    pub fn new(source: S, format: Fourcc, flags: FrameFlags, planes: Vec<Bytes>) -> Self {
+        Self {
+            source,
+            format,
+            flags: flags - FrameFlags::ENCODED,
+            bit_depth: 8,
+            planes,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    #[inline]
+    pub fn with_dimensions(mut self, width: u16, height: u16) -> Self {
+        self.dimensions = (width, height);
+        self
+    }
+
+    #[inline]
+    pub fn with_bit_depth(mut self, bit_depth: u8) -> Self {
+        self.bit_depth = bit_depth;
+        self
+    }
+}
+
+impl<S: FrameSource> DataFrame for RawFrame<S> {
+    type Source = S;
+    type Chunk = Bytes;
+
+    #[inline]
+    fn source(&self) -> &Self::Source {
+        &self.source
+    }
+
+    #[inline]
+    fn chunks(&self) -> impl Send + Iterator<Item = <Self::Chunk as MemBlock>::Ref<'_>> {
+        self.planes.iter()
+    }
+
+    #[inline]
+    fn into_chunks(self) -> impl Send + Iterator<Item = Self::Chunk> {
+        self.planes.into_iter()
+    }
+}
+
+impl<S: FrameSource> Frame for RawFrame<S> {
+    #[inline]
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    #[inline]
+    fn codec(&self) -> Fourcc {
+        self.format
+    }
+
+    #[inline]
+    fn flags(&self) -> FrameFlags {
+        self.flags
+    }
+}
+
+impl<S: FrameSource> VideoFrame for RawFrame<S> {
+    #[inline]
+    fn dimensions(&self) -> (u16, u16) {
+        self.dimensions
+    }
+
+    #[inline]
+    fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+}
+
+/// Owned uncompressed audio frame with interleaved samples
+///
+/// `format` is `Fourcc::AUDIO_PCM` (signed little endian integers, unsigned for
+/// 8-bit samples), `AUDIO_PCM_FLOAT`, `AUDIO_PCM_ALAW` or `AUDIO_PCM_ULAW`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RawAudioFrame<S = ()> {
+    pub source: S,
+    pub format: Fourcc,
+    pub flags: FrameFlags,
+
+    /// Timestamp in microseconds
+    pub timestamp: u64,
+
+    /// Duration in microseconds (0 if unknown)
+    pub duration: u64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub sample_size: u8,
+    pub data: Bytes,
+}
+
+impl<S: FrameSource> RawAudioFrame<S> {
+    pub fn new(source: S, format: Fourcc, flags: FrameFlags, data: Bytes) -> Self {
+        Self {
+            source,
+            format,
+            flags: flags - FrameFlags::ENCODED,
+            data,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    #[inline]
+    pub fn with_layout(mut self, sample_rate: u32, channels: u16, sample_size: u8) -> Self {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        self.sample_size = sample_size;
+        self
+    }
+
+    /// Number of samples per channel
+    pub fn samples(&self) -> usize {
+        let frame_size = self.channels as usize * self.sample_size.div_ceil(8) as usize;
+
+        self.data.len().checked_div(frame_size).unwrap_or(0)
+    }
+}
+
+impl<S: FrameSource> DataFrame for RawAudioFrame<S> {
+    type Source = S;
+    type Chunk = Bytes;
+
+    #[inline]
+    fn source(&self) -> &Self::Source {
+        &self.source
+    }
+
+    #[inline]
+    fn chunks(&self) -> impl Send + Iterator<Item = <Self::Chunk as MemBlock>::Ref<'_>> {
+        std::iter::once(&self.data)
+    }
+
+    #[inline]
+    fn into_chunks(self) -> impl Send + Iterator<Item = Self::Chunk> {
+        std::iter::once(self.data)
+    }
+}
+
+impl<S: FrameSource> Frame for RawAudioFrame<S> {
+    #[inline]
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    #[inline]
+    fn codec(&self) -> Fourcc {
+        self.format
+    }
+
+    #[inline]
+    fn flags(&self) -> FrameFlags {
+        self.flags
+    }
+}
+
+impl<S: FrameSource> AudioFrame for RawAudioFrame<S> {
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    #[inline]
+    fn sample_size(&self) -> u8 {
+        self.sample_size
+    }
+}
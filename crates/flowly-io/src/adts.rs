@@ -0,0 +1,293 @@
+use bytes::{Buf, Bytes};
+use flowly_codec::aac::{ADTS_CRC_SIZE, ADTS_HEADER_SIZE, AdtsHeader, SAMPLES_PER_FRAME};
+use flowly_core::{Chunked, DataFrame, Fourcc, FrameFlags, FrameSource, Packet};
+use flowly_service::{Context, Service};
+
+use crate::error::Error;
+
+/// ADTS AAC stream parser.
+///
+/// Accepts arbitrary chunks of the raw ADTS byte stream and yields a [`Packet`]
+/// per AAC access unit with the ADTS header stripped, `AudioSpecificConfig`
+/// built from the header is attached as params. Timestamps count 1024 samples
+/// per frame from the first one. Garbage between frames is skipped, a frame is
+/// accepted only if the next one starts right after it (until the stream is
+/// synchronised), so call [`AdtsDemuxer::flush`] to get the last frame of the
+/// stream. Frames failing the CRC check are reported and dropped.
+/// The state is reset when the frame source changes.
+#[derive(Debug, Clone)]
+pub struct AdtsDemuxer<S = ()> {
+    source: S,
+    buf: Chunked<Bytes>,
+    synced: bool,
+    asc: Option<(AdtsHeader, Bytes)>,
+
+    /// Timestamp of the last sample rate change and samples since then
+    base: u64,
+    samples: u64,
+}
+
+impl<S: FrameSource> AdtsDemuxer<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            buf: Chunked::new(),
+            synced: false,
+            asc: None,
+            base: 0,
+            samples: 0,
+        }
+    }
+
+    /// Feeds the chunk of the stream and returns all completed packets
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<Packet<S>, Error>> {
+        self.buf.put(chunk);
+        self.parse(false)
+    }
+
+    /// Returns the frames held back waiting for the next syncword
+    pub fn flush(&mut self) -> Vec<Result<Packet<S>, Error>> {
+        self.parse(true)
+    }
+
+    fn parse(&mut self, eos: bool) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        loop {
+            let mut head = [0u8; ADTS_HEADER_SIZE + ADTS_CRC_SIZE];
+            let len = self.buf.peek(0, &mut head);
+            if len < ADTS_HEADER_SIZE {
+                break;
+            }
+
+            let header = match AdtsHeader::parse(&head[..len]) {
+                Ok(header) => header,
+                Err(flowly_codec::Error::UnexpectedEof(_)) => break,
+                Err(_) => {
+                    self.synced = false;
+                    self.buf.advance(1);
+                    continue;
+                }
+            };
+
+            let frame_length = header.frame_length as usize;
+            let available = self.buf.remaining();
+
+            if available < frame_length {
+                // truncated frame or false syncword at the end of stream
+                if eos {
+                    self.synced = false;
+                    self.buf.advance(1);
+                    continue;
+                }
+
+                break;
+            }
+
+            // the following syncword confirms the frame unless already in sync
+            if !self.synced && !eos {
+                if available < frame_length + 2 {
+                    break;
+                }
+
+                let mut next = [0u8; 2];
+                self.buf.peek(frame_length, &mut next);
+
+                if next[0] != 0xFF || next[1] & 0xF6 != 0xF0 {
+                    self.buf.advance(1);
+                    continue;
+                }
+            }
+
+            self.synced = true;
+            let frame = self.buf.copy_to_bytes(frame_length);
+
+            if let Some(packet) = self.frame(header, frame).transpose() {
+                out.push(packet);
+            }
+        }
+
+        out
+    }
+
+    fn frame(&mut self, header: AdtsHeader, frame: Bytes) -> Result<Option<Packet<S>>, Error> {
+        if header.raw_blocks > 1 {
+            return Err(Error::Unsupported("adts: multiple raw data blocks"));
+        }
+
+        if header
+            .crc
+            .is_some_and(|crc| crc != AdtsHeader::compute_crc(&frame))
+        {
+            return Err(Error::InvalidData("adts: CRC mismatch"));
+        }
+
+        let rate = header.sample_rate() as u64;
+        let asc = match &self.asc {
+            Some((last, asc)) if last.to_asc() == header.to_asc() => asc.clone(),
+            last => {
+                if let Some((last, _)) = last {
+                    // keep timestamps continuous over the sample rate change
+                    self.base += self.samples * 1_000_000 / last.sample_rate() as u64;
+                    self.samples = 0;
+                }
+
+                let asc = header.to_asc().to_bytes();
+                self.asc = Some((header, asc.clone()));
+                asc
+            }
+        };
+
+        let timestamp = self.base + self.samples * 1_000_000 / rate;
+        self.samples += SAMPLES_PER_FRAME as u64;
+
+        let mut packet = Packet::new(
+            self.source.clone(),
+            Fourcc::AUDIO_AAC,
+            FrameFlags::AUDIO_STREAM | FrameFlags::KEYFRAME,
+            frame.slice(header.size()..),
+        )
+        .with_timestamps(timestamp, timestamp as i64)
+        .with_params(vec![asc]);
+
+        packet.duration = self.base + self.samples * 1_000_000 / rate - timestamp;
+
+        Ok(Some(packet))
+    }
+
+    fn reset(&mut self, source: S) {
+        *self = Self::new();
+        self.source = source;
+    }
+}
+
+impl<S: FrameSource> Default for AdtsDemuxer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F> Service<F> for AdtsDemuxer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let mut out = Vec::new();
+
+        if frame.source() != &self.source {
+            out.extend(self.flush());
+            self.reset(frame.source().clone());
+        }
+
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use flowly_codec::aac::{AdtsHeader, AudioSpecificConfig};
+    use flowly_core::{EncodedFrame, Frame};
+
+    use super::AdtsDemuxer;
+
+    /// CRC protected frame (LC, 44.1 kHz, stereo), the CRC is computed by the
+    /// bitwise CRC-16 of ISO/IEC 11172-3 outside of `AdtsHeader::compute_crc`
+    const CRC_FRAME: &[u8] = &[
+        0xFF, 0xF0, 0x50, 0x80, 0x04, 0xFF, 0xFC, 0x78, 0x36, 0x21, 0x00, 0x49, 0x90, 0x02, 0x19,
+        0x00, 0x23, 0x80, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x1B,
+        0x1C, 0x1D, 0x1E, 0x1F, 0x20, 0x21, 0x22, 0x23, 0x24,
+    ];
+
+    fn adts_frame(asc: &AudioSpecificConfig, payload: &[u8], crc: bool) -> Bytes {
+        let mut header = AdtsHeader::from_asc(asc, payload.len()).unwrap();
+        let mut buf = BytesMut::new();
+
+        if crc {
+            header.frame_length += 2;
+            header.crc = Some(0);
+            header.write(&mut buf);
+            buf.put_slice(payload);
+
+            header.crc = Some(AdtsHeader::compute_crc(&buf));
+            buf.clear();
+            header.write(&mut buf);
+        } else {
+            header.write(&mut buf);
+        }
+
+        buf.put_slice(payload);
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_adts_demux() {
+        let asc = AudioSpecificConfig::parse(&[0x11, 0x90]).unwrap();
+
+        let mut stream = BytesMut::new();
+        // garbage with a false header
+        stream.put_slice(&[0x00, 0xFF, 0xF1, 0x50, 0x80, 0x01, 0x1F, 0xFC, 0x55, 0x66]);
+        stream.put_slice(&adts_frame(&asc, &[1; 30], false));
+        stream.put_slice(&adts_frame(&asc, &[2; 40], true));
+
+        let mut corrupted = adts_frame(&asc, &[3; 20], true).to_vec();
+        corrupted[12] ^= 0xFF;
+        stream.put_slice(&corrupted);
+
+        stream.put_slice(&adts_frame(&asc, &[4; 10], false));
+        let stream = stream.freeze();
+
+        let mut demuxer = AdtsDemuxer::<()>::new();
+        let mut out = Vec::new();
+        for chunk in stream.chunks(11) {
+            out.extend(demuxer.push(Bytes::copy_from_slice(chunk)));
+        }
+
+        // frames after the synchronisation are not held back
+        assert_eq!(out.len(), 4);
+        assert!(demuxer.flush().is_empty());
+
+        assert!(out[2].is_err());
+        let packets: Vec<_> = out.into_iter().filter_map(Result::ok).collect();
+
+        let summary: Vec<_> = packets
+            .iter()
+            .map(|x| (x.timestamp(), x.data[0], x.data.len()))
+            .collect();
+
+        assert_eq!(summary, [(0, 1, 30), (21_333, 2, 40), (42_666, 4, 10)]);
+        assert_eq!(packets[0].params().collect::<Vec<_>>(), [&[0x11, 0x90][..]]);
+        assert!(packets.iter().all(|x| x.is_keyframe() && x.is_audio()));
+    }
+
+    #[test]
+    fn test_adts_crc_vector() {
+        let demux = |frame: &[u8]| {
+            let mut demuxer = AdtsDemuxer::<()>::new();
+            let mut out = demuxer.push(Bytes::copy_from_slice(frame));
+            out.extend(demuxer.flush());
+            out
+        };
+
+        let packets = demux(CRC_FRAME);
+        assert_eq!(packets.len(), 1);
+
+        let packet = packets[0].as_ref().unwrap();
+        assert_eq!(packet.params().collect::<Vec<_>>(), [&[0x12, 0x10][..]]);
+        assert_eq!(&packet.data[..], &CRC_FRAME[9..]);
+
+        // the last protected payload byte and the first unprotected one
+        for (idx, valid) in [(32, false), (33, true)] {
+            let mut frame = CRC_FRAME.to_vec();
+            frame[idx] ^= 0x01;
+
+            assert_eq!(demux(&frame)[0].is_ok(), valid);
+        }
+    }
+}
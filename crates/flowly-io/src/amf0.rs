@@ -0,0 +1,288 @@
+use bytes::{Buf, BufMut};
+use flowly_core::{Reader, Writer};
+
+use crate::error::Error;
+
+const MARKER_NUMBER: u8 = 0x00;
+const MARKER_BOOLEAN: u8 = 0x01;
+const MARKER_STRING: u8 = 0x02;
+const MARKER_OBJECT: u8 = 0x03;
+const MARKER_NULL: u8 = 0x05;
+const MARKER_UNDEFINED: u8 = 0x06;
+const MARKER_ECMA_ARRAY: u8 = 0x08;
+const MARKER_OBJECT_END: u8 = 0x09;
+const MARKER_STRICT_ARRAY: u8 = 0x0A;
+const MARKER_DATE: u8 = 0x0B;
+const MARKER_LONG_STRING: u8 = 0x0C;
+
+/// Nesting depth of objects and arrays the decoder accepts
+const MAX_DEPTH: usize = 64;
+
+/// AMF0 value (used by FLV script data and RTMP commands)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Object(Vec<(String, Value)>),
+    Null,
+    Undefined,
+    EcmaArray(Vec<(String, Value)>),
+    StrictArray(Vec<Value>),
+    Date(f64),
+}
+
+impl Value {
+    pub fn decode<B: Buf>(buf: &mut B) -> Result<Self, Error> {
+        Self::decode_nested(buf, 0)
+    }
+
+    fn decode_nested<B: Buf>(buf: &mut B, depth: usize) -> Result<Self, Error> {
+        let marker = buf.read_u8()?;
+
+        if depth >= MAX_DEPTH
+            && matches!(
+                marker,
+                MARKER_OBJECT | MARKER_ECMA_ARRAY | MARKER_STRICT_ARRAY
+            )
+        {
+            return Err(Error::InvalidData("amf0: values nested too deep"));
+        }
+
+        Ok(match marker {
+            MARKER_NUMBER => Self::Number(buf.read_f64()?),
+            MARKER_BOOLEAN => Self::Boolean(buf.read_u8()? != 0),
+            MARKER_STRING => Self::String(read_utf8(buf)?),
+            MARKER_LONG_STRING => {
+                let len = buf.read_u32()? as usize;
+                Self::String(read_string(buf, len)?)
+            }
+            MARKER_OBJECT => Self::Object(read_properties(buf, depth + 1)?),
+            MARKER_NULL => Self::Null,
+            MARKER_UNDEFINED => Self::Undefined,
+            MARKER_ECMA_ARRAY => {
+                // the count is only a hint, the array is terminated like an object
+                let _count = buf.read_u32()?;
+                Self::EcmaArray(read_properties(buf, depth + 1)?)
+            }
+            MARKER_STRICT_ARRAY => {
+                let count = buf.read_u32()?;
+                let mut items = Vec::with_capacity(count.min(1024) as usize);
+
+                for _ in 0..count {
+                    items.push(Self::decode_nested(buf, depth + 1)?);
+                }
+
+                Self::StrictArray(items)
+            }
+            MARKER_DATE => {
+                let millis = buf.read_f64()?;
+                let _timezone = buf.read_i16()?;
+                Self::Date(millis)
+            }
+            _ => return Err(Error::InvalidData("amf0: unsupported value marker")),
+        })
+    }
+
+    /// Decodes values until the end of the buffer
+    pub fn decode_all<B: Buf>(buf: &mut B) -> Result<Vec<Self>, Error> {
+        let mut values = Vec::new();
+
+        while buf.has_remaining() {
+            values.push(Self::decode(buf)?);
+        }
+
+        Ok(values)
+    }
+
+    pub fn encode<B: BufMut>(&self, buf: &mut B) {
+        match self {
+            Self::Number(val) => {
+                buf.put_u8(MARKER_NUMBER);
+                buf.put_f64(*val);
+            }
+            Self::Boolean(val) => {
+                buf.put_u8(MARKER_BOOLEAN);
+                buf.put_u8(*val as u8);
+            }
+            Self::String(val) if val.len() > u16::MAX as usize => {
+                buf.put_u8(MARKER_LONG_STRING);
+                buf.put_u32(val.len() as u32);
+                buf.put_str(val);
+            }
+            Self::String(val) => {
+                buf.put_u8(MARKER_STRING);
+                put_utf8(buf, val);
+            }
+            Self::Object(props) => {
+                buf.put_u8(MARKER_OBJECT);
+                put_properties(buf, props);
+            }
+            Self::Null => buf.put_u8(MARKER_NULL),
+            Self::Undefined => buf.put_u8(MARKER_UNDEFINED),
+            Self::EcmaArray(props) => {
+                buf.put_u8(MARKER_ECMA_ARRAY);
+                buf.put_u32(props.len() as u32);
+                put_properties(buf, props);
+            }
+            Self::StrictArray(items) => {
+                buf.put_u8(MARKER_STRICT_ARRAY);
+                buf.put_u32(items.len() as u32);
+
+                for item in items {
+                    item.encode(buf);
+                }
+            }
+            Self::Date(millis) => {
+                buf.put_u8(MARKER_DATE);
+                buf.put_f64(*millis);
+                buf.put_i16(0);
+            }
+        }
+    }
+
+    /// Property of the `Object` or `EcmaArray`
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(props) | Self::EcmaArray(props) => {
+                props.iter().find_map(|(k, v)| (k == key).then_some(v))
+            }
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self::Boolean(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+fn read_string<B: Buf>(buf: &mut B, len: usize) -> Result<String, Error> {
+    let bytes = buf.read_bytes(len)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn read_utf8<B: Buf>(buf: &mut B) -> Result<String, Error> {
+    let len = buf.read_u16()? as usize;
+    read_string(buf, len)
+}
+
+fn read_properties<B: Buf>(buf: &mut B, depth: usize) -> Result<Vec<(String, Value)>, Error> {
+    let mut props = Vec::new();
+
+    loop {
+        let key = read_utf8(buf)?;
+
+        if key.is_empty() && buf.chunk().first() == Some(&MARKER_OBJECT_END) {
+            buf.advance(1);
+            break;
+        }
+
+        props.push((key, Value::decode_nested(buf, depth)?));
+    }
+
+    Ok(props)
+}
+
+fn put_utf8<B: BufMut>(buf: &mut B, val: &str) {
+    let len = val.len().min(u16::MAX as usize);
+    buf.put_u16(len as u16);
+    buf.put_slice(&val.as_bytes()[..len]);
+}
+
+fn put_properties<B: BufMut>(buf: &mut B, props: &[(String, Value)]) {
+    for (key, val) in props {
+        put_utf8(buf, key);
+        val.encode(buf);
+    }
+
+    buf.put_u16(0);
+    buf.put_u8(MARKER_OBJECT_END);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Value;
+    use crate::error::Error;
+
+    #[test]
+    fn test_amf0_roundtrip() {
+        let value = Value::EcmaArray(vec![
+            ("duration".into(), Value::Number(12.5)),
+            ("encoder".into(), "flowly".into()),
+            ("stereo".into(), Value::Boolean(true)),
+            (
+                "keyframes".into(),
+                Value::Object(vec![(
+                    "times".into(),
+                    Value::StrictArray(vec![Value::Number(0.0), Value::Number(2.0)]),
+                )]),
+            ),
+        ]);
+
+        let mut buf = Vec::new();
+        Value::from("onMetaData").encode(&mut buf);
+        value.encode(&mut buf);
+
+        let values = Value::decode_all(&mut &buf[..]).unwrap();
+
+        assert_eq!(values, [Value::from("onMetaData"), value]);
+        assert_eq!(
+            values[1].get("duration").and_then(Value::as_f64),
+            Some(12.5)
+        );
+
+        // strict arrays of a single strict array
+        let nested = [0x0A, 0, 0, 0, 1].repeat(200_000);
+        assert!(matches!(
+            Value::decode(&mut &nested[..]),
+            Err(Error::InvalidData(_))
+        ));
+
+        let nested = [&[0x0A, 0, 0, 0, 1].repeat(63)[..], &[0x0A, 0, 0, 0, 0]].concat();
+        assert!(Value::decode(&mut &nested[..]).is_ok());
+    }
+}
@@ -1,3 +1,4 @@
+use bytes::TryGetError;
 use flowly_core::Void;
 
 #[derive(Debug, thiserror::Error)]
@@ -11,6 +12,24 @@ pub enum Error<E: std::error::Error + Send + Sync + 'static = Void> {
     #[error("Glob Pattern Error: {0}")]
     GlobPatternError(#[from] glob::PatternError),
 
+    #[error("Codec Error: {0}")]
+    CodecError(#[from] flowly_codec::Error),
+
+    #[error("Unexpected end of data: {0}")]
+    UnexpectedEof(#[from] TryGetError),
+
+    #[error("Invalid data: {0}")]
+    InvalidData(&'static str),
+
+    #[error("Unsupported: {0}")]
+    Unsupported(&'static str),
+
+    #[error("RTSP status: {0} {1}")]
+    RtspStatus(u16, String),
+
+    #[error("RTMP status: {0}")]
+    RtmpStatus(String),
+
     #[error(transparent)]
     Other(E),
 }
@@ -17,6 +17,12 @@ pub struct FileSouce {
     path: String,
 }
 
+impl FileSouce {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
 impl FrameSource for FileSouce {
     type Source = flowly_core::Void;
 
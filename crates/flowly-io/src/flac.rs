@@ -0,0 +1,463 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flowly_codec::flac::{
+    BLOCK_STREAMINFO, FrameHeader, MARKER, MAX_HEADER_SIZE, STREAMINFO_SIZE, StreamInfo, crc16,
+};
+use flowly_core::{DataFrame, Fourcc, FrameFlags, FrameSource, Packet};
+use flowly_service::{Context, Service};
+
+use crate::error::Error;
+
+/// Frame search gives up after this many bytes when STREAMINFO has no maximum
+const MAX_FRAME_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Marker,
+    Metadata,
+    Frames,
+    Failed,
+}
+
+/// Native FLAC stream parser.
+///
+/// Accepts arbitrary chunks of the `fLaC` stream and yields a [`Packet`] per
+/// frame. FLAC frames carry no length, so a frame ends where the next valid
+/// frame header starts and the CRC-16 of the frame matches, call
+/// [`FlacDemuxer::flush`] to get the last frame of the stream. The STREAMINFO
+/// block is attached as params in the `dfLa` form (metadata block header
+/// included, last-block flag set). Timestamps and durations come from the
+/// frame/sample numbers and block sizes of the frame headers.
+/// The state is reset when the frame source changes.
+#[derive(Debug, Clone)]
+pub struct FlacDemuxer<S = ()> {
+    source: S,
+    state: State,
+    buf: BytesMut,
+    skip: usize,
+
+    /// Offset to resume the search of the next frame header from
+    scan: usize,
+    info: Option<StreamInfo>,
+    params: Option<Bytes>,
+}
+
+impl<S: FrameSource> FlacDemuxer<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            state: State::Marker,
+            buf: BytesMut::new(),
+            skip: 0,
+            scan: 0,
+            info: None,
+            params: None,
+        }
+    }
+
+    pub fn stream_info(&self) -> Option<&StreamInfo> {
+        self.info.as_ref()
+    }
+
+    /// Feeds the chunk of the stream and returns all completed packets
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<Packet<S>, Error>> {
+        self.buf.extend_from_slice(&chunk);
+        self.parse(false)
+    }
+
+    /// Returns the last frame of the stream
+    pub fn flush(&mut self) -> Vec<Result<Packet<S>, Error>> {
+        self.parse(true)
+    }
+
+    fn parse(&mut self, eos: bool) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        loop {
+            if self.skip > 0 {
+                let len = self.skip.min(self.buf.len());
+                self.buf.advance(len);
+                self.skip -= len;
+
+                if self.skip > 0 {
+                    break;
+                }
+            }
+
+            match self.state {
+                State::Marker => {
+                    if self.buf.len() < 10 {
+                        break;
+                    }
+
+                    if self.buf.starts_with(b"ID3") {
+                        let size = self.buf[6..10]
+                            .iter()
+                            .fold(0usize, |acc, &x| acc << 7 | (x & 0x7F) as usize);
+                        let footer = if self.buf[5] & 0x10 != 0 { 10 } else { 0 };
+
+                        self.skip = 10 + size + footer;
+                        continue;
+                    }
+
+                    if !self.buf.starts_with(MARKER) {
+                        self.state = State::Failed;
+                        out.push(Err(Error::InvalidData("flac: missing fLaC marker")));
+                        continue;
+                    }
+
+                    self.buf.advance(MARKER.len());
+                    self.state = State::Metadata;
+                }
+
+                State::Metadata => {
+                    if self.buf.len() < 4 {
+                        break;
+                    }
+
+                    let last = self.buf[0] & 0x80 != 0;
+                    let kind = self.buf[0] & 0x7F;
+                    let len =
+                        u32::from_be_bytes([0, self.buf[1], self.buf[2], self.buf[3]]) as usize;
+
+                    if self.buf.len() < 4 + len {
+                        break;
+                    }
+
+                    let block = self.buf.split_to(4 + len).freeze();
+
+                    if kind == BLOCK_STREAMINFO {
+                        match StreamInfo::parse(&block[4..]) {
+                            Ok(info) => {
+                                let mut params = BytesMut::with_capacity(4 + STREAMINFO_SIZE);
+                                params.put_u32(0x8000_0000 | STREAMINFO_SIZE as u32);
+                                params.put_slice(&info.to_bytes());
+
+                                self.info = Some(info);
+                                self.params = Some(params.freeze());
+                            }
+                            Err(err) => {
+                                self.state = State::Failed;
+                                out.push(Err(err.into()));
+                                continue;
+                            }
+                        }
+                    }
+
+                    if last {
+                        if self.info.is_none() {
+                            self.state = State::Failed;
+                            out.push(Err(Error::InvalidData("flac: missing STREAMINFO")));
+                            continue;
+                        }
+
+                        self.state = State::Frames;
+                    }
+                }
+
+                State::Frames => {
+                    let header = match FrameHeader::parse(&self.buf) {
+                        Ok(header) => header,
+                        Err(flowly_codec::Error::UnexpectedEof(_)) if !eos => break,
+                        Err(_) if self.buf.is_empty() => break,
+                        Err(_) => {
+                            self.buf.advance(1);
+                            self.scan = 0;
+                            continue;
+                        }
+                    };
+
+                    let size = match self.find_frame_end(&header, eos) {
+                        Some(size) => size,
+                        None if eos || self.scan > self.max_frame_size() => {
+                            // truncated or corrupted frame, look for the next one
+                            self.buf.advance(1);
+                            self.scan = 0;
+                            continue;
+                        }
+                        None => break,
+                    };
+
+                    self.scan = 0;
+                    let frame = self.buf.split_to(size).freeze();
+                    out.push(self.frame(header, frame));
+                }
+
+                State::Failed => {
+                    self.buf.clear();
+                    break;
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Size of the frame starting at the beginning of the buffer
+    fn find_frame_end(&mut self, header: &FrameHeader, eos: bool) -> Option<usize> {
+        let buf = &self.buf[..];
+        let mut pos = self.scan.max(header.size + 2);
+
+        while pos + 1 < buf.len() {
+            if buf[pos] != 0xFF || buf[pos + 1] & 0xFE != 0xF8 {
+                pos += 1;
+                continue;
+            }
+
+            match FrameHeader::parse(&buf[pos..buf.len().min(pos + MAX_HEADER_SIZE)]) {
+                Ok(next)
+                    if next.variable_blocksize == header.variable_blocksize
+                        && crc16(&buf[..pos]) == 0 =>
+                {
+                    return Some(pos);
+                }
+
+                // the candidate is not complete yet
+                Err(flowly_codec::Error::UnexpectedEof(_)) if !eos => break,
+                _ => pos += 1,
+            }
+        }
+
+        self.scan = pos;
+
+        (eos && crc16(buf) == 0).then_some(buf.len())
+    }
+
+    fn max_frame_size(&self) -> usize {
+        match self.info {
+            Some(info) if info.max_frame_size > 0 => info.max_frame_size as usize,
+            _ => MAX_FRAME_SIZE,
+        }
+    }
+
+    fn frame(&self, header: FrameHeader, frame: Bytes) -> Result<Packet<S>, Error> {
+        let info = self.info.unwrap_or_default();
+        let rate = header.sample_rate.unwrap_or(info.sample_rate) as i128;
+        let first = header.first_sample(&info) as i128;
+
+        if rate == 0 {
+            return Err(Error::InvalidData("flac: invalid sample rate"));
+        }
+
+        let micros = |sample: i128| {
+            i64::try_from(sample * 1_000_000 / rate)
+                .map_err(|_| Error::InvalidData("flac: timestamp out of range"))
+        };
+
+        let timestamp = micros(first)?;
+        let end = micros(first + header.block_size as i128)?;
+
+        let mut packet = Packet::new(
+            self.source.clone(),
+            Fourcc::AUDIO_FLAC,
+            FrameFlags::AUDIO_STREAM | FrameFlags::KEYFRAME,
+            frame,
+        )
+        .with_timestamps(timestamp as u64, timestamp)
+        .with_params(self.params.iter().cloned().collect());
+
+        packet.duration = (end - timestamp) as u64;
+        Ok(packet)
+    }
+
+    fn reset(&mut self, source: S) {
+        *self = Self::new();
+        self.source = source;
+    }
+}
+
+impl<S: FrameSource> Default for FlacDemuxer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F> Service<F> for FlacDemuxer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let mut out = Vec::new();
+
+        if frame.source() != &self.source {
+            out.extend(self.flush());
+            self.reset(frame.source().clone());
+        }
+
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use flowly_codec::flac::{StreamInfo, crc8, crc16};
+    use flowly_core::{EncodedFrame, Frame};
+
+    use super::FlacDemuxer;
+
+    /// Fixed block size frame at 48 kHz, stereo, 16 bit
+    fn flac_frame(number: u8, block_size: Option<u16>, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![
+            0xFF,
+            0xF8,
+            if block_size.is_some() { 0x7A } else { 0xCA },
+            0x18,
+            number,
+        ];
+        if let Some(size) = block_size {
+            frame.extend_from_slice(&(size - 1).to_be_bytes());
+        }
+
+        frame.push(crc8(&frame));
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(&crc16(&frame).to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_flac_demux() {
+        let info = StreamInfo {
+            min_block_size: 4096,
+            max_block_size: 4096,
+            min_frame_size: 0,
+            max_frame_size: 0,
+            sample_rate: 48000,
+            channels: 2,
+            bits_per_sample: 16,
+            total_samples: 4096 * 2 + 1000,
+            md5: [0; 16],
+        };
+
+        let mut stream = BytesMut::new();
+        stream.put_slice(b"fLaC");
+        stream.put_u32(34);
+        stream.put_slice(&info.to_bytes());
+        stream.put_u32(0x8100_0003);
+        stream.put_slice(&[0; 3]);
+
+        // the false header inside the first frame fails the CRC-16 check
+        let mut payload = vec![0x11; 200];
+        let fake = flac_frame(1, None, &[]);
+        payload[50..50 + fake.len()].copy_from_slice(&fake);
+
+        stream.put_slice(&flac_frame(0, None, &payload));
+        stream.put_slice(&flac_frame(1, None, &[0x22; 300]));
+        stream.put_slice(&flac_frame(2, Some(1000), &[0x33; 100]));
+        let stream = stream.freeze();
+
+        let mut demuxer = FlacDemuxer::<()>::new();
+        let mut out = Vec::new();
+        for chunk in stream.chunks(50) {
+            out.extend(demuxer.push(Bytes::copy_from_slice(chunk)));
+        }
+
+        // the last frame ends with the stream
+        assert_eq!(out.len(), 2);
+        out.extend(demuxer.flush());
+
+        let packets: Vec<_> = out.into_iter().map(Result::unwrap).collect();
+        let summary: Vec<_> = packets
+            .iter()
+            .map(|x| (x.timestamp(), x.duration, x.data.len()))
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (0, 85_333, 208),
+                (85_333, 85_333, 308),
+                (170_666, 20_834, 110)
+            ]
+        );
+
+        let params: Vec<_> = packets[0].params().collect();
+        assert_eq!(params[0][..4], [0x80, 0, 0, 34]);
+        assert_eq!(StreamInfo::parse(&params[0][4..]).unwrap(), info);
+        assert_eq!(demuxer.stream_info(), Some(&info));
+    }
+
+    #[test]
+    fn test_flac_demux_large_frame_number() {
+        let info = StreamInfo {
+            min_block_size: 4096,
+            max_block_size: 4096,
+            sample_rate: 48000,
+            channels: 2,
+            bits_per_sample: 16,
+            ..Default::default()
+        };
+
+        let mut stream = BytesMut::new();
+        stream.put_slice(b"fLaC");
+        stream.put_u32(0x8000_0022);
+        stream.put_slice(&info.to_bytes());
+
+        // the largest coded frame number, its first sample overflows microseconds in u64
+        let mut frame = vec![
+            0xFF, 0xF8, 0xCA, 0x18, 0xFE, 0xBF, 0xBF, 0xBF, 0xBF, 0xBF, 0xBF,
+        ];
+        frame.push(crc8(&frame));
+        frame.extend_from_slice(&[0x11; 20]);
+        frame.extend_from_slice(&crc16(&frame).to_be_bytes());
+        stream.put_slice(&frame);
+
+        let mut demuxer = FlacDemuxer::<()>::new();
+        let mut out = demuxer.push(stream.freeze());
+        out.extend(demuxer.flush());
+
+        let first = ((1i128 << 36) - 1) * 4096;
+        let packet = out.pop().unwrap().unwrap();
+        assert_eq!(packet.pts() as i128, first * 1_000_000 / 48000);
+        assert_eq!(packet.duration, 85_333);
+    }
+
+    #[test]
+    fn test_flac_demux_malformed() {
+        let info = StreamInfo {
+            min_block_size: 4096,
+            max_block_size: 4096,
+            sample_rate: 48000,
+            channels: 2,
+            bits_per_sample: 16,
+            ..Default::default()
+        };
+
+        let mut no_marker = BytesMut::new();
+        no_marker.put_slice(b"fLaX");
+        no_marker.put_u32(0x8000_0022);
+        no_marker.put_slice(&info.to_bytes());
+
+        // the only metadata block is padding
+        let mut no_info = BytesMut::new();
+        no_info.put_slice(b"fLaC");
+        no_info.put_u32(0x8100_0003);
+        no_info.put_slice(&[0; 3]);
+        no_info.put_slice(&flac_frame(0, None, &[0; 10]));
+
+        // zero sample rate
+        let mut bad_info = BytesMut::new();
+        bad_info.put_slice(b"fLaC");
+        bad_info.put_u32(0x8000_0022);
+        bad_info.put_slice(
+            &StreamInfo {
+                sample_rate: 0,
+                ..info
+            }
+            .to_bytes(),
+        );
+
+        for stream in [no_marker, no_info, bad_info] {
+            let mut demuxer = FlacDemuxer::<()>::new();
+            let mut out = demuxer.push(stream.freeze());
+            out.extend(demuxer.flush());
+
+            assert!(matches!(out[..], [Err(_)]));
+        }
+    }
+}
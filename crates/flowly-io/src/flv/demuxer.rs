@@ -0,0 +1,572 @@
+use bytes::{Buf, Bytes};
+use flowly_codec::{VideoInfo, h264::AvcConfig, h265::HevcConfig, nal};
+use flowly_core::{Chunked, DataFrame, Fourcc, FrameFlags, FrameSource, Packet, Reader, ReaderExt};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Header,
+    TagHeader,
+    TagData(TagHeader),
+    Failed,
+}
+
+/// Decoding parameters of the elementary stream
+#[derive(Debug, Default, Clone)]
+struct Track {
+    codec: Fourcc,
+    params: Vec<Bytes>,
+    dimensions: (u16, u16),
+    bit_depth: u8,
+
+    /// NAL unit length size of the `avcC`/`hvcC` record, 0 if not applicable
+    length_size: u8,
+}
+
+/// FLV demuxer.
+///
+/// Accepts arbitrary chunks of the FLV byte stream (e.g. produced by `FileReader`)
+/// and yields a [`Packet`] per audio/video/script tag. Video payloads are kept
+/// length prefixed (AVCC/HVCC) with 4-byte lengths whatever the configuration
+/// record says, parameter sets are attached to every keyframe.
+/// The state is reset when the frame source changes.
+#[derive(Debug, Clone)]
+pub struct FlvDemuxer<S = ()> {
+    source: S,
+    state: State,
+    skip: usize,
+    buf: Chunked<Bytes>,
+    flags: FrameFlags,
+    video: Track,
+    audio: Track,
+    metadata: Option<Metadata>,
+}
+
+impl<S: FrameSource> FlvDemuxer<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            state: State::Header,
+            skip: 0,
+            buf: Chunked::new(),
+            flags: FrameFlags::empty(),
+            video: Track::default(),
+            audio: Track::default(),
+            metadata: None,
+        }
+    }
+
+    /// Last received `onMetaData`
+    #[inline]
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Feeds the chunk of the stream and returns all completed packets
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        self.buf.put(chunk);
+
+        loop {
+            if self.skip > 0 {
+                let len = self.skip.min(self.buf.remaining());
+                self.buf.advance(len);
+                self.skip -= len;
+
+                if self.skip > 0 {
+                    break;
+                }
+            }
+
+            match self.state {
+                State::Header => {
+                    if self.buf.remaining() < HEADER_SIZE as usize {
+                        break;
+                    }
+
+                    match Header::read(&mut self.buf) {
+                        Ok(header) => {
+                            self.flags = FrameFlags::empty();
+                            if header.has_audio && header.has_video {
+                                self.flags |= FrameFlags::MULTICHANNEL;
+                            }
+
+                            // PreviousTagSize0 goes after the header
+                            self.skip = (header.data_offset - HEADER_SIZE) as usize + 4;
+                            self.state = State::TagHeader;
+                        }
+                        Err(err) => {
+                            out.push(Err(err));
+                            self.state = State::Failed;
+                        }
+                    }
+                }
+
+                State::TagHeader => {
+                    if self.buf.remaining() < TAG_HEADER_SIZE {
+                        break;
+                    }
+
+                    self.state = match TagHeader::read(&mut self.buf) {
+                        Ok(header) => State::TagData(header),
+                        Err(err) => {
+                            out.push(Err(err));
+                            State::Failed
+                        }
+                    };
+                }
+
+                State::TagData(header) => {
+                    let size = header.data_size as usize;
+
+                    if self.buf.remaining() < size + 4 {
+                        break;
+                    }
+
+                    let data = self.buf.copy_to_bytes(size);
+                    self.buf.advance(4);
+                    self.state = State::TagHeader;
+
+                    if header.filtered {
+                        continue;
+                    }
+
+                    match self.push_tag(header.kind, header.timestamp, data) {
+                        Ok(Some(packet)) => out.push(Ok(packet)),
+                        Ok(None) => (),
+                        Err(err) => out.push(Err(err)),
+                    }
+                }
+
+                State::Failed => {
+                    let len = self.buf.remaining();
+                    self.buf.advance(len);
+                    break;
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Parses the body of the tag (e.g. RTMP audio/video/data message payload),
+    /// `timestamp` is in milliseconds
+    pub fn push_tag(
+        &mut self,
+        kind: u8,
+        timestamp: u32,
+        data: Bytes,
+    ) -> Result<Option<Packet<S>>, Error> {
+        match kind {
+            TAG_VIDEO => self.parse_video(timestamp, data),
+            TAG_AUDIO => self.parse_audio(timestamp, data),
+            TAG_SCRIPT => self.parse_script(timestamp, data),
+            _ => Ok(None),
+        }
+    }
+
+    fn parse_video(&mut self, timestamp: u32, mut data: Bytes) -> Result<Option<Packet<S>>, Error> {
+        let (is_ex_header, frame_type, packet_type) = data.read_u8p3::<1, 3, 4>()?;
+
+        if frame_type == FRAME_TYPE_COMMAND {
+            return Ok(None);
+        }
+
+        let (codec, packet_type, cts) = if is_ex_header != 0 {
+            let codec = read_fourcc(&mut data)?;
+
+            let cts = match packet_type {
+                VIDEO_PACKET_CODED_FRAMES
+                    if matches!(codec, Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC) =>
+                {
+                    data.read_i24()?
+                }
+                VIDEO_PACKET_SEQUENCE_START
+                | VIDEO_PACKET_CODED_FRAMES
+                | VIDEO_PACKET_CODED_FRAMES_X => 0,
+
+                // sequence end, metadata, multitrack and ModEx packets are skipped
+                _ => return Ok(None),
+            };
+
+            let packet_type = if packet_type == VIDEO_PACKET_SEQUENCE_START {
+                AVC_SEQUENCE_HEADER
+            } else {
+                AVC_NALU
+            };
+
+            (codec, packet_type, cts)
+        } else {
+            let Some(codec) = video_codec(packet_type) else {
+                return Ok(None);
+            };
+
+            (codec, data.read_u8()?, data.read_i24()?)
+        };
+
+        match packet_type {
+            AVC_SEQUENCE_HEADER => {
+                self.set_video_config(codec, data)?;
+                Ok(None)
+            }
+
+            AVC_NALU if !data.is_empty() => {
+                if self.video.codec == codec && !matches!(self.video.length_size, 0 | 4) {
+                    data = nal::resize_length_prefix(data, self.video.length_size, 4)?;
+                }
+
+                let mut flags = self.flags | FrameFlags::VIDEO_STREAM;
+                if frame_type == FRAME_TYPE_KEY {
+                    flags |= FrameFlags::KEYFRAME;
+                }
+
+                let dts = timestamp as u64 * 1000;
+                let pts = (timestamp as i64 + cts as i64) * 1000;
+
+                let mut packet = Packet::new(self.source.clone(), codec, flags, data)
+                    .with_timestamps(dts, pts)
+                    .with_track(TRACK_VIDEO);
+
+                if frame_type == FRAME_TYPE_KEY && self.video.codec == codec {
+                    packet = packet.with_params(self.video.params.clone());
+                }
+
+                packet.dimensions = self.video.dimensions;
+                packet.bit_depth = self.video.bit_depth;
+
+                Ok(Some(packet))
+            }
+
+            _ => Ok(None),
+        }
+    }
+
+    fn set_video_config(&mut self, codec: Fourcc, config: Bytes) -> Result<(), Error> {
+        let (params, length_size): (Vec<Bytes>, u8) = match codec {
+            Fourcc::VIDEO_AVC => {
+                let config = AvcConfig::parse(&config)?;
+                (config.params().cloned().collect(), config.length_size)
+            }
+            Fourcc::VIDEO_HEVC => {
+                let config = HevcConfig::parse(&config)?;
+                (config.params().cloned().collect(), config.length_size)
+            }
+
+            // AV1CodecConfigurationRecord, VPCodecConfigurationRecord etc. are passed as is
+            _ => (vec![config], 0),
+        };
+
+        let info = VideoInfo::from_params(codec, &params).ok();
+
+        self.video = Track {
+            codec,
+            dimensions: info
+                .as_ref()
+                .map(VideoInfo::dimensions)
+                .or_else(|| self.metadata.as_ref()?.dimensions())
+                .unwrap_or_default(),
+            bit_depth: info.map(|x| x.bit_depth).unwrap_or(8),
+            params,
+            length_size,
+        };
+
+        Ok(())
+    }
+
+    fn parse_audio(&mut self, timestamp: u32, mut data: Bytes) -> Result<Option<Packet<S>>, Error> {
+        let (sound_format, rate, size, channels) = data.read_u8p4::<4, 2, 1, 1>()?;
+
+        let (codec, is_config) = if sound_format == SOUND_FORMAT_EX_HEADER {
+            // the low nibble is reused as `AudioPacketType`
+            let packet_type = rate << 2 | size << 1 | channels;
+            let codec = read_fourcc(&mut data)?;
+
+            match packet_type {
+                AUDIO_PACKET_SEQUENCE_START => (codec, true),
+                AUDIO_PACKET_CODED_FRAMES => (codec, false),
+                _ => return Ok(None),
+            }
+        } else {
+            let Some(codec) = audio_codec(sound_format) else {
+                return Ok(None);
+            };
+
+            if codec == Fourcc::AUDIO_AAC {
+                (codec, data.read_u8()? == AAC_SEQUENCE_HEADER)
+            } else {
+                (codec, false)
+            }
+        };
+
+        if is_config {
+            self.audio = Track {
+                codec,
+                params: vec![data],
+                ..Default::default()
+            };
+
+            return Ok(None);
+        }
+
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        let flags = self.flags | FrameFlags::AUDIO_STREAM | FrameFlags::KEYFRAME;
+        let ts = timestamp as u64 * 1000;
+
+        let mut packet = Packet::new(self.source.clone(), codec, flags, data)
+            .with_timestamps(ts, ts as i64)
+            .with_track(TRACK_AUDIO);
+
+        if self.audio.codec == codec {
+            packet = packet.with_params(self.audio.params.clone());
+        }
+
+        Ok(Some(packet))
+    }
+
+    fn parse_script(&mut self, timestamp: u32, data: Bytes) -> Result<Option<Packet<S>>, Error> {
+        if let Some(metadata) = Metadata::parse(&mut data.clone())? {
+            if self.video.dimensions == (0, 0) {
+                self.video.dimensions = metadata.dimensions().unwrap_or_default();
+            }
+
+            self.metadata = Some(metadata);
+        }
+
+        let ts = timestamp as u64 * 1000;
+
+        Ok(Some(
+            Packet::new(
+                self.source.clone(),
+                Fourcc::METADATA_AMF0,
+                self.flags | FrameFlags::METADATA_STREAM,
+                data,
+            )
+            .with_timestamps(ts, ts as i64)
+            .with_track(TRACK_METADATA),
+        ))
+    }
+
+    fn reset(&mut self, source: S) {
+        *self = Self::new();
+        self.source = source;
+    }
+}
+
+impl<S: FrameSource> Default for FlvDemuxer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F> Service<F> for FlvDemuxer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        if frame.source() != &self.source {
+            self.reset(frame.source().clone());
+        }
+
+        let mut out = Vec::new();
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use flowly_codec::h264::AvcConfig;
+    use flowly_core::{EncodedFrame, Fourcc, Frame, VideoFrame};
+
+    use super::FlvDemuxer;
+    use crate::{
+        amf0::Value,
+        error::Error,
+        flv::*,
+        test_fixtures::{PPS, SPS},
+    };
+
+    fn put_tag(buf: &mut BytesMut, kind: u8, timestamp: u32, data: &[u8]) {
+        TagHeader {
+            kind,
+            data_size: data.len() as u32,
+            timestamp,
+            ..Default::default()
+        }
+        .write(buf);
+
+        buf.put_slice(data);
+        buf.put_u32((data.len() + TAG_HEADER_SIZE) as u32);
+    }
+
+    fn sample_flv() -> Bytes {
+        let mut buf = BytesMut::new();
+
+        Header {
+            has_audio: true,
+            has_video: true,
+            ..Default::default()
+        }
+        .write(&mut buf);
+
+        let mut script = Vec::new();
+        Value::from("onMetaData").encode(&mut script);
+        Value::EcmaArray(vec![("duration".into(), 1.0.into())]).encode(&mut script);
+        put_tag(&mut buf, TAG_SCRIPT, 0, &script);
+
+        let config = AvcConfig::from_params([SPS, PPS]).unwrap().to_bytes();
+        let mut video = vec![0x17, AVC_SEQUENCE_HEADER, 0, 0, 0];
+        video.extend_from_slice(&config);
+        put_tag(&mut buf, TAG_VIDEO, 0, &video);
+
+        put_tag(
+            &mut buf,
+            TAG_AUDIO,
+            0,
+            &[0xAF, AAC_SEQUENCE_HEADER, 0x12, 0x10],
+        );
+
+        // keyframe with 80ms composition offset
+        put_tag(
+            &mut buf,
+            TAG_VIDEO,
+            40,
+            &[0x17, AVC_NALU, 0, 0, 80, 0, 0, 0, 2, 0x65, 0x88],
+        );
+        put_tag(&mut buf, TAG_AUDIO, 46, &[0xAF, AAC_RAW, 0x21, 0x00]);
+
+        // enhanced RTMP AV1 sequence start and coded frame
+        let mut av1 = vec![0x80 | FRAME_TYPE_KEY << 4 | VIDEO_PACKET_SEQUENCE_START];
+        av1.extend_from_slice(b"av01");
+        av1.extend_from_slice(&[0x81, 0x08, 0x0C, 0x00]);
+        put_tag(&mut buf, TAG_VIDEO, 80, &av1);
+
+        let mut av1 = vec![0x80 | 2 << 4 | VIDEO_PACKET_CODED_FRAMES];
+        av1.extend_from_slice(b"av01");
+        av1.extend_from_slice(&[0x32, 0x12, 0x00]);
+        put_tag(&mut buf, TAG_VIDEO, 120, &av1);
+
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_flv_demux_split_chunks() {
+        let flv = sample_flv();
+        let mut demuxer = FlvDemuxer::<()>::new();
+
+        // feed byte by byte so every tag spans several chunks
+        let packets: Vec<_> = (0..flv.len())
+            .flat_map(|i| demuxer.push(flv.slice(i..i + 1)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(demuxer.metadata().and_then(|x| x.duration), Some(1.0));
+        assert_eq!(packets.len(), 4);
+
+        assert!(packets[0].is_metadata());
+        assert_eq!(packets[0].codec(), Fourcc::METADATA_AMF0);
+
+        let video = &packets[1];
+        assert_eq!(video.codec(), Fourcc::VIDEO_AVC);
+        assert!(video.is_keyframe() && video.is_video() && video.is_multichannel());
+        assert_eq!((video.dts(), video.pts()), (40_000, 120_000));
+        assert_eq!(video.params().collect::<Vec<_>>(), [SPS, PPS]);
+        assert_eq!(video.dimensions(), (1920, 1080));
+        assert_eq!(&video.data[..], &[0, 0, 0, 2, 0x65, 0x88]);
+
+        let audio = &packets[2];
+        assert_eq!(audio.codec(), Fourcc::AUDIO_AAC);
+        assert!(audio.is_audio());
+        assert_eq!(audio.dts(), 46_000);
+        assert_eq!(audio.params().collect::<Vec<_>>(), [&[0x12, 0x10][..]]);
+        assert_eq!(&audio.data[..], &[0x21, 0x00]);
+
+        let av1 = &packets[3];
+        assert_eq!(av1.codec(), Fourcc::VIDEO_AV1);
+        assert!(!av1.is_keyframe());
+        assert_eq!(av1.dts(), 120_000);
+        assert_eq!(&av1.data[..], &[0x32, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn test_flv_demux_length_size() {
+        let mut buf = BytesMut::new();
+        Header {
+            has_video: true,
+            ..Default::default()
+        }
+        .write(&mut buf);
+
+        let config = AvcConfig {
+            length_size: 2,
+            ..AvcConfig::from_params([SPS, PPS]).unwrap()
+        };
+        let mut video = vec![0x17, AVC_SEQUENCE_HEADER, 0, 0, 0];
+        video.extend_from_slice(&config.to_bytes());
+        put_tag(&mut buf, TAG_VIDEO, 0, &video);
+
+        // 2-byte NAL lengths, the second tag is truncated
+        put_tag(
+            &mut buf,
+            TAG_VIDEO,
+            40,
+            &[0x17, AVC_NALU, 0, 0, 0, 0, 2, 0x65, 0x88, 0, 1, 0x06],
+        );
+        put_tag(
+            &mut buf,
+            TAG_VIDEO,
+            80,
+            &[0x27, AVC_NALU, 0, 0, 0, 0, 5, 0x41],
+        );
+
+        let mut out = FlvDemuxer::<()>::new().push(buf.freeze());
+        assert_eq!(out.len(), 2);
+        assert!(out.pop().unwrap().is_err());
+
+        let video = out.pop().unwrap().unwrap();
+        assert_eq!(&video.data[..], &[0, 0, 0, 2, 0x65, 0x88, 0, 0, 0, 1, 0x06]);
+    }
+
+    #[test]
+    fn test_flv_demux_malformed() {
+        let res =
+            FlvDemuxer::<()>::new().push(Bytes::from_static(b"FLX\x01\x05\0\0\0\x09\0\0\0\0"));
+        assert!(matches!(
+            res[..],
+            [Err(Error::InvalidData("flv: invalid signature"))]
+        ));
+
+        // bad avcC version, truncated audio tag
+        let mut buf = BytesMut::new();
+        Header {
+            has_audio: true,
+            has_video: true,
+            ..Default::default()
+        }
+        .write(&mut buf);
+        put_tag(
+            &mut buf,
+            TAG_VIDEO,
+            0,
+            &[0x17, AVC_SEQUENCE_HEADER, 0, 0, 0, 0, 0x64],
+        );
+        put_tag(&mut buf, TAG_AUDIO, 0, &[0xAF]);
+
+        let res = FlvDemuxer::<()>::new().push(buf.freeze());
+        assert_eq!(res.len(), 2);
+        assert!(res.iter().all(Result::is_err));
+    }
+}
@@ -0,0 +1,244 @@
+mod demuxer;
+mod muxer;
+
+pub use demuxer::FlvDemuxer;
+pub use muxer::FlvMuxer;
+
+use bytes::{Buf, BufMut};
+use flowly_core::{Fourcc, Reader, Writer};
+
+use crate::{amf0, error::Error};
+
+pub const SIGNATURE: &[u8; 3] = b"FLV";
+pub const HEADER_SIZE: u32 = 9;
+pub const TAG_HEADER_SIZE: usize = 11;
+
+pub const FLAG_AUDIO: u8 = 0x04;
+pub const FLAG_VIDEO: u8 = 0x01;
+
+pub const TAG_AUDIO: u8 = 8;
+pub const TAG_VIDEO: u8 = 9;
+pub const TAG_SCRIPT: u8 = 18;
+
+/// Track numbers of the produced packets
+pub const TRACK_VIDEO: u32 = 0;
+pub const TRACK_AUDIO: u32 = 1;
+pub const TRACK_METADATA: u32 = 2;
+
+pub const FRAME_TYPE_KEY: u8 = 1;
+pub const FRAME_TYPE_COMMAND: u8 = 5;
+
+/// Legacy `CodecID` of the video tag
+pub const VIDEO_CODEC_AVC: u8 = 7;
+pub const VIDEO_CODEC_HEVC: u8 = 12;
+
+/// `AVCPacketType` of the legacy video tag
+pub const AVC_SEQUENCE_HEADER: u8 = 0;
+pub const AVC_NALU: u8 = 1;
+pub const AVC_END_OF_SEQUENCE: u8 = 2;
+
+/// `VideoPacketType` of the enhanced RTMP video tag
+pub const VIDEO_PACKET_SEQUENCE_START: u8 = 0;
+pub const VIDEO_PACKET_CODED_FRAMES: u8 = 1;
+pub const VIDEO_PACKET_SEQUENCE_END: u8 = 2;
+pub const VIDEO_PACKET_CODED_FRAMES_X: u8 = 3;
+
+/// Legacy `SoundFormat` of the audio tag
+pub const SOUND_FORMAT_MP3: u8 = 2;
+pub const SOUND_FORMAT_ALAW: u8 = 7;
+pub const SOUND_FORMAT_ULAW: u8 = 8;
+pub const SOUND_FORMAT_EX_HEADER: u8 = 9;
+pub const SOUND_FORMAT_AAC: u8 = 10;
+pub const SOUND_FORMAT_MP3_8K: u8 = 14;
+
+pub const AAC_SEQUENCE_HEADER: u8 = 0;
+pub const AAC_RAW: u8 = 1;
+
+/// `AudioPacketType` of the enhanced RTMP audio tag
+pub const AUDIO_PACKET_SEQUENCE_START: u8 = 0;
+pub const AUDIO_PACKET_CODED_FRAMES: u8 = 1;
+
+/// FLV file header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub has_audio: bool,
+    pub has_video: bool,
+    pub data_offset: u32,
+}
+
+impl Header {
+    pub fn read<B: Buf>(buf: &mut B) -> Result<Self, Error> {
+        let mut signature = [0u8; 3];
+        buf.read_slice(&mut signature)?;
+
+        if &signature != SIGNATURE {
+            return Err(Error::InvalidData("flv: invalid signature"));
+        }
+
+        let version = buf.read_u8()?;
+        let flags = buf.read_u8()?;
+        let data_offset = buf.read_u32()?;
+
+        if data_offset < HEADER_SIZE {
+            return Err(Error::InvalidData("flv: invalid header size"));
+        }
+
+        Ok(Self {
+            version,
+            has_audio: flags & FLAG_AUDIO != 0,
+            has_video: flags & FLAG_VIDEO != 0,
+            data_offset,
+        })
+    }
+
+    /// Writes the header followed by `PreviousTagSize0`
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        buf.put_slice(SIGNATURE);
+        buf.put_u8(self.version);
+        buf.put_u8(
+            if self.has_audio { FLAG_AUDIO } else { 0 }
+                | if self.has_video { FLAG_VIDEO } else { 0 },
+        );
+        buf.put_u32(HEADER_SIZE);
+        buf.put_u32(0);
+    }
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self {
+            version: 1,
+            has_audio: false,
+            has_video: false,
+            data_offset: HEADER_SIZE,
+        }
+    }
+}
+
+/// FLV tag header
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TagHeader {
+    pub kind: u8,
+    pub filtered: bool,
+    pub data_size: u32,
+
+    /// Timestamp in milliseconds
+    pub timestamp: u32,
+    pub stream_id: u32,
+}
+
+impl TagHeader {
+    pub fn read<B: Buf>(buf: &mut B) -> Result<Self, Error> {
+        let kind = buf.read_u8()?;
+        let data_size = buf.read_u24()?;
+        let timestamp = buf.read_u24()? | (buf.read_u8()? as u32) << 24;
+        let stream_id = buf.read_u24()?;
+
+        Ok(Self {
+            kind: kind & 0x1F,
+            filtered: kind & 0x20 != 0,
+            data_size,
+            timestamp,
+            stream_id,
+        })
+    }
+
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        buf.put_u8(self.kind | if self.filtered { 0x20 } else { 0 });
+        buf.put_u24(self.data_size);
+        buf.put_u24(self.timestamp & 0xFF_FFFF);
+        buf.put_u8((self.timestamp >> 24) as u8);
+        buf.put_u24(self.stream_id);
+    }
+}
+
+/// Reads enhanced RTMP `FourCC` codec identifier
+pub fn read_fourcc<B: Buf>(buf: &mut B) -> Result<Fourcc, Error> {
+    let mut fourcc = [0u8; 4];
+    buf.read_slice(&mut fourcc)?;
+
+    Ok(Fourcc::from(fourcc))
+}
+
+/// Fourcc of the legacy video `CodecID`
+pub fn video_codec(codec_id: u8) -> Option<Fourcc> {
+    match codec_id {
+        VIDEO_CODEC_AVC => Some(Fourcc::VIDEO_AVC),
+        VIDEO_CODEC_HEVC => Some(Fourcc::VIDEO_HEVC),
+        _ => None,
+    }
+}
+
+/// Fourcc of the legacy `SoundFormat`
+pub fn audio_codec(sound_format: u8) -> Option<Fourcc> {
+    match sound_format {
+        SOUND_FORMAT_MP3 | SOUND_FORMAT_MP3_8K => Some(Fourcc::AUDIO_MP3),
+        SOUND_FORMAT_ALAW => Some(Fourcc::AUDIO_PCM_ALAW),
+        SOUND_FORMAT_ULAW => Some(Fourcc::AUDIO_PCM_ULAW),
+        SOUND_FORMAT_AAC => Some(Fourcc::AUDIO_AAC),
+        _ => None,
+    }
+}
+
+/// Stream properties of the `onMetaData` script tag
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Metadata {
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    pub framerate: Option<f64>,
+    pub video_data_rate: Option<f64>,
+    pub audio_sample_rate: Option<f64>,
+    pub audio_channels: Option<f64>,
+    pub audio_data_rate: Option<f64>,
+    pub encoder: Option<String>,
+
+    /// All the properties as decoded
+    pub properties: Option<amf0::Value>,
+}
+
+impl Metadata {
+    /// Decodes script tag payload, `None` if it is not `onMetaData`
+    pub fn parse<B: Buf>(buf: &mut B) -> Result<Option<Self>, Error> {
+        let values = amf0::Value::decode_all(buf)?;
+        let mut iter = values.into_iter();
+
+        let props = loop {
+            match iter.next() {
+                // written by `@setDataFrame` RTMP command
+                Some(amf0::Value::String(name)) if name == "@setDataFrame" => continue,
+                Some(amf0::Value::String(name)) if name == "onMetaData" => break iter.next(),
+                _ => return Ok(None),
+            }
+        };
+
+        let Some(props) = props else {
+            return Ok(None);
+        };
+
+        let number = |key| props.get(key).and_then(amf0::Value::as_f64);
+
+        Ok(Some(Self {
+            duration: number("duration"),
+            width: number("width"),
+            height: number("height"),
+            framerate: number("framerate"),
+            video_data_rate: number("videodatarate"),
+            audio_sample_rate: number("audiosamplerate"),
+            audio_channels: number("audiochannels"),
+            audio_data_rate: number("audiodatarate"),
+            encoder: props
+                .get("encoder")
+                .and_then(amf0::Value::as_str)
+                .map(Into::into),
+            properties: Some(props),
+        }))
+    }
+
+    /// Video dimensions if both are present
+    pub fn dimensions(&self) -> Option<(u16, u16)> {
+        Some((self.width? as u16, self.height? as u16))
+    }
+}
@@ -0,0 +1,366 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly_codec::{BitstreamConverter, h264::AvcConfig, h265::HevcConfig};
+use flowly_core::{
+    EncodedFrame, Fourcc, Frame, Multichannel, Packet, VideoFrame, Writer, WriterExt,
+};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+/// FLV muxer.
+///
+/// Accepts audio/video frames of any [`EncodedFrame`] stream and yields FLV byte
+/// chunks: the file header first, then one chunk per tag. Sequence headers are
+/// generated from `params()` and re-sent whenever the params change. H.264 uses
+/// the legacy `CodecID`, HEVC, AV1, VP9, Opus and FLAC use enhanced RTMP headers.
+/// Timestamps are shifted so the first frame starts at zero.
+#[derive(Debug, Clone)]
+pub struct FlvMuxer {
+    header: Header,
+    header_written: bool,
+    start_dts: Option<u64>,
+    video_params: Option<(Fourcc, Vec<Bytes>)>,
+    audio_params: Option<(Fourcc, Vec<Bytes>)>,
+    converter: BitstreamConverter,
+}
+
+impl FlvMuxer {
+    pub fn new() -> Self {
+        Self {
+            header: Header {
+                has_audio: true,
+                has_video: true,
+                ..Default::default()
+            },
+            header_written: false,
+            start_dts: None,
+            video_params: None,
+            audio_params: None,
+            converter: BitstreamConverter::length_prefixed(),
+        }
+    }
+
+    /// Sets audio presence flag of the FLV header
+    pub fn with_audio(mut self, has_audio: bool) -> Self {
+        self.header.has_audio = has_audio;
+        self
+    }
+
+    /// Sets video presence flag of the FLV header
+    pub fn with_video(mut self, has_video: bool) -> Self {
+        self.header.has_video = has_video;
+        self
+    }
+
+    /// Encodes the frame into FLV tags (prefixed with the file header on the first call)
+    pub fn push<F: EncodedFrame + Multichannel + VideoFrame>(
+        &mut self,
+        frame: F,
+    ) -> Result<Vec<Bytes>, Error> {
+        let mut out = Vec::with_capacity(3);
+
+        if !self.header_written {
+            let mut buf = BytesMut::with_capacity(HEADER_SIZE as usize + 4);
+            self.header.write(&mut buf);
+            out.push(buf.freeze());
+            self.header_written = true;
+        }
+
+        let start = *self.start_dts.get_or_insert(frame.dts());
+        let timestamp = (frame.dts().saturating_sub(start) / 1000) as u32;
+        let codec = frame.codec();
+
+        match codec {
+            Fourcc::METADATA_AMF0 => {
+                let packet = Packet::from_frame(frame);
+                out.push(tag(TAG_SCRIPT, timestamp, &[], &packet.data));
+            }
+
+            Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC | Fourcc::VIDEO_AV1 | Fourcc::VIDEO_VP9 => {
+                self.push_video(frame, timestamp, &mut out)?
+            }
+
+            Fourcc::AUDIO_AAC
+            | Fourcc::AUDIO_MP3
+            | Fourcc::AUDIO_PCM_ALAW
+            | Fourcc::AUDIO_PCM_ULAW
+            | Fourcc::AUDIO_OPUS
+            | Fourcc::AUDIO_FLAC => self.push_audio(frame, timestamp, &mut out)?,
+
+            codec => return Err(flowly_codec::Error::UnsupportedCodec(codec).into()),
+        }
+
+        Ok(out)
+    }
+
+    fn push_video<F: EncodedFrame + Multichannel + VideoFrame>(
+        &mut self,
+        frame: F,
+        timestamp: u32,
+        out: &mut Vec<Bytes>,
+    ) -> Result<(), Error> {
+        let codec = frame.codec();
+        let packet = if matches!(
+            codec,
+            Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC | Fourcc::VIDEO_AV1
+        ) {
+            // moves in-band parameter sets of AnnexB streams to `params`,
+            // AV1 Annex B is converted to the low overhead format (Section 5)
+            self.converter.convert(frame)?
+        } else {
+            Packet::from_frame(frame)
+        };
+
+        let frame_type = if packet.is_keyframe() {
+            FRAME_TYPE_KEY
+        } else {
+            2
+        };
+
+        let cts = ((packet.pts - packet.dts as i64) / 1000) as i32;
+
+        if !packet.params.is_empty()
+            && self.video_params.as_ref() != Some(&(codec, packet.params.clone()))
+        {
+            let config = match codec {
+                Fourcc::VIDEO_AVC => AvcConfig::from_params(&packet.params)?.to_bytes(),
+                Fourcc::VIDEO_HEVC => HevcConfig::from_params(&packet.params)?.to_bytes(),
+                _ => packet.params[0].clone(),
+            };
+
+            let mut header = BytesMut::with_capacity(8);
+            put_video_header(
+                &mut header,
+                codec,
+                FRAME_TYPE_KEY,
+                VIDEO_PACKET_SEQUENCE_START,
+                0,
+            );
+
+            out.push(tag(TAG_VIDEO, timestamp, &header, &config));
+            self.video_params = Some((codec, packet.params.clone()));
+        }
+
+        let mut header = BytesMut::with_capacity(8);
+        put_video_header(
+            &mut header,
+            codec,
+            frame_type,
+            VIDEO_PACKET_CODED_FRAMES,
+            cts,
+        );
+
+        out.push(tag(TAG_VIDEO, timestamp, &header, &packet.data));
+
+        Ok(())
+    }
+
+    fn push_audio<F: EncodedFrame + Multichannel + VideoFrame>(
+        &mut self,
+        frame: F,
+        timestamp: u32,
+        out: &mut Vec<Bytes>,
+    ) -> Result<(), Error> {
+        let codec = frame.codec();
+        let packet = Packet::from_frame(frame);
+
+        if let Some(config) = packet.params.first()
+            && matches!(
+                codec,
+                Fourcc::AUDIO_AAC | Fourcc::AUDIO_OPUS | Fourcc::AUDIO_FLAC
+            )
+            && self.audio_params.as_ref() != Some(&(codec, packet.params.clone()))
+        {
+            let mut header = BytesMut::with_capacity(8);
+            put_audio_header(&mut header, codec, AUDIO_PACKET_SEQUENCE_START);
+
+            out.push(tag(TAG_AUDIO, timestamp, &header, config));
+            self.audio_params = Some((codec, packet.params.clone()));
+        }
+
+        let mut header = BytesMut::with_capacity(8);
+        put_audio_header(&mut header, codec, AUDIO_PACKET_CODED_FRAMES);
+
+        out.push(tag(TAG_AUDIO, timestamp, &header, &packet.data));
+
+        Ok(())
+    }
+}
+
+impl Default for FlvMuxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Video tag header, `packet_type` is the enhanced `VideoPacketType`
+fn put_video_header<B: BufMut>(
+    buf: &mut B,
+    codec: Fourcc,
+    frame_type: u8,
+    packet_type: u8,
+    cts: i32,
+) {
+    if codec == Fourcc::VIDEO_AVC {
+        buf.put_u8p2::<4, 4>(frame_type, VIDEO_CODEC_AVC);
+        buf.put_u8(if packet_type == VIDEO_PACKET_SEQUENCE_START {
+            AVC_SEQUENCE_HEADER
+        } else {
+            AVC_NALU
+        });
+        buf.put_i24(cts);
+    } else {
+        // the composition time is only defined for avc1/hvc1
+        let coded_with_cts =
+            packet_type == VIDEO_PACKET_CODED_FRAMES && codec == Fourcc::VIDEO_HEVC && cts != 0;
+        let packet_type = if packet_type == VIDEO_PACKET_CODED_FRAMES && !coded_with_cts {
+            VIDEO_PACKET_CODED_FRAMES_X
+        } else {
+            packet_type
+        };
+
+        buf.put_u8p3::<1, 3, 4>(1, frame_type, packet_type);
+        buf.put_slice(&<[u8; 4]>::from(codec));
+
+        if coded_with_cts {
+            buf.put_i24(cts);
+        }
+    }
+}
+
+/// Audio tag header, `packet_type` is the enhanced `AudioPacketType`
+fn put_audio_header<B: BufMut>(buf: &mut B, codec: Fourcc, packet_type: u8) {
+    match codec {
+        // 44 kHz, 16 bit, stereo - ignored by decoders for AAC
+        Fourcc::AUDIO_AAC => {
+            buf.put_u8p4::<4, 2, 1, 1>(SOUND_FORMAT_AAC, 3, 1, 1);
+            buf.put_u8(if packet_type == AUDIO_PACKET_SEQUENCE_START {
+                AAC_SEQUENCE_HEADER
+            } else {
+                AAC_RAW
+            });
+        }
+        Fourcc::AUDIO_MP3 => buf.put_u8p4::<4, 2, 1, 1>(SOUND_FORMAT_MP3, 3, 1, 1),
+
+        // G.711 is always 8 kHz mono
+        Fourcc::AUDIO_PCM_ALAW => buf.put_u8p4::<4, 2, 1, 1>(SOUND_FORMAT_ALAW, 0, 1, 0),
+        Fourcc::AUDIO_PCM_ULAW => buf.put_u8p4::<4, 2, 1, 1>(SOUND_FORMAT_ULAW, 0, 1, 0),
+        _ => {
+            buf.put_u8p2::<4, 4>(SOUND_FORMAT_EX_HEADER, packet_type);
+            buf.put_slice(&<[u8; 4]>::from(codec));
+        }
+    }
+}
+
+/// Complete tag with the trailing `PreviousTagSize`
+fn tag(kind: u8, timestamp: u32, header: &[u8], data: &[u8]) -> Bytes {
+    let data_size = header.len() + data.len();
+    let mut buf = BytesMut::with_capacity(TAG_HEADER_SIZE + data_size + 4);
+
+    TagHeader {
+        kind,
+        data_size: data_size as u32,
+        timestamp,
+        ..Default::default()
+    }
+    .write(&mut buf);
+
+    buf.put_slice(header);
+    buf.put_slice(data);
+    buf.put_u32((TAG_HEADER_SIZE + data_size) as u32);
+
+    buf.freeze()
+}
+
+impl<F: EncodedFrame + Multichannel + VideoFrame> Service<F> for FlvMuxer {
+    type Out = Result<Bytes, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let out: Vec<_> = match self.push(frame) {
+            Ok(chunks) => chunks.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        };
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use flowly_codec::av1;
+    use flowly_core::{EncodedFrame, Fourcc, FrameFlags, Packet, VideoFrame};
+
+    use crate::{
+        flv::{FlvDemuxer, FlvMuxer},
+        test_fixtures::{PPS, SPS},
+    };
+
+    #[test]
+    fn test_flv_mux_demux() {
+        let mut annexb = Vec::new();
+        for nal in [SPS, PPS, &[0x65, 0x88, 0x80]] {
+            annexb.extend_from_slice(&[0, 0, 0, 1]);
+            annexb.extend_from_slice(nal);
+        }
+
+        let frames = [
+            Packet::new(
+                (),
+                Fourcc::VIDEO_AVC,
+                FrameFlags::VIDEO_STREAM | FrameFlags::KEYFRAME | FrameFlags::ANNEXB,
+                Bytes::from(annexb),
+            )
+            .with_timestamps(1_000_000, 1_080_000),
+            Packet::new(
+                (),
+                Fourcc::AUDIO_AAC,
+                FrameFlags::AUDIO_STREAM,
+                Bytes::from_static(&[0x21, 0x00]),
+            )
+            .with_timestamps(1_020_000, 1_020_000)
+            .with_params(vec![Bytes::from_static(&[0x12, 0x10])]),
+            // Annex B with composition offset, neither goes into the FLV
+            Packet::new(
+                (),
+                Fourcc::VIDEO_AV1,
+                FrameFlags::VIDEO_STREAM | FrameFlags::KEYFRAME | FrameFlags::ANNEXB,
+                av1::section5_to_annexb(&[0x32, 0x00]).unwrap(),
+            )
+            .with_timestamps(1_040_000, 1_080_000)
+            .with_params(vec![Bytes::from_static(&[0x81, 0x08, 0x0C, 0x00])]),
+        ];
+
+        let mut muxer = FlvMuxer::new();
+        let mut demuxer = FlvDemuxer::<()>::new();
+
+        let packets: Vec<_> = frames
+            .into_iter()
+            .flat_map(|x| muxer.push(x).unwrap())
+            .flat_map(|x| demuxer.push(x))
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(packets.len(), 3);
+
+        let video = &packets[0];
+        assert_eq!(video.codec, Fourcc::VIDEO_AVC);
+        assert_eq!((video.dts, video.pts), (0, 80_000));
+        assert_eq!(video.params().collect::<Vec<_>>(), [SPS, PPS]);
+        assert_eq!(video.dimensions(), (1920, 1080));
+        assert_eq!(&video.data[..], &[0, 0, 0, 3, 0x65, 0x88, 0x80]);
+
+        let audio = &packets[1];
+        assert_eq!(audio.codec, Fourcc::AUDIO_AAC);
+        assert_eq!(audio.dts, 20_000);
+        assert_eq!(audio.params, [&[0x12, 0x10][..]]);
+        assert_eq!(&audio.data[..], &[0x21, 0x00]);
+
+        let av1 = &packets[2];
+        assert_eq!(av1.codec, Fourcc::VIDEO_AV1);
+        assert_eq!(av1.dts, 40_000);
+        assert_eq!(av1.params, [&[0x81, 0x08, 0x0C, 0x00][..]]);
+        assert_eq!(&av1.data[..], &[0x32, 0x00]);
+    }
+}
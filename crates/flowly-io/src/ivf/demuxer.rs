@@ -0,0 +1,195 @@
+use bytes::{Buf, Bytes};
+use flowly_codec::av1;
+use flowly_core::{Chunked, DataFrame, Fourcc, FrameFlags, FrameSource, Packet};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Header,
+    FrameHeader,
+    FrameData(FrameHeader),
+    Failed,
+}
+
+/// IVF demuxer.
+///
+/// Accepts arbitrary chunks of the IVF byte stream and yields a [`Packet`] per
+/// frame of the VP8, VP9 or AV1 elementary stream. Frame timestamps are converted
+/// from the file timebase to microseconds and used as both DTS and PTS, the frame
+/// dimensions come from the file header. IVF has no frame flags, so keyframes are
+/// detected from the frame headers.
+/// The state is reset when the frame source changes.
+#[derive(Debug, Clone)]
+pub struct IvfDemuxer<S = ()> {
+    source: S,
+    state: State,
+    buf: Chunked<Bytes>,
+    header: Option<Header>,
+    codec: Fourcc,
+}
+
+impl<S: FrameSource> IvfDemuxer<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            state: State::Header,
+            buf: Chunked::new(),
+            header: None,
+            codec: Fourcc::default(),
+        }
+    }
+
+    /// File header, available once received
+    #[inline]
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    /// Feeds the chunk of the stream and returns all completed packets
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        self.buf.put(chunk);
+
+        loop {
+            match self.state {
+                State::Header => {
+                    // the header size field goes after the signature and version
+                    let mut head = [0u8; 8];
+                    if self.buf.peek(0, &mut head) < head.len() {
+                        break;
+                    }
+
+                    let size = u16::from_le_bytes([head[6], head[7]]).max(HEADER_SIZE);
+                    if self.buf.remaining() < size as usize {
+                        break;
+                    }
+
+                    let header = Header::read(&mut self.buf).and_then(|header| {
+                        let codec = header
+                            .codec()
+                            .ok_or(Error::Unsupported("ivf: unsupported fourcc"))?;
+
+                        Ok((header, codec))
+                    });
+
+                    match header {
+                        Ok((header, codec)) => {
+                            self.header = Some(header);
+                            self.codec = codec;
+                            self.state = State::FrameHeader;
+                        }
+                        Err(err) => {
+                            out.push(Err(err));
+                            self.state = State::Failed;
+                        }
+                    }
+                }
+
+                State::FrameHeader => {
+                    if self.buf.remaining() < FRAME_HEADER_SIZE {
+                        break;
+                    }
+
+                    self.state = match FrameHeader::read(&mut self.buf) {
+                        Ok(header) => State::FrameData(header),
+                        Err(err) => {
+                            out.push(Err(err));
+                            State::Failed
+                        }
+                    };
+                }
+
+                State::FrameData(frame) => {
+                    if self.buf.remaining() < frame.size as usize {
+                        break;
+                    }
+
+                    let data = self.buf.copy_to_bytes(frame.size as usize);
+                    self.state = State::FrameHeader;
+
+                    let Some(header) = self.header.as_ref() else {
+                        continue;
+                    };
+
+                    let mut flags = FrameFlags::VIDEO_STREAM;
+                    if is_keyframe(self.codec, &data) {
+                        flags |= FrameFlags::KEYFRAME;
+                    }
+
+                    let timestamp = header.ticks_to_micros(frame.timestamp);
+                    let mut packet = Packet::new(self.source.clone(), self.codec, flags, data)
+                        .with_timestamps(timestamp, timestamp as i64);
+
+                    packet.dimensions = (header.width, header.height);
+                    out.push(Ok(packet));
+                }
+
+                State::Failed => {
+                    let len = self.buf.remaining();
+                    self.buf.advance(len);
+                    break;
+                }
+            }
+        }
+
+        out
+    }
+
+    fn reset(&mut self, source: S) {
+        *self = Self::new();
+        self.source = source;
+    }
+}
+
+impl<S: FrameSource> Default for IvfDemuxer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks the frame for the random access point
+fn is_keyframe(codec: Fourcc, data: &[u8]) -> bool {
+    match codec {
+        // inverse key frame flag of the frame tag
+        Fourcc::VIDEO_VP8 => data.first().is_some_and(|x| x & 1 == 0),
+
+        // frame_marker, profile, show_existing_frame and frame_type
+        Fourcc::VIDEO_VP9 => match data.first() {
+            Some(&byte) if byte >> 6 == 2 => {
+                let profile = (byte >> 5 & 1) | (byte >> 3 & 2);
+                let shift = if profile == 3 { 1 } else { 2 };
+
+                byte >> shift & 0x03 == 0
+            }
+            _ => false,
+        },
+
+        Fourcc::VIDEO_AV1 => av1::is_keyframe(data, None).unwrap_or(false),
+
+        _ => false,
+    }
+}
+
+impl<F> Service<F> for IvfDemuxer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        if frame.source() != &self.source {
+            self.reset(frame.source().clone());
+        }
+
+        let mut out = Vec::new();
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
@@ -0,0 +1,177 @@
+mod demuxer;
+mod muxer;
+
+pub use demuxer::IvfDemuxer;
+pub use muxer::IvfMuxer;
+
+use bytes::{Buf, BufMut, Bytes};
+use flowly_core::Fourcc;
+
+use crate::error::Error;
+
+pub const SIGNATURE: &[u8; 4] = b"DKIF";
+pub const HEADER_SIZE: u16 = 32;
+pub const FRAME_HEADER_SIZE: usize = 12;
+
+pub const FOURCC_VP8: [u8; 4] = *b"VP80";
+pub const FOURCC_VP9: [u8; 4] = *b"VP90";
+pub const FOURCC_AV1: [u8; 4] = *b"AV01";
+
+/// IVF file header
+///
+/// Frame timestamps are counted in `scale / rate` seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u16,
+    pub fourcc: [u8; 4],
+    pub width: u16,
+    pub height: u16,
+    pub rate: u32,
+    pub scale: u32,
+
+    /// Number of frames, zero if unknown
+    pub frame_count: u32,
+    pub reserved: u32,
+
+    /// Bytes after the 32 byte header when the header size field is larger
+    pub extra: Bytes,
+}
+
+impl Header {
+    /// Reads the header including the extra bytes
+    pub fn read<B: Buf>(buf: &mut B) -> Result<Self, Error> {
+        let mut signature = [0u8; 4];
+        buf.try_copy_to_slice(&mut signature)?;
+
+        if &signature != SIGNATURE {
+            return Err(Error::InvalidData("ivf: invalid signature"));
+        }
+
+        let version = buf.try_get_u16_le()?;
+        let header_size = buf.try_get_u16_le()?;
+
+        if header_size < HEADER_SIZE {
+            return Err(Error::InvalidData("ivf: invalid header size"));
+        }
+
+        let mut fourcc = [0u8; 4];
+        buf.try_copy_to_slice(&mut fourcc)?;
+
+        let mut header = Self {
+            version,
+            fourcc,
+            width: buf.try_get_u16_le()?,
+            height: buf.try_get_u16_le()?,
+            rate: buf.try_get_u32_le()?,
+            scale: buf.try_get_u32_le()?,
+            frame_count: buf.try_get_u32_le()?,
+            reserved: buf.try_get_u32_le()?,
+            extra: Bytes::new(),
+        };
+
+        if header.rate == 0 || header.scale == 0 {
+            return Err(Error::InvalidData("ivf: invalid timebase"));
+        }
+
+        let extra = (header_size - HEADER_SIZE) as usize;
+        if buf.remaining() < extra {
+            return Err(Error::InvalidData("ivf: truncated header"));
+        }
+
+        header.extra = buf.copy_to_bytes(extra);
+
+        Ok(header)
+    }
+
+    /// Header size including the extra bytes
+    #[inline]
+    pub fn size(&self) -> usize {
+        HEADER_SIZE as usize + self.extra.len()
+    }
+
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        buf.put_slice(SIGNATURE);
+        buf.put_u16_le(self.version);
+        buf.put_u16_le(self.size() as u16);
+        buf.put_slice(&self.fourcc);
+        buf.put_u16_le(self.width);
+        buf.put_u16_le(self.height);
+        buf.put_u32_le(self.rate);
+        buf.put_u32_le(self.scale);
+        buf.put_u32_le(self.frame_count);
+        buf.put_u32_le(self.reserved);
+        buf.put_slice(&self.extra);
+    }
+
+    /// Codec of the stream, `None` for unknown fourcc
+    pub fn codec(&self) -> Option<Fourcc> {
+        match &self.fourcc {
+            b"VP80" => Some(Fourcc::VIDEO_VP8),
+            b"VP90" => Some(Fourcc::VIDEO_VP9),
+            b"AV01" => Some(Fourcc::VIDEO_AV1),
+            _ => None,
+        }
+    }
+
+    /// Converts the frame timestamp to microseconds
+    pub fn ticks_to_micros(&self, ticks: u64) -> u64 {
+        let den = self.rate as u128;
+        ((ticks as u128 * self.scale as u128 * 1_000_000 + den / 2) / den) as u64
+    }
+
+    /// Converts microseconds to the frame timestamp, inverse of [`Header::ticks_to_micros`]
+    pub fn micros_to_ticks(&self, micros: u64) -> u64 {
+        let den = self.scale as u128 * 1_000_000;
+        ((micros as u128 * self.rate as u128 + den / 2) / den) as u64
+    }
+}
+
+impl Default for Header {
+    /// Millisecond timebase without the codec
+    fn default() -> Self {
+        Self {
+            version: 0,
+            fourcc: [0; 4],
+            width: 0,
+            height: 0,
+            rate: 1000,
+            scale: 1,
+            frame_count: 0,
+            reserved: 0,
+            extra: Bytes::new(),
+        }
+    }
+}
+
+/// IVF fourcc of the codec
+pub fn fourcc(codec: Fourcc) -> Option<[u8; 4]> {
+    match codec {
+        Fourcc::VIDEO_VP8 => Some(FOURCC_VP8),
+        Fourcc::VIDEO_VP9 => Some(FOURCC_VP9),
+        Fourcc::VIDEO_AV1 => Some(FOURCC_AV1),
+        _ => None,
+    }
+}
+
+/// IVF frame header
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub size: u32,
+
+    /// Timestamp in the units of the file timebase
+    pub timestamp: u64,
+}
+
+impl FrameHeader {
+    pub fn read<B: Buf>(buf: &mut B) -> Result<Self, Error> {
+        Ok(Self {
+            size: buf.try_get_u32_le()?,
+            timestamp: buf.try_get_u64_le()?,
+        })
+    }
+
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        buf.put_u32_le(self.size);
+        buf.put_u64_le(self.timestamp);
+    }
+}
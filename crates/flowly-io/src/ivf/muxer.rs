@@ -0,0 +1,224 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly_core::{EncodedFrame, Multichannel, Packet, VideoFrame};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+/// IVF muxer.
+///
+/// Accepts VP8, VP9 or AV1 frames and yields IVF byte chunks: the file header
+/// first, then one chunk per frame. The header is taken from [`IvfMuxer::with_header`]
+/// when given (e.g. copied from [`IvfDemuxer::header`](super::IvfDemuxer::header),
+/// which makes the demux/mux round trip byte exact), otherwise it is built from the
+/// first frame with the millisecond timebase and an unknown frame count.
+/// `Frame::timestamp()` is converted to the file timebase as is, without shifting.
+#[derive(Debug, Clone)]
+pub struct IvfMuxer {
+    header: Option<Header>,
+    timebase: (u32, u32),
+    header_written: bool,
+}
+
+impl IvfMuxer {
+    pub fn new() -> Self {
+        Self {
+            header: None,
+            timebase: (1, 1000),
+            header_written: false,
+        }
+    }
+
+    /// Uses the header as is instead of building it from the first frame
+    pub fn with_header(mut self, header: Header) -> Self {
+        self.header = Some(header);
+        self
+    }
+
+    /// Sets the timebase of the built header, `num / den` seconds per tick
+    pub fn with_timebase(mut self, num: u32, den: u32) -> Self {
+        self.timebase = (num.max(1), den.max(1));
+        self
+    }
+
+    /// Encodes the frame (prefixed with the file header on the first call)
+    pub fn push<F: EncodedFrame + Multichannel + VideoFrame>(
+        &mut self,
+        frame: F,
+    ) -> Result<Vec<Bytes>, Error> {
+        let mut out = Vec::with_capacity(2);
+        let codec = frame.codec();
+
+        let fourcc = fourcc(codec).ok_or(flowly_codec::Error::UnsupportedCodec(codec))?;
+
+        let (width, height) = frame.dimensions();
+        let header = self.header.get_or_insert_with(|| Header {
+            fourcc,
+            width,
+            height,
+            rate: self.timebase.1,
+            scale: self.timebase.0,
+            ..Default::default()
+        });
+
+        if header.codec() != Some(codec) {
+            return Err(Error::InvalidData("ivf: codec does not match the header"));
+        }
+
+        if !self.header_written {
+            let mut buf = BytesMut::with_capacity(header.size());
+            header.write(&mut buf);
+            out.push(buf.freeze());
+            self.header_written = true;
+        }
+
+        let timestamp = header.micros_to_ticks(frame.timestamp());
+        let packet = Packet::from_frame(frame);
+
+        let mut buf = BytesMut::with_capacity(FRAME_HEADER_SIZE + packet.data.len());
+        FrameHeader {
+            size: packet.data.len() as u32,
+            timestamp,
+        }
+        .write(&mut buf);
+        buf.put_slice(&packet.data);
+        out.push(buf.freeze());
+
+        Ok(out)
+    }
+}
+
+impl Default for IvfMuxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: EncodedFrame + Multichannel + VideoFrame> Service<F> for IvfMuxer {
+    type Out = Result<Bytes, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let out: Vec<_> = match self.push(frame) {
+            Ok(chunks) => chunks.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        };
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use flowly_core::{EncodedFrame, Fourcc, Frame, VideoFrame};
+
+    use super::IvfMuxer;
+    use crate::ivf::{FrameHeader, Header, IvfDemuxer};
+
+    #[test]
+    fn test_ivf_roundtrip() {
+        let header = Header {
+            fourcc: *b"VP80",
+            width: 176,
+            height: 144,
+            rate: 30,
+            scale: 1,
+            frame_count: 3,
+
+            // extended header is kept as is
+            extra: Bytes::from_static(&[1, 2, 3, 4]),
+            ..Default::default()
+        };
+
+        let frames: [&[u8]; 3] = [
+            &[
+                0x50, 0x42, 0x00, 0x9D, 0x01, 0x2A, 0xB0, 0x00, 0x90, 0x00, 0x11,
+            ],
+            &[0x31, 0x02, 0x00, 0x22],
+            &[0x51, 0x02, 0x00, 0x33, 0x44],
+        ];
+
+        let mut file = BytesMut::new();
+        header.write(&mut file);
+        for (i, data) in frames.iter().enumerate() {
+            FrameHeader {
+                size: data.len() as u32,
+                timestamp: i as u64,
+            }
+            .write(&mut file);
+            file.put_slice(data);
+        }
+        let file = file.freeze();
+
+        // split at odd offsets to exercise partial headers
+        let mut demuxer = IvfDemuxer::<()>::new();
+        let mut packets = Vec::new();
+        for chunk in [file.slice(..7), file.slice(7..40), file.slice(40..)] {
+            packets.extend(demuxer.push(chunk));
+        }
+
+        let packets: Vec<_> = packets.into_iter().collect::<Result<_, _>>().unwrap();
+        let summary: Vec<_> = packets
+            .iter()
+            .map(|x| {
+                (
+                    x.codec(),
+                    x.timestamp(),
+                    x.pts(),
+                    x.is_keyframe(),
+                    x.dimensions(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (Fourcc::VIDEO_VP8, 0, 0, true, (176, 144)),
+                (Fourcc::VIDEO_VP8, 33_333, 33_333, false, (176, 144)),
+                (Fourcc::VIDEO_VP8, 66_667, 66_667, false, (176, 144)),
+            ]
+        );
+
+        let mut muxer = IvfMuxer::new().with_header(demuxer.header().unwrap().clone());
+        let mut out = BytesMut::new();
+        for packet in packets {
+            for chunk in muxer.push(packet).unwrap() {
+                out.put_slice(&chunk);
+            }
+        }
+
+        assert_eq!(&file[6..8], &[36, 0]);
+        assert_eq!(out.freeze(), file);
+    }
+
+    #[test]
+    fn test_ivf_header_malformed() {
+        let mut valid = BytesMut::new();
+        Header {
+            fourcc: *b"VP80",
+            rate: 30,
+            scale: 1,
+            ..Default::default()
+        }
+        .write(&mut valid);
+
+        let mut signature = valid.clone();
+        signature[0] = b'X';
+
+        // header size below 32
+        let mut size = valid.clone();
+        size[6] = 16;
+
+        let mut timebase = valid.clone();
+        timebase[16..20].fill(0);
+
+        // header size covers extra bytes which are missing
+        let mut extra = valid.clone();
+        extra[6] = 40;
+
+        for file in [signature, size, timebase, extra, valid.split_to(20)] {
+            assert!(Header::read(&mut file.freeze()).is_err());
+        }
+    }
+}
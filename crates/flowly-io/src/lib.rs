@@ -1,4 +1,23 @@
+pub mod adts;
+pub mod amf0;
 pub mod error;
 pub mod file;
+pub mod flac;
+pub mod flv;
 pub mod http;
+pub mod ivf;
 pub mod locator;
+pub mod mjpeg;
+pub mod mkv;
+pub mod mp3;
+pub mod mp4;
+pub mod ogg;
+pub mod rtmp;
+pub mod rtp;
+pub mod rtsp;
+pub mod ts;
+pub mod wav;
+pub mod y4m;
+
+#[cfg(test)]
+mod test_fixtures;
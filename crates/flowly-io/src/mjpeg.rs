@@ -0,0 +1,367 @@
+use std::time::Instant;
+
+use bytes::{Buf, Bytes, BytesMut};
+use flowly_codec::jpeg::FrameHeader;
+use flowly_core::{DataFrame, Fourcc, FrameFlags, FrameSource, Packet};
+use flowly_service::{Context, Service};
+
+use crate::error::Error;
+
+/// Upper limit of the part headers size
+const MAX_HEADERS_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Delimiter,
+    Headers,
+    Body(Option<usize>),
+}
+
+/// `multipart/x-mixed-replace` MJPEG stream splitter.
+///
+/// Accepts arbitrary chunks of the HTTP response body and yields a [`Packet`]
+/// per JPEG image. The boundary is taken from [`MjpegDemuxer::with_boundary`]
+/// or detected from the first delimiter line. Parts are cut by `Content-Length`
+/// if present, otherwise by the next delimiter, so call [`MjpegDemuxer::flush`]
+/// at the end of stream. Dimensions and bit depth come from the JPEG frame
+/// header, images that can't be parsed are reported and dropped. Every frame
+/// is a keyframe. Timestamps count frames at [`MjpegDemuxer::with_frame_rate`],
+/// otherwise the wall clock time since the first frame is used and the stream
+/// is tagged as live.
+/// The state is reset when the frame source changes.
+#[derive(Debug, Clone)]
+pub struct MjpegDemuxer<S = ()> {
+    source: S,
+    state: State,
+    buf: BytesMut,
+
+    /// Full delimiter, `--` followed by the boundary
+    delimiter: Option<Bytes>,
+    frame_rate: Option<(u32, u32)>,
+    frames: u64,
+    start: Option<Instant>,
+}
+
+impl<S: FrameSource> MjpegDemuxer<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            state: State::Delimiter,
+            buf: BytesMut::new(),
+            delimiter: None,
+            frame_rate: None,
+            frames: 0,
+            start: None,
+        }
+    }
+
+    /// Sets the boundary from the `Content-Type` header instead of detecting it
+    pub fn with_boundary(mut self, boundary: &str) -> Self {
+        let boundary = boundary.trim().trim_start_matches("--");
+        self.delimiter = Some(Bytes::from(format!("--{boundary}")));
+        self
+    }
+
+    /// Derives timestamps from the frame counter instead of the wall clock
+    pub fn with_frame_rate(mut self, num: u32, den: u32) -> Self {
+        self.frame_rate = (num > 0 && den > 0).then_some((num, den));
+        self
+    }
+
+    /// Feeds the chunk of the stream and returns all completed packets
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<Packet<S>, Error>> {
+        self.buf.extend_from_slice(&chunk);
+        self.parse(false)
+    }
+
+    /// Returns the last part if it isn't terminated by a delimiter
+    pub fn flush(&mut self) -> Vec<Result<Packet<S>, Error>> {
+        self.parse(true)
+    }
+
+    fn parse(&mut self, eos: bool) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        loop {
+            match self.state {
+                State::Delimiter => {
+                    let Some(delimiter) = self.delimiter.clone() else {
+                        if !self.detect_boundary() {
+                            break;
+                        }
+
+                        continue;
+                    };
+
+                    let Some(pos) = find_delimiter(&self.buf, &delimiter) else {
+                        // the delimiter may be split between the chunks
+                        let keep = (delimiter.len() + 1).min(self.buf.len());
+                        self.buf.advance(self.buf.len() - keep);
+                        break;
+                    };
+
+                    let start = pos + delimiter.len();
+                    let Some(eol) = find_byte(&self.buf[start..], b'\n') else {
+                        self.buf.advance(pos);
+                        break;
+                    };
+
+                    let close = self.buf[start..].starts_with(b"--");
+                    self.buf.advance(start + eol + 1);
+
+                    if !close {
+                        self.state = State::Headers;
+                    }
+                }
+
+                State::Headers => {
+                    let Some((size, content_length)) = parse_headers(&self.buf) else {
+                        if self.buf.len() > MAX_HEADERS_SIZE {
+                            out.push(Err(Error::InvalidData("mjpeg: part headers too long")));
+                            self.buf.clear();
+                            self.state = State::Delimiter;
+                        }
+
+                        break;
+                    };
+
+                    self.buf.advance(size);
+                    self.state = State::Body(content_length);
+                }
+
+                State::Body(Some(len)) => {
+                    if self.buf.len() < len {
+                        break;
+                    }
+
+                    let data = self.buf.split_to(len).freeze();
+                    self.state = State::Delimiter;
+                    out.push(self.frame(data));
+                }
+
+                State::Body(None) => {
+                    let delimiter = self.delimiter.clone().unwrap_or_default();
+
+                    let len = match find_delimiter(&self.buf, &delimiter) {
+                        Some(pos) => pos,
+                        None if eos && !self.buf.is_empty() => self.buf.len(),
+                        None => break,
+                    };
+
+                    let mut data = self.buf.split_to(len);
+                    if data.ends_with(b"\r\n") {
+                        data.truncate(data.len() - 2);
+                    } else if data.ends_with(b"\n") {
+                        data.truncate(data.len() - 1);
+                    }
+
+                    self.state = State::Delimiter;
+                    out.push(self.frame(data.freeze()));
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Takes the boundary from the first line starting with `--`
+    fn detect_boundary(&mut self) -> bool {
+        let mut pos = 0;
+
+        while let Some(eol) = find_byte(&self.buf[pos..], b'\n') {
+            let line = self.buf[pos..pos + eol].trim_ascii();
+
+            if line.len() > 2 && line.starts_with(b"--") {
+                self.delimiter = Some(Bytes::copy_from_slice(line));
+                self.buf.advance(pos);
+                return true;
+            }
+
+            pos += eol + 1;
+        }
+
+        // preamble without a delimiter is dropped
+        self.buf.advance(pos);
+        false
+    }
+
+    fn frame(&mut self, data: Bytes) -> Result<Packet<S>, Error> {
+        let header = FrameHeader::parse(&data)?;
+
+        let mut flags = FrameFlags::VIDEO_STREAM | FrameFlags::KEYFRAME;
+        let timestamp = match self.frame_rate {
+            Some((num, den)) => self.frames * den as u64 * 1_000_000 / num as u64,
+            None => {
+                flags |= FrameFlags::LIVE;
+                let start = *self.start.get_or_insert_with(Instant::now);
+                start.elapsed().as_micros() as u64
+            }
+        };
+
+        self.frames += 1;
+
+        let mut packet = Packet::new(self.source.clone(), Fourcc::VIDEO_MJPEG, flags, data)
+            .with_timestamps(timestamp, timestamp as i64);
+
+        packet.dimensions = (header.width, header.height);
+        packet.bit_depth = header.precision;
+
+        Ok(packet)
+    }
+
+    fn reset(&mut self, source: S) {
+        let delimiter = self.delimiter.take();
+        let frame_rate = self.frame_rate;
+
+        *self = Self::new();
+        self.source = source;
+        self.delimiter = delimiter;
+        self.frame_rate = frame_rate;
+    }
+}
+
+impl<S: FrameSource> Default for MjpegDemuxer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Extracts the boundary parameter of the `multipart/x-mixed-replace` content type
+pub fn content_type_boundary(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// Position of the delimiter placed at the beginning of a line
+fn find_delimiter(buf: &[u8], delimiter: &[u8]) -> Option<usize> {
+    if delimiter.is_empty() {
+        return None;
+    }
+
+    buf.windows(delimiter.len())
+        .enumerate()
+        .find(|&(pos, window)| window == delimiter && (pos == 0 || buf[pos - 1] == b'\n'))
+        .map(|(pos, _)| pos)
+}
+
+/// Returns the size of the part headers including the empty line and the
+/// `Content-Length` value, `None` if the headers are incomplete
+fn parse_headers(buf: &[u8]) -> Option<(usize, Option<usize>)> {
+    let mut pos = 0;
+    let mut content_length = None;
+
+    loop {
+        let eol = find_byte(&buf[pos..], b'\n')?;
+        let line = buf[pos..pos + eol].trim_ascii();
+        pos += eol + 1;
+
+        if line.is_empty() {
+            return Some((pos, content_length));
+        }
+
+        let line = String::from_utf8_lossy(line);
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-length")
+        {
+            content_length = value.trim().parse().ok();
+        }
+    }
+}
+
+#[inline]
+fn find_byte(buf: &[u8], byte: u8) -> Option<usize> {
+    buf.iter().position(|&x| x == byte)
+}
+
+impl<F> Service<F> for MjpegDemuxer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let mut out = Vec::new();
+
+        if frame.source() != &self.source {
+            out.extend(self.flush());
+            self.reset(frame.source().clone());
+        }
+
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use flowly_core::{Fourcc, Frame, VideoFrame};
+
+    use super::{MjpegDemuxer, content_type_boundary};
+
+    fn jpeg(width: u16, height: u16, fill: u8) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x0B, 0x08];
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&[1, 1, 0x11, 0]);
+        data.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, fill, fill, 0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn test_mjpeg_split() {
+        assert_eq!(
+            content_type_boundary("multipart/x-mixed-replace; boundary=\"frame\""),
+            Some("frame")
+        );
+
+        let mut stream = BytesMut::new();
+        stream.put_slice(b"preamble\r\n--frame\r\nContent-Type: image/jpeg\r\n");
+        stream.put_slice(b"Content-Length: 23\r\n\r\n");
+        stream.put_slice(&jpeg(640, 480, 1));
+        stream.put_slice(b"\r\n--frame\r\nContent-Type: image/jpeg\r\n\r\n");
+        stream.put_slice(&jpeg(320, 240, 2));
+        stream.put_slice(b"\r\n--frame\r\n\r\nnot a jpeg\r\n--frame\r\n\r\n");
+        stream.put_slice(&jpeg(160, 120, 3));
+        let stream = stream.freeze();
+
+        let mut demuxer = MjpegDemuxer::<()>::new().with_frame_rate(10, 1);
+        let mut out = Vec::new();
+        for chunk in stream.chunks(7) {
+            out.extend(demuxer.push(Bytes::copy_from_slice(chunk)));
+        }
+
+        assert_eq!(out.len(), 3);
+        out.extend(demuxer.flush());
+
+        assert!(out[2].is_err());
+        let packets: Vec<_> = out.into_iter().filter_map(Result::ok).collect();
+
+        let summary: Vec<_> = packets
+            .iter()
+            .map(|x| (x.timestamp(), x.dimensions(), x.data[19]))
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (0, (640, 480), 1),
+                (100_000, (320, 240), 2),
+                (200_000, (160, 120), 3)
+            ]
+        );
+
+        assert!(packets.iter().all(|x| x.data.len() == 23));
+        assert!(packets.iter().all(|x| x.is_keyframe() && !x.is_live()));
+        assert!(packets.iter().all(|x| x.bit_depth() == 8));
+        assert!(packets.iter().all(|x| x.codec() == Fourcc::VIDEO_MJPEG));
+    }
+}
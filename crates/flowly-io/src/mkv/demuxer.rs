@@ -0,0 +1,588 @@
+use bytes::{Buf, Bytes};
+use flowly_codec::{VideoInfo, h264::AvcConfig, h265::HevcConfig};
+use flowly_core::{Chunked, DataFrame, Fourcc, FrameFlags, FrameSource, Packet, Reader};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+/// Largest element header: 4 bytes of ID and 8 bytes of size
+const MAX_HEADER_SIZE: usize = 12;
+
+/// Limit of the elements read into memory (blocks, tracks, info)
+pub const DEFAULT_MAX_ELEMENT_SIZE: usize = 64 * 1024 * 1024;
+
+/// Supported track and its decoding parameters
+#[derive(Debug, Clone)]
+struct Track {
+    entry: TrackEntry,
+    codec: Fourcc,
+    params: Vec<Bytes>,
+    dimensions: (u16, u16),
+    bit_depth: u8,
+}
+
+impl Track {
+    fn new(entry: TrackEntry, codec: Fourcc) -> Result<Self, Error> {
+        let private = entry.codec_private.clone().filter(|x| !x.is_empty());
+
+        let params: Vec<Bytes> = match (codec, private) {
+            (Fourcc::VIDEO_AVC, Some(config)) => {
+                AvcConfig::parse(&config)?.params().cloned().collect()
+            }
+            (Fourcc::VIDEO_HEVC, Some(config)) => {
+                HevcConfig::parse(&config)?.params().cloned().collect()
+            }
+
+            // `OpusHead`, `AudioSpecificConfig`, `av1C` etc. are passed as is
+            (_, Some(config)) => vec![config],
+            (_, None) => Vec::new(),
+        };
+
+        let info = VideoInfo::from_params(codec, &params).ok();
+        let dimensions = match info.as_ref() {
+            Some(info) if entry.width == 0 => info.dimensions(),
+            _ => (entry.width, entry.height),
+        };
+
+        Ok(Self {
+            codec,
+            params,
+            dimensions,
+            bit_depth: info.map(|x| x.bit_depth).unwrap_or(8),
+            entry,
+        })
+    }
+
+    #[inline]
+    fn is_video(&self) -> bool {
+        self.entry.kind == TRACK_TYPE_VIDEO
+    }
+}
+
+/// Matroska/WebM demuxer.
+///
+/// Accepts arbitrary chunks of the Matroska byte stream and yields a [`Packet`]
+/// per frame of SimpleBlock/BlockGroup (laced blocks are split into frames with
+/// `DefaultDuration` spacing). Segments and clusters may have unknown size, so
+/// live WebM without Cues works as well. Tracks are indexed in the order of the
+/// `Tracks` element, tracks with unsupported codecs or content encodings are
+/// skipped. H.264/H.265 payloads are kept length prefixed.
+///
+/// Elements read into memory are limited to [`MkvDemuxer::with_max_element_size`]
+/// bytes, a larger one or a timestamp out of range fails the demuxing.
+/// Matroska stores presentation timestamps only, so DTS equals PTS.
+/// The state is reset when the frame source changes.
+#[derive(Debug, Clone)]
+pub struct MkvDemuxer<S = ()> {
+    source: S,
+    buf: Chunked<Bytes>,
+    skip: u64,
+    timecode_scale: u64,
+    cluster_timecode: u64,
+    tracks: Vec<Track>,
+    max_element_size: usize,
+    failed: bool,
+}
+
+impl<S: FrameSource> MkvDemuxer<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            buf: Chunked::new(),
+            skip: 0,
+            timecode_scale: DEFAULT_TIMECODE_SCALE,
+            cluster_timecode: 0,
+            tracks: Vec::new(),
+            max_element_size: DEFAULT_MAX_ELEMENT_SIZE,
+            failed: false,
+        }
+    }
+
+    /// Limit of the elements read into memory, e.g. the size of a block
+    pub fn with_max_element_size(mut self, size: usize) -> Self {
+        self.max_element_size = size;
+        self
+    }
+
+    /// Supported tracks, indexed by `Multichannel::track`
+    pub fn tracks(&self) -> impl Iterator<Item = &TrackEntry> {
+        self.tracks.iter().map(|x| &x.entry)
+    }
+
+    /// Feeds the chunk of the stream and returns all completed packets
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        if self.failed {
+            return out;
+        }
+
+        self.buf.put(chunk);
+
+        loop {
+            if self.skip > 0 {
+                let len = self.skip.min(self.buf.remaining() as u64);
+                self.buf.advance(len as usize);
+                self.skip -= len;
+
+                if self.skip > 0 {
+                    break;
+                }
+            }
+
+            match self.next_element(&mut out) {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(err) => {
+                    out.push(Err(err));
+                    self.failed = true;
+                    self.buf = Chunked::new();
+                    break;
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Handles the element at the beginning of the buffer, false if more data needed
+    fn next_element(&mut self, out: &mut Vec<Result<Packet<S>, Error>>) -> Result<bool, Error> {
+        let mut head = [0u8; MAX_HEADER_SIZE];
+        let len = self.buf.peek(0, &mut head);
+
+        let header = match ElementHeader::peek(&head[..len]) {
+            Some(header) => header?,
+            None => return Ok(false),
+        };
+
+        match header.id {
+            // master elements which may have unknown size are entered in place,
+            // their children are handled as the top-level ones
+            ID_SEGMENT | ID_CLUSTER => {
+                self.buf.advance(header.header_size as usize);
+
+                if header.id == ID_CLUSTER {
+                    self.cluster_timecode = 0;
+                }
+            }
+
+            ID_EBML | ID_INFO | ID_TRACKS | ID_TIMECODE | ID_SIMPLE_BLOCK | ID_BLOCK_GROUP => {
+                let Some(size) = header.size else {
+                    return Err(Error::InvalidData("mkv: unknown-size element"));
+                };
+
+                if size > self.max_element_size as u64 {
+                    return Err(Error::InvalidData("mkv: element exceeds the size limit"));
+                }
+
+                if self.buf.remaining() < header.header_size as usize + size as usize {
+                    return Ok(false);
+                }
+
+                self.buf.advance(header.header_size as usize);
+                let data = self.buf.copy_to_bytes(size as usize);
+
+                if let Err(err) = self.parse_element(header.id, data, out) {
+                    if header.id == ID_EBML {
+                        return Err(err);
+                    }
+
+                    out.push(Err(err));
+                }
+            }
+
+            _ => {
+                let Some(size) = header.size else {
+                    return Err(Error::Unsupported("mkv: unknown-size element"));
+                };
+
+                self.skip = header.header_size as u64 + size;
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn parse_element(
+        &mut self,
+        id: u32,
+        data: Bytes,
+        out: &mut Vec<Result<Packet<S>, Error>>,
+    ) -> Result<(), Error> {
+        match id {
+            ID_EBML => {
+                for child in Elements::new(data) {
+                    let (id, payload) = child?;
+
+                    if id == ID_DOC_TYPE
+                        && !matches!(read_string(&payload).as_str(), "webm" | "matroska")
+                    {
+                        return Err(Error::Unsupported("mkv: unknown DocType"));
+                    }
+                }
+            }
+
+            ID_INFO => {
+                for child in Elements::new(data) {
+                    let (id, payload) = child?;
+
+                    if id == ID_TIMECODE_SCALE {
+                        self.timecode_scale = read_uint(&payload).max(1);
+                    }
+                }
+            }
+
+            ID_TRACKS => {
+                self.tracks.clear();
+
+                for child in Elements::new(data) {
+                    let (id, payload) = child?;
+                    if id != ID_TRACK_ENTRY {
+                        continue;
+                    }
+
+                    let entry = TrackEntry::parse(payload)?;
+                    if entry.encoded || !matches!(entry.kind, TRACK_TYPE_VIDEO | TRACK_TYPE_AUDIO) {
+                        continue;
+                    }
+
+                    if let Some(codec) = entry.codec() {
+                        self.tracks.push(Track::new(entry, codec)?);
+                    }
+                }
+            }
+
+            ID_TIMECODE => self.cluster_timecode = read_uint(&data),
+
+            ID_SIMPLE_BLOCK => self.parse_block(data, None, out)?,
+
+            ID_BLOCK_GROUP => {
+                let mut block = None;
+                let mut referenced = false;
+
+                for child in Elements::new(data) {
+                    let (id, payload) = child?;
+
+                    match id {
+                        ID_BLOCK => block = Some(payload),
+                        ID_REFERENCE_BLOCK => referenced = true,
+                        _ => (),
+                    }
+                }
+
+                if let Some(block) = block {
+                    self.parse_block(block, Some(!referenced), out)?;
+                }
+            }
+
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Parses Block/SimpleBlock, `keyframe` is derived from flags for SimpleBlock
+    fn parse_block(
+        &mut self,
+        mut data: Bytes,
+        keyframe: Option<bool>,
+        out: &mut Vec<Result<Packet<S>, Error>>,
+    ) -> Result<(), Error> {
+        let (number, _) = read_vint(&mut data)?;
+        let timecode = data.read_i16()? as i64;
+        let flags = data.read_u8()?;
+
+        let Some(index) = self.tracks.iter().position(|x| x.entry.number == number) else {
+            return Ok(());
+        };
+
+        let track = &self.tracks[index];
+        let keyframe = keyframe.unwrap_or(flags & BLOCK_FLAG_KEYFRAME != 0) || !track.is_video();
+
+        let mut frame_flags = if track.is_video() {
+            FrameFlags::VIDEO_STREAM
+        } else {
+            FrameFlags::AUDIO_STREAM
+        };
+
+        if keyframe {
+            frame_flags |= FrameFlags::KEYFRAME;
+        }
+
+        if self.tracks.len() > 1 {
+            frame_flags |= FrameFlags::MULTICHANNEL;
+        }
+
+        // nanoseconds, wide enough for any timecode and scale
+        let timestamp =
+            (self.cluster_timecode as i128 + timecode as i128) * self.timecode_scale as i128;
+        let duration = track.entry.default_duration.unwrap_or(0) as i128;
+
+        for (i, frame) in split_lacing((flags >> 1) & 0x03, data)?
+            .into_iter()
+            .enumerate()
+        {
+            let pts = i64::try_from((timestamp + i as i128 * duration) / 1000)
+                .map_err(|_| Error::InvalidData("mkv: timestamp out of range"))?;
+
+            let mut packet = Packet::new(self.source.clone(), track.codec, frame_flags, frame)
+                .with_timestamps(pts.max(0) as u64, pts)
+                .with_track(index as u32);
+
+            if keyframe && !track.params.is_empty() {
+                packet = packet.with_params(track.params.clone());
+            }
+
+            if track.is_video() {
+                packet.dimensions = track.dimensions;
+                packet.bit_depth = track.bit_depth;
+            }
+
+            out.push(Ok(packet));
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self, source: S) {
+        *self = Self::new().with_max_element_size(self.max_element_size);
+        self.source = source;
+    }
+}
+
+impl<S: FrameSource> Default for MkvDemuxer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F> Service<F> for MkvDemuxer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        if frame.source() != &self.source {
+            self.reset(frame.source().clone());
+        }
+
+        let mut out = Vec::new();
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes};
+    use flowly_core::{EncodedFrame, Fourcc, Frame, Multichannel, VideoFrame};
+
+    use super::MkvDemuxer;
+    use crate::mkv::*;
+
+    const OPUS_HEAD: &[u8] = &[
+        b'O', b'p', b'u', b's', b'H', b'e', b'a', b'd', 1, 2, 0x38, 0x01, 0x80, 0xBB, 0, 0, 0, 0, 0,
+    ];
+
+    fn element(id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data: Vec<u8> = id
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&x| x == 0)
+            .collect();
+        data.put_u8(0x01);
+        data.put_uint(payload.len() as u64, 7);
+        data.put_slice(payload);
+        data
+    }
+
+    fn live(id: u32) -> Vec<u8> {
+        let mut data: Vec<u8> = id
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&x| x == 0)
+            .collect();
+        data.extend_from_slice(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        data
+    }
+
+    fn uint(id: u32, value: u64) -> Vec<u8> {
+        element(id, &value.to_be_bytes())
+    }
+
+    fn block(track: u8, timecode: i16, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x80 | track];
+        data.put_i16(timecode);
+        data.put_u8(flags);
+        data.put_slice(payload);
+        data
+    }
+
+    #[test]
+    fn test_mkv_demux() {
+        let mut mkv = element(ID_EBML, &element(ID_DOC_TYPE, b"webm"));
+        mkv.extend(live(ID_SEGMENT));
+        mkv.extend(element(ID_INFO, &uint(ID_TIMECODE_SCALE, 1_000_000)));
+
+        let video = [
+            uint(ID_TRACK_NUMBER, 1),
+            uint(ID_TRACK_TYPE, TRACK_TYPE_VIDEO as u64),
+            element(ID_CODEC_ID, b"V_VP9"),
+            element(
+                ID_VIDEO,
+                &[uint(ID_PIXEL_WIDTH, 640), uint(ID_PIXEL_HEIGHT, 360)].concat(),
+            ),
+        ]
+        .concat();
+
+        let audio = [
+            uint(ID_TRACK_NUMBER, 2),
+            uint(ID_TRACK_TYPE, TRACK_TYPE_AUDIO as u64),
+            element(ID_CODEC_ID, b"A_OPUS"),
+            element(ID_CODEC_PRIVATE, OPUS_HEAD),
+            uint(ID_DEFAULT_DURATION, 20_000_000),
+            element(
+                ID_AUDIO,
+                &[
+                    element(ID_SAMPLING_FREQUENCY, &48_000f64.to_be_bytes()),
+                    uint(ID_CHANNELS, 2),
+                ]
+                .concat(),
+            ),
+        ]
+        .concat();
+
+        let vorbis = [
+            uint(ID_TRACK_NUMBER, 3),
+            uint(ID_TRACK_TYPE, TRACK_TYPE_AUDIO as u64),
+            element(ID_CODEC_ID, b"A_VORBIS"),
+        ]
+        .concat();
+
+        mkv.extend(element(
+            ID_TRACKS,
+            &[
+                element(ID_TRACK_ENTRY, &video),
+                element(ID_TRACK_ENTRY, &audio),
+                element(ID_TRACK_ENTRY, &vorbis),
+            ]
+            .concat(),
+        ));
+
+        mkv.extend(live(ID_CLUSTER));
+        mkv.extend(uint(ID_TIMECODE, 1000));
+        mkv.extend(element(ID_SIMPLE_BLOCK, &block(1, 0, 0x80, &[1; 50])));
+
+        // Xiph lacing of two frames
+        let mut laced = vec![1, 10];
+        laced.extend_from_slice(&[5; 10]);
+        laced.extend_from_slice(&[6; 12]);
+        mkv.extend(element(ID_SIMPLE_BLOCK, &block(2, 0, 0x82, &laced)));
+
+        mkv.extend(element(
+            ID_BLOCK_GROUP,
+            &[
+                element(ID_BLOCK, &block(1, 40, 0, &[2; 30])),
+                element(ID_REFERENCE_BLOCK, &[0xD8]),
+            ]
+            .concat(),
+        ));
+        mkv.extend(element(ID_SIMPLE_BLOCK, &block(3, 0, 0x80, &[9; 8])));
+        mkv.extend(element(ID_CUES, &[0; 20]));
+
+        // EBML lacing of three frames: 4, 6 (+2) and the rest
+        mkv.extend(live(ID_CLUSTER));
+        mkv.extend(uint(ID_TIMECODE, 1080));
+        let mut laced = vec![2, 0x84, 0xC1];
+        laced.extend_from_slice(&[7; 4]);
+        laced.extend_from_slice(&[8; 6]);
+        laced.extend_from_slice(&[9; 5]);
+        mkv.extend(element(ID_SIMPLE_BLOCK, &block(2, 0, 0x86, &laced)));
+        mkv.extend(element(ID_SIMPLE_BLOCK, &block(1, 0, 0x80, &[3; 20])));
+
+        let mut demuxer = MkvDemuxer::<()>::new();
+        let mut out = Vec::new();
+        for chunk in mkv.chunks(3) {
+            out.extend(demuxer.push(Bytes::copy_from_slice(chunk)));
+        }
+
+        let out: Vec<_> = out.into_iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(demuxer.tracks().count(), 2);
+
+        let summary: Vec<_> = out
+            .iter()
+            .map(|x| (x.track(), x.pts(), x.is_keyframe(), x.data.len()))
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (0, 1_000_000, true, 50),
+                (1, 1_000_000, true, 10),
+                (1, 1_020_000, true, 12),
+                (0, 1_040_000, false, 30),
+                (1, 1_080_000, true, 4),
+                (1, 1_100_000, true, 6),
+                (1, 1_120_000, true, 5),
+                (0, 1_080_000, true, 20),
+            ]
+        );
+
+        assert_eq!(out[0].codec(), Fourcc::VIDEO_VP9);
+        assert_eq!(out[0].dimensions(), (640, 360));
+        assert!(out[0].is_multichannel());
+        assert_eq!(out[1].codec(), Fourcc::AUDIO_OPUS);
+        assert_eq!(out[1].params().collect::<Vec<_>>(), [OPUS_HEAD]);
+        assert_eq!(out[1].dts(), 1_000_000);
+    }
+
+    #[test]
+    fn test_mkv_demux_malformed() {
+        let demux = |demuxer: &mut MkvDemuxer, data: &[u8]| {
+            demuxer
+                .push(Bytes::copy_from_slice(data))
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        let mut header = element(ID_EBML, &element(ID_DOC_TYPE, b"webm"));
+        header.extend(live(ID_SEGMENT));
+        header.extend(element(
+            ID_TRACKS,
+            &element(
+                ID_TRACK_ENTRY,
+                &[
+                    uint(ID_TRACK_NUMBER, 1),
+                    uint(ID_TRACK_TYPE, TRACK_TYPE_VIDEO as u64),
+                    element(ID_CODEC_ID, b"V_VP9"),
+                ]
+                .concat(),
+            ),
+        ));
+        header.extend(live(ID_CLUSTER));
+
+        // the timestamp overflows 64 bits in nanoseconds
+        let mut mkv = header.clone();
+        mkv.extend(uint(ID_TIMECODE, 1 << 61));
+        mkv.extend(element(ID_SIMPLE_BLOCK, &block(1, 0, 0x80, &[1; 10])));
+        assert!(demux(&mut MkvDemuxer::new(), &mkv).is_err());
+
+        let mut mkv = header.clone();
+        mkv.extend(element(ID_SIMPLE_BLOCK, &block(1, 0, 0x80, &[1; 100])));
+        assert!(demux(&mut MkvDemuxer::new().with_max_element_size(64), &mkv).is_err());
+        assert_eq!(demux(&mut MkvDemuxer::new(), &mkv).unwrap().len(), 1);
+
+        // truncated block header
+        let mut mkv = header;
+        mkv.extend(element(ID_SIMPLE_BLOCK, &[0x81, 0]));
+        assert!(demux(&mut MkvDemuxer::new(), &mkv).is_err());
+
+        let mkv = element(ID_EBML, &element(ID_DOC_TYPE, b"avi"));
+        assert!(demux(&mut MkvDemuxer::new(), &mkv).is_err());
+    }
+}
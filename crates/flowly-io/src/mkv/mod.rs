@@ -0,0 +1,434 @@
+mod demuxer;
+mod muxer;
+
+pub use demuxer::{DEFAULT_MAX_ELEMENT_SIZE, MkvDemuxer};
+pub use muxer::MkvMuxer;
+
+use bytes::{Buf, BufMut, Bytes};
+use flowly_core::{Fourcc, Reader};
+
+use crate::error::Error;
+
+pub const ID_EBML: u32 = 0x1A45_DFA3;
+pub const ID_EBML_VERSION: u32 = 0x4286;
+pub const ID_EBML_READ_VERSION: u32 = 0x42F7;
+pub const ID_EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+pub const ID_EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+pub const ID_DOC_TYPE: u32 = 0x4282;
+pub const ID_DOC_TYPE_VERSION: u32 = 0x4287;
+pub const ID_DOC_TYPE_READ_VERSION: u32 = 0x4285;
+pub const ID_VOID: u32 = 0xEC;
+pub const ID_CRC32: u32 = 0xBF;
+
+pub const ID_SEGMENT: u32 = 0x1853_8067;
+pub const ID_SEEK_HEAD: u32 = 0x114D_9B74;
+pub const ID_CUES: u32 = 0x1C53_BB6B;
+pub const ID_TAGS: u32 = 0x1254_C367;
+
+pub const ID_INFO: u32 = 0x1549_A966;
+pub const ID_TIMECODE_SCALE: u32 = 0x2A_D7B1;
+pub const ID_DURATION: u32 = 0x4489;
+pub const ID_MUXING_APP: u32 = 0x4D80;
+pub const ID_WRITING_APP: u32 = 0x5741;
+
+pub const ID_TRACKS: u32 = 0x1654_AE6B;
+pub const ID_TRACK_ENTRY: u32 = 0xAE;
+pub const ID_TRACK_NUMBER: u32 = 0xD7;
+pub const ID_TRACK_UID: u32 = 0x73C5;
+pub const ID_TRACK_TYPE: u32 = 0x83;
+pub const ID_FLAG_LACING: u32 = 0x9C;
+pub const ID_DEFAULT_DURATION: u32 = 0x23_E383;
+pub const ID_CODEC_ID: u32 = 0x86;
+pub const ID_CODEC_PRIVATE: u32 = 0x63A2;
+pub const ID_CODEC_DELAY: u32 = 0x56AA;
+pub const ID_SEEK_PRE_ROLL: u32 = 0x56BB;
+pub const ID_VIDEO: u32 = 0xE0;
+pub const ID_PIXEL_WIDTH: u32 = 0xB0;
+pub const ID_PIXEL_HEIGHT: u32 = 0xBA;
+pub const ID_AUDIO: u32 = 0xE1;
+pub const ID_SAMPLING_FREQUENCY: u32 = 0xB5;
+pub const ID_CHANNELS: u32 = 0x9F;
+pub const ID_BIT_DEPTH: u32 = 0x6264;
+pub const ID_CONTENT_ENCODINGS: u32 = 0x6D80;
+
+pub const ID_CLUSTER: u32 = 0x1F43_B675;
+pub const ID_TIMECODE: u32 = 0xE7;
+pub const ID_SIMPLE_BLOCK: u32 = 0xA3;
+pub const ID_BLOCK_GROUP: u32 = 0xA0;
+pub const ID_BLOCK: u32 = 0xA1;
+pub const ID_BLOCK_DURATION: u32 = 0x9B;
+pub const ID_REFERENCE_BLOCK: u32 = 0xFB;
+
+pub const TRACK_TYPE_VIDEO: u8 = 1;
+pub const TRACK_TYPE_AUDIO: u8 = 2;
+
+/// Default `TimecodeScale` in nanoseconds
+pub const DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+
+pub const BLOCK_FLAG_KEYFRAME: u8 = 0x80;
+pub const BLOCK_FLAG_INVISIBLE: u8 = 0x08;
+pub const BLOCK_FLAG_DISCARDABLE: u8 = 0x01;
+
+pub const LACING_NONE: u8 = 0;
+pub const LACING_XIPH: u8 = 1;
+pub const LACING_FIXED: u8 = 2;
+pub const LACING_EBML: u8 = 3;
+
+/// Parsed element header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElementHeader {
+    /// Element ID with the length marker (as written in the specs)
+    pub id: u32,
+
+    /// Payload size, `None` for the unknown-size (live) elements
+    pub size: Option<u64>,
+    pub header_size: u8,
+}
+
+impl ElementHeader {
+    /// Parses the header from the beginning of `data`, `None` if more data needed
+    pub fn peek(data: &[u8]) -> Option<Result<Self, Error>> {
+        let first = *data.first()?;
+        let id_len = first.leading_zeros() as usize + 1;
+
+        if id_len > 4 {
+            return Some(Err(Error::InvalidData("mkv: invalid element id")));
+        }
+
+        let id = data
+            .get(..id_len)?
+            .iter()
+            .fold(0u32, |acc, &x| acc << 8 | x as u32);
+
+        let (size, size_len) = match peek_vint(data.get(id_len..)?)? {
+            Ok(x) => x,
+            Err(err) => return Some(Err(err)),
+        };
+
+        let unknown = size == (1 << (7 * size_len)) - 1;
+
+        Some(Ok(Self {
+            id,
+            size: (!unknown).then_some(size),
+            header_size: (id_len + size_len) as u8,
+        }))
+    }
+}
+
+/// Parses the variable size integer without the length marker, `None` if more data needed
+fn peek_vint(data: &[u8]) -> Option<Result<(u64, usize), Error>> {
+    let first = *data.first()?;
+    let len = first.leading_zeros() as usize + 1;
+
+    if len > 8 {
+        return Some(Err(Error::InvalidData(
+            "mkv: invalid variable size integer",
+        )));
+    }
+
+    let value = data
+        .get(1..len)?
+        .iter()
+        .fold((first as u64) & (0xFF >> len), |acc, &x| {
+            acc << 8 | x as u64
+        });
+
+    Some(Ok((value, len)))
+}
+
+/// Reads the variable size integer, returns the value and its length
+pub fn read_vint<B: Buf>(buf: &mut B) -> Result<(u64, usize), Error> {
+    // block payloads are contiguous, so the integer is never split between chunks
+    let (value, len) = peek_vint(buf.chunk())
+        .ok_or(Error::InvalidData("mkv: truncated variable size integer"))??;
+
+    buf.advance(len);
+
+    Ok((value, len))
+}
+
+/// Reads the signed variable size integer of the EBML lacing
+pub fn read_svint<B: Buf>(buf: &mut B) -> Result<i64, Error> {
+    let (value, len) = read_vint(buf)?;
+
+    Ok(value as i64 - ((1i64 << (7 * len - 1)) - 1))
+}
+
+/// Big endian unsigned integer element
+pub fn read_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0u64, |acc, &x| acc << 8 | x as u64)
+}
+
+/// Float element (4 or 8 bytes)
+pub fn read_float(mut data: &[u8]) -> Result<f64, Error> {
+    Ok(match data.len() {
+        0 => 0.0,
+        4 => data.read_f32()? as f64,
+        8 => data.read_f64()?,
+        _ => return Err(Error::InvalidData("mkv: invalid float size")),
+    })
+}
+
+/// String element, trailing zeros are trimmed
+pub fn read_string(data: &[u8]) -> String {
+    let len = data.iter().rposition(|&x| x != 0).map_or(0, |x| x + 1);
+
+    String::from_utf8_lossy(&data[..len]).into_owned()
+}
+
+/// Writes the element ID (the length marker is a part of the ID)
+pub fn put_element_id<B: BufMut>(buf: &mut B, id: u32) {
+    let len = (4 - id.leading_zeros() / 8).max(1) as usize;
+    buf.put_slice(&id.to_be_bytes()[4 - len..]);
+}
+
+/// Writes the shortest variable size integer (all ones are reserved for the unknown size)
+pub fn put_vint<B: BufMut>(buf: &mut B, value: u64) {
+    let len = (1..=8)
+        .find(|&len| value < (1 << (7 * len)) - 1)
+        .unwrap_or(8);
+    buf.put_uint(value | 1 << (7 * len), len);
+}
+
+/// Writes the master element header with the unknown size (live streaming)
+pub fn put_unknown_size_header<B: BufMut>(buf: &mut B, id: u32) {
+    put_element_id(buf, id);
+    buf.put_u8(0x01);
+    buf.put_bytes(0xFF, 7);
+}
+
+pub fn put_element<B: BufMut>(buf: &mut B, id: u32, payload: &[u8]) {
+    put_element_id(buf, id);
+    put_vint(buf, payload.len() as u64);
+    buf.put_slice(payload);
+}
+
+pub fn put_uint_element<B: BufMut>(buf: &mut B, id: u32, value: u64) {
+    let len = (8 - value.leading_zeros() / 8).max(1) as usize;
+    put_element(buf, id, &value.to_be_bytes()[8 - len..]);
+}
+
+pub fn put_float_element<B: BufMut>(buf: &mut B, id: u32, value: f64) {
+    put_element(buf, id, &value.to_be_bytes());
+}
+
+pub fn put_string_element<B: BufMut>(buf: &mut B, id: u32, value: &str) {
+    put_element(buf, id, value.as_bytes());
+}
+
+/// Iterator over the child elements of the master element payload, yields `(id, payload)`
+#[derive(Debug, Clone)]
+pub struct Elements {
+    data: Bytes,
+}
+
+impl Elements {
+    pub fn new(data: Bytes) -> Self {
+        Self { data }
+    }
+}
+
+impl Iterator for Elements {
+    type Item = Result<(u32, Bytes), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let header = match ElementHeader::peek(&self.data) {
+            Some(Ok(header)) => header,
+            Some(Err(err)) => {
+                self.data.clear();
+                return Some(Err(err));
+            }
+            None => {
+                self.data.clear();
+                return Some(Err(Error::InvalidData("mkv: truncated element header")));
+            }
+        };
+
+        self.data.advance(header.header_size as usize);
+
+        let size = header.size.unwrap_or(self.data.len() as u64);
+        if size > self.data.len() as u64 {
+            self.data.clear();
+            return Some(Err(Error::InvalidData("mkv: truncated element")));
+        }
+
+        Some(Ok((header.id, self.data.split_to(size as usize))))
+    }
+}
+
+/// Decoded `TrackEntry`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TrackEntry {
+    pub number: u64,
+    pub kind: u8,
+    pub codec_id: String,
+    pub codec_private: Option<Bytes>,
+
+    /// `DefaultDuration` in nanoseconds
+    pub default_duration: Option<u64>,
+
+    /// `CodecDelay` in nanoseconds
+    pub codec_delay: u64,
+    pub width: u16,
+    pub height: u16,
+    pub channels: u16,
+    pub sample_rate: f64,
+    pub bit_depth: u8,
+
+    /// `ContentEncodings` are present (compressed or encrypted blocks)
+    pub encoded: bool,
+}
+
+impl TrackEntry {
+    pub fn parse(data: Bytes) -> Result<Self, Error> {
+        let mut entry = Self::default();
+
+        for child in Elements::new(data) {
+            let (id, payload) = child?;
+
+            match id {
+                ID_TRACK_NUMBER => entry.number = read_uint(&payload),
+                ID_TRACK_TYPE => entry.kind = read_uint(&payload) as u8,
+                ID_CODEC_ID => entry.codec_id = read_string(&payload),
+                ID_CODEC_PRIVATE => entry.codec_private = Some(payload),
+                ID_DEFAULT_DURATION => entry.default_duration = Some(read_uint(&payload)),
+                ID_CODEC_DELAY => entry.codec_delay = read_uint(&payload),
+                ID_CONTENT_ENCODINGS => entry.encoded = true,
+                ID_VIDEO => {
+                    for child in Elements::new(payload) {
+                        let (id, payload) = child?;
+
+                        match id {
+                            ID_PIXEL_WIDTH => entry.width = read_uint(&payload) as u16,
+                            ID_PIXEL_HEIGHT => entry.height = read_uint(&payload) as u16,
+                            _ => (),
+                        }
+                    }
+                }
+                ID_AUDIO => {
+                    for child in Elements::new(payload) {
+                        let (id, payload) = child?;
+
+                        match id {
+                            ID_SAMPLING_FREQUENCY => entry.sample_rate = read_float(&payload)?,
+                            ID_CHANNELS => entry.channels = read_uint(&payload) as u16,
+                            ID_BIT_DEPTH => entry.bit_depth = read_uint(&payload) as u8,
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(entry)
+    }
+
+    /// Codec of the track, `None` if it has no [`Fourcc`]
+    pub fn codec(&self) -> Option<Fourcc> {
+        codec_from_id(&self.codec_id)
+    }
+}
+
+/// Maps [`Fourcc`] to Matroska `CodecID`
+pub fn codec_id(codec: Fourcc) -> Option<&'static str> {
+    Some(match codec {
+        Fourcc::VIDEO_VP8 => "V_VP8",
+        Fourcc::VIDEO_VP9 => "V_VP9",
+        Fourcc::VIDEO_AV1 => "V_AV1",
+        Fourcc::VIDEO_AVC => "V_MPEG4/ISO/AVC",
+        Fourcc::VIDEO_HEVC => "V_MPEGH/ISO/HEVC",
+        Fourcc::VIDEO_MJPEG => "V_MJPEG",
+        Fourcc::AUDIO_OPUS => "A_OPUS",
+        Fourcc::AUDIO_FLAC => "A_FLAC",
+        Fourcc::AUDIO_MP3 => "A_MPEG/L3",
+        Fourcc::AUDIO_AC3 => "A_AC3",
+        Fourcc::AUDIO_EC3 => "A_EAC3",
+        Fourcc::AUDIO_AAC => "A_AAC",
+        _ => return None,
+    })
+}
+
+/// Maps Matroska `CodecID` to [`Fourcc`]
+pub fn codec_from_id(codec_id: &str) -> Option<Fourcc> {
+    Some(match codec_id {
+        "V_VP8" => Fourcc::VIDEO_VP8,
+        "V_VP9" => Fourcc::VIDEO_VP9,
+        "V_AV1" => Fourcc::VIDEO_AV1,
+        "V_MPEG4/ISO/AVC" => Fourcc::VIDEO_AVC,
+        "V_MPEGH/ISO/HEVC" => Fourcc::VIDEO_HEVC,
+        "V_MJPEG" => Fourcc::VIDEO_MJPEG,
+        "A_OPUS" => Fourcc::AUDIO_OPUS,
+        "A_FLAC" => Fourcc::AUDIO_FLAC,
+        "A_MPEG/L3" => Fourcc::AUDIO_MP3,
+        "A_AC3" => Fourcc::AUDIO_AC3,
+        "A_EAC3" => Fourcc::AUDIO_EC3,
+        id if id.starts_with("A_AAC") => Fourcc::AUDIO_AAC,
+        _ => return None,
+    })
+}
+
+/// Splits the laced block payload into frames
+pub fn split_lacing(lacing: u8, mut data: Bytes) -> Result<Vec<Bytes>, Error> {
+    if lacing == LACING_NONE {
+        return Ok(vec![data]);
+    }
+
+    let count = data.read_u8()? as usize + 1;
+    let mut sizes = Vec::with_capacity(count);
+
+    match lacing {
+        LACING_XIPH => {
+            for _ in 1..count {
+                let mut size = 0;
+
+                loop {
+                    let byte = data.read_u8()?;
+                    size += byte as usize;
+
+                    if byte != 0xFF {
+                        break;
+                    }
+                }
+
+                sizes.push(size);
+            }
+        }
+
+        LACING_EBML => {
+            let mut size = read_vint(&mut data)?.0 as i64;
+            sizes.push(size as usize);
+
+            for _ in 2..count {
+                size += read_svint(&mut data)?;
+
+                if size < 0 {
+                    return Err(Error::InvalidData("mkv: negative EBML lace size"));
+                }
+
+                sizes.push(size as usize);
+            }
+        }
+
+        _ => {
+            if !data.len().is_multiple_of(count) {
+                return Err(Error::InvalidData("mkv: uneven fixed-size lacing"));
+            }
+
+            sizes.resize(count - 1, data.len() / count);
+        }
+    }
+
+    let mut frames = Vec::with_capacity(count);
+    for size in sizes {
+        frames.push(data.read_bytes(size)?);
+    }
+
+    frames.push(data);
+
+    Ok(frames)
+}
@@ -0,0 +1,552 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly_codec::{
+    BitstreamConverter, VideoInfo, h264::AvcConfig, h265::HevcConfig, opus::OpusHead,
+};
+use flowly_core::{BitReader, EncodedFrame, Fourcc, Frame, Multichannel, Packet, VideoFrame};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+/// Audio only clusters are cut at least that often (microseconds)
+const AUDIO_CLUSTER_DURATION: i64 = 1_000_000;
+
+/// Opus decoders need 80 ms of preroll after seeking (RFC 7845 4.6)
+const OPUS_SEEK_PRE_ROLL: u64 = 80_000_000;
+
+#[derive(Debug, Clone)]
+struct Track {
+    codec: Fourcc,
+    params: Vec<Bytes>,
+    dimensions: Option<(u16, u16)>,
+}
+
+impl Track {
+    #[inline]
+    fn is_video(&self) -> bool {
+        is_video(self.codec)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Block {
+    track: usize,
+    pts: i64,
+    keyframe: bool,
+    data: Bytes,
+}
+
+/// Live Matroska/WebM muxer.
+///
+/// Accepts VP8, VP9, AV1, AVC, HEVC and Opus frames (tracks are keyed by
+/// `Frame::codec()`) and yields the byte stream of the unknown-size segment:
+/// the EBML header with `Info` and `Tracks` first, then clusters with SimpleBlocks.
+/// A cluster starts on the video keyframe once the current one lasts at least
+/// `cluster_duration` (on every keyframe by default), audio only streams are cut
+/// by duration only. The first cluster is held back until it is complete, so all
+/// tracks appearing in it make it to `Tracks` (with `CodecPrivate` built from
+/// `params()`), tracks appearing after that are ignored. Later blocks are written
+/// as soon as they arrive. Timestamps are shifted so the first frame starts at zero.
+#[derive(Debug, Clone)]
+pub struct MkvMuxer {
+    cluster_duration: u64,
+    tracks: Vec<Track>,
+    header_written: bool,
+    pending: Vec<Block>,
+
+    /// Timestamp of the open cluster in microseconds
+    cluster: Option<i64>,
+    start_dts: Option<u64>,
+    converter: BitstreamConverter,
+}
+
+impl MkvMuxer {
+    pub fn new() -> Self {
+        Self {
+            cluster_duration: 0,
+            tracks: Vec::new(),
+            header_written: false,
+            pending: Vec::new(),
+            cluster: None,
+            start_dts: None,
+            converter: BitstreamConverter::length_prefixed(),
+        }
+    }
+
+    /// Minimal cluster duration in microseconds
+    pub fn with_cluster_duration(mut self, duration: u64) -> Self {
+        self.cluster_duration = duration;
+        self
+    }
+
+    /// Adds the frame to the stream, returns the chunks completed by it
+    pub fn push<F: EncodedFrame + Multichannel + VideoFrame>(
+        &mut self,
+        frame: F,
+    ) -> Result<Vec<Bytes>, Error> {
+        let codec = frame.codec();
+        let is_last = frame.is_last();
+
+        if !matches!(
+            codec,
+            Fourcc::VIDEO_VP8
+                | Fourcc::VIDEO_VP9
+                | Fourcc::VIDEO_AV1
+                | Fourcc::VIDEO_AVC
+                | Fourcc::VIDEO_HEVC
+                | Fourcc::AUDIO_OPUS
+        ) {
+            return Err(flowly_codec::Error::UnsupportedCodec(codec).into());
+        }
+
+        let packet = if matches!(codec, Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC) {
+            self.converter.convert(frame)?
+        } else {
+            Packet::from_frame(frame)
+        };
+
+        let start = *self.start_dts.get_or_insert(packet.dts);
+        let pts = packet.pts - start as i64;
+
+        let mut out = Vec::new();
+
+        let track = match self.tracks.iter().position(|x| x.codec == codec) {
+            Some(idx) => idx,
+            None if self.header_written => return Ok(out),
+            None => {
+                // a video stream has to start with the keyframe
+                if is_video(codec) && !packet.is_keyframe() {
+                    return Ok(out);
+                }
+
+                self.tracks.push(Track {
+                    codec,
+                    params: packet.params.clone(),
+                    dimensions: None,
+                });
+
+                self.tracks.len() - 1
+            }
+        };
+
+        let is_video = self.tracks[track].is_video();
+        let keyframe = packet.is_keyframe() || !is_video;
+
+        if is_video && keyframe && self.tracks[track].dimensions.is_none() {
+            self.tracks[track].dimensions = dimensions(codec, &packet.params, &packet.data);
+        }
+
+        let has_video = self.tracks.iter().any(Track::is_video);
+        let cluster_start = if self.header_written {
+            self.cluster
+        } else {
+            self.pending.first().map(|x| x.pts)
+        };
+
+        let should_cut = cluster_start.is_some_and(|start| {
+            let duration = pts - start;
+
+            // relative block timecode is a 16-bit number of milliseconds
+            if duration / 1000 > i16::MAX as i64 {
+                return true;
+            }
+
+            if has_video {
+                is_video && keyframe && duration >= self.cluster_duration as i64
+            } else {
+                duration >= AUDIO_CLUSTER_DURATION.max(self.cluster_duration as i64)
+            }
+        });
+
+        let block = Block {
+            track,
+            pts,
+            keyframe,
+            data: packet.data,
+        };
+
+        if !self.header_written {
+            if should_cut {
+                out.extend(self.flush());
+            } else {
+                self.pending.push(block);
+                return Ok(out);
+            }
+        }
+
+        let mut buf = BytesMut::with_capacity(block.data.len() + 32);
+        if should_cut || self.cluster.is_none() {
+            self.put_cluster_header(&mut buf, block.pts);
+        }
+
+        self.put_block(&mut buf, &block);
+        out.push(buf.freeze());
+
+        if is_last {
+            out.extend(self.flush());
+        }
+
+        Ok(out)
+    }
+
+    /// Writes the header and the first cluster if they are still held back
+    pub fn flush(&mut self) -> Vec<Bytes> {
+        if self.header_written || self.pending.is_empty() {
+            return Vec::new();
+        }
+
+        let mut header = BytesMut::with_capacity(512);
+        self.put_header(&mut header);
+        self.header_written = true;
+
+        let pending = std::mem::take(&mut self.pending);
+        let mut buf = BytesMut::with_capacity(pending.iter().map(|x| x.data.len() + 16).sum());
+
+        self.put_cluster_header(&mut buf, pending[0].pts);
+        for block in &pending {
+            self.put_block(&mut buf, block);
+        }
+
+        vec![header.freeze(), buf.freeze()]
+    }
+
+    fn put_header(&self, buf: &mut BytesMut) {
+        let is_webm = self.tracks.iter().all(|x| {
+            matches!(
+                x.codec,
+                Fourcc::VIDEO_VP8 | Fourcc::VIDEO_VP9 | Fourcc::VIDEO_AV1 | Fourcc::AUDIO_OPUS
+            )
+        });
+
+        let mut ebml = BytesMut::new();
+        put_uint_element(&mut ebml, ID_EBML_VERSION, 1);
+        put_uint_element(&mut ebml, ID_EBML_READ_VERSION, 1);
+        put_uint_element(&mut ebml, ID_EBML_MAX_ID_LENGTH, 4);
+        put_uint_element(&mut ebml, ID_EBML_MAX_SIZE_LENGTH, 8);
+        put_string_element(
+            &mut ebml,
+            ID_DOC_TYPE,
+            if is_webm { "webm" } else { "matroska" },
+        );
+        put_uint_element(&mut ebml, ID_DOC_TYPE_VERSION, 4);
+        put_uint_element(&mut ebml, ID_DOC_TYPE_READ_VERSION, 2);
+        put_element(buf, ID_EBML, &ebml);
+
+        put_unknown_size_header(buf, ID_SEGMENT);
+
+        let mut info = BytesMut::new();
+        put_uint_element(&mut info, ID_TIMECODE_SCALE, DEFAULT_TIMECODE_SCALE);
+        put_string_element(&mut info, ID_MUXING_APP, "flowly");
+        put_string_element(&mut info, ID_WRITING_APP, "flowly");
+        put_element(buf, ID_INFO, &info);
+
+        let mut tracks = BytesMut::new();
+        for (idx, track) in self.tracks.iter().enumerate() {
+            let mut entry = BytesMut::new();
+            put_uint_element(&mut entry, ID_TRACK_NUMBER, idx as u64 + 1);
+            put_uint_element(&mut entry, ID_TRACK_UID, idx as u64 + 1);
+            put_uint_element(
+                &mut entry,
+                ID_TRACK_TYPE,
+                if track.is_video() {
+                    TRACK_TYPE_VIDEO
+                } else {
+                    TRACK_TYPE_AUDIO
+                } as u64,
+            );
+            put_uint_element(&mut entry, ID_FLAG_LACING, 0);
+            put_string_element(
+                &mut entry,
+                ID_CODEC_ID,
+                codec_id(track.codec).unwrap_or_default(),
+            );
+
+            if let Some(private) = codec_private(track) {
+                put_element(&mut entry, ID_CODEC_PRIVATE, &private);
+            }
+
+            if track.is_video() {
+                // PixelWidth/PixelHeight are mandatory, so `Video` is omitted when they are unknown
+                if let Some((width, height)) = track.dimensions {
+                    let mut video = BytesMut::new();
+                    put_uint_element(&mut video, ID_PIXEL_WIDTH, width as u64);
+                    put_uint_element(&mut video, ID_PIXEL_HEIGHT, height as u64);
+                    put_element(&mut entry, ID_VIDEO, &video);
+                }
+            } else {
+                let head = track.params.first();
+                let channels = head.and_then(|x| x.get(9)).copied().unwrap_or(2);
+                let pre_skip = head
+                    .and_then(|x| x.get(10..12))
+                    .map_or(0, |x| u16::from_le_bytes([x[0], x[1]]));
+
+                put_uint_element(
+                    &mut entry,
+                    ID_CODEC_DELAY,
+                    pre_skip as u64 * 1_000_000_000 / 48_000,
+                );
+                put_uint_element(&mut entry, ID_SEEK_PRE_ROLL, OPUS_SEEK_PRE_ROLL);
+
+                let mut audio = BytesMut::new();
+                put_float_element(&mut audio, ID_SAMPLING_FREQUENCY, 48_000.0);
+                put_uint_element(&mut audio, ID_CHANNELS, channels as u64);
+                put_element(&mut entry, ID_AUDIO, &audio);
+            }
+
+            put_element(&mut tracks, ID_TRACK_ENTRY, &entry);
+        }
+
+        put_element(buf, ID_TRACKS, &tracks);
+    }
+
+    fn put_cluster_header(&mut self, buf: &mut BytesMut, pts: i64) {
+        let pts = pts.max(0);
+
+        put_unknown_size_header(buf, ID_CLUSTER);
+        put_uint_element(buf, ID_TIMECODE, pts as u64 / 1000);
+
+        self.cluster = Some(pts / 1000 * 1000);
+    }
+
+    fn put_block(&self, buf: &mut BytesMut, block: &Block) {
+        let cluster = self.cluster.unwrap_or_default();
+        let timecode =
+            ((block.pts - cluster).div_euclid(1000)).clamp(i16::MIN as i64, i16::MAX as i64);
+
+        put_element_id(buf, ID_SIMPLE_BLOCK);
+        put_vint(buf, block.data.len() as u64 + 4);
+        put_vint(buf, block.track as u64 + 1);
+        buf.put_i16(timecode as i16);
+        buf.put_u8(if block.keyframe {
+            BLOCK_FLAG_KEYFRAME
+        } else {
+            0
+        });
+        buf.put_slice(&block.data);
+    }
+}
+
+impl Default for MkvMuxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[inline]
+fn is_video(codec: Fourcc) -> bool {
+    codec != Fourcc::AUDIO_OPUS
+}
+
+/// `CodecPrivate` in the form defined by the Matroska codec mappings
+fn codec_private(track: &Track) -> Option<Bytes> {
+    match track.codec {
+        Fourcc::VIDEO_AVC => AvcConfig::from_params(&track.params)
+            .ok()
+            .map(|x| x.to_bytes()),
+        Fourcc::VIDEO_HEVC => HevcConfig::from_params(&track.params)
+            .ok()
+            .map(|x| x.to_bytes()),
+        Fourcc::AUDIO_OPUS => Some(
+            track
+                .params
+                .first()
+                .filter(|x| x.starts_with(b"OpusHead"))
+                .cloned()
+                .unwrap_or_else(|| OpusHead::default().to_bytes()),
+        ),
+
+        // av1C, VP8/VP9 have no mandatory CodecPrivate
+        _ => track.params.first().cloned(),
+    }
+}
+
+/// Frame dimensions from the parameter sets or the VP8/VP9 keyframe header
+fn dimensions(codec: Fourcc, params: &[Bytes], data: &[u8]) -> Option<(u16, u16)> {
+    match codec {
+        Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC => VideoInfo::from_params(codec, params)
+            .ok()
+            .map(|x| x.dimensions()),
+
+        // frame tag, start code 9d 01 2a, 14-bit little endian sizes
+        Fourcc::VIDEO_VP8 => match data {
+            [tag, _, _, 0x9D, 0x01, 0x2A, w0, w1, h0, h1, ..] if tag & 1 == 0 => Some((
+                u16::from_le_bytes([*w0, *w1]) & 0x3FFF,
+                u16::from_le_bytes([*h0, *h1]) & 0x3FFF,
+            )),
+            _ => None,
+        },
+
+        Fourcc::VIDEO_VP9 => vp9_dimensions(data).ok().flatten(),
+
+        _ => None,
+    }
+}
+
+/// Frame size of the VP9 keyframe uncompressed header
+fn vp9_dimensions(data: &[u8]) -> Result<Option<(u16, u16)>, Error> {
+    let mut r = BitReader::new(data);
+
+    if r.read_u8(2)? != 2 {
+        return Ok(None);
+    }
+
+    let profile = r.read_u8(1)? | r.read_u8(1)? << 1;
+    if profile == 3 {
+        r.skip_bits(1)?;
+    }
+
+    // show_existing_frame, frame_type
+    if r.read_flag()? || r.read_flag()? {
+        return Ok(None);
+    }
+
+    // show_frame, error_resilient_mode, frame_sync_code
+    r.skip_bits(2)?;
+    if r.read_u32(24)? != 0x49_83_42 {
+        return Ok(None);
+    }
+
+    if profile >= 2 {
+        r.skip_bits(1)?;
+    }
+
+    let color_space = r.read_u8(3)?;
+    if color_space != 7 {
+        // color_range, subsampling_x/y and reserved_zero
+        r.skip_bits(if profile & 1 == 1 { 4 } else { 1 })?;
+    } else if profile & 1 == 1 {
+        r.skip_bits(1)?;
+    }
+
+    let width = r.read_u32(16)? + 1;
+    let height = r.read_u32(16)? + 1;
+
+    Ok(Some((width as u16, height as u16)))
+}
+
+impl<F: EncodedFrame + Multichannel + VideoFrame> Service<F> for MkvMuxer {
+    type Out = Result<Bytes, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let out: Vec<_> = match self.push(frame) {
+            Ok(chunks) => chunks.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        };
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use flowly_core::{
+        BitWriter, EncodedFrame, Fourcc, Frame, FrameFlags, Multichannel, Packet, VideoFrame,
+    };
+
+    use super::MkvMuxer;
+    use crate::mkv::MkvDemuxer;
+
+    const OPUS_HEAD: &[u8] = &[
+        b'O', b'p', b'u', b's', b'H', b'e', b'a', b'd', 1, 2, 0x38, 0x01, 0x80, 0xBB, 0, 0, 0, 0, 0,
+    ];
+
+    fn vp9_keyframe(width: u16, height: u16) -> Bytes {
+        let mut w = BitWriter::new(BytesMut::new());
+        w.put_bits(2, 2);
+        w.put_bits(2, 0); // profile 0
+        w.put_bits(2, 0); // show_existing_frame, keyframe
+        w.put_bits(2, 0b10); // show_frame, error_resilient_mode
+        w.put_bits(24, 0x49_83_42);
+        w.put_bits(3, 1); // BT.601
+        w.put_bits(1, 0);
+        w.put_bits(16, width as u64 - 1);
+        w.put_bits(16, height as u64 - 1);
+        w.put_bits(32, 0xDEAD_BEEF);
+        w.finish().freeze()
+    }
+
+    #[test]
+    fn test_mkv_mux_demux() {
+        let mut muxer = MkvMuxer::new();
+        let mut frames = Vec::new();
+
+        for i in 0..6u64 {
+            // keyframes at 0 and 80 ms
+            if i % 2 == 0 {
+                let ts = 1_000_000 + i * 20_000;
+                let (flags, data) = if i % 4 == 0 {
+                    (FrameFlags::KEYFRAME, vp9_keyframe(640, 360))
+                } else {
+                    (FrameFlags::empty(), Bytes::from(vec![i as u8; 20]))
+                };
+
+                frames.push(
+                    Packet::new(
+                        (),
+                        Fourcc::VIDEO_VP9,
+                        flags | FrameFlags::VIDEO_STREAM,
+                        data,
+                    )
+                    .with_timestamps(ts, ts as i64),
+                );
+            }
+
+            let ts = 1_000_000 + i * 20_000;
+            frames.push(
+                Packet::new(
+                    (),
+                    Fourcc::AUDIO_OPUS,
+                    FrameFlags::KEYFRAME | FrameFlags::AUDIO_STREAM,
+                    Bytes::from(vec![0xA0 | i as u8; 10]),
+                )
+                .with_timestamps(ts, ts as i64)
+                .with_params(vec![Bytes::from_static(OPUS_HEAD)]),
+            );
+        }
+
+        let mut chunks = Vec::new();
+        for frame in frames {
+            chunks.extend(muxer.push(frame).unwrap());
+        }
+        chunks.extend(muxer.flush());
+
+        // header and the first cluster are released by the second keyframe
+        assert_eq!(chunks.len(), 2 + 3);
+
+        let mut demuxer = MkvDemuxer::<()>::new();
+        let mut out = Vec::new();
+        for chunk in chunks {
+            out.extend(demuxer.push(chunk));
+        }
+
+        let out: Vec<_> = out.into_iter().collect::<Result<_, _>>().unwrap();
+        let tracks: Vec<_> = demuxer.tracks().map(|x| x.codec_id.as_str()).collect();
+        assert_eq!(tracks, ["V_VP9", "A_OPUS"]);
+
+        let summary: Vec<_> = out
+            .iter()
+            .map(|x| (x.track(), x.pts(), x.is_keyframe()))
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (0, 0, true),
+                (1, 0, true),
+                (1, 20_000, true),
+                (0, 40_000, false),
+                (1, 40_000, true),
+                (1, 60_000, true),
+                (0, 80_000, true),
+                (1, 80_000, true),
+                (1, 100_000, true),
+            ]
+        );
+
+        assert_eq!(out[0].codec(), Fourcc::VIDEO_VP9);
+        assert_eq!(out[0].dimensions(), (640, 360));
+        assert_eq!(out[1].params().collect::<Vec<_>>(), [OPUS_HEAD]);
+        assert_eq!(&out[8].data[..], &[0xA5; 10]);
+    }
+}
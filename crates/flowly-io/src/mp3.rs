@@ -0,0 +1,307 @@
+use bytes::{Buf, Bytes};
+use flowly_codec::mpa::{FrameHeader, HEADER_SIZE, VbrInfo};
+use flowly_core::{Chunked, DataFrame, Fourcc, FrameFlags, FrameSource, Packet};
+use flowly_service::{Context, Service};
+
+use crate::error::Error;
+
+const ID3_HEADER_SIZE: usize = 10;
+
+/// MP3 elementary stream parser.
+///
+/// Accepts arbitrary chunks of the MPEG-1/2/2.5 layer III stream and yields a
+/// [`Packet`] per frame including its header. Leading ID3v2 tags are skipped,
+/// the Xing/Info or VBRI frame is consumed and exposed through
+/// [`Mp3Demuxer::vbr_info`]. Timestamps and durations count the samples of the
+/// frames from the first one. Like [`AdtsDemuxer`](crate::adts::AdtsDemuxer), a
+/// frame is accepted only if the next one starts right after it until the
+/// stream is synchronised, so call [`Mp3Demuxer::flush`] at the end of stream.
+/// The state is reset when the frame source changes.
+#[derive(Debug, Clone)]
+pub struct Mp3Demuxer<S = ()> {
+    source: S,
+    buf: Chunked<Bytes>,
+    skip: usize,
+    synced: bool,
+    frames: u64,
+    vbr_info: Option<VbrInfo>,
+
+    /// Timestamp of the last sample rate change and samples since then
+    sample_rate: u32,
+    base: u64,
+    samples: u64,
+}
+
+impl<S: FrameSource> Mp3Demuxer<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            buf: Chunked::new(),
+            skip: 0,
+            synced: false,
+            frames: 0,
+            vbr_info: None,
+            sample_rate: 0,
+            base: 0,
+            samples: 0,
+        }
+    }
+
+    /// Stream information of the Xing/Info or VBRI header, if any
+    pub fn vbr_info(&self) -> Option<&VbrInfo> {
+        self.vbr_info.as_ref()
+    }
+
+    /// Feeds the chunk of the stream and returns all completed packets
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<Packet<S>, Error>> {
+        self.buf.put(chunk);
+        self.parse(false)
+    }
+
+    /// Returns the frame held back waiting for the next sync
+    pub fn flush(&mut self) -> Vec<Result<Packet<S>, Error>> {
+        self.parse(true)
+    }
+
+    fn parse(&mut self, eos: bool) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        loop {
+            if self.skip > 0 {
+                let len = self.skip.min(self.buf.remaining());
+                self.buf.advance(len);
+                self.skip -= len;
+
+                if self.skip > 0 {
+                    break;
+                }
+            }
+
+            let mut head = [0u8; ID3_HEADER_SIZE];
+            let len = self.buf.peek(0, &mut head);
+            if len < HEADER_SIZE {
+                break;
+            }
+
+            if head.starts_with(b"ID3") {
+                if len < ID3_HEADER_SIZE {
+                    break;
+                }
+
+                self.skip = id3_size(&head);
+                continue;
+            }
+
+            let header = match FrameHeader::parse(&head) {
+                Ok(header) => header,
+                Err(_) => {
+                    self.synced = false;
+                    self.buf.advance(1);
+                    continue;
+                }
+            };
+
+            let frame_size = header.frame_size();
+            let available = self.buf.remaining();
+
+            if available < frame_size {
+                // truncated frame or false sync at the end of stream
+                if eos {
+                    self.synced = false;
+                    self.buf.advance(1);
+                    continue;
+                }
+
+                break;
+            }
+
+            // the following header confirms the frame unless already in sync
+            if !self.synced && !eos {
+                if available < frame_size + HEADER_SIZE {
+                    break;
+                }
+
+                let mut next = [0u8; HEADER_SIZE];
+                self.buf.peek(frame_size, &mut next);
+
+                if !FrameHeader::parse(&next).is_ok_and(|x| x.is_compatible(&header)) {
+                    self.buf.advance(1);
+                    continue;
+                }
+            }
+
+            self.synced = true;
+            let frame = self.buf.copy_to_bytes(frame_size);
+
+            if let Some(packet) = self.frame(header, frame) {
+                out.push(Ok(packet));
+            }
+        }
+
+        out
+    }
+
+    fn frame(&mut self, header: FrameHeader, frame: Bytes) -> Option<Packet<S>> {
+        self.frames += 1;
+
+        if self.frames == 1
+            && let Some(info) = VbrInfo::parse(&header, &frame)
+        {
+            self.vbr_info = Some(info);
+            return None;
+        }
+
+        if header.sample_rate != self.sample_rate {
+            // keep timestamps continuous over the sample rate change
+            if self.sample_rate != 0 {
+                self.base += self.samples * 1_000_000 / self.sample_rate as u64;
+                self.samples = 0;
+            }
+
+            self.sample_rate = header.sample_rate;
+        }
+
+        let rate = self.sample_rate as u64;
+        let timestamp = self.base + self.samples * 1_000_000 / rate;
+        self.samples += header.samples() as u64;
+
+        let mut packet = Packet::new(
+            self.source.clone(),
+            Fourcc::AUDIO_MP3,
+            FrameFlags::AUDIO_STREAM | FrameFlags::KEYFRAME,
+            frame,
+        )
+        .with_timestamps(timestamp, timestamp as i64);
+
+        packet.duration = self.base + self.samples * 1_000_000 / rate - timestamp;
+
+        Some(packet)
+    }
+
+    fn reset(&mut self, source: S) {
+        *self = Self::new();
+        self.source = source;
+    }
+}
+
+impl<S: FrameSource> Default for Mp3Demuxer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Size of the ID3v2 tag including its header and footer
+fn id3_size(head: &[u8; ID3_HEADER_SIZE]) -> usize {
+    let size = head[6..10]
+        .iter()
+        .fold(0usize, |acc, &x| acc << 7 | (x & 0x7F) as usize);
+    let footer = if head[5] & 0x10 != 0 {
+        ID3_HEADER_SIZE
+    } else {
+        0
+    };
+
+    ID3_HEADER_SIZE + size + footer
+}
+
+impl<F> Service<F> for Mp3Demuxer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let mut out = Vec::new();
+
+        if frame.source() != &self.source {
+            out.extend(self.flush());
+            self.reset(frame.source().clone());
+        }
+
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use flowly_core::Frame;
+
+    use super::Mp3Demuxer;
+
+    /// MPEG-1 layer III, 128 kbit/s, 48 kHz, stereo: 384 bytes per frame
+    fn mp3_frame(fill: u8) -> Vec<u8> {
+        let mut frame = vec![fill; 384];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x94, 0x04]);
+        frame
+    }
+
+    #[test]
+    fn test_mp3_demux() {
+        let mut stream = BytesMut::new();
+
+        // ID3v2 tag with a false sync inside
+        stream.put_slice(b"ID3\x04\x00\x00\x00\x00\x00\x06");
+        stream.put_slice(&[0xFF, 0xFB, 0x94, 0x04, 0, 0]);
+
+        // Xing header with frame count and LAME delay/padding
+        let mut xing = mp3_frame(0);
+        xing[36..40].copy_from_slice(b"Info");
+        xing[40..48].copy_from_slice(&[0, 0, 0, 0x01, 0, 0, 0, 3]);
+        xing[48..52].copy_from_slice(b"LAME");
+        xing[69..72].copy_from_slice(&[0x24, 0x01, 0x20]);
+        stream.put_slice(&[0x12, 0xFF]);
+        stream.put_slice(&xing);
+
+        for fill in 1..=3 {
+            stream.put_slice(&mp3_frame(fill));
+        }
+
+        let stream = stream.freeze();
+        let mut demuxer = Mp3Demuxer::<()>::new();
+        let mut out = Vec::new();
+
+        for chunk in stream.chunks(100) {
+            out.extend(demuxer.push(Bytes::copy_from_slice(chunk)));
+        }
+
+        // frames after the synchronisation are not held back
+        assert_eq!(out.len(), 3);
+        assert!(demuxer.flush().is_empty());
+
+        let info = demuxer.vbr_info().unwrap();
+        assert_eq!((info.frames, info.delay, info.padding), (Some(3), 576, 288));
+
+        let summary: Vec<_> = out
+            .into_iter()
+            .map(Result::unwrap)
+            .map(|x| (x.timestamp(), x.duration, x.data[4], x.data.len()))
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (0, 24_000, 1, 384),
+                (24_000, 24_000, 2, 384),
+                (48_000, 24_000, 3, 384)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mp3_demux_malformed() {
+        // reserved bitrate/sample rate, false syncs and a truncated last frame
+        let mut stream = vec![0xFF, 0xFB, 0xF4, 0x04, 0xFF, 0xFB, 0x9C, 0x04, 0xFF, 0xFF];
+        stream.extend_from_slice(&mp3_frame(1)[..200]);
+
+        let mut demuxer = Mp3Demuxer::<()>::new();
+        let mut out = demuxer.push(Bytes::from(stream));
+        out.extend(demuxer.flush());
+
+        assert!(out.is_empty());
+    }
+}
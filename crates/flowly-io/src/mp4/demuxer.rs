@@ -0,0 +1,1249 @@
+use std::{collections::VecDeque, io::SeekFrom, path::Path, sync::Arc};
+
+use bytes::{Buf, Bytes, BytesMut};
+use flowly_codec::{VideoInfo, nal};
+use flowly_core::{Chunked, DataFrame, FrameFlags, FrameSource, Packet, Reader};
+use flowly_service::{Context, Service};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::*;
+use crate::{error::Error, file::FileSouce};
+
+/// Limit of the data kept in memory (media data waiting for `moov`, incomplete
+/// boxes and samples)
+pub const DEFAULT_MAX_RETAINED: usize = 128 * 1024 * 1024;
+
+/// `default-base-is-moof` flag of `tfhd`
+const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x02_0000;
+
+/// Sample defaults of the fragmented track (`trex`, `tfhd`)
+#[derive(Debug, Default, Clone, Copy)]
+struct SampleDefaults {
+    duration: u32,
+    size: u32,
+    flags: u32,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Track {
+    id: u32,
+    timescale: u32,
+    kind: FrameFlags,
+    entry: SampleEntry,
+    bit_depth: u8,
+    defaults: SampleDefaults,
+
+    /// Decoding time of the next fragment sample (in timescale units)
+    next_dts: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    track: usize,
+    offset: u64,
+    size: u32,
+    dts: u64,
+    cts: i32,
+    duration: u32,
+    keyframe: bool,
+}
+
+/// Sample tables of the progressive (non-fragmented) track
+#[derive(Debug, Default)]
+struct SampleTables {
+    sizes: Vec<u32>,
+    chunk_offsets: Vec<u64>,
+    sample_to_chunk: Vec<(u32, u32)>,
+    time_to_sample: Vec<(u32, u32)>,
+    composition_offsets: Vec<(u32, i32)>,
+    sync_samples: Option<Vec<u32>>,
+}
+
+/// ISO-BMFF (MP4/MOV) demuxer.
+///
+/// Accepts arbitrary chunks of the file (e.g. produced by `FileReader`) and yields
+/// a [`Packet`] per sample in the file order. Both progressive (`moov` + `mdat`) and
+/// fragmented (`moof` + `mdat`) files are supported. When `moov` goes after `mdat`
+/// the demuxer either asks the reader to skip `mdat` and come back once the
+/// sample tables are known (see [`Mp4Demuxer::with_seeking`] and
+/// [`Mp4FileReader`]) or keeps the media data in memory up to
+/// [`Mp4Demuxer::with_max_retained`] bytes, the same limit applies to any box or
+/// sample being buffered. Video payloads are length prefixed (AVCC/HVCC) with
+/// 4-byte lengths, parameter sets are attached to every keyframe.
+#[derive(Debug, Clone)]
+pub struct Mp4Demuxer<S = ()> {
+    source: S,
+    buf: Chunked<Bytes>,
+
+    /// File offset of the first buffered byte
+    buf_pos: u64,
+
+    /// File offset of the next top-level box
+    cursor: u64,
+
+    /// Start of `mdat` found before the sample tables, kept until `moov` is parsed
+    retain_from: Option<u64>,
+    max_retained: usize,
+
+    /// The reader is able to seek
+    seeking: bool,
+
+    /// File offset the reader has to continue from
+    seek_to: Option<u64>,
+
+    /// Start of `mdat` skipped to look for `moov`
+    skipped_mdat: Option<u64>,
+    has_moov: bool,
+    tracks: Vec<Track>,
+    samples: VecDeque<Sample>,
+    failed: bool,
+}
+
+impl<S: FrameSource> Mp4Demuxer<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            buf: Chunked::new(),
+            buf_pos: 0,
+            cursor: 0,
+            retain_from: None,
+            max_retained: DEFAULT_MAX_RETAINED,
+            seeking: false,
+            seek_to: None,
+            skipped_mdat: None,
+            has_moov: false,
+            tracks: Vec::new(),
+            samples: VecDeque::new(),
+            failed: false,
+        }
+    }
+
+    /// Limit of the data kept in memory: the media data when `moov` goes after
+    /// `mdat` and the reader can't seek, an incomplete box or sample otherwise,
+    /// the demuxing fails when it's exceeded
+    pub fn with_max_retained(mut self, size: usize) -> Self {
+        self.max_retained = size;
+        self
+    }
+
+    /// The reader follows [`Mp4Demuxer::seek_request`], `mdat` found before
+    /// `moov` is skipped and read again once the sample tables are known
+    pub fn with_seeking(mut self) -> Self {
+        self.seeking = true;
+        self
+    }
+
+    /// File offset the reader has to seek to before the next `push`
+    pub fn seek_request(&self) -> Option<u64> {
+        self.seek_to
+    }
+
+    /// Drops the buffered data, the next pushed chunk starts at `pos` of the file
+    pub fn seek(&mut self, pos: u64) {
+        self.buf = Chunked::new();
+        self.buf_pos = pos;
+        self.cursor = pos;
+        self.seek_to = None;
+    }
+
+    /// Codec and decoding parameters of the tracks, indexed by `Multichannel::track`
+    pub fn tracks(&self) -> impl Iterator<Item = &SampleEntry> {
+        self.tracks.iter().map(|x| &x.entry)
+    }
+
+    /// Feeds the chunk of the file and returns all completed packets
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        if self.failed {
+            return out;
+        }
+
+        self.buf.put(chunk);
+
+        while self.seek_to.is_none() {
+            self.emit_samples(&mut out);
+
+            match self.next_box() {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(err) => {
+                    out.push(Err(err));
+                    self.failed = true;
+                    self.buf = Chunked::new();
+                    return out;
+                }
+            }
+
+            self.trim();
+        }
+
+        self.trim();
+
+        if self.buf.remaining() > self.max_retained {
+            out.push(Err(if self.retain_from.is_some() {
+                Error::Unsupported("mp4: mdat before moov exceeds the retained data limit")
+            } else {
+                Error::InvalidData("mp4: box or sample exceeds the retained data limit")
+            }));
+            self.failed = true;
+            self.buf = Chunked::new();
+        }
+
+        out
+    }
+
+    #[inline]
+    fn buf_end(&self) -> u64 {
+        self.buf_pos + self.buf.remaining() as u64
+    }
+
+    /// Handles the top-level box at the cursor, false if more data needed
+    fn next_box(&mut self) -> Result<bool, Error> {
+        if self.cursor < self.buf_pos || self.cursor >= self.buf_end() {
+            return Ok(false);
+        }
+
+        let offset = (self.cursor - self.buf_pos) as usize;
+        let head = copy_range(&self.buf, offset, 16.min(self.buf.remaining() - offset));
+
+        let header = match BoxHeader::peek(&head) {
+            Some(header) => header?,
+            None => return Ok(false),
+        };
+
+        match &header.kind {
+            b"moov" | b"moof" if !(self.has_moov && &header.kind == b"moov") => {
+                let Some(size) = header.size else {
+                    return Err(Error::Unsupported("mp4: unsized moov/moof box"));
+                };
+
+                if self.cursor + size > self.buf_end() {
+                    return Ok(false);
+                }
+
+                let payload = copy_range(
+                    &self.buf,
+                    offset + header.header_size as usize,
+                    (size - header.header_size as u64) as usize,
+                );
+
+                if &header.kind == b"moov" {
+                    self.parse_moov(payload)?;
+                    self.has_moov = true;
+                    self.retain_from = None;
+                } else {
+                    self.parse_moof(self.cursor, payload)?;
+                }
+
+                self.cursor += size;
+
+                // back to the media data skipped while looking for the sample tables
+                if let Some(pos) = self.skipped_mdat.take() {
+                    self.cursor = pos;
+                    self.seek_to = Some(pos);
+                }
+            }
+
+            b"mdat" if !self.has_moov && self.retain_from.is_none() => {
+                let end = header.size.map(|size| self.cursor + size);
+
+                match end {
+                    Some(end) if self.seeking && end > self.buf_end() => {
+                        // the first one, the sample tables may refer to all of them
+                        self.skipped_mdat.get_or_insert(self.cursor);
+                        self.seek_to = Some(end);
+                        self.cursor = end;
+                    }
+
+                    _ => {
+                        self.retain_from = Some(self.cursor);
+                        self.cursor = end.unwrap_or(u64::MAX);
+                    }
+                }
+            }
+
+            _ => self.cursor = header.size.map_or(u64::MAX, |size| self.cursor + size),
+        }
+
+        Ok(true)
+    }
+
+    /// Drops the buffered data which is not needed anymore
+    fn trim(&mut self) {
+        let mut keep_from = self.cursor;
+
+        if let Some(sample) = self.samples.front() {
+            keep_from = keep_from.min(sample.offset);
+        }
+
+        if let Some(pos) = self.retain_from {
+            keep_from = keep_from.min(pos);
+        }
+
+        if keep_from > self.buf_pos {
+            let len = (keep_from - self.buf_pos).min(self.buf.remaining() as u64);
+            self.buf.advance(len as usize);
+            self.buf_pos += len;
+        }
+    }
+
+    fn emit_samples(&mut self, out: &mut Vec<Result<Packet<S>, Error>>) {
+        while let Some(sample) = self.samples.front().copied() {
+            // the data has been skipped already (overlapping or corrupted tables)
+            if sample.offset < self.buf_pos {
+                self.samples.pop_front();
+                continue;
+            }
+
+            if sample.offset + sample.size as u64 > self.buf_end() {
+                break;
+            }
+
+            self.samples.pop_front();
+
+            let data = copy_range(
+                &self.buf,
+                (sample.offset - self.buf_pos) as usize,
+                sample.size as usize,
+            );
+
+            out.push(self.packet(&sample, data));
+        }
+    }
+
+    fn packet(&self, sample: &Sample, mut data: Bytes) -> Result<Packet<S>, Error> {
+        let track = &self.tracks[sample.track];
+        let timescale = track.timescale.max(1) as i128;
+
+        let mut flags = track.kind;
+        if self.tracks.len() > 1 {
+            flags |= FrameFlags::MULTICHANNEL;
+        }
+
+        let is_video = track.kind.contains(FrameFlags::VIDEO_STREAM);
+        if sample.keyframe || !is_video {
+            flags |= FrameFlags::KEYFRAME;
+        }
+
+        if !matches!(track.entry.length_size, 0 | 4) {
+            data = nal::resize_length_prefix(data, track.entry.length_size, 4)?;
+        }
+
+        let dts = sample.dts as i128 * 1_000_000 / timescale;
+        let pts = (sample.dts as i128 + sample.cts as i128) * 1_000_000 / timescale;
+
+        let mut packet = Packet::new(self.source.clone(), track.entry.codec, flags, data)
+            .with_timestamps(dts as u64, pts as i64)
+            .with_track(sample.track as u32);
+
+        packet.duration = (sample.duration as i128 * 1_000_000 / timescale) as u64;
+
+        if flags.contains(FrameFlags::KEYFRAME) {
+            packet = packet.with_params(track.entry.params.clone());
+        }
+
+        if is_video {
+            packet.dimensions = (track.entry.width, track.entry.height);
+            packet.bit_depth = track.bit_depth;
+        }
+
+        Ok(packet)
+    }
+
+    fn reset(&mut self, source: S) {
+        let seeking = self.seeking;
+
+        *self = Self::new().with_max_retained(self.max_retained);
+        self.seeking = seeking;
+        self.source = source;
+    }
+
+    fn parse_moov(&mut self, data: Bytes) -> Result<(), Error> {
+        let mut trex = Vec::new();
+        let mut samples = Vec::new();
+
+        for child in Boxes::new(data) {
+            let (kind, payload) = child?;
+
+            match &kind {
+                b"trak" => {
+                    if let Some((track, tables)) = parse_trak(payload)? {
+                        build_samples(self.tracks.len(), &tables, &mut samples)?;
+                        self.tracks.push(track);
+                    }
+                }
+
+                b"mvex" => {
+                    for child in Boxes::new(payload) {
+                        let (kind, mut payload) = child?;
+
+                        if &kind == b"trex" {
+                            read_full_box(&mut payload)?;
+                            let track_id = payload.read_u32()?;
+                            let _description_index = payload.read_u32()?;
+
+                            trex.push((
+                                track_id,
+                                SampleDefaults {
+                                    duration: payload.read_u32()?,
+                                    size: payload.read_u32()?,
+                                    flags: payload.read_u32()?,
+                                },
+                            ));
+                        }
+                    }
+                }
+
+                _ => (),
+            }
+        }
+
+        for (track_id, defaults) in trex {
+            if let Some(track) = self.tracks.iter_mut().find(|x| x.id == track_id) {
+                track.defaults = defaults;
+            }
+        }
+
+        samples.sort_by_key(|x| x.offset);
+        self.samples.extend(samples);
+
+        Ok(())
+    }
+
+    fn parse_moof(&mut self, moof_pos: u64, data: Bytes) -> Result<(), Error> {
+        let mut samples = Vec::new();
+
+        // without an explicit base the data of a track fragment follows the
+        // data of the previous one
+        let mut data_end = moof_pos;
+
+        for child in Boxes::new(data) {
+            let (kind, payload) = child?;
+
+            if &kind == b"traf" {
+                data_end = self.parse_traf(moof_pos, data_end, payload, &mut samples)?;
+            }
+        }
+
+        let sorted = samples.is_sorted_by_key(|x| x.offset)
+            && match (self.samples.back(), samples.first()) {
+                (Some(last), Some(first)) => last.offset <= first.offset,
+                _ => true,
+            };
+
+        self.samples.extend(samples);
+
+        if !sorted {
+            self.samples.make_contiguous().sort_by_key(|x| x.offset);
+        }
+
+        Ok(())
+    }
+
+    /// Parses the track fragment, returns the end of its data
+    fn parse_traf(
+        &mut self,
+        moof_pos: u64,
+        prev_end: u64,
+        data: Bytes,
+        samples: &mut Vec<Sample>,
+    ) -> Result<u64, Error> {
+        let mut track_idx = None;
+        let mut base_offset = prev_end;
+        let mut defaults = SampleDefaults::default();
+        let mut data_pos = None;
+
+        for child in Boxes::new(data) {
+            let (kind, mut payload) = child?;
+
+            match &kind {
+                b"tfhd" => {
+                    let (_, flags) = read_full_box(&mut payload)?;
+                    let track_id = payload.read_u32()?;
+
+                    let Some(idx) = self.tracks.iter().position(|x| x.id == track_id) else {
+                        return Ok(prev_end);
+                    };
+
+                    track_idx = Some(idx);
+                    defaults = self.tracks[idx].defaults;
+
+                    if flags & 0x01 != 0 {
+                        base_offset = payload.read_u64()?;
+                    } else if flags & TFHD_DEFAULT_BASE_IS_MOOF != 0 {
+                        base_offset = moof_pos;
+                    }
+                    if flags & 0x02 != 0 {
+                        let _description_index = payload.read_u32()?;
+                    }
+                    if flags & 0x08 != 0 {
+                        defaults.duration = payload.read_u32()?;
+                    }
+                    if flags & 0x10 != 0 {
+                        defaults.size = payload.read_u32()?;
+                    }
+                    if flags & 0x20 != 0 {
+                        defaults.flags = payload.read_u32()?;
+                    }
+                }
+
+                b"tfdt" => {
+                    let Some(idx) = track_idx else { continue };
+                    let (version, _) = read_full_box(&mut payload)?;
+
+                    self.tracks[idx].next_dts = if version == 1 {
+                        payload.read_u64()?
+                    } else {
+                        payload.read_u32()? as u64
+                    };
+                }
+
+                b"trun" => {
+                    let Some(idx) = track_idx else { continue };
+                    let (_, flags) = read_full_box(&mut payload)?;
+                    let count = payload.read_u32()?;
+
+                    let mut pos = if flags & 0x01 != 0 {
+                        base_offset.wrapping_add_signed(payload.read_i32()? as i64)
+                    } else {
+                        data_pos.unwrap_or(base_offset)
+                    };
+
+                    let first_flags = if flags & 0x04 != 0 {
+                        Some(payload.read_u32()?)
+                    } else {
+                        None
+                    };
+
+                    let track = &mut self.tracks[idx];
+
+                    for i in 0..count {
+                        let duration = if flags & 0x100 != 0 {
+                            payload.read_u32()?
+                        } else {
+                            defaults.duration
+                        };
+
+                        let size = if flags & 0x200 != 0 {
+                            payload.read_u32()?
+                        } else {
+                            defaults.size
+                        };
+
+                        let sample_flags = if flags & 0x400 != 0 {
+                            payload.read_u32()?
+                        } else if i == 0
+                            && let Some(first_flags) = first_flags
+                        {
+                            first_flags
+                        } else {
+                            defaults.flags
+                        };
+
+                        let cts = if flags & 0x800 != 0 {
+                            payload.read_i32()?
+                        } else {
+                            0
+                        };
+
+                        samples.push(Sample {
+                            track: idx,
+                            offset: pos,
+                            size,
+                            dts: track.next_dts,
+                            cts,
+                            duration,
+                            keyframe: sample_flags & SAMPLE_FLAG_NON_SYNC == 0,
+                        });
+
+                        pos += size as u64;
+                        track.next_dts += duration as u64;
+                    }
+
+                    data_pos = Some(pos);
+                }
+
+                _ => (),
+            }
+        }
+
+        Ok(data_pos.unwrap_or(base_offset))
+    }
+}
+
+impl<S: FrameSource> Default for Mp4Demuxer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Copies `len` bytes starting at `offset` of the buffer, zero-copy if a single chunk is spanned
+fn copy_range(buf: &Chunked<Bytes>, mut offset: usize, len: usize) -> Bytes {
+    let mut out = BytesMut::new();
+    let mut left = len;
+
+    for chunk in buf.iter() {
+        if left == 0 {
+            break;
+        }
+
+        if offset >= chunk.len() {
+            offset -= chunk.len();
+            continue;
+        }
+
+        let end = chunk.len().min(offset + left);
+
+        if left == len && end - offset == len {
+            return chunk.slice(offset..end);
+        }
+
+        out.extend_from_slice(&chunk[offset..end]);
+        left -= end - offset;
+        offset = 0;
+    }
+
+    out.freeze()
+}
+
+fn parse_trak(data: Bytes) -> Result<Option<(Track, SampleTables)>, Error> {
+    let mut track = Track {
+        bit_depth: 8,
+        ..Default::default()
+    };
+    let mut tables = SampleTables::default();
+    let mut entry = None;
+
+    for child in Boxes::new(data) {
+        let (kind, mut payload) = child?;
+
+        match &kind {
+            b"tkhd" => {
+                let (version, _) = read_full_box(&mut payload)?;
+                payload.read_bytes(if version == 1 { 16 } else { 8 })?;
+                track.id = payload.read_u32()?;
+            }
+
+            b"mdia" => {
+                for child in Boxes::new(payload) {
+                    let (kind, mut payload) = child?;
+
+                    match &kind {
+                        b"mdhd" => {
+                            let (version, _) = read_full_box(&mut payload)?;
+                            payload.read_bytes(if version == 1 { 16 } else { 8 })?;
+                            track.timescale = payload.read_u32()?;
+                        }
+
+                        b"hdlr" => {
+                            read_full_box(&mut payload)?;
+                            let _pre_defined = payload.read_u32()?;
+
+                            track.kind = match &payload.read_u32()?.to_be_bytes() {
+                                b"vide" => FrameFlags::VIDEO_STREAM,
+                                b"soun" => FrameFlags::AUDIO_STREAM,
+                                _ => return Ok(None),
+                            };
+                        }
+
+                        b"minf" => {
+                            for child in Boxes::new(payload) {
+                                let (kind, payload) = child?;
+
+                                if &kind == b"stbl" {
+                                    entry = parse_stbl(payload, &mut tables)?;
+                                }
+                            }
+                        }
+
+                        _ => (),
+                    }
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    let Some(entry) = entry else {
+        return Ok(None);
+    };
+
+    if track.kind.contains(FrameFlags::VIDEO_STREAM)
+        && let Ok(info) = VideoInfo::from_params(entry.codec, &entry.params)
+    {
+        track.bit_depth = info.bit_depth;
+    }
+
+    track.entry = entry;
+
+    Ok(Some((track, tables)))
+}
+
+fn parse_stbl(data: Bytes, tables: &mut SampleTables) -> Result<Option<SampleEntry>, Error> {
+    let mut entry = None;
+
+    for child in Boxes::new(data) {
+        let (kind, mut payload) = child?;
+
+        if &kind == b"stsd" {
+            read_full_box(&mut payload)?;
+            let _count = payload.read_u32()?;
+
+            // only the first sample description is used
+            if let Some(first) = Boxes::new(payload).next() {
+                let (kind, payload) = first?;
+                entry = SampleEntry::parse(kind, payload)?;
+            }
+
+            continue;
+        }
+
+        read_full_box(&mut payload)?;
+
+        match &kind {
+            b"stsz" => {
+                let size = payload.read_u32()?;
+                let count = payload.read_u32()? as usize;
+
+                tables.sizes = if size == 0 {
+                    read_table(&mut payload, count, |r| r.read_u32())?
+                } else {
+                    vec![size; count]
+                };
+            }
+
+            b"stco" => {
+                let count = payload.read_u32()? as usize;
+                tables.chunk_offsets =
+                    read_table(&mut payload, count, |r| Ok(r.read_u32()? as u64))?;
+            }
+
+            b"co64" => {
+                let count = payload.read_u32()? as usize;
+                tables.chunk_offsets = read_table(&mut payload, count, |r| r.read_u64())?;
+            }
+
+            b"stsc" => {
+                let count = payload.read_u32()? as usize;
+                tables.sample_to_chunk = read_table(&mut payload, count, |r| {
+                    let first_chunk = r.read_u32()?;
+                    let samples_per_chunk = r.read_u32()?;
+                    let _description_index = r.read_u32()?;
+
+                    Ok((first_chunk, samples_per_chunk))
+                })?;
+            }
+
+            b"stts" => {
+                let count = payload.read_u32()? as usize;
+                tables.time_to_sample =
+                    read_table(&mut payload, count, |r| Ok((r.read_u32()?, r.read_u32()?)))?;
+            }
+
+            // offsets of version 0 are unsigned but negative values are common in the wild
+            b"ctts" => {
+                let count = payload.read_u32()? as usize;
+                tables.composition_offsets =
+                    read_table(&mut payload, count, |r| Ok((r.read_u32()?, r.read_i32()?)))?;
+            }
+
+            b"stss" => {
+                let count = payload.read_u32()? as usize;
+                tables.sync_samples = Some(read_table(&mut payload, count, |r| r.read_u32())?);
+            }
+
+            _ => (),
+        }
+    }
+
+    Ok(entry)
+}
+
+fn read_table<T>(
+    buf: &mut Bytes,
+    count: usize,
+    f: impl Fn(&mut Bytes) -> Result<T, bytes::TryGetError>,
+) -> Result<Vec<T>, Error> {
+    // the count comes from the file, do not trust it for the allocation
+    let mut table = Vec::with_capacity(count.min(buf.remaining()));
+
+    for _ in 0..count {
+        table.push(f(buf)?);
+    }
+
+    Ok(table)
+}
+
+fn build_samples(track: usize, tables: &SampleTables, out: &mut Vec<Sample>) -> Result<(), Error> {
+    let mut stts = tables
+        .time_to_sample
+        .iter()
+        .flat_map(|&(count, delta)| std::iter::repeat_n(delta, count as usize));
+
+    let mut ctts = tables
+        .composition_offsets
+        .iter()
+        .flat_map(|&(count, offset)| std::iter::repeat_n(offset, count as usize));
+
+    let mut sync = tables.sync_samples.as_ref().map(|x| x.iter().peekable());
+    let mut sizes = tables.sizes.iter().copied().enumerate();
+    let mut dts = 0u64;
+
+    for (chunk_idx, &chunk_offset) in tables.chunk_offsets.iter().enumerate() {
+        let chunk_no = chunk_idx as u32 + 1;
+
+        let samples_per_chunk = tables
+            .sample_to_chunk
+            .iter()
+            .rev()
+            .find(|x| x.0 <= chunk_no)
+            .map(|x| x.1)
+            .ok_or(Error::InvalidData("mp4: invalid sample to chunk table"))?;
+
+        let mut offset = chunk_offset;
+
+        for _ in 0..samples_per_chunk {
+            let Some((idx, size)) = sizes.next() else {
+                return Ok(());
+            };
+
+            let keyframe = match &mut sync {
+                Some(sync) => {
+                    let sample_no = idx as u32 + 1;
+
+                    while sync.next_if(|&&x| x < sample_no).is_some() {}
+                    sync.next_if_eq(&&sample_no).is_some()
+                }
+                None => true,
+            };
+
+            let duration = stts.next().unwrap_or(0);
+
+            out.push(Sample {
+                track,
+                offset,
+                size,
+                dts,
+                cts: ctts.next().unwrap_or(0),
+                duration,
+                keyframe,
+            });
+
+            offset += size as u64;
+            dts += duration as u64;
+        }
+    }
+
+    Ok(())
+}
+
+impl<F> Service<F> for Mp4Demuxer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        if frame.source() != &self.source {
+            self.reset(frame.source().clone());
+        }
+
+        let mut out = Vec::new();
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
+
+/// Reads the MP4 file and demuxes it with [`Mp4Demuxer`], seeking over `mdat`
+/// when `moov` goes after it
+#[derive(Debug, Clone, Copy)]
+pub struct Mp4FileReader {
+    chunk_size: usize,
+}
+
+impl Mp4FileReader {
+    pub fn new(chunk_size: usize) -> Self {
+        Self { chunk_size }
+    }
+}
+
+impl Default for Mp4FileReader {
+    fn default() -> Self {
+        Self {
+            chunk_size: 64 * 1024,
+        }
+    }
+}
+
+impl<P: AsRef<Path> + Send + Sync> Service<P> for Mp4FileReader {
+    type Out = Result<Packet<Arc<FileSouce>>, Error>;
+
+    fn handle(&mut self, path: P, cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        async_stream::stream! {
+            let mut file = match tokio::fs::File::open(&path).await {
+                Ok(file) => file,
+                Err(err) => {
+                    yield Err(err.into());
+                    return;
+                }
+            };
+
+            let mut demuxer = Mp4Demuxer::new().with_seeking();
+            demuxer.source = Arc::new(FileSouce::new(path.as_ref().display().to_string()));
+
+            let mut buf = vec![0u8; self.chunk_size];
+
+            loop {
+                match cx.abort_recv.has_changed() {
+                    Ok(true) | Err(_) => break,
+                    _ => ()
+                }
+
+                if let Some(pos) = demuxer.seek_request() {
+                    if let Err(err) = file.seek(SeekFrom::Start(pos)).await {
+                        yield Err(err.into());
+                        break;
+                    }
+
+                    demuxer.seek(pos);
+                }
+
+                match file.read(&mut buf[..]).await {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        for packet in demuxer.push(Bytes::copy_from_slice(&buf[..n])) {
+                            yield packet;
+                        }
+                    }
+                    Err(err) => {
+                        yield Err(err.into());
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes};
+    use flowly_codec::h264::AvcConfig;
+    use flowly_core::{EncodedFrame, Fourcc, Frame, Packet, VideoFrame};
+
+    use super::Mp4Demuxer;
+    use crate::{
+        error::Error,
+        test_fixtures::{PPS, SPS},
+    };
+
+    const VIDEO0: &[u8] = &[0, 0, 0, 2, 0x65, 0x88];
+    const AUDIO0: &[u8] = &[0x21, 0x00];
+    const VIDEO1: &[u8] = &[0, 0, 0, 2, 0x41, 0x9A];
+
+    fn boxed(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.put_u32(payload.len() as u32 + 8);
+        buf.put_slice(kind);
+        buf.put_slice(payload);
+        buf
+    }
+
+    fn full(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        boxed(kind, &[&[0, 0, 0, 0], payload].concat())
+    }
+
+    fn table(entries: &[&[u32]]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.put_u32(entries.len() as u32);
+        for entry in entries {
+            for x in *entry {
+                buf.put_u32(*x);
+            }
+        }
+        buf
+    }
+
+    fn trak(id: u32, timescale: u32, handler: &[u8; 4], stbl: Vec<u8>) -> Vec<u8> {
+        let mut tkhd = vec![0; 8];
+        tkhd.put_u32(id);
+        tkhd.extend_from_slice(&[0; 68]);
+
+        let mut mdhd = vec![0; 8];
+        mdhd.put_u32(timescale);
+        mdhd.extend_from_slice(&[0; 8]);
+
+        let hdlr = [&[0, 0, 0, 0][..], handler, &[0; 13]].concat();
+
+        let mdia = [
+            full(b"mdhd", &mdhd),
+            full(b"hdlr", &hdlr),
+            boxed(b"minf", &boxed(b"stbl", &stbl)),
+        ]
+        .concat();
+
+        boxed(
+            b"trak",
+            &[full(b"tkhd", &tkhd), boxed(b"mdia", &mdia)].concat(),
+        )
+    }
+
+    fn moov(mdat_data: u32) -> Vec<u8> {
+        let mut avc1 = vec![0; 24];
+        avc1.put_u16(1920);
+        avc1.put_u16(1080);
+        avc1.extend_from_slice(&[0; 50]);
+        avc1.extend(boxed(
+            b"avcC",
+            &AvcConfig::from_params([SPS, PPS]).unwrap().to_bytes(),
+        ));
+
+        let video = [
+            full(
+                b"stsd",
+                &[&1u32.to_be_bytes()[..], &boxed(b"avc1", &avc1)].concat(),
+            ),
+            full(b"stts", &table(&[&[2, 3000]])),
+            full(b"ctts", &table(&[&[2, 3000]])),
+            full(b"stss", &table(&[&[1]])),
+            full(
+                b"stsz",
+                &[&[0, 0, 0, 0][..], &table(&[&[6], &[6]])].concat(),
+            ),
+            full(b"stsc", &table(&[&[1, 1, 1]])),
+            full(b"stco", &table(&[&[mdat_data], &[mdat_data + 8]])),
+        ]
+        .concat();
+
+        let esds = [
+            &[0x03, 0x17, 0, 1, 0][..],
+            &[0x04, 0x0F, 0x40, 0x15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[0x05, 0x02, 0x11, 0x90],
+            &[0x06, 0x01, 0x02],
+        ]
+        .concat();
+
+        let mut mp4a = vec![0; 16];
+        mp4a.put_u16(2);
+        mp4a.put_u16(16);
+        mp4a.put_u32(0);
+        mp4a.put_u32(48000 << 16);
+        mp4a.extend(full(b"esds", &esds));
+
+        let audio = [
+            full(
+                b"stsd",
+                &[&1u32.to_be_bytes()[..], &boxed(b"mp4a", &mp4a)].concat(),
+            ),
+            full(b"stts", &table(&[&[1, 1024]])),
+            full(
+                b"stsz",
+                &[&2u32.to_be_bytes()[..], &1u32.to_be_bytes()].concat(),
+            ),
+            full(b"stsc", &table(&[&[1, 1, 1]])),
+            full(b"stco", &table(&[&[mdat_data + 6]])),
+        ]
+        .concat();
+
+        boxed(
+            b"moov",
+            &[
+                trak(1, 90000, b"vide", video),
+                trak(2, 48000, b"soun", audio),
+            ]
+            .concat(),
+        )
+    }
+
+    fn sample_mp4(moov_first: bool) -> Bytes {
+        let ftyp = boxed(b"ftyp", b"isom\0\0\0\0isom");
+        let mdat = boxed(b"mdat", &[VIDEO0, AUDIO0, VIDEO1].concat());
+        let moov_len = moov(0).len() as u32;
+
+        let file = if moov_first {
+            let data = ftyp.len() as u32 + moov_len + 8;
+            [ftyp, moov(data), mdat].concat()
+        } else {
+            let data = ftyp.len() as u32 + 8;
+            [ftyp, mdat, moov(data)].concat()
+        };
+
+        Bytes::from(file)
+    }
+
+    /// Feeds the file in 5-byte chunks following the seek requests
+    fn demux_chunks(demuxer: &mut Mp4Demuxer, file: &Bytes) -> Vec<Result<Packet, Error>> {
+        let mut packets = Vec::new();
+        let mut pos = 0;
+
+        loop {
+            if let Some(to) = demuxer.seek_request() {
+                pos = to as usize;
+                demuxer.seek(to);
+            }
+
+            if pos >= file.len() {
+                break;
+            }
+
+            let end = file.len().min(pos + 5);
+            packets.extend(demuxer.push(file.slice(pos..end)));
+            pos = end;
+        }
+
+        packets
+    }
+
+    #[test]
+    fn test_mp4_demux() {
+        for (moov_first, seeking) in [(true, false), (false, false), (false, true)] {
+            let file = sample_mp4(moov_first);
+            let mut demuxer = Mp4Demuxer::<()>::new();
+            if seeking {
+                demuxer = demuxer.with_seeking();
+            }
+
+            let packets: Vec<_> = demux_chunks(&mut demuxer, &file)
+                .into_iter()
+                .collect::<Result<_, _>>()
+                .unwrap();
+
+            assert_eq!(packets.len(), 3);
+
+            let video = &packets[0];
+            assert_eq!(video.codec(), Fourcc::VIDEO_AVC);
+            assert!(video.is_keyframe() && video.is_video() && video.is_multichannel());
+            assert_eq!((video.dts(), video.pts()), (0, 33_333));
+            assert_eq!(video.params().collect::<Vec<_>>(), [SPS, PPS]);
+            assert_eq!(video.dimensions(), (1920, 1080));
+            assert_eq!(&video.data[..], VIDEO0);
+
+            let audio = &packets[1];
+            assert_eq!(audio.codec(), Fourcc::AUDIO_AAC);
+            assert_eq!(audio.track, 1);
+            assert_eq!(audio.duration, 21_333);
+            assert_eq!(audio.params, [&[0x11, 0x90][..]]);
+            assert_eq!(&audio.data[..], AUDIO0);
+
+            let video = &packets[2];
+            assert!(!video.is_keyframe());
+            assert_eq!((video.dts(), video.pts()), (33_333, 66_666));
+            assert_eq!(&video.data[..], VIDEO1);
+        }
+    }
+
+    #[test]
+    fn test_mp4_demux_limits() {
+        // mdat before moov is retained up to the limit when the reader can't seek
+        let file = sample_mp4(false);
+        let mut demuxer = Mp4Demuxer::<()>::new().with_max_retained(16);
+        let res = demuxer.push(file.slice(..40));
+        assert!(matches!(res[..], [Err(Error::Unsupported(_))]));
+
+        // two track fragments without a base offset, the second one's data
+        // follows the data of the first one
+        let mut video_trun = vec![0, 0, 0x02, 0x01];
+        video_trun.put_u32(1);
+        let offset_pos = video_trun.len();
+        video_trun.put_u32(0);
+        video_trun.put_u32(VIDEO0.len() as u32);
+
+        let mut audio_trun = vec![0, 0, 0x02, 0x00];
+        audio_trun.put_u32(1);
+        audio_trun.put_u32(AUDIO0.len() as u32);
+
+        let moof = |video_trun: &[u8]| {
+            boxed(
+                b"moof",
+                &[
+                    boxed(
+                        b"traf",
+                        &[
+                            full(b"tfhd", &1u32.to_be_bytes()),
+                            boxed(b"trun", video_trun),
+                        ]
+                        .concat(),
+                    ),
+                    boxed(
+                        b"traf",
+                        &[
+                            full(b"tfhd", &2u32.to_be_bytes()),
+                            boxed(b"trun", &audio_trun),
+                        ]
+                        .concat(),
+                    ),
+                ]
+                .concat(),
+            )
+        };
+
+        let data_offset = moof(&video_trun).len() as u32 + 8;
+        video_trun[offset_pos..offset_pos + 4].copy_from_slice(&data_offset.to_be_bytes());
+
+        let file = [
+            sample_mp4(true).to_vec(),
+            moof(&video_trun),
+            boxed(b"mdat", &[VIDEO0, AUDIO0].concat()),
+        ]
+        .concat();
+
+        let packets: Vec<_> = Mp4Demuxer::<()>::new()
+            .push(Bytes::from(file))
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(packets.len(), 5);
+        assert_eq!((packets[3].track, &packets[3].data[..]), (0, VIDEO0));
+        assert_eq!((packets[4].track, &packets[4].data[..]), (1, AUDIO0));
+    }
+
+    #[test]
+    fn test_mp4_demux_malformed() {
+        // a huge box is not buffered past the limit
+        let mut huge = vec![0, 0, 0, 1];
+        huge.extend_from_slice(b"moov");
+        huge.put_u64(1 << 40);
+        huge.resize(100, 0);
+
+        let mut demuxer = Mp4Demuxer::<()>::new().with_max_retained(64);
+        let res = demuxer.push(Bytes::from(huge));
+        assert!(matches!(res[..], [Err(Error::InvalidData(_))]));
+
+        // 2-byte NAL lengths of `avcC` are rewritten to 4 bytes
+        let mut file = sample_mp4(true).to_vec();
+        let avcc = file.windows(4).position(|x| x == b"avcC").unwrap();
+        file[avcc + 8] = 0xFD;
+
+        let packets = Mp4Demuxer::<()>::new().push(Bytes::from(file));
+        let video = packets[0].as_ref().unwrap();
+        assert_eq!(&video.data[..], &[0, 0, 0, 0, 0, 0, 0, 2, 0x65, 0x88]);
+
+        // the seeking reader comes back to the first of two mdat boxes
+        let ftyp = boxed(b"ftyp", b"isom\0\0\0\0isom");
+        let data = ftyp.len() as u32 + 8;
+        let file = [
+            ftyp,
+            boxed(b"mdat", &[VIDEO0, AUDIO0, VIDEO1].concat()),
+            boxed(b"mdat", &[0; 16]),
+            moov(data),
+        ]
+        .concat();
+
+        let mut demuxer = Mp4Demuxer::<()>::new().with_seeking();
+        demuxer.reset(());
+        assert!(demuxer.seeking);
+
+        let packets = demux_chunks(&mut demuxer, &Bytes::from(file));
+        let data: Vec<_> = packets
+            .iter()
+            .map(|x| &x.as_ref().unwrap().data[..])
+            .collect();
+        assert_eq!(data, [VIDEO0, AUDIO0, VIDEO1]);
+    }
+}
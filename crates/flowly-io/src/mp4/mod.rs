@@ -0,0 +1,416 @@
+mod demuxer;
+mod muxer;
+
+pub use demuxer::{DEFAULT_MAX_RETAINED, Mp4Demuxer, Mp4FileReader};
+pub use muxer::{DEFAULT_START_TIMEOUT, Fmp4Muxer, Segment, SegmentKind};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flowly_core::{Fourcc, Reader};
+
+use crate::error::Error;
+
+pub type BoxType = [u8; 4];
+
+/// `sample_is_non_sync_sample` bit of the fragment sample flags
+pub const SAMPLE_FLAG_NON_SYNC: u32 = 0x0001_0000;
+
+/// Parsed box header, `size` includes the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoxHeader {
+    pub kind: BoxType,
+
+    /// Box size, `None` if the box extends to the end of file
+    pub size: Option<u64>,
+    pub header_size: u8,
+}
+
+impl BoxHeader {
+    /// Parses the header from the beginning of `data`, `None` if more data needed
+    pub fn peek(data: &[u8]) -> Option<Result<Self, Error>> {
+        let mut r = data;
+        if r.len() < 8 {
+            return None;
+        }
+
+        let size = r.get_u32();
+        let kind = r.get_u32().to_be_bytes();
+
+        Some(Ok(match size {
+            0 => Self {
+                kind,
+                size: None,
+                header_size: 8,
+            },
+            1 => {
+                if r.len() < 8 {
+                    return None;
+                }
+
+                let size = r.get_u64();
+                if size < 16 {
+                    return Some(Err(Error::InvalidData("mp4: invalid box size")));
+                }
+
+                Self {
+                    kind,
+                    size: Some(size),
+                    header_size: 16,
+                }
+            }
+            2..8 => return Some(Err(Error::InvalidData("mp4: invalid box size"))),
+            size => Self {
+                kind,
+                size: Some(size as u64),
+                header_size: 8,
+            },
+        }))
+    }
+}
+
+/// Iterator over the child boxes of the container payload, yields `(type, payload)`
+#[derive(Debug, Clone)]
+pub struct Boxes {
+    data: Bytes,
+}
+
+impl Boxes {
+    pub fn new(data: Bytes) -> Self {
+        Self { data }
+    }
+}
+
+impl Iterator for Boxes {
+    type Item = Result<(BoxType, Bytes), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let header = match BoxHeader::peek(&self.data) {
+            Some(Ok(header)) => header,
+            Some(Err(err)) => {
+                self.data.clear();
+                return Some(Err(err));
+            }
+            None => {
+                self.data.clear();
+                return Some(Err(Error::InvalidData("mp4: truncated box header")));
+            }
+        };
+
+        let size = header.size.unwrap_or(self.data.len() as u64);
+        if size > self.data.len() as u64 {
+            self.data.clear();
+            return Some(Err(Error::InvalidData("mp4: truncated box")));
+        }
+
+        let mut payload = self.data.split_to(size as usize);
+        payload.advance(header.header_size as usize);
+
+        Some(Ok((header.kind, payload)))
+    }
+}
+
+/// Reads `version` and `flags` of the full box
+#[inline]
+pub fn read_full_box<B: Buf>(buf: &mut B) -> Result<(u8, u32), Error> {
+    Ok((buf.read_u8()?, buf.read_u24()?))
+}
+
+/// Reads length of the MPEG-4 descriptor (ISO/IEC 14496-1 8.3.3)
+fn read_descriptor_len<B: Buf>(buf: &mut B) -> Result<usize, Error> {
+    let mut len = 0;
+
+    for _ in 0..4 {
+        let byte = buf.read_u8()?;
+        len = (len << 7) | (byte & 0x7F) as usize;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    Ok(len)
+}
+
+/// Decoded sample description (`stsd` entry)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SampleEntry {
+    pub codec: Fourcc,
+    pub width: u16,
+    pub height: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+
+    /// Decoding parameters in the form used by [`flowly_core::EncodedFrame::params`]:
+    /// parameter sets for AVC/HEVC, `AudioSpecificConfig` for AAC, `OpusHead` for Opus,
+    /// the configuration record as is for the rest
+    pub params: Vec<Bytes>,
+
+    /// NAL unit length size of the `avcC`/`hvcC` record, 0 for other codecs
+    pub length_size: u8,
+}
+
+impl SampleEntry {
+    /// Parses the sample entry, `Ok(None)` for unsupported formats
+    pub fn parse(kind: BoxType, mut data: Bytes) -> Result<Option<Self>, Error> {
+        match &kind {
+            b"avc1" | b"avc3" | b"hvc1" | b"hev1" | b"av01" | b"vp08" | b"vp09" => {
+                // reserved, data_reference_index, pre_defined, reserved
+                data.read_bytes(24)?;
+                let width = data.read_u16()?;
+                let height = data.read_u16()?;
+
+                // resolutions, reserved, frame_count, compressorname, depth, pre_defined
+                data.read_bytes(50)?;
+
+                let mut entry = Self {
+                    width,
+                    height,
+                    ..Default::default()
+                };
+
+                for child in Boxes::new(data) {
+                    let (child, payload) = child?;
+
+                    match (&kind, &child) {
+                        (b"avc1" | b"avc3", b"avcC") => {
+                            let config = flowly_codec::h264::AvcConfig::parse(&payload)?;
+                            entry.codec = Fourcc::VIDEO_AVC;
+                            entry.params = config.params().cloned().collect();
+                            entry.length_size = config.length_size;
+                        }
+                        (b"hvc1" | b"hev1", b"hvcC") => {
+                            let config = flowly_codec::h265::HevcConfig::parse(&payload)?;
+                            entry.codec = Fourcc::VIDEO_HEVC;
+                            entry.params = config.params().cloned().collect();
+                            entry.length_size = config.length_size;
+                        }
+                        (b"av01", b"av1C") => {
+                            entry.codec = Fourcc::VIDEO_AV1;
+                            entry.params = vec![payload];
+                        }
+                        (b"vp08" | b"vp09", b"vpcC") => {
+                            entry.codec = if &kind == b"vp08" {
+                                Fourcc::VIDEO_VP8
+                            } else {
+                                Fourcc::VIDEO_VP9
+                            };
+                            entry.params = vec![payload];
+                        }
+                        _ => (),
+                    }
+                }
+
+                // `avc3`/`hev1` may carry parameter sets in-band only
+                if entry.codec == Fourcc::default() {
+                    entry.codec = match &kind {
+                        b"avc1" | b"avc3" => Fourcc::VIDEO_AVC,
+                        b"hvc1" | b"hev1" => Fourcc::VIDEO_HEVC,
+                        _ => return Ok(None),
+                    };
+                }
+
+                Ok(Some(entry))
+            }
+
+            b"mp4a" | b"Opus" | b"fLaC" | b"ac-3" | b"ec-3" | b".mp3" | b"alaw" | b"ulaw" => {
+                // reserved, data_reference_index
+                data.read_bytes(8)?;
+                let version = data.read_u16()?;
+                data.read_bytes(6)?;
+
+                let channels = data.read_u16()?;
+                let _sample_size = data.read_u16()?;
+                data.read_bytes(4)?;
+                let sample_rate = data.read_u32()? >> 16;
+
+                // QuickTime sound description extensions
+                match version {
+                    1 => data.read_bytes(16)?,
+                    2 => data.read_bytes(36)?,
+                    _ => Bytes::new(),
+                };
+
+                let mut entry = Self {
+                    codec: match &kind {
+                        b"mp4a" => Fourcc::AUDIO_AAC,
+                        b"Opus" => Fourcc::AUDIO_OPUS,
+                        b"fLaC" => Fourcc::AUDIO_FLAC,
+                        b"ac-3" => Fourcc::AUDIO_AC3,
+                        b"ec-3" => Fourcc::AUDIO_EC3,
+                        b".mp3" => Fourcc::AUDIO_MP3,
+                        b"alaw" => Fourcc::AUDIO_PCM_ALAW,
+                        _ => Fourcc::AUDIO_PCM_ULAW,
+                    },
+                    channels,
+                    sample_rate,
+                    ..Default::default()
+                };
+
+                for child in Boxes::new(data) {
+                    let (child, mut payload) = child?;
+
+                    match &child {
+                        b"esds" => {
+                            let (object_type, config) = parse_esds(payload)?;
+
+                            // MPEG-1/2 audio in `mp4a`
+                            if matches!(object_type, 0x69 | 0x6B) {
+                                entry.codec = Fourcc::AUDIO_MP3;
+                            }
+
+                            entry.params.extend(config);
+                        }
+                        b"dOps" => entry.params = vec![opus_head_from_dops(&payload)?],
+                        b"dfLa" => {
+                            read_full_box(&mut payload)?;
+                            entry.params = vec![payload];
+                        }
+                        b"dac3" | b"dec3" => entry.params = vec![payload],
+                        _ => (),
+                    }
+                }
+
+                Ok(Some(entry))
+            }
+
+            _ => Ok(None),
+        }
+    }
+}
+
+/// `ES_Descriptor` of the `esds` box, returns `objectTypeIndication` and `DecoderSpecificInfo`
+fn parse_esds(mut data: Bytes) -> Result<(u8, Option<Bytes>), Error> {
+    read_full_box(&mut data)?;
+
+    let mut object_type = 0;
+
+    while data.has_remaining() {
+        let tag = data.read_u8()?;
+        let len = read_descriptor_len(&mut data)?;
+
+        match tag {
+            // ES_Descriptor, the nested descriptors follow the header
+            0x03 => {
+                data.read_u16()?;
+                let flags = data.read_u8()?;
+
+                if flags & 0x80 != 0 {
+                    data.read_u16()?;
+                }
+
+                if flags & 0x40 != 0 {
+                    let url_len = data.read_u8()? as usize;
+                    data.read_bytes(url_len)?;
+                }
+
+                if flags & 0x20 != 0 {
+                    data.read_u16()?;
+                }
+            }
+
+            // DecoderConfigDescriptor
+            0x04 => {
+                object_type = data.read_u8()?;
+                data.read_bytes(12)?;
+            }
+
+            // DecoderSpecificInfo
+            0x05 => return Ok((object_type, Some(data.read_bytes(len)?))),
+
+            _ => {
+                data.read_bytes(len.min(data.remaining()))?;
+            }
+        }
+    }
+
+    Ok((object_type, None))
+}
+
+/// Converts `OpusSpecificBox` into the Ogg `OpusHead` packet (RFC 7845 5.1)
+pub fn opus_head_from_dops(mut data: &[u8]) -> Result<Bytes, Error> {
+    let r = &mut data;
+    let _version = r.read_u8()?;
+    let channels = r.read_u8()?;
+
+    let mut buf = BytesMut::with_capacity(19 + 2 + channels as usize);
+    buf.put_slice(b"OpusHead");
+    buf.put_u8(1);
+    buf.put_u8(channels);
+    buf.put_u16_le(r.read_u16()?);
+    buf.put_u32_le(r.read_u32()?);
+    buf.put_i16_le(r.read_i16()?);
+
+    let family = r.read_u8()?;
+    buf.put_u8(family);
+
+    if family != 0 {
+        buf.put_u8(r.read_u8()?);
+        buf.put_u8(r.read_u8()?);
+        buf.put_slice(&r.read_bytes(channels as usize)?);
+    }
+
+    Ok(buf.freeze())
+}
+
+/// Builds `OpusSpecificBox` payload from the Ogg `OpusHead` packet
+pub fn dops_from_opus_head(mut data: &[u8]) -> Result<Bytes, Error> {
+    let r = &mut data;
+
+    let mut magic = [0u8; 8];
+    r.read_slice(&mut magic)?;
+
+    if &magic != b"OpusHead" {
+        return Err(Error::InvalidData("opus: invalid OpusHead magic"));
+    }
+
+    let _version = r.read_u8()?;
+    let channels = r.read_u8()?;
+
+    let mut buf = BytesMut::with_capacity(11 + 2 + channels as usize);
+    buf.put_u8(0);
+    buf.put_u8(channels);
+    buf.put_u16(r.try_get_u16_le()?);
+    buf.put_u32(r.try_get_u32_le()?);
+    buf.put_i16(r.try_get_i16_le()?);
+
+    let family = r.read_u8()?;
+    buf.put_u8(family);
+
+    if family != 0 {
+        buf.put_u8(r.read_u8()?);
+        buf.put_u8(r.read_u8()?);
+        buf.put_slice(&r.read_bytes(channels as usize)?);
+    }
+
+    Ok(buf.freeze())
+}
+
+/// Writes the box, `f` writes the payload
+pub fn put_box(buf: &mut BytesMut, kind: &BoxType, f: impl FnOnce(&mut BytesMut)) {
+    let start = buf.len();
+
+    buf.put_u32(0);
+    buf.put_slice(kind);
+    f(buf);
+
+    let size = (buf.len() - start) as u32;
+    buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+/// Writes the full box, `f` writes the payload following version and flags
+pub fn put_full_box(
+    buf: &mut BytesMut,
+    kind: &BoxType,
+    version: u8,
+    flags: u32,
+    f: impl FnOnce(&mut BytesMut),
+) {
+    put_box(buf, kind, |buf| {
+        buf.put_u8(version);
+        buf.put_uint(flags as u64, 3);
+        f(buf);
+    })
+}
@@ -0,0 +1,858 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly_codec::{
+    BitstreamConverter, VideoInfo, aac::AudioSpecificConfig, h264::AvcConfig, h265::HevcConfig,
+};
+use flowly_core::{EncodedFrame, Fourcc, Frame, Multichannel, Packet, VideoFrame};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+const VIDEO_TIMESCALE: u32 = 90_000;
+
+/// How long the init segment waits for the tracks by default (microseconds)
+pub const DEFAULT_START_TIMEOUT: u64 = 1_000_000;
+
+const SAMPLE_FLAGS_SYNC: u32 = 0x0200_0000;
+const SAMPLE_FLAGS_NON_SYNC: u32 = 0x0100_0000 | SAMPLE_FLAG_NON_SYNC;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    /// `ftyp` + `moov`
+    Init,
+
+    /// `moof` + `mdat`
+    Media,
+}
+
+/// Output of [`Fmp4Muxer`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    pub data: Bytes,
+
+    /// Decoding timestamp of the first sample in microseconds (0 for init segment)
+    pub dts: u64,
+
+    /// Duration in microseconds (0 for init segment)
+    pub duration: u64,
+
+    /// Fragment starts with the video keyframe (always true for audio only streams)
+    pub keyframe: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Sample {
+    dts: u64,
+    pts: i64,
+    keyframe: bool,
+    data: Bytes,
+}
+
+#[derive(Debug, Clone)]
+struct Track {
+    entry: SampleEntry,
+
+    /// Codec configuration of the sample entry (`avcC`, `hvcC`, `av1C`, ASC or `dOps`)
+    config: Bytes,
+    timescale: u32,
+    samples: Vec<Sample>,
+
+    /// Duration of the last written sample in timescale units
+    last_duration: u64,
+}
+
+impl Track {
+    #[inline]
+    fn is_video(&self) -> bool {
+        !is_audio(self.entry.codec)
+    }
+
+    #[inline]
+    fn to_timescale(&self, us: i64) -> i64 {
+        (us as i128 * self.timescale as i128 / 1_000_000) as i64
+    }
+}
+
+/// Fragmented MP4 (CMAF) muxer.
+///
+/// Accepts AVC, HEVC, AV1, AAC and Opus frames (tracks are keyed by `Frame::codec()`)
+/// and yields an init segment followed by `moof` + `mdat` fragments. A fragment is
+/// cut on the video keyframe once it lasts at least `fragment_duration` (on every
+/// keyframe by default), audio only streams are cut by duration only. The init
+/// segment is written with the first fragment and re-sent when video parameter
+/// sets change, tracks appearing after that are ignored. So the first fragment
+/// waits until all expected tracks have appeared ([`Fmp4Muxer::with_expected_tracks`],
+/// otherwise until the first video keyframe) or the pending frames last
+/// `start_timeout`. Frames flagged `LAST` flush the pending fragment. Timestamps
+/// are shifted so the first frame starts at zero.
+#[derive(Debug, Clone)]
+pub struct Fmp4Muxer {
+    fragment_duration: u64,
+    expected_tracks: Option<usize>,
+    start_timeout: u64,
+    tracks: Vec<Track>,
+    init_written: bool,
+    sequence_number: u32,
+    start_dts: Option<u64>,
+    converter: BitstreamConverter,
+}
+
+impl Fmp4Muxer {
+    pub fn new() -> Self {
+        Self {
+            fragment_duration: 0,
+            expected_tracks: None,
+            start_timeout: DEFAULT_START_TIMEOUT,
+            tracks: Vec::new(),
+            init_written: false,
+            sequence_number: 0,
+            start_dts: None,
+            converter: BitstreamConverter::length_prefixed(),
+        }
+    }
+
+    /// Minimal fragment duration in microseconds
+    pub fn with_fragment_duration(mut self, duration: u64) -> Self {
+        self.fragment_duration = duration;
+        self
+    }
+
+    /// Number of tracks the init segment waits for
+    pub fn with_expected_tracks(mut self, count: usize) -> Self {
+        self.expected_tracks = Some(count);
+        self
+    }
+
+    /// Longest wait for the tracks before the init segment in microseconds
+    pub fn with_start_timeout(mut self, timeout: u64) -> Self {
+        self.start_timeout = timeout;
+        self
+    }
+
+    /// Adds the frame to the pending fragment, returns the segments completed by it
+    pub fn push<F: EncodedFrame + Multichannel + VideoFrame>(
+        &mut self,
+        frame: F,
+    ) -> Result<Vec<Segment>, Error> {
+        let codec = frame.codec();
+        let is_last = frame.is_last();
+
+        if !matches!(
+            codec,
+            Fourcc::VIDEO_AVC
+                | Fourcc::VIDEO_HEVC
+                | Fourcc::VIDEO_AV1
+                | Fourcc::AUDIO_AAC
+                | Fourcc::AUDIO_OPUS
+        ) {
+            return Err(flowly_codec::Error::UnsupportedCodec(codec).into());
+        }
+
+        let packet = if matches!(codec, Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC) {
+            self.converter.convert(frame)?
+        } else {
+            Packet::from_frame(frame)
+        };
+
+        let start = *self.start_dts.get_or_insert(packet.dts);
+        let dts = packet.dts.saturating_sub(start);
+        let pts = packet.pts - start as i64;
+
+        let mut out = Vec::new();
+
+        let idx = match self.tracks.iter().position(|x| x.entry.codec == codec) {
+            Some(idx) => idx,
+            None if self.init_written => return Ok(out),
+            None => {
+                // a video stream has to start with the keyframe
+                if !is_audio(codec) && !packet.is_keyframe() {
+                    return Ok(out);
+                }
+
+                self.tracks.push(new_track(codec, &packet.params)?);
+                self.tracks.len() - 1
+            }
+        };
+
+        let is_video = self.tracks[idx].is_video();
+        let has_video = self.tracks.iter().any(Track::is_video);
+        let pending_start = self.pending_start();
+
+        let should_cut = pending_start.is_some_and(|start| {
+            let duration = dts.saturating_sub(start);
+
+            let ready = self.init_written
+                || duration >= self.start_timeout
+                || match self.expected_tracks {
+                    Some(count) => self.tracks.len() >= count,
+                    None => has_video,
+                };
+
+            if !ready {
+                false
+            } else if has_video {
+                is_video && packet.is_keyframe() && duration >= self.fragment_duration
+            } else {
+                duration >= self.fragment_duration
+            }
+        });
+
+        if should_cut {
+            out.extend(self.flush_fragment(Some((idx, dts))));
+        }
+
+        // changed parameter sets require the new init segment
+        if is_video
+            && packet.is_keyframe()
+            && !packet.params.is_empty()
+            && packet.params != self.tracks[idx].entry.params
+        {
+            let track = new_track(codec, &packet.params)?;
+
+            out.extend(self.flush_fragment(Some((idx, dts))));
+            self.tracks[idx] = Track {
+                samples: std::mem::take(&mut self.tracks[idx].samples),
+                ..track
+            };
+            self.init_written = false;
+        }
+
+        self.tracks[idx].samples.push(Sample {
+            dts,
+            pts,
+            keyframe: packet.is_keyframe() || !is_video,
+            data: packet.data,
+        });
+
+        if is_last {
+            out.extend(self.flush_fragment(None));
+        }
+
+        Ok(out)
+    }
+
+    /// Writes all pending samples as a fragment
+    pub fn flush(&mut self) -> Vec<Segment> {
+        self.flush_fragment(None)
+    }
+
+    fn pending_start(&self) -> Option<u64> {
+        self.tracks
+            .iter()
+            .filter_map(|x| x.samples.first())
+            .map(|x| x.dts)
+            .min()
+    }
+
+    /// `next` is the track and dts of the frame following the fragment
+    fn flush_fragment(&mut self, next: Option<(usize, u64)>) -> Vec<Segment> {
+        let mut out = Vec::with_capacity(2);
+
+        let Some(start) = self.pending_start() else {
+            return out;
+        };
+
+        if !self.init_written {
+            out.push(Segment {
+                kind: SegmentKind::Init,
+                data: self.init_segment(),
+                dts: 0,
+                duration: 0,
+                keyframe: false,
+            });
+
+            self.init_written = true;
+        }
+
+        self.sequence_number += 1;
+
+        let keyframe = self
+            .tracks
+            .iter()
+            .filter(|x| x.is_video())
+            .all(|x| x.samples.first().is_none_or(|x| x.keyframe));
+
+        let (data, end) = self.fragment(next);
+
+        for track in &mut self.tracks {
+            track.samples.clear();
+        }
+
+        out.push(Segment {
+            kind: SegmentKind::Media,
+            data,
+            dts: start,
+            duration: end.saturating_sub(start),
+            keyframe,
+        });
+
+        out
+    }
+
+    fn init_segment(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1024);
+
+        put_box(&mut buf, b"ftyp", |buf| {
+            buf.put_slice(b"iso6");
+            buf.put_u32(0);
+            buf.put_slice(b"iso6cmfcmp41");
+        });
+
+        put_box(&mut buf, b"moov", |buf| {
+            put_full_box(buf, b"mvhd", 0, 0, |buf| {
+                buf.put_u64(0);
+                buf.put_u32(1000);
+                buf.put_u32(0);
+                buf.put_u32(0x0001_0000);
+                buf.put_u16(0x0100);
+                buf.put_slice(&[0; 10]);
+                put_matrix(buf);
+                buf.put_slice(&[0; 24]);
+                buf.put_u32(self.tracks.len() as u32 + 1);
+            });
+
+            for (idx, track) in self.tracks.iter().enumerate() {
+                put_trak(buf, idx as u32 + 1, track);
+            }
+
+            put_box(buf, b"mvex", |buf| {
+                for idx in 0..self.tracks.len() {
+                    put_full_box(buf, b"trex", 0, 0, |buf| {
+                        buf.put_u32(idx as u32 + 1);
+                        buf.put_u32(1);
+                        buf.put_u32(0);
+                        buf.put_u32(0);
+                        buf.put_u32(0);
+                    });
+                }
+            });
+        });
+
+        buf.freeze()
+    }
+
+    /// Writes `moof` + `mdat`, returns the data and the end timestamp of the fragment
+    fn fragment(&mut self, next: Option<(usize, u64)>) -> (Bytes, u64) {
+        let mut end = 0u64;
+        let mut trun_offsets = Vec::new();
+        let mut buf = BytesMut::with_capacity(
+            self.tracks
+                .iter()
+                .flat_map(|x| &x.samples)
+                .map(|x| x.data.len() + 16)
+                .sum::<usize>()
+                + 256,
+        );
+
+        put_box(&mut buf, b"moof", |buf| {
+            put_full_box(buf, b"mfhd", 0, 0, |buf| buf.put_u32(self.sequence_number));
+
+            for (idx, track) in self.tracks.iter_mut().enumerate() {
+                if track.samples.is_empty() {
+                    continue;
+                }
+
+                let next_dts = match next {
+                    Some((next_idx, dts)) if next_idx == idx => Some(dts),
+                    _ => None,
+                };
+
+                let mut durations = track
+                    .samples
+                    .windows(2)
+                    .map(|x| {
+                        (track.to_timescale(x[1].dts as i64) - track.to_timescale(x[0].dts as i64))
+                            as u64
+                    })
+                    .collect::<Vec<_>>();
+
+                let last = track.samples.last().unwrap();
+                let last_duration = match next_dts {
+                    Some(dts) if dts > last.dts => {
+                        (track.to_timescale(dts as i64) - track.to_timescale(last.dts as i64))
+                            as u64
+                    }
+                    _ => durations.last().copied().unwrap_or(track.last_duration),
+                };
+
+                durations.push(last_duration);
+                track.last_duration = last_duration;
+
+                end = end.max(last.dts + last_duration * 1_000_000 / track.timescale as u64);
+
+                put_box(buf, b"traf", |buf| {
+                    // default-base-is-moof
+                    put_full_box(buf, b"tfhd", 0, 0x02_0000, |buf| {
+                        buf.put_u32(idx as u32 + 1)
+                    });
+
+                    put_full_box(buf, b"tfdt", 1, 0, |buf| {
+                        buf.put_u64(track.to_timescale(track.samples[0].dts as i64) as u64)
+                    });
+
+                    put_full_box(
+                        buf,
+                        b"trun",
+                        1,
+                        0x01 | 0x100 | 0x200 | 0x400 | 0x800,
+                        |buf| {
+                            buf.put_u32(track.samples.len() as u32);
+
+                            trun_offsets.push(buf.len());
+                            buf.put_i32(0);
+
+                            for (sample, duration) in track.samples.iter().zip(&durations) {
+                                buf.put_u32(*duration as u32);
+                                buf.put_u32(sample.data.len() as u32);
+                                buf.put_u32(if sample.keyframe {
+                                    SAMPLE_FLAGS_SYNC
+                                } else {
+                                    SAMPLE_FLAGS_NON_SYNC
+                                });
+                                buf.put_i32(
+                                    (track.to_timescale(sample.pts)
+                                        - track.to_timescale(sample.dts as i64))
+                                        as i32,
+                                );
+                            }
+                        },
+                    );
+                });
+            }
+        });
+
+        // data offsets are relative to the beginning of `moof`
+        let mut data_offset = buf.len() + 8;
+        let tracks = self.tracks.iter().filter(|x| !x.samples.is_empty());
+
+        for (pos, track) in trun_offsets.into_iter().zip(tracks) {
+            buf[pos..pos + 4].copy_from_slice(&(data_offset as i32).to_be_bytes());
+            data_offset += track.samples.iter().map(|x| x.data.len()).sum::<usize>();
+        }
+
+        put_box(&mut buf, b"mdat", |buf| {
+            for sample in self.tracks.iter().flat_map(|x| &x.samples) {
+                buf.put_slice(&sample.data);
+            }
+        });
+
+        (buf.freeze(), end)
+    }
+}
+
+impl Default for Fmp4Muxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[inline]
+fn is_audio(codec: Fourcc) -> bool {
+    matches!(codec, Fourcc::AUDIO_AAC | Fourcc::AUDIO_OPUS)
+}
+
+fn new_track(codec: Fourcc, params: &[Bytes]) -> Result<Track, Error> {
+    let mut entry = SampleEntry {
+        codec,
+        params: params.to_vec(),
+        ..Default::default()
+    };
+
+    let (timescale, last_duration, config) = match codec {
+        Fourcc::AUDIO_AAC => {
+            let asc = params.first().ok_or(flowly_codec::Error::MissingParams(
+                "aac: AudioSpecificConfig",
+            ))?;
+
+            let (sample_rate, channels) = parse_asc(asc)?;
+            entry.sample_rate = sample_rate;
+            entry.channels = channels;
+
+            (sample_rate, 1024, asc.clone())
+        }
+
+        Fourcc::AUDIO_OPUS => {
+            let head = params
+                .first()
+                .ok_or(flowly_codec::Error::MissingParams("opus: OpusHead"))?;
+
+            entry.channels = head.get(9).copied().unwrap_or(2) as u16;
+            entry.sample_rate = 48_000;
+
+            (48_000, 960, dops_from_opus_head(head)?)
+        }
+
+        _ => {
+            if params.is_empty() {
+                return Err(
+                    flowly_codec::Error::MissingParams("video: codec configuration").into(),
+                );
+            }
+
+            if let Ok(info) = VideoInfo::from_params(codec, params) {
+                (entry.width, entry.height) = info.dimensions();
+            }
+
+            let config = match codec {
+                Fourcc::VIDEO_AVC => AvcConfig::from_params(params)?.to_bytes(),
+                Fourcc::VIDEO_HEVC => HevcConfig::from_params(params)?.to_bytes(),
+                _ => params[0].clone(),
+            };
+
+            (VIDEO_TIMESCALE, 3000, config)
+        }
+    };
+
+    if timescale == 0 {
+        return Err(Error::InvalidData("mp4: zero timescale"));
+    }
+
+    Ok(Track {
+        entry,
+        config,
+        timescale,
+        samples: Vec::new(),
+        last_duration,
+    })
+}
+
+/// Sample rate and channel count of the `AudioSpecificConfig`
+fn parse_asc(asc: &[u8]) -> Result<(u32, u16), Error> {
+    let asc = AudioSpecificConfig::parse(asc)?;
+
+    Ok((asc.sample_rate, asc.channels()))
+}
+
+fn put_matrix(buf: &mut BytesMut) {
+    for x in [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000u32] {
+        buf.put_u32(x);
+    }
+}
+
+fn put_trak(buf: &mut BytesMut, track_id: u32, track: &Track) {
+    let entry = &track.entry;
+    let is_video = track.is_video();
+
+    put_box(buf, b"trak", |buf| {
+        // enabled, in movie
+        put_full_box(buf, b"tkhd", 0, 0x03, |buf| {
+            buf.put_u64(0);
+            buf.put_u32(track_id);
+            buf.put_u32(0);
+            buf.put_u32(0);
+            buf.put_u64(0);
+            buf.put_u16(0);
+            buf.put_u16(0);
+            buf.put_u16(if is_video { 0 } else { 0x0100 });
+            buf.put_u16(0);
+            put_matrix(buf);
+            buf.put_u32((entry.width as u32) << 16);
+            buf.put_u32((entry.height as u32) << 16);
+        });
+
+        put_box(buf, b"mdia", |buf| {
+            put_full_box(buf, b"mdhd", 0, 0, |buf| {
+                buf.put_u64(0);
+                buf.put_u32(track.timescale);
+                buf.put_u32(0);
+
+                // "und" language
+                buf.put_u16(0x55C4);
+                buf.put_u16(0);
+            });
+
+            put_full_box(buf, b"hdlr", 0, 0, |buf| {
+                buf.put_u32(0);
+                buf.put_slice(if is_video { b"vide" } else { b"soun" });
+                buf.put_slice(&[0; 12]);
+                buf.put_slice(if is_video { b"Video\0" } else { b"Audio\0" });
+            });
+
+            put_box(buf, b"minf", |buf| {
+                if is_video {
+                    put_full_box(buf, b"vmhd", 0, 1, |buf| buf.put_u64(0));
+                } else {
+                    put_full_box(buf, b"smhd", 0, 0, |buf| buf.put_u32(0));
+                }
+
+                put_box(buf, b"dinf", |buf| {
+                    put_full_box(buf, b"dref", 0, 0, |buf| {
+                        buf.put_u32(1);
+
+                        // self-contained
+                        put_full_box(buf, b"url ", 0, 1, |_| ());
+                    });
+                });
+
+                put_box(buf, b"stbl", |buf| {
+                    put_full_box(buf, b"stsd", 0, 0, |buf| {
+                        buf.put_u32(1);
+                        put_sample_entry(buf, track);
+                    });
+
+                    put_full_box(buf, b"stts", 0, 0, |buf| buf.put_u32(0));
+                    put_full_box(buf, b"stsc", 0, 0, |buf| buf.put_u32(0));
+                    put_full_box(buf, b"stsz", 0, 0, |buf| buf.put_u64(0));
+                    put_full_box(buf, b"stco", 0, 0, |buf| buf.put_u32(0));
+                });
+            });
+        });
+    });
+}
+
+fn put_sample_entry(buf: &mut BytesMut, track: &Track) {
+    let entry = &track.entry;
+    let config = &track.config[..];
+
+    let visual = |buf: &mut BytesMut, kind: &BoxType, config_kind: &BoxType, config: &[u8]| {
+        put_box(buf, kind, |buf| {
+            buf.put_slice(&[0; 6]);
+            buf.put_u16(1);
+            buf.put_slice(&[0; 16]);
+            buf.put_u16(entry.width);
+            buf.put_u16(entry.height);
+
+            // 72 dpi
+            buf.put_u32(0x0048_0000);
+            buf.put_u32(0x0048_0000);
+            buf.put_u32(0);
+            buf.put_u16(1);
+            buf.put_slice(&[0; 32]);
+            buf.put_u16(0x0018);
+            buf.put_i16(-1);
+
+            put_box(buf, config_kind, |buf| buf.put_slice(config));
+        })
+    };
+
+    let audio = |buf: &mut BytesMut, kind: &BoxType, f: &dyn Fn(&mut BytesMut)| {
+        put_box(buf, kind, |buf| {
+            buf.put_slice(&[0; 6]);
+            buf.put_u16(1);
+            buf.put_slice(&[0; 8]);
+            buf.put_u16(entry.channels);
+            buf.put_u16(16);
+            buf.put_u32(0);
+            buf.put_u32(entry.sample_rate.min(u16::MAX as u32) << 16);
+            f(buf);
+        })
+    };
+
+    match entry.codec {
+        Fourcc::VIDEO_AVC => visual(buf, b"avc1", b"avcC", config),
+        Fourcc::VIDEO_HEVC => visual(buf, b"hvc1", b"hvcC", config),
+        Fourcc::VIDEO_AV1 => visual(buf, b"av01", b"av1C", config),
+
+        Fourcc::AUDIO_AAC => audio(buf, b"mp4a", &|buf| {
+            let asc = config;
+
+            put_full_box(buf, b"esds", 0, 0, |buf| {
+                // ES_Descriptor
+                buf.put_u8(0x03);
+                buf.put_u8(23 + asc.len() as u8);
+                buf.put_u16(1);
+                buf.put_u8(0);
+
+                // DecoderConfigDescriptor: Audio ISO/IEC 14496-3, AudioStream
+                buf.put_u8(0x04);
+                buf.put_u8(15 + asc.len() as u8);
+                buf.put_u8(0x40);
+                buf.put_u8(0x15);
+                buf.put_slice(&[0; 11]);
+
+                // DecoderSpecificInfo
+                buf.put_u8(0x05);
+                buf.put_u8(asc.len() as u8);
+                buf.put_slice(asc);
+
+                // SLConfigDescriptor
+                buf.put_slice(&[0x06, 0x01, 0x02]);
+            });
+        }),
+
+        _ => audio(buf, b"Opus", &|buf| {
+            put_box(buf, b"dOps", |buf| buf.put_slice(config));
+        }),
+    }
+}
+
+impl<F: EncodedFrame + Multichannel + VideoFrame> Service<F> for Fmp4Muxer {
+    type Out = Result<Segment, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let out: Vec<_> = match self.push(frame) {
+            Ok(segments) => segments.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        };
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use flowly_codec::aac::AudioSpecificConfig;
+    use flowly_core::{EncodedFrame, Fourcc, Frame, FrameFlags, Packet, VideoFrame};
+
+    use super::{Fmp4Muxer, SegmentKind, new_track};
+    use crate::{
+        mp4::Mp4Demuxer,
+        test_fixtures::{PPS, SPS},
+    };
+
+    const ASC: &[u8] = &[0x11, 0x90];
+
+    fn video(dts: u64, keyframe: bool) -> Packet {
+        let mut flags = FrameFlags::VIDEO_STREAM;
+        if keyframe {
+            flags |= FrameFlags::KEYFRAME;
+        }
+
+        Packet::new(
+            (),
+            Fourcc::VIDEO_AVC,
+            flags,
+            Bytes::from(vec![0, 0, 0, 2, 0x65, dts as u8]),
+        )
+        .with_timestamps(dts, dts as i64 + 40_000)
+        .with_params(vec![Bytes::from_static(SPS), Bytes::from_static(PPS)])
+    }
+
+    fn audio(dts: u64) -> Packet {
+        Packet::new(
+            (),
+            Fourcc::AUDIO_AAC,
+            FrameFlags::AUDIO_STREAM,
+            Bytes::from(vec![0x21, dts as u8]),
+        )
+        .with_timestamps(dts, dts as i64)
+        .with_params(vec![Bytes::from_static(ASC)])
+    }
+
+    #[test]
+    fn test_fmp4_mux_demux() {
+        let mut frames = Vec::new();
+        for i in 0..4u64 {
+            frames.push(video(1_000_000 + i * 40_000, i % 2 == 0));
+            frames.push(audio(1_000_000 + i * 20_000));
+        }
+
+        let last = frames.last_mut().unwrap();
+        last.flags |= FrameFlags::LAST;
+
+        let mut muxer = Fmp4Muxer::new();
+        let segments: Vec<_> = frames
+            .iter()
+            .cloned()
+            .flat_map(|x| muxer.push(x).unwrap())
+            .collect();
+
+        assert_eq!(
+            segments.iter().map(|x| x.kind).collect::<Vec<_>>(),
+            [SegmentKind::Init, SegmentKind::Media, SegmentKind::Media]
+        );
+        assert!(segments[1].keyframe && segments[2].keyframe);
+        assert_eq!((segments[2].dts, segments[2].duration), (40_000, 120_000));
+
+        let mut demuxer = Mp4Demuxer::<()>::new();
+        let mut packets: Vec<_> = segments
+            .into_iter()
+            .flat_map(|x| demuxer.push(x.data))
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        packets.sort_by_key(|x| (x.track, x.dts));
+        assert_eq!(packets.len(), frames.len());
+
+        let (video, audio): (Vec<_>, Vec<_>) = packets.iter().partition(|x| x.is_video());
+
+        for (i, packet) in video.iter().enumerate() {
+            assert_eq!(packet.codec, Fourcc::VIDEO_AVC);
+            assert_eq!(packet.dts(), i as u64 * 40_000);
+            assert_eq!(packet.pts(), i as i64 * 40_000 + 40_000);
+            assert_eq!(packet.is_keyframe(), i % 2 == 0);
+            assert_eq!(packet.dimensions(), (1920, 1080));
+            assert_eq!(&packet.data[..], &frames[i * 2].data[..]);
+        }
+
+        for (i, packet) in audio.iter().enumerate() {
+            assert_eq!(packet.codec, Fourcc::AUDIO_AAC);
+            assert_eq!(packet.dts(), i as u64 * 20_000);
+            assert_eq!(packet.params, [ASC]);
+            assert_eq!(&packet.data[..], &frames[i * 2 + 1].data[..]);
+        }
+    }
+
+    #[test]
+    fn test_fmp4_audio_first() {
+        let mut frames = vec![
+            audio(1_000_000),
+            audio(1_020_000),
+            video(1_030_000, false),
+            audio(1_040_000),
+            video(1_070_000, true),
+            audio(1_060_000),
+            video(1_110_000, false),
+            video(1_150_000, true),
+            audio(1_080_000),
+        ];
+
+        frames.last_mut().unwrap().flags |= FrameFlags::LAST;
+
+        let mut muxer = Fmp4Muxer::new();
+        let segments: Vec<_> = frames
+            .iter()
+            .cloned()
+            .flat_map(|x| muxer.push(x).unwrap())
+            .collect();
+
+        // the init segment waits for the video keyframe, the audio before it
+        // goes to the first fragment
+        assert_eq!(
+            segments.iter().map(|x| x.kind).collect::<Vec<_>>(),
+            [
+                SegmentKind::Init,
+                SegmentKind::Media,
+                SegmentKind::Media,
+                SegmentKind::Media
+            ]
+        );
+
+        let mut demuxer = Mp4Demuxer::<()>::new();
+        let packets: Vec<_> = segments
+            .into_iter()
+            .flat_map(|x| demuxer.push(x.data))
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(demuxer.tracks().count(), 2);
+        assert_eq!(packets.iter().filter(|x| x.is_video()).count(), 3);
+        assert_eq!(packets.iter().filter(|x| !x.is_video()).count(), 5);
+
+        // the stream without the video keyframe starts after the timeout
+        let mut muxer = Fmp4Muxer::new().with_start_timeout(50_000);
+        assert!(muxer.push(audio(0)).unwrap().is_empty());
+        assert!(muxer.push(audio(40_000)).unwrap().is_empty());
+        assert_eq!(muxer.push(audio(60_000)).unwrap().len(), 2);
+
+        // broken parameter sets aren't written as an empty avcC
+        let broken = video(0, true).with_params(vec![Bytes::from_static(&[0x67, 0x64])]);
+        assert!(Fmp4Muxer::new().push(broken).is_err());
+    }
+
+    #[test]
+    fn test_fmp4_audio_config() {
+        // channel configuration 7 is 7.1
+        let track = new_track(Fourcc::AUDIO_AAC, &[Bytes::from_static(&[0x11, 0xB8])]).unwrap();
+        assert_eq!((track.timescale, track.entry.channels), (48_000, 8));
+
+        let asc = AudioSpecificConfig {
+            object_type: 2,
+            sample_rate: 0,
+            channel_config: 2,
+        };
+        assert!(new_track(Fourcc::AUDIO_AAC, &[asc.to_bytes()]).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use bytes::{Buf, Bytes, BytesMut};
+use flowly_codec::opus::{self, OpusHead, OpusTags};
+use flowly_core::{Chunked, DataFrame, Fourcc, FrameFlags, FrameSource, Packet};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+/// Limit of the packets buffered across pages
+pub const DEFAULT_MAX_PACKET_SIZE: usize = 16 * 1024 * 1024;
+
+/// Logical bitstream being demuxed
+#[derive(Debug, Clone)]
+struct Stream {
+    serial: u32,
+    head: Option<(OpusHead, Bytes)>,
+    tags: Option<OpusTags>,
+
+    /// Granule position of the next packet start
+    position: Option<i64>,
+
+    /// Packet continued on the next page
+    partial: BytesMut,
+    drop_partial: bool,
+    ended: bool,
+}
+
+/// Ogg Opus demuxer.
+///
+/// Accepts arbitrary chunks of the Ogg byte stream and yields a [`Packet`] per
+/// Opus packet of the first Opus logical stream (chained streams follow one
+/// another, other multiplexed streams are ignored). `OpusHead` is attached as
+/// params, `OpusTags` is available via [`OggDemuxer::tags`]. Timestamps come from
+/// the page granule positions and packet TOC durations with pre-skip applied, so
+/// the first packets have negative PTS (DTS is clamped at zero); the duration of
+/// the last packet is trimmed to the end granule. Pages failing the CRC check
+/// are reported and skipped, so are pages with a negative granule position and
+/// packets above the size limit ([`DEFAULT_MAX_PACKET_SIZE`] by default). The
+/// state is reset when the frame source changes.
+#[derive(Debug, Clone)]
+pub struct OggDemuxer<S = ()> {
+    source: S,
+    buf: Chunked<Bytes>,
+    stream: Option<Stream>,
+    max_packet_size: usize,
+
+    /// Timestamp of the chained stream start
+    base: i64,
+    end: i64,
+}
+
+impl<S: FrameSource> OggDemuxer<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            buf: Chunked::new(),
+            stream: None,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            base: 0,
+            end: 0,
+        }
+    }
+
+    /// Sets the size limit of a packet
+    pub fn with_max_packet_size(mut self, size: usize) -> Self {
+        self.max_packet_size = size;
+        self
+    }
+
+    /// Identification header of the current stream
+    pub fn head(&self) -> Option<&OpusHead> {
+        self.stream.as_ref()?.head.as_ref().map(|x| &x.0)
+    }
+
+    /// Comment header of the current stream
+    pub fn tags(&self) -> Option<&OpusTags> {
+        self.stream.as_ref()?.tags.as_ref()
+    }
+
+    /// Feeds the chunk of the stream and returns all completed packets
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        self.buf.put(chunk);
+
+        loop {
+            let mut head = [0u8; PAGE_HEADER_SIZE + MAX_SEGMENTS];
+            let len = self.buf.peek(0, &mut head);
+
+            let header = match PageHeader::parse(&head[..len]) {
+                Ok(Some(header)) => header,
+                Ok(None) => break,
+                Err(_) => {
+                    // resync on the next capture pattern
+                    self.buf.advance(1);
+                    continue;
+                }
+            };
+
+            let size = header.size() + header.data_size();
+            if self.buf.remaining() < size {
+                break;
+            }
+
+            let mut page = self.buf.clone();
+            let page = page.copy_to_bytes(size);
+
+            if !verify_page(&page) {
+                out.push(Err(Error::InvalidData("ogg: page CRC mismatch")));
+                self.buf.advance(1);
+                continue;
+            }
+
+            self.buf.advance(size);
+            self.page(&header, page.slice(header.size()..), &mut out);
+        }
+
+        out
+    }
+
+    fn page(&mut self, header: &PageHeader, data: Bytes, out: &mut Vec<Result<Packet<S>, Error>>) {
+        match &self.stream {
+            Some(stream) if stream.serial == header.serial => (),
+
+            // the first or the chained stream, must start with `OpusHead`
+            Some(stream) if !(stream.ended && header.is_bos()) => return,
+            _ if !header.is_bos() || !data.starts_with(opus::OPUS_HEAD) => return,
+            _ => {
+                self.base = self.end;
+                self.stream = Some(Stream {
+                    serial: header.serial,
+                    head: None,
+                    tags: None,
+                    position: None,
+                    partial: BytesMut::new(),
+                    drop_partial: false,
+                    ended: false,
+                });
+            }
+        }
+
+        let Some(stream) = self.stream.as_mut() else {
+            return;
+        };
+
+        if header.granule < NO_GRANULE {
+            out.push(Err(Error::InvalidData("ogg: invalid granule position")));
+            return;
+        }
+
+        // the beginning of the continued packet was lost
+        if header.is_continued() && stream.partial.is_empty() {
+            stream.drop_partial = true;
+        } else if !header.is_continued() {
+            stream.partial.clear();
+            stream.drop_partial = false;
+        }
+
+        let mut packets = Vec::new();
+        let mut offset = 0;
+
+        for &lacing in &header.lacing {
+            if stream.drop_partial {
+                stream.partial.clear();
+            } else if stream.partial.len() + lacing as usize > self.max_packet_size {
+                out.push(Err(Error::InvalidData(
+                    "ogg: packet exceeds the size limit",
+                )));
+                stream.partial.clear();
+                stream.drop_partial = true;
+            } else {
+                stream
+                    .partial
+                    .extend_from_slice(&data[offset..offset + lacing as usize]);
+            }
+
+            offset += lacing as usize;
+
+            if lacing < 255 {
+                let packet = std::mem::take(&mut stream.partial).freeze();
+
+                if !std::mem::take(&mut stream.drop_partial) {
+                    packets.push(packet);
+                }
+            }
+        }
+
+        if header.is_eos() {
+            stream.ended = true;
+        }
+
+        let mut audio = Vec::new();
+        for packet in packets {
+            if stream.head.is_none() {
+                match OpusHead::parse(&packet) {
+                    Ok(head) => stream.head = Some((head, packet)),
+                    Err(err) => out.push(Err(err.into())),
+                }
+            } else if stream.tags.is_none() && packet.starts_with(opus::OPUS_TAGS) {
+                stream.tags = OpusTags::parse(&packet).ok();
+            } else {
+                let samples = opus::packet_samples(&packet).unwrap_or(0) as i64;
+                audio.push((packet, samples));
+            }
+        }
+
+        if audio.is_empty() {
+            return;
+        }
+
+        let Some((head, params)) = stream.head.as_ref() else {
+            return;
+        };
+
+        let total: i64 = audio.iter().map(|x| x.1).sum();
+        let mut position = *stream
+            .position
+            .get_or_insert(if header.granule == NO_GRANULE {
+                0
+            } else {
+                // the first page may begin after zero
+                (header.granule - total).max(0)
+            });
+
+        let pre_skip = head.pre_skip as i128;
+        let rate = opus::SAMPLE_RATE as i128;
+        let micros = |position: i128| {
+            i64::try_from(self.base as i128 + (position - pre_skip) * 1_000_000 / rate)
+                .map_err(|_| Error::InvalidData("ogg: timestamp out of range"))
+        };
+
+        for (packet, mut samples) in audio {
+            // end trimming of the last page
+            if header.is_eos() && header.granule != NO_GRANULE {
+                samples = samples.min(header.granule - position).max(0);
+            }
+
+            let (pts, end) = match (
+                micros(position as i128),
+                micros(position as i128 + samples as i128),
+            ) {
+                (Ok(pts), Ok(end)) => (pts, end),
+                (Err(err), _) | (_, Err(err)) => {
+                    out.push(Err(err));
+                    break;
+                }
+            };
+
+            let mut packet = Packet::new(
+                self.source.clone(),
+                Fourcc::AUDIO_OPUS,
+                FrameFlags::AUDIO_STREAM | FrameFlags::KEYFRAME,
+                packet,
+            )
+            .with_timestamps(pts.max(0) as u64, pts)
+            .with_params(vec![params.clone()]);
+
+            packet.duration = (end - pts).max(0) as u64;
+            self.end = end;
+            position = position.saturating_add(samples);
+
+            out.push(Ok(packet));
+        }
+
+        stream.position = Some(position);
+    }
+
+    fn reset(&mut self, source: S) {
+        *self = Self::new().with_max_packet_size(self.max_packet_size);
+        self.source = source;
+    }
+}
+
+impl<S: FrameSource> Default for OggDemuxer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F> Service<F> for OggDemuxer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        if frame.source() != &self.source {
+            self.reset(frame.source().clone());
+        }
+
+        let mut out = Vec::new();
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
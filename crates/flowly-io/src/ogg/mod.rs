@@ -0,0 +1,155 @@
+mod demuxer;
+mod muxer;
+
+pub use demuxer::{DEFAULT_MAX_PACKET_SIZE, OggDemuxer};
+pub use muxer::OggMuxer;
+
+use bytes::{BufMut, BytesMut};
+
+use crate::error::Error;
+
+pub const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+pub const PAGE_HEADER_SIZE: usize = 27;
+pub const MAX_SEGMENTS: usize = 255;
+
+/// Offset of the checksum field in the page header
+const CHECKSUM_OFFSET: usize = 22;
+
+pub const FLAG_CONTINUED: u8 = 0x01;
+pub const FLAG_BOS: u8 = 0x02;
+pub const FLAG_EOS: u8 = 0x04;
+
+/// Granule position of the page without completed packets
+pub const NO_GRANULE: i64 = -1;
+
+/// Ogg page header (RFC 3533 6)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PageHeader {
+    pub flags: u8,
+    pub granule: i64,
+    pub serial: u32,
+    pub sequence: u32,
+    pub checksum: u32,
+
+    /// Segment table, a value below 255 terminates the packet
+    pub lacing: Vec<u8>,
+}
+
+impl PageHeader {
+    /// Parses the header, `None` if `data` is shorter than the header
+    pub fn parse(data: &[u8]) -> Result<Option<Self>, Error> {
+        if data.len() < PAGE_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        if &data[..4] != CAPTURE_PATTERN {
+            return Err(Error::InvalidData("ogg: invalid capture pattern"));
+        }
+
+        if data[4] != 0 {
+            return Err(Error::Unsupported("ogg: unsupported version"));
+        }
+
+        let segments = data[26] as usize;
+        let Some(lacing) = data.get(PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + segments) else {
+            return Ok(None);
+        };
+
+        let u32_at = |pos: usize| u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+
+        Ok(Some(Self {
+            flags: data[5],
+            granule: i64::from_le_bytes(data[6..14].try_into().unwrap()),
+            serial: u32_at(14),
+            sequence: u32_at(18),
+            checksum: u32_at(CHECKSUM_OFFSET),
+            lacing: lacing.to_vec(),
+        }))
+    }
+
+    /// Header size including the segment table
+    #[inline]
+    pub fn size(&self) -> usize {
+        PAGE_HEADER_SIZE + self.lacing.len()
+    }
+
+    /// Payload size
+    #[inline]
+    pub fn data_size(&self) -> usize {
+        self.lacing.iter().map(|&x| x as usize).sum()
+    }
+
+    #[inline]
+    pub fn is_continued(&self) -> bool {
+        self.flags & FLAG_CONTINUED != 0
+    }
+
+    #[inline]
+    pub fn is_bos(&self) -> bool {
+        self.flags & FLAG_BOS != 0
+    }
+
+    #[inline]
+    pub fn is_eos(&self) -> bool {
+        self.flags & FLAG_EOS != 0
+    }
+
+    /// Writes the page with the computed checksum
+    pub fn write(&self, buf: &mut BytesMut, data: &[u8]) {
+        let start = buf.len();
+
+        buf.put_slice(CAPTURE_PATTERN);
+        buf.put_u8(0);
+        buf.put_u8(self.flags);
+        buf.put_i64_le(self.granule);
+        buf.put_u32_le(self.serial);
+        buf.put_u32_le(self.sequence);
+        buf.put_u32_le(0);
+        buf.put_u8(self.lacing.len() as u8);
+        buf.put_slice(&self.lacing);
+        buf.put_slice(data);
+
+        let crc = crc32(&buf[start..]);
+        buf[start + CHECKSUM_OFFSET..start + CHECKSUM_OFFSET + 4]
+            .copy_from_slice(&crc.to_le_bytes());
+    }
+}
+
+/// Checks the checksum of the whole page
+pub fn verify_page(page: &[u8]) -> bool {
+    if page.len() < PAGE_HEADER_SIZE {
+        return false;
+    }
+
+    let expected = u32::from_le_bytes(
+        page[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4]
+            .try_into()
+            .unwrap(),
+    );
+    let crc = crc32_update(0, &page[..CHECKSUM_OFFSET]);
+    let crc = crc32_update(crc, &[0; 4]);
+    let crc = crc32_update(crc, &page[CHECKSUM_OFFSET + 4..]);
+
+    crc == expected
+}
+
+/// Page checksum (polynomial 0x04C11DB7, zero initial value, no reflection)
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
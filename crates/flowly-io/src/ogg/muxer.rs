@@ -0,0 +1,346 @@
+use bytes::{Bytes, BytesMut};
+use flowly_codec::opus::{self, OpusHead, OpusTags};
+use flowly_core::{EncodedFrame, Fourcc, Multichannel, Packet, VideoFrame};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+/// Default serial number of the logical stream ("flow")
+const DEFAULT_SERIAL: u32 = 0x666C_6F77;
+
+/// Ogg Opus muxer.
+///
+/// Accepts Opus frames and yields Ogg pages: `OpusHead` (taken from `params()`,
+/// or built from the TOC of the first packet) and `OpusTags` pages first, then
+/// audio pages holding up to `page_duration` of packets. Granule positions are
+/// counted from the TOC durations of the packets starting at zero, so frame
+/// timestamps are not used and the pre-skip of `OpusHead` applies as is.
+/// [`OggMuxer::finish`] (or a frame flagged `LAST`) writes the last page with
+/// the EOS flag.
+#[derive(Debug, Clone)]
+pub struct OggMuxer {
+    serial: u32,
+    sequence: u32,
+    page_duration: u64,
+    tags: OpusTags,
+    headers_written: bool,
+    finished: bool,
+
+    /// Granule position after the last pushed packet
+    granule: i64,
+    pending: Vec<(Bytes, i64)>,
+    pending_samples: u64,
+}
+
+impl OggMuxer {
+    pub fn new() -> Self {
+        Self {
+            serial: DEFAULT_SERIAL,
+            sequence: 0,
+            page_duration: 1_000_000,
+            tags: OpusTags {
+                vendor: "flowly".into(),
+                comments: Vec::new(),
+            },
+            headers_written: false,
+            finished: false,
+            granule: 0,
+            pending: Vec::new(),
+            pending_samples: 0,
+        }
+    }
+
+    /// Serial number of the logical stream
+    pub fn with_serial(mut self, serial: u32) -> Self {
+        self.serial = serial;
+        self
+    }
+
+    /// Maximal duration of the audio page in microseconds
+    pub fn with_page_duration(mut self, duration: u64) -> Self {
+        self.page_duration = duration;
+        self
+    }
+
+    /// Comment header written after `OpusHead`
+    pub fn with_tags(mut self, tags: OpusTags) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Adds the packet to the stream, returns the pages completed by it
+    pub fn push<F: EncodedFrame + Multichannel + VideoFrame>(
+        &mut self,
+        frame: F,
+    ) -> Result<Vec<Bytes>, Error> {
+        let codec = frame.codec();
+        if codec != Fourcc::AUDIO_OPUS {
+            return Err(flowly_codec::Error::UnsupportedCodec(codec).into());
+        }
+
+        if self.finished {
+            return Err(Error::InvalidData("ogg: stream is finished"));
+        }
+
+        let is_last = frame.is_last();
+        let packet = Packet::from_frame(frame);
+        let samples = opus::packet_samples(&packet.data)?;
+
+        let mut out = Vec::new();
+
+        if !self.headers_written {
+            let head = match packet.params.first() {
+                Some(head) => OpusHead::parse(head)?.to_bytes(),
+                None => OpusHead {
+                    channels: if opus::Toc::parse(packet.data[0]).stereo {
+                        2
+                    } else {
+                        1
+                    },
+                    coupled_count: opus::Toc::parse(packet.data[0]).stereo as u8,
+                    ..Default::default()
+                }
+                .to_bytes(),
+            };
+
+            // headers go on their own pages
+            out.push(self.page(FLAG_BOS, 0, &[head]));
+            out.push(self.page(0, 0, &[self.tags.to_bytes()]));
+            self.headers_written = true;
+        }
+
+        self.granule += samples as i64;
+        self.pending.push((packet.data, self.granule));
+        self.pending_samples += samples as u64;
+
+        if is_last {
+            out.extend(self.finish());
+        } else if self.pending_samples * 1_000_000 / opus::SAMPLE_RATE as u64 >= self.page_duration
+        {
+            out.extend(self.flush_pages(false));
+        }
+
+        Ok(out)
+    }
+
+    /// Writes the pending packets on the final page
+    pub fn finish(&mut self) -> Vec<Bytes> {
+        if !self.headers_written || self.finished {
+            return Vec::new();
+        }
+
+        self.finished = true;
+        self.flush_pages(true)
+    }
+
+    fn flush_pages(&mut self, eos: bool) -> Vec<Bytes> {
+        let mut out = Vec::new();
+        let pending = std::mem::take(&mut self.pending);
+        self.pending_samples = 0;
+
+        let mut lacing = Vec::with_capacity(MAX_SEGMENTS);
+        let mut data = BytesMut::new();
+        let mut granule = NO_GRANULE;
+        let mut continued = false;
+
+        for (packet, end) in &pending {
+            let segments = packet.len() / 255 + 1;
+
+            for i in 0..segments {
+                if lacing.len() == MAX_SEGMENTS {
+                    let flags = if continued { FLAG_CONTINUED } else { 0 };
+                    out.push(self.raw_page(flags, granule, &lacing, &data));
+
+                    lacing.clear();
+                    data.clear();
+                    granule = NO_GRANULE;
+                    continued = i > 0;
+                }
+
+                let chunk = &packet[i * 255..packet.len().min((i + 1) * 255)];
+                lacing.push(chunk.len() as u8);
+                data.extend_from_slice(chunk);
+            }
+
+            granule = *end;
+        }
+
+        if !lacing.is_empty() || eos {
+            let mut flags = if continued { FLAG_CONTINUED } else { 0 };
+            if eos {
+                flags |= FLAG_EOS;
+                granule = self.granule;
+            }
+
+            out.push(self.raw_page(flags, granule, &lacing, &data));
+        }
+
+        out
+    }
+
+    /// Page holding the whole packets
+    fn page(&mut self, flags: u8, granule: i64, packets: &[Bytes]) -> Bytes {
+        let mut lacing = Vec::new();
+        let mut data = BytesMut::new();
+
+        for packet in packets {
+            lacing.extend(std::iter::repeat_n(255, packet.len() / 255));
+            lacing.push((packet.len() % 255) as u8);
+            data.extend_from_slice(packet);
+        }
+
+        self.raw_page(flags, granule, &lacing, &data)
+    }
+
+    fn raw_page(&mut self, flags: u8, granule: i64, lacing: &[u8], data: &[u8]) -> Bytes {
+        let header = PageHeader {
+            flags,
+            granule,
+            serial: self.serial,
+            sequence: self.sequence,
+            checksum: 0,
+            lacing: lacing.to_vec(),
+        };
+
+        self.sequence += 1;
+
+        let mut buf = BytesMut::with_capacity(header.size() + data.len());
+        header.write(&mut buf, data);
+        buf.freeze()
+    }
+}
+
+impl Default for OggMuxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: EncodedFrame + Multichannel + VideoFrame> Service<F> for OggMuxer {
+    type Out = Result<Bytes, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let out: Vec<_> = match self.push(frame) {
+            Ok(chunks) => chunks.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        };
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use flowly_codec::opus::OpusHead;
+    use flowly_core::{EncodedFrame, Fourcc, FrameFlags, Packet};
+
+    use super::OggMuxer;
+    use crate::ogg::{FLAG_BOS, FLAG_CONTINUED, NO_GRANULE, OggDemuxer, PageHeader};
+
+    fn page(flags: u8, granule: i64, lacing: &[u8], data: &[u8]) -> Bytes {
+        let header = PageHeader {
+            flags,
+            granule,
+            serial: 1,
+            lacing: lacing.to_vec(),
+            ..Default::default()
+        };
+
+        let mut buf = BytesMut::new();
+        header.write(&mut buf, data);
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_ogg_opus_roundtrip() {
+        let head = OpusHead {
+            channels: 1,
+            pre_skip: 312,
+            coupled_count: 0,
+            ..Default::default()
+        };
+
+        let mut muxer = OggMuxer::new().with_page_duration(100_000);
+        let mut pages = Vec::new();
+
+        for i in 0..12u8 {
+            // CELT-only 20 ms mono, the third packet spans several segments
+            let mut data = vec![0xF8, i];
+            if i == 2 {
+                data.resize(600, i);
+            }
+
+            let frame = Packet::new(
+                (),
+                Fourcc::AUDIO_OPUS,
+                FrameFlags::AUDIO_STREAM | FrameFlags::KEYFRAME,
+                Bytes::from(data),
+            )
+            .with_params(vec![head.to_bytes()]);
+
+            pages.extend(muxer.push(frame).unwrap());
+        }
+        pages.extend(muxer.finish());
+
+        // headers, two pages of five packets and the final one
+        assert_eq!(pages.len(), 5);
+        let last = PageHeader::parse(&pages[4]).unwrap().unwrap();
+        assert!(last.is_eos());
+        assert_eq!(last.granule, 12 * 960);
+
+        let stream: BytesMut = pages.iter().flat_map(|x| x.to_vec()).collect();
+        let stream = stream.freeze();
+
+        let mut demuxer = OggDemuxer::<()>::new();
+        let mut out = Vec::new();
+        for chunk in stream.chunks(100) {
+            out.extend(demuxer.push(Bytes::copy_from_slice(chunk)));
+        }
+
+        let packets: Vec<_> = out.into_iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(packets.len(), 12);
+        assert_eq!(demuxer.head(), Some(&head));
+        assert_eq!(demuxer.tags().unwrap().vendor, "flowly");
+
+        let pts: Vec<_> = packets.iter().map(|x| x.pts()).collect();
+        let expected: Vec<_> = (0..12).map(|i| -6500 + i * 20_000).collect();
+        assert_eq!(pts, expected);
+
+        assert_eq!(packets[2].data.len(), 600);
+        assert_eq!(packets[0].dts, 0);
+        assert_eq!(packets[0].params().next().unwrap(), &head.to_bytes());
+    }
+
+    #[test]
+    fn test_ogg_demux_malformed() {
+        let head = OpusHead {
+            channels: 1,
+            ..Default::default()
+        }
+        .to_bytes();
+        let packet = [0xF8, 0];
+
+        for granule in [i64::MIN, -2, 1 << 60, i64::MAX] {
+            let mut demuxer = OggDemuxer::<()>::new();
+            let mut out = demuxer.push(page(FLAG_BOS, 0, &[head.len() as u8], &head));
+            out.extend(demuxer.push(page(0, granule, &[2], &packet)));
+            out.extend(demuxer.push(page(0, NO_GRANULE, &[2], &packet)));
+
+            assert!(out.iter().any(|x| x.is_err()), "{granule}");
+        }
+
+        // the oversized packet is dropped up to its end
+        let mut demuxer = OggDemuxer::<()>::new().with_max_packet_size(300);
+        let mut out = demuxer.push(page(FLAG_BOS, 0, &[head.len() as u8], &head));
+        out.extend(demuxer.push(page(0, NO_GRANULE, &[255], &[0xF8; 255])));
+        out.extend(demuxer.push(page(FLAG_CONTINUED, NO_GRANULE, &[255], &[0; 255])));
+        out.extend(demuxer.push(page(FLAG_CONTINUED, 1920, &[1, 2], &[0, 0xF8, 0])));
+
+        assert!(out[0].is_err());
+        let packets: Vec<_> = out[1..].iter().map(|x| x.as_ref().unwrap()).collect();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(&packets[0].data[..], &packet);
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use super::message::{MSG_AUDIO, MSG_VIDEO, Message};
+use crate::error::Error;
+
+/// Chunk size until `SetChunkSize` is received
+pub const DEFAULT_CHUNK_SIZE: usize = 128;
+
+/// Largest chunk size allowed by the spec (5.4.1)
+pub const MAX_CHUNK_SIZE: usize = 0xFF_FFFF;
+
+/// Limit of the payload of the unfinished messages of all chunk streams
+pub const DEFAULT_MAX_BUFFERED: usize = 64 * 1024 * 1024;
+
+/// Timestamp field value signalling the extended timestamp
+const EXTENDED_TIMESTAMP: u32 = 0xFF_FFFF;
+
+/// Chunk stream ids used for the outgoing messages
+pub const CSID_CONTROL: u32 = 2;
+pub const CSID_COMMAND: u32 = 3;
+pub const CSID_AUDIO: u32 = 4;
+pub const CSID_VIDEO: u32 = 6;
+
+/// Header fields of the last chunk of the chunk stream, the compressed
+/// headers (fmt 1-3) reuse them
+#[derive(Debug, Default, Clone)]
+struct ChunkStream {
+    timestamp: u32,
+    delta: u32,
+    length: u32,
+    kind: u8,
+    stream_id: u32,
+    extended: bool,
+    payload: BytesMut,
+}
+
+/// Chunk stream demultiplexer.
+///
+/// Reassembles messages from the interleaved chunks of all chunk streams
+/// (basic header of 1-3 bytes, message header of types 0-3 and the extended
+/// timestamp). The payload of the unfinished messages is limited to
+/// [`DEFAULT_MAX_BUFFERED`] bytes in total.
+#[derive(Debug, Clone)]
+pub struct ChunkReader {
+    chunk_size: usize,
+    streams: HashMap<u32, ChunkStream>,
+    buffered: usize,
+    max_buffered: usize,
+}
+
+impl ChunkReader {
+    pub fn new() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            streams: HashMap::new(),
+            buffered: 0,
+            max_buffered: DEFAULT_MAX_BUFFERED,
+        }
+    }
+
+    /// Limit of the payload of the unfinished messages of all chunk streams
+    pub fn with_max_buffered(mut self, size: usize) -> Self {
+        self.max_buffered = size;
+        self
+    }
+
+    /// Applies `SetChunkSize` of the peer
+    pub fn set_chunk_size(&mut self, size: usize) -> Result<(), Error> {
+        if size == 0 || size > MAX_CHUNK_SIZE {
+            return Err(Error::InvalidData("rtmp: invalid chunk size"));
+        }
+
+        self.chunk_size = size;
+        Ok(())
+    }
+
+    /// Discards the partially received message of the chunk stream (`Abort`)
+    pub fn abort(&mut self, csid: u32) {
+        if let Some(stream) = self.streams.get_mut(&csid) {
+            self.buffered -= stream.payload.len();
+            stream.payload.clear();
+        }
+    }
+
+    /// Takes complete chunks from the beginning of `buf` and returns the first
+    /// completed message, `None` if more data is needed
+    pub fn read(&mut self, buf: &mut BytesMut) -> Result<Option<Message>, Error> {
+        loop {
+            let Some(&first) = buf.first() else {
+                return Ok(None);
+            };
+
+            let fmt = first >> 6;
+            let (csid, mut pos) = match first & 0x3F {
+                0 if buf.len() >= 2 => (64 + buf[1] as u32, 2),
+                1 if buf.len() >= 3 => (64 + buf[1] as u32 + ((buf[2] as u32) << 8), 3),
+                0 | 1 => return Ok(None),
+                csid => (csid as u32, 1),
+            };
+
+            let header_len = [11, 7, 3, 0][fmt as usize];
+            if buf.len() < pos + header_len {
+                return Ok(None);
+            }
+
+            let stream = match self.streams.get(&csid) {
+                Some(stream) => Some(stream),
+                None if fmt == 0 => None,
+                None => return Err(Error::InvalidData("rtmp: chunk without message header")),
+            };
+
+            let header = &buf[pos..pos + header_len];
+            pos += header_len;
+
+            let field = if fmt < 3 {
+                u32::from_be_bytes([0, header[0], header[1], header[2]])
+            } else {
+                0
+            };
+
+            let extended = if fmt < 3 {
+                field == EXTENDED_TIMESTAMP
+            } else {
+                stream.is_some_and(|x| x.extended)
+            };
+
+            let ext = if extended {
+                let Some(ext) = buf.get(pos..pos + 4) else {
+                    return Ok(None);
+                };
+
+                pos += 4;
+                Some(u32::from_be_bytes([ext[0], ext[1], ext[2], ext[3]]))
+            } else {
+                None
+            };
+
+            // a new message header drops the unfinished one
+            let (length, received) = match stream {
+                Some(stream) if fmt == 3 => (stream.length, stream.payload.len()),
+                _ if fmt < 2 => (u32::from_be_bytes([0, header[3], header[4], header[5]]), 0),
+                stream => (stream.map_or(0, |x| x.length), 0),
+            };
+
+            let len = (length as usize - received).min(self.chunk_size);
+            if buf.len() < pos + len {
+                return Ok(None);
+            }
+
+            // the completed message isn't kept
+            let dropped = stream.map_or(0, |x| x.payload.len()) - received;
+            let complete = received + len == length as usize;
+
+            if !complete && self.buffered - dropped + len > self.max_buffered {
+                return Err(Error::InvalidData(
+                    "rtmp: too much data of unfinished messages",
+                ));
+            }
+
+            let stream = self.streams.entry(csid).or_default();
+
+            if fmt < 3 {
+                stream.payload.clear();
+                stream.extended = extended;
+                stream.delta = ext.unwrap_or(field);
+                stream.length = length;
+            }
+
+            if fmt < 2 {
+                stream.kind = header[6];
+            }
+
+            if fmt == 0 {
+                stream.stream_id =
+                    u32::from_le_bytes([header[7], header[8], header[9], header[10]]);
+                stream.timestamp = stream.delta;
+            } else if stream.payload.is_empty() {
+                // continuation chunks (fmt 3) keep the message timestamp
+                stream.timestamp = stream.timestamp.wrapping_add(stream.delta);
+            }
+
+            buf.advance(pos);
+            stream.payload.extend_from_slice(&buf.split_to(len));
+            self.buffered = self.buffered - dropped + len;
+
+            if complete {
+                self.buffered -= stream.payload.len();
+
+                return Ok(Some(Message::new(
+                    stream.kind,
+                    stream.stream_id,
+                    stream.timestamp,
+                    stream.payload.split().freeze(),
+                )));
+            }
+        }
+    }
+}
+
+impl Default for ChunkReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Chunk stream multiplexer, every message starts with the full (type 0)
+/// header followed by type 3 continuation chunks
+#[derive(Debug, Clone)]
+pub struct ChunkWriter {
+    chunk_size: usize,
+}
+
+impl ChunkWriter {
+    pub fn new() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Size of the outgoing chunks, `SetChunkSize` must be sent to the peer first
+    pub fn set_chunk_size(&mut self, size: usize) {
+        self.chunk_size = size.clamp(1, MAX_CHUNK_SIZE);
+    }
+
+    pub fn write<B: BufMut>(&self, csid: u32, message: &Message, buf: &mut B) {
+        let extended = message.timestamp >= EXTENDED_TIMESTAMP;
+
+        put_basic_header(buf, 0, csid);
+        buf.put_uint(message.timestamp.min(EXTENDED_TIMESTAMP) as u64, 3);
+        buf.put_uint(message.payload.len() as u64, 3);
+        buf.put_u8(message.kind);
+        buf.put_u32_le(message.stream_id);
+
+        if extended {
+            buf.put_u32(message.timestamp);
+        }
+
+        for (idx, chunk) in message.payload.chunks(self.chunk_size).enumerate() {
+            if idx > 0 {
+                put_basic_header(buf, 3, csid);
+
+                if extended {
+                    buf.put_u32(message.timestamp);
+                }
+            }
+
+            buf.put_slice(chunk);
+        }
+    }
+}
+
+impl Default for ChunkWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Chunk stream of the outgoing message, media gets its own streams so the
+/// compressed headers of the peer stay effective
+pub fn chunk_stream_id(kind: u8) -> u32 {
+    match kind {
+        MSG_AUDIO => CSID_AUDIO,
+        MSG_VIDEO => CSID_VIDEO,
+        1..=6 => CSID_CONTROL,
+        _ => CSID_COMMAND,
+    }
+}
+
+fn put_basic_header<B: BufMut>(buf: &mut B, fmt: u8, csid: u32) {
+    match csid {
+        2..=63 => buf.put_u8(fmt << 6 | csid as u8),
+        64..=319 => {
+            buf.put_u8(fmt << 6);
+            buf.put_u8((csid - 64) as u8);
+        }
+        _ => {
+            buf.put_u8(fmt << 6 | 1);
+            buf.put_u16_le((csid - 64) as u16);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+
+    use super::{ChunkReader, ChunkWriter};
+    use crate::{
+        amf0::Value,
+        rtmp::{
+            Command, Message,
+            message::{MSG_AUDIO, MSG_VIDEO},
+        },
+    };
+
+    #[test]
+    fn test_rtmp_chunks() {
+        let mut writer = ChunkWriter::new();
+        writer.set_chunk_size(100);
+
+        let video = Message::new(MSG_VIDEO, 1, 0x0100_0000, Bytes::from(vec![7u8; 250]));
+        let audio = Message::new(MSG_AUDIO, 1, 40, Bytes::from_static(&[0xAF, 0x01, 0x21]));
+        let command = Message::command(0, &Command::new("createStream", 4.0, Value::Null));
+
+        let mut buf = BytesMut::new();
+        writer.write(6, &video, &mut buf);
+        writer.write(320, &audio, &mut buf);
+        writer.write(3, &command, &mut buf);
+
+        // compressed headers (spec 5.3.2.2): type 1 and type 3 chunks of csid 4
+        // continue the stream with the 20 ms delta
+        buf.extend_from_slice(&[0x04, 0x00, 0x03, 0xE8, 0x00, 0x00, 0x02, MSG_AUDIO]);
+        buf.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0xAA, 0xBB]);
+        buf.extend_from_slice(&[0x44, 0x00, 0x00, 0x14, 0x00, 0x00, 0x01, MSG_AUDIO, 0xCC]);
+        buf.extend_from_slice(&[0xC4, 0xDD]);
+
+        let data = buf.split().freeze();
+        let mut reader = ChunkReader::new();
+        reader.set_chunk_size(100).unwrap();
+
+        let mut out = Vec::new();
+        for byte in data {
+            buf.extend_from_slice(&[byte]);
+
+            while let Some(message) = reader.read(&mut buf).unwrap() {
+                out.push(message);
+            }
+        }
+
+        assert_eq!(out.len(), 6);
+        assert_eq!(out[0], video);
+        assert_eq!(out[1], audio);
+        assert_eq!(
+            Command::parse(&mut out[2].payload.clone()).unwrap().name,
+            "createStream"
+        );
+
+        assert_eq!(
+            (out[3].timestamp, &out[3].payload[..]),
+            (1000, &[0xAA, 0xBB][..])
+        );
+        assert_eq!((out[4].timestamp, out[4].stream_id), (1020, 0));
+        assert_eq!(&out[4].payload[..], &[0xCC]);
+        assert_eq!((out[5].timestamp, &out[5].payload[..]), (1040, &[0xDD][..]));
+    }
+
+    #[test]
+    fn test_rtmp_chunks_limit() {
+        let writer = ChunkWriter::new();
+        let video = Message::new(MSG_VIDEO, 1, 0, Bytes::from(vec![7u8; 200]));
+
+        // two chunks of 128 and 72 bytes per message
+        let mut chunks = Vec::new();
+        for csid in [6, 7] {
+            let mut buf = BytesMut::new();
+            writer.write(csid, &video, &mut buf);
+            chunks.push((buf.split_to(12 + 128), buf));
+        }
+
+        let interleaved = [&chunks[0].0, &chunks[1].0, &chunks[0].1, &chunks[1].1];
+
+        let mut reader = ChunkReader::new().with_max_buffered(300);
+        let mut out = Vec::new();
+        for chunk in interleaved {
+            let mut buf = chunk.clone();
+            out.extend(reader.read(&mut buf).unwrap());
+        }
+
+        assert_eq!(out, [video.clone(), video]);
+        assert_eq!(reader.buffered, 0);
+
+        let mut reader = ChunkReader::new().with_max_buffered(250);
+        assert!(reader.read(&mut chunks[0].0.clone()).unwrap().is_none());
+        assert!(reader.read(&mut chunks[1].0.clone()).is_err());
+    }
+}
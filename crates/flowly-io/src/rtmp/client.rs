@@ -0,0 +1,359 @@
+use std::time::Duration;
+
+use bytes::{BufMut, BytesMut};
+use flowly_core::{EncodedFrame, Multichannel, VideoFrame};
+use flowly_service::{Context, Service};
+use tokio::net::TcpStream;
+
+use super::{
+    connection::Connection,
+    handshake::client_handshake,
+    message::{Command, Message},
+};
+use crate::{
+    amf0::Value,
+    error::Error,
+    flv::{FlvMuxer, SIGNATURE, TAG_HEADER_SIZE, TAG_SCRIPT, TagHeader},
+};
+
+pub const DEFAULT_PORT: u16 = 1935;
+
+/// Enhanced RTMP codecs announced in `connect`
+const FOURCC_LIST: [&str; 5] = ["hvc1", "av01", "vp09", "Opus", "fLaC"];
+
+/// RTMP publisher client.
+///
+/// Publishes the frames to `rtmp://host[:port]/<app>/<stream key>`. The
+/// connection is opened on the first frame (handshake, `connect`,
+/// `createStream` and `publish`) and the frames are sent as the FLV tag bodies
+/// of `FlvMuxer`, so HEVC, AV1, VP9, Opus and FLAC use enhanced RTMP headers.
+/// Video frames before the first keyframe are dropped. After an error the
+/// connection is closed and reopened with the next frame. Yields the number of
+/// bytes sent for the frame.
+#[derive(Debug)]
+pub struct RtmpClient {
+    url: String,
+    chunk_size: usize,
+    timeout: Duration,
+    publisher: Option<Publisher>,
+}
+
+impl RtmpClient {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            chunk_size: 4096,
+            timeout: Duration::from_secs(10),
+            publisher: None,
+        }
+    }
+
+    /// Size of the outgoing chunks
+    pub fn with_chunk_size(mut self, size: usize) -> Self {
+        self.chunk_size = size.max(128);
+        self
+    }
+
+    /// Timeout of the connection setup
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn connect(&self) -> Result<Publisher, Error> {
+        let url = RtmpUrl::parse(&self.url)?;
+
+        let mut stream = TcpStream::connect(&url.address).await?;
+        stream.set_nodelay(true)?;
+        client_handshake(&mut stream).await?;
+
+        let mut conn = Connection::new(stream);
+        conn.set_chunk_size(self.chunk_size).await?;
+
+        let fourcc_list = FOURCC_LIST.iter().map(|&x| x.into()).collect();
+        let connect = Command::new(
+            "connect",
+            1.0,
+            Value::Object(vec![
+                ("app".to_string(), url.app.as_str().into()),
+                ("type".to_string(), "nonprivate".into()),
+                (
+                    "flashVer".to_string(),
+                    "FMLE/3.0 (compatible; flowly)".into(),
+                ),
+                ("tcUrl".to_string(), url.tc_url.as_str().into()),
+                ("fourCcList".to_string(), Value::StrictArray(fourcc_list)),
+            ]),
+        );
+
+        conn.send(&[Message::command(0, &connect)]).await?;
+        wait_result(&mut conn, 1.0).await?;
+
+        let key = url.key.as_str();
+        conn.send(&[
+            Message::command(
+                0,
+                &Command::new("releaseStream", 2.0, Value::Null).with_arg(key),
+            ),
+            Message::command(
+                0,
+                &Command::new("FCPublish", 3.0, Value::Null).with_arg(key),
+            ),
+            Message::command(0, &Command::new("createStream", 4.0, Value::Null)),
+        ])
+        .await?;
+
+        let stream_id = wait_result(&mut conn, 4.0)
+            .await?
+            .arg(0)
+            .and_then(Value::as_f64)
+            .ok_or(Error::InvalidData("rtmp: createStream without stream id"))?
+            as u32;
+
+        let publish = Command::new("publish", 5.0, Value::Null)
+            .with_arg(key)
+            .with_arg("live");
+
+        conn.send(&[Message::command(stream_id, &publish)]).await?;
+
+        loop {
+            let Some(command) = read_command(&conn.read_message().await?)? else {
+                continue;
+            };
+
+            match command.name.as_str() {
+                "onStatus" if status_code(&command) == "NetStream.Publish.Start" => break,
+                "onStatus" | "_error" if is_error(&command) => return Err(status_error(&command)),
+                _ => (),
+            }
+        }
+
+        Ok(Publisher {
+            conn,
+            stream_id,
+            muxer: FlvMuxer::new(),
+            started: false,
+        })
+    }
+}
+
+impl<F: EncodedFrame + Multichannel + VideoFrame> Service<F> for RtmpClient {
+    type Out = Result<usize, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        async_stream::stream! {
+            if self.publisher.is_none() {
+                match tokio::time::timeout(self.timeout, self.connect()).await {
+                    Ok(Ok(publisher)) => self.publisher = Some(publisher),
+                    Ok(Err(err)) => {
+                        yield Err(err);
+                        return;
+                    }
+                    Err(_) => {
+                        yield Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into());
+                        return;
+                    }
+                }
+            }
+
+            if let Some(publisher) = &mut self.publisher {
+                let res = publisher.send(frame).await;
+
+                if res.is_err() {
+                    self.publisher = None;
+                }
+
+                yield res;
+            }
+        }
+    }
+}
+
+/// Published message stream of the connection
+#[derive(Debug)]
+struct Publisher {
+    conn: Connection,
+    stream_id: u32,
+    muxer: FlvMuxer,
+
+    /// The first video keyframe is sent
+    started: bool,
+}
+
+impl Publisher {
+    async fn send<F: EncodedFrame + Multichannel + VideoFrame>(
+        &mut self,
+        frame: F,
+    ) -> Result<usize, Error> {
+        for message in self.conn.drain().await? {
+            if let Some(command) = read_command(&message)?
+                && is_error(&command)
+            {
+                return Err(status_error(&command));
+            }
+        }
+
+        if frame.is_video() && !self.started {
+            if !frame.is_keyframe() {
+                return Ok(0);
+            }
+
+            self.started = true;
+        }
+
+        let mut messages = Vec::new();
+
+        for tag in self.muxer.push(frame)? {
+            // the file header of the first call
+            if tag.starts_with(SIGNATURE) {
+                continue;
+            }
+
+            let header = TagHeader::read(&mut tag.clone())?;
+            let body = tag.slice(TAG_HEADER_SIZE..TAG_HEADER_SIZE + header.data_size as usize);
+
+            let payload = if header.kind == TAG_SCRIPT {
+                let mut buf = BytesMut::new();
+                Value::from("@setDataFrame").encode(&mut buf);
+                buf.put_slice(&body);
+                buf.freeze()
+            } else {
+                body
+            };
+
+            // FLV tag types are the same as the message types
+            messages.push(Message::new(
+                header.kind,
+                self.stream_id,
+                header.timestamp,
+                payload,
+            ));
+        }
+
+        self.conn.send(&messages).await
+    }
+}
+
+/// `rtmp://host[:port]/<app>/<stream key>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RtmpUrl {
+    /// `host:port` to connect to
+    address: String,
+    app: String,
+    key: String,
+
+    /// Url of the application (`tcUrl` of `connect`)
+    tc_url: String,
+}
+
+impl RtmpUrl {
+    fn parse(url: &str) -> Result<Self, Error> {
+        let rest = url
+            .get(..7)
+            .filter(|x| x.eq_ignore_ascii_case("rtmp://"))
+            .map(|_| &url[7..])
+            .ok_or(Error::Unsupported("rtmp: url scheme"))?;
+
+        let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if host.is_empty() {
+            return Err(Error::InvalidData("rtmp: url without host"));
+        }
+
+        let (app, key) = path
+            .rsplit_once('/')
+            .filter(|(app, key)| !app.is_empty() && !key.is_empty())
+            .ok_or(Error::InvalidData("rtmp: url without app or stream key"))?;
+
+        // the port is after the last colon unless it is a part of IPv6 address
+        let address = match host.rsplit_once(':') {
+            Some((_, port)) if !port.contains(']') => {
+                port.parse::<u16>()
+                    .map_err(|_| Error::InvalidData("rtmp: invalid url port"))?;
+
+                host.to_string()
+            }
+            _ => format!("{host}:{DEFAULT_PORT}"),
+        };
+
+        Ok(Self {
+            address,
+            app: app.to_string(),
+            key: key.to_string(),
+            tc_url: format!("rtmp://{host}/{app}"),
+        })
+    }
+}
+
+fn read_command(message: &Message) -> Result<Option<Command>, Error> {
+    if !message.is_command() {
+        return Ok(None);
+    }
+
+    Ok(Some(Command::parse(&mut message.amf0_payload())?))
+}
+
+/// Waits for `_result` of the transaction
+async fn wait_result(conn: &mut Connection, transaction_id: f64) -> Result<Command, Error> {
+    loop {
+        let Some(command) = read_command(&conn.read_message().await?)? else {
+            continue;
+        };
+
+        if command.transaction_id != transaction_id {
+            continue;
+        }
+
+        match command.name.as_str() {
+            "_result" => return Ok(command),
+            "_error" => return Err(status_error(&command)),
+            _ => (),
+        }
+    }
+}
+
+fn status_code(command: &Command) -> &str {
+    command
+        .info()
+        .and_then(|x| x.get("code"))
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+fn is_error(command: &Command) -> bool {
+    command.name == "_error"
+        || command
+            .info()
+            .and_then(|x| x.get("level"))
+            .and_then(Value::as_str)
+            == Some("error")
+}
+
+fn status_error(command: &Command) -> Error {
+    match status_code(command) {
+        "" => Error::RtmpStatus(command.name.clone()),
+        code => Error::RtmpStatus(code.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RtmpUrl;
+
+    #[test]
+    fn test_rtmp_url() {
+        let url = RtmpUrl::parse("rtmp://example.com/live/key?token=1").unwrap();
+        assert_eq!(url.address, "example.com:1935");
+        assert_eq!(
+            (url.app.as_str(), url.key.as_str()),
+            ("live", "key?token=1")
+        );
+        assert_eq!(url.tc_url, "rtmp://example.com/live");
+
+        let url = RtmpUrl::parse("RTMP://[::1]:1936/app/inst/key").unwrap();
+        assert_eq!(url.address, "[::1]:1936");
+        assert_eq!(url.app, "app/inst");
+
+        assert!(RtmpUrl::parse("rtmp://example.com/key").is_err());
+        assert!(RtmpUrl::parse("rtsp://example.com/live/key").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use std::io;
+
+use bytes::BytesMut;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::{
+    chunk::{ChunkReader, ChunkWriter, chunk_stream_id},
+    message::{
+        EVENT_PING_REQUEST, EVENT_PING_RESPONSE, MSG_ABORT, MSG_ACKNOWLEDGEMENT,
+        MSG_SET_CHUNK_SIZE, MSG_SET_PEER_BANDWIDTH, MSG_USER_CONTROL, MSG_WINDOW_ACK_SIZE, Message,
+    },
+};
+use crate::error::Error;
+
+/// RTMP connection after the handshake, answers the protocol control
+/// messages (chunk size, acknowledgements and pings) of the peer
+#[derive(Debug)]
+pub struct Connection {
+    stream: TcpStream,
+    buf: BytesMut,
+    reader: ChunkReader,
+    writer: ChunkWriter,
+    out: BytesMut,
+    received: u64,
+    acknowledged: u64,
+    ack_window: u64,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            buf: BytesMut::with_capacity(64 * 1024),
+            reader: ChunkReader::new(),
+            writer: ChunkWriter::new(),
+            out: BytesMut::new(),
+            received: 0,
+            acknowledged: 0,
+            ack_window: 0,
+        }
+    }
+
+    /// Reads the next message which isn't a protocol control one
+    pub async fn read_message(&mut self) -> Result<Message, Error> {
+        loop {
+            if let Some(message) = self.next_message().await? {
+                return Ok(message);
+            }
+
+            let len = self.stream.read_buf(&mut self.buf).await?;
+            if len == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+
+            self.received_bytes(len).await?;
+        }
+    }
+
+    /// Messages which are already received, doesn't wait for the peer
+    pub async fn drain(&mut self) -> Result<Vec<Message>, Error> {
+        let mut out = Vec::new();
+
+        loop {
+            while let Some(message) = self.next_message().await? {
+                out.push(message);
+            }
+
+            match self.stream.try_read_buf(&mut self.buf) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(len) => self.received_bytes(len).await?,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(out),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Sends the messages with a single write, returns the number of bytes sent
+    pub async fn send(&mut self, messages: &[Message]) -> Result<usize, Error> {
+        for message in messages {
+            self.writer
+                .write(chunk_stream_id(message.kind), message, &mut self.out);
+        }
+
+        let len = self.out.len();
+        self.stream.write_all(&self.out).await?;
+        self.out.clear();
+
+        Ok(len)
+    }
+
+    /// Announces and applies the size of the outgoing chunks
+    pub async fn set_chunk_size(&mut self, size: usize) -> Result<(), Error> {
+        self.send(&[Message::set_chunk_size(size as u32)]).await?;
+        self.writer.set_chunk_size(size);
+
+        Ok(())
+    }
+
+    async fn next_message(&mut self) -> Result<Option<Message>, Error> {
+        while let Some(message) = self.reader.read(&mut self.buf)? {
+            match message.kind {
+                MSG_SET_CHUNK_SIZE => self.reader.set_chunk_size(message.read_u32()? as usize)?,
+                MSG_ABORT => self.reader.abort(message.read_u32()?),
+                MSG_WINDOW_ACK_SIZE => self.ack_window = message.read_u32()? as u64,
+                MSG_USER_CONTROL => {
+                    let payload = &message.payload;
+
+                    if payload.len() >= 6
+                        && u16::from_be_bytes([payload[0], payload[1]]) == EVENT_PING_REQUEST
+                    {
+                        let value =
+                            u32::from_be_bytes([payload[2], payload[3], payload[4], payload[5]]);
+                        self.send(&[Message::user_control(EVENT_PING_RESPONSE, value)])
+                            .await?;
+                    }
+                }
+
+                // acknowledgements and the peer bandwidth limit aren't used
+                MSG_ACKNOWLEDGEMENT | MSG_SET_PEER_BANDWIDTH => (),
+                _ => return Ok(Some(message)),
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn received_bytes(&mut self, len: usize) -> Result<(), Error> {
+        self.received += len as u64;
+
+        if self.ack_window > 0 && self.received - self.acknowledged >= self.ack_window {
+            self.acknowledged = self.received;
+            self.send(&[Message::acknowledgement(self.received as u32)])
+                .await?;
+        }
+
+        Ok(())
+    }
+}
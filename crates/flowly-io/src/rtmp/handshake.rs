@@ -0,0 +1,74 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    time::SystemTime,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::Error;
+
+pub const RTMP_VERSION: u8 = 3;
+
+/// Size of C1/C2/S1/S2
+pub const HANDSHAKE_SIZE: usize = 1536;
+
+/// Client side of the simple handshake: C0+C1, S0+S1+S2, C2 (echo of S1)
+pub async fn client_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> Result<(), Error> {
+    let mut c0c1 = vec![RTMP_VERSION];
+    c0c1.extend_from_slice(&random_block());
+    stream.write_all(&c0c1).await?;
+
+    let mut s0s1s2 = vec![0u8; 1 + 2 * HANDSHAKE_SIZE];
+    stream.read_exact(&mut s0s1s2).await?;
+
+    if s0s1s2[0] != RTMP_VERSION {
+        return Err(Error::Unsupported("rtmp: protocol version"));
+    }
+
+    stream.write_all(&s0s1s2[1..1 + HANDSHAKE_SIZE]).await?;
+
+    Ok(())
+}
+
+/// Server side of the simple handshake: C0+C1, S0+S1+S2 (echo of C1), C2.
+/// The digest of C1 sent by Flash based clients isn't verified, so they are
+/// served the same way
+pub async fn server_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> Result<(), Error> {
+    let mut c0c1 = vec![0u8; 1 + HANDSHAKE_SIZE];
+    stream.read_exact(&mut c0c1).await?;
+
+    if c0c1[0] != RTMP_VERSION {
+        return Err(Error::Unsupported("rtmp: protocol version"));
+    }
+
+    let mut s0s1s2 = vec![RTMP_VERSION];
+    s0s1s2.extend_from_slice(&random_block());
+    s0s1s2.extend_from_slice(&c0c1[1..]);
+    stream.write_all(&s0s1s2).await?;
+
+    let mut c2 = vec![0u8; HANDSHAKE_SIZE];
+    stream.read_exact(&mut c2).await?;
+
+    Ok(())
+}
+
+/// C1/S1: time, zero and random bytes
+fn random_block() -> Vec<u8> {
+    let mut block = vec![0u8; HANDSHAKE_SIZE];
+    let mut state = RandomState::new().hash_one(SystemTime::now()) | 1;
+
+    for chunk in block[8..].chunks_mut(8) {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+
+        chunk.copy_from_slice(&state.to_le_bytes()[..chunk.len()]);
+    }
+
+    block
+}
@@ -0,0 +1,192 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flowly_core::Reader;
+
+use crate::{amf0::Value, error::Error};
+
+pub const MSG_SET_CHUNK_SIZE: u8 = 1;
+pub const MSG_ABORT: u8 = 2;
+pub const MSG_ACKNOWLEDGEMENT: u8 = 3;
+pub const MSG_USER_CONTROL: u8 = 4;
+pub const MSG_WINDOW_ACK_SIZE: u8 = 5;
+pub const MSG_SET_PEER_BANDWIDTH: u8 = 6;
+pub const MSG_AUDIO: u8 = 8;
+pub const MSG_VIDEO: u8 = 9;
+pub const MSG_DATA_AMF3: u8 = 15;
+pub const MSG_COMMAND_AMF3: u8 = 17;
+pub const MSG_DATA_AMF0: u8 = 18;
+pub const MSG_COMMAND_AMF0: u8 = 20;
+
+pub const EVENT_STREAM_BEGIN: u16 = 0;
+pub const EVENT_PING_REQUEST: u16 = 6;
+pub const EVENT_PING_RESPONSE: u16 = 7;
+
+/// `SetPeerBandwidth` limit type
+pub const BANDWIDTH_LIMIT_DYNAMIC: u8 = 2;
+
+/// RTMP message, the payload of the reassembled chunks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub kind: u8,
+    pub stream_id: u32,
+
+    /// Timestamp in milliseconds
+    pub timestamp: u32,
+    pub payload: Bytes,
+}
+
+impl Message {
+    pub fn new(kind: u8, stream_id: u32, timestamp: u32, payload: Bytes) -> Self {
+        Self {
+            kind,
+            stream_id,
+            timestamp,
+            payload,
+        }
+    }
+
+    pub fn set_chunk_size(size: u32) -> Self {
+        Self::control(MSG_SET_CHUNK_SIZE, &(size & 0x7FFF_FFFF).to_be_bytes())
+    }
+
+    pub fn acknowledgement(sequence: u32) -> Self {
+        Self::control(MSG_ACKNOWLEDGEMENT, &sequence.to_be_bytes())
+    }
+
+    pub fn window_ack_size(size: u32) -> Self {
+        Self::control(MSG_WINDOW_ACK_SIZE, &size.to_be_bytes())
+    }
+
+    pub fn set_peer_bandwidth(size: u32, limit_type: u8) -> Self {
+        let mut payload = size.to_be_bytes().to_vec();
+        payload.push(limit_type);
+
+        Self::control(MSG_SET_PEER_BANDWIDTH, &payload)
+    }
+
+    pub fn user_control(event: u16, value: u32) -> Self {
+        let mut payload = event.to_be_bytes().to_vec();
+        payload.extend_from_slice(&value.to_be_bytes());
+
+        Self::control(MSG_USER_CONTROL, &payload)
+    }
+
+    /// AMF0 command message of the message stream
+    pub fn command(stream_id: u32, command: &Command) -> Self {
+        Self::new(MSG_COMMAND_AMF0, stream_id, 0, command.to_bytes())
+    }
+
+    /// Protocol control messages are sent on the message stream 0
+    fn control(kind: u8, payload: &[u8]) -> Self {
+        Self::new(kind, 0, 0, Bytes::copy_from_slice(payload))
+    }
+
+    #[inline]
+    pub fn is_command(&self) -> bool {
+        matches!(self.kind, MSG_COMMAND_AMF0 | MSG_COMMAND_AMF3)
+    }
+
+    /// First `u32` of the payload (the value of most protocol control messages)
+    pub fn read_u32(&self) -> Result<u32, Error> {
+        Ok(self.payload.clone().read_u32()?)
+    }
+
+    /// AMF0 payload of the command or data message, AMF3 messages start with
+    /// a format selector byte followed by AMF0 values
+    pub fn amf0_payload(&self) -> Bytes {
+        match self.kind {
+            MSG_COMMAND_AMF3 | MSG_DATA_AMF3 if self.payload.first() == Some(&0) => {
+                self.payload.slice(1..)
+            }
+            _ => self.payload.clone(),
+        }
+    }
+}
+
+/// AMF0 encoded command: name, transaction id, command object and optional
+/// arguments
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    pub name: String,
+    pub transaction_id: f64,
+    pub object: Value,
+    pub args: Vec<Value>,
+}
+
+impl Command {
+    pub fn new(name: impl Into<String>, transaction_id: f64, object: Value) -> Self {
+        Self {
+            name: name.into(),
+            transaction_id,
+            object,
+            args: Vec::new(),
+        }
+    }
+
+    pub fn with_arg(mut self, arg: impl Into<Value>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn parse<B: Buf>(buf: &mut B) -> Result<Self, Error> {
+        let Value::String(name) = Value::decode(buf)? else {
+            return Err(Error::InvalidData("rtmp: command without name"));
+        };
+
+        // some messages (e.g. `onStatus` of old servers) omit the trailing values
+        let transaction_id = if buf.has_remaining() {
+            Value::decode(buf)?.as_f64().unwrap_or_default()
+        } else {
+            0.0
+        };
+
+        let object = if buf.has_remaining() {
+            Value::decode(buf)?
+        } else {
+            Value::Null
+        };
+
+        Ok(Self {
+            name,
+            transaction_id,
+            object,
+            args: Value::decode_all(buf)?,
+        })
+    }
+
+    /// Argument after the command object
+    #[inline]
+    pub fn arg(&self, idx: usize) -> Option<&Value> {
+        self.args.get(idx)
+    }
+
+    /// Status object of `onStatus` and `_error` (the first object argument)
+    #[inline]
+    pub fn info(&self) -> Option<&Value> {
+        self.args.iter().find(|x| matches!(x, Value::Object(_)))
+    }
+
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        Value::String(self.name.clone()).encode(buf);
+        Value::Number(self.transaction_id).encode(buf);
+        self.object.encode(buf);
+
+        for arg in &self.args {
+            arg.encode(buf);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.write(&mut buf);
+        buf.freeze()
+    }
+}
+
+/// `_result`/`onStatus` info object
+pub fn status_object(level: &str, code: &str, description: &str) -> Value {
+    Value::Object(vec![
+        ("level".into(), level.into()),
+        ("code".into(), code.into()),
+        ("description".into(), description.into()),
+    ])
+}
@@ -0,0 +1,42 @@
+mod chunk;
+mod client;
+mod connection;
+mod handshake;
+mod message;
+mod server;
+
+pub use chunk::{ChunkReader, ChunkWriter, DEFAULT_CHUNK_SIZE};
+pub use client::{DEFAULT_PORT, RtmpClient};
+pub use handshake::{HANDSHAKE_SIZE, RTMP_VERSION, client_handshake, server_handshake};
+pub use message::{Command, Message};
+pub use server::RtmpServer;
+
+use flowly_core::{FrameSource, FrameSourceKind};
+
+/// Published stream of the RTMP server
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RtmpSource {
+    url: String,
+    name: String,
+}
+
+impl FrameSource for RtmpSource {
+    type Source = flowly_core::Void;
+
+    fn source(&self) -> &Self::Source {
+        unreachable!()
+    }
+
+    fn kind(&self) -> FrameSourceKind {
+        FrameSourceKind::Url
+    }
+
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Stream key without the query
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use flowly_core::{FrameFlags, Packet};
+use flowly_service::{Context, Service};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, watch},
+};
+
+use super::{
+    RtmpSource,
+    chunk::DEFAULT_CHUNK_SIZE,
+    connection::Connection,
+    handshake::server_handshake,
+    message::{
+        BANDWIDTH_LIMIT_DYNAMIC, Command, EVENT_STREAM_BEGIN, MSG_AUDIO, MSG_DATA_AMF0,
+        MSG_DATA_AMF3, MSG_VIDEO, Message, status_object,
+    },
+};
+use crate::{amf0::Value, error::Error, flv::FlvDemuxer};
+
+/// Acknowledgement window and peer bandwidth announced to the publishers
+const WINDOW_SIZE: u32 = 2_500_000;
+
+type Output = Result<Packet<Arc<RtmpSource>>, Error>;
+
+/// RTMP ingest server.
+///
+/// Listens on the address and accepts any number of publishers (`connect`,
+/// `createStream` and `publish`). Audio, video and data messages are parsed
+/// as FLV tag bodies, so the packets are the same as of `FlvDemuxer`,
+/// including enhanced RTMP codecs (HEVC, AV1, VP9, Opus and FLAC) announced
+/// with `fourCcList`. Packets of all publishers are yielded as they arrive,
+/// the source is `rtmp://host:port/<app>/<stream key>`. Publishing ends with
+/// `deleteStream` or the disconnect, the listener stops with the context abort.
+#[derive(Debug, Clone)]
+pub struct RtmpServer {
+    chunk_size: usize,
+    local_addr: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl RtmpServer {
+    pub fn new() -> Self {
+        Self {
+            chunk_size: 4096,
+            local_addr: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Size of the outgoing chunks
+    pub fn with_chunk_size(mut self, size: usize) -> Self {
+        self.chunk_size = size.max(DEFAULT_CHUNK_SIZE);
+        self
+    }
+
+    /// Address of the listener once it is bound
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        *self.local_addr.lock().unwrap()
+    }
+}
+
+impl Default for RtmpServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: AsRef<str> + Send + Sync> Service<A> for RtmpServer {
+    type Out = Output;
+
+    fn handle(
+        &mut self,
+        address: A,
+        cx: &Context,
+    ) -> impl futures::Stream<Item = Self::Out> + Send {
+        async_stream::stream! {
+            let listener = match TcpListener::bind(address.as_ref()).await {
+                Ok(listener) => listener,
+                Err(err) => {
+                    yield Err(err.into());
+                    return;
+                }
+            };
+
+            let local_addr = match listener.local_addr() {
+                Ok(addr) => addr,
+                Err(err) => {
+                    yield Err(err.into());
+                    return;
+                }
+            };
+
+            *self.local_addr.lock().unwrap() = Some(local_addr);
+
+            let (tx, mut rx) = mpsc::channel(256);
+            let mut abort = cx.abort_recv.clone();
+
+            enum Event {
+                Accepted(TcpStream),
+                Packet(Output),
+                Stop,
+            }
+
+            loop {
+                let event = tokio::select! {
+                    biased;
+
+                    _ = abort.changed() => Event::Stop,
+                    Some(out) = rx.recv() => Event::Packet(out),
+                    res = listener.accept() => match res {
+                        Ok((stream, _)) => Event::Accepted(stream),
+                        Err(err) => Event::Packet(Err(err.into())),
+                    },
+                };
+
+                match event {
+                    Event::Stop => break,
+                    Event::Packet(out) => yield out,
+                    Event::Accepted(stream) => {
+                        let _ = stream.set_nodelay(true);
+
+                        let session = Session {
+                            local_addr,
+                            chunk_size: self.chunk_size,
+                            tx: tx.clone(),
+                            app: String::new(),
+                            stream_id: 0,
+                            publishing: None,
+                        };
+
+                        tokio::spawn(session.run(stream, abort.clone()));
+                    }
+                }
+            }
+
+            *self.local_addr.lock().unwrap() = None;
+        }
+    }
+}
+
+/// Connection of the publisher
+struct Session {
+    local_addr: SocketAddr,
+    chunk_size: usize,
+    tx: mpsc::Sender<Output>,
+    app: String,
+
+    /// Id of the last created message stream
+    stream_id: u32,
+    publishing: Option<(Arc<RtmpSource>, FlvDemuxer<Arc<RtmpSource>>)>,
+}
+
+impl Session {
+    async fn run(mut self, stream: TcpStream, mut abort: watch::Receiver<bool>) {
+        let res = tokio::select! {
+            _ = abort.changed() => Ok(()),
+            res = self.serve(stream) => res,
+        };
+
+        match res {
+            Err(Error::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => (),
+            Err(err) => {
+                let _ = self.tx.send(Err(err)).await;
+            }
+            Ok(()) => (),
+        }
+    }
+
+    async fn serve(&mut self, mut stream: TcpStream) -> Result<(), Error> {
+        server_handshake(&mut stream).await?;
+
+        let mut conn = Connection::new(stream);
+
+        loop {
+            let message = conn.read_message().await?;
+
+            if message.is_command() {
+                let command = Command::parse(&mut message.amf0_payload())?;
+
+                if !self.command(&mut conn, message.stream_id, command).await? {
+                    return Ok(());
+                }
+
+                continue;
+            }
+
+            let Some((source, demuxer)) = &mut self.publishing else {
+                continue;
+            };
+
+            let (kind, payload) = match message.kind {
+                MSG_AUDIO | MSG_VIDEO => (message.kind, message.payload),
+                MSG_DATA_AMF0 | MSG_DATA_AMF3 => {
+                    (MSG_DATA_AMF0, strip_set_data_frame(message.amf0_payload()))
+                }
+                _ => continue,
+            };
+
+            let out = demuxer
+                .push_tag(kind, message.timestamp, payload)
+                .map(|packet| {
+                    packet.map(|mut packet| {
+                        packet.source = source.clone();
+                        packet.flags |= FrameFlags::LIVE | FrameFlags::MULTICHANNEL;
+                        packet
+                    })
+                });
+
+            let out = match out {
+                Ok(Some(packet)) => Ok(packet),
+                Ok(None) => continue,
+                Err(err) => Err(err),
+            };
+
+            if self.tx.send(out).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Answers the command, `false` when the publishing is over
+    async fn command(
+        &mut self,
+        conn: &mut Connection,
+        stream_id: u32,
+        command: Command,
+    ) -> Result<bool, Error> {
+        let transaction_id = command.transaction_id;
+
+        match command.name.as_str() {
+            "connect" => {
+                self.app = command
+                    .object
+                    .get("app")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .trim_matches('/')
+                    .to_string();
+
+                let mut properties = vec![
+                    ("fmsVer".to_string(), "FMS/3,0,1,123".into()),
+                    ("capabilities".to_string(), 31.0.into()),
+                ];
+
+                // enhanced RTMP: the codecs of the client list are accepted
+                if let Some(list) = command.object.get("fourCcList") {
+                    properties.push(("fourCcList".to_string(), list.clone()));
+                }
+
+                let mut info = status_object(
+                    "status",
+                    "NetConnection.Connect.Success",
+                    "Connection succeeded.",
+                );
+
+                if let Value::Object(props) = &mut info {
+                    props.push(("objectEncoding".to_string(), 0.0.into()));
+                }
+
+                let result = Command::new("_result", transaction_id, Value::Object(properties))
+                    .with_arg(info);
+
+                conn.send(&[
+                    Message::window_ack_size(WINDOW_SIZE),
+                    Message::set_peer_bandwidth(WINDOW_SIZE, BANDWIDTH_LIMIT_DYNAMIC),
+                ])
+                .await?;
+                conn.set_chunk_size(self.chunk_size).await?;
+                conn.send(&[Message::command(0, &result)]).await?;
+            }
+
+            "createStream" => {
+                self.stream_id += 1;
+
+                let result = Command::new("_result", transaction_id, Value::Null)
+                    .with_arg(self.stream_id as f64);
+
+                conn.send(&[Message::command(0, &result)]).await?;
+            }
+
+            "publish" => {
+                let key = command
+                    .arg(0)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string();
+
+                let name = key.split('?').next().unwrap_or_default();
+                let source = Arc::new(RtmpSource {
+                    url: format!("rtmp://{}/{}/{name}", self.local_addr, self.app),
+                    name: name.to_string(),
+                });
+
+                self.publishing = Some((source, FlvDemuxer::new()));
+
+                let status = Command::new("onStatus", 0.0, Value::Null).with_arg(status_object(
+                    "status",
+                    "NetStream.Publish.Start",
+                    &format!("{name} is now published."),
+                ));
+
+                conn.send(&[
+                    Message::user_control(EVENT_STREAM_BEGIN, stream_id),
+                    Message::command(stream_id, &status),
+                ])
+                .await?;
+            }
+
+            "play" => {
+                let status = Command::new("onStatus", 0.0, Value::Null).with_arg(status_object(
+                    "error",
+                    "NetStream.Play.Failed",
+                    "Playback is not supported.",
+                ));
+
+                conn.send(&[Message::command(stream_id, &status)]).await?;
+                return Ok(false);
+            }
+
+            "deleteStream" | "closeStream" if self.publishing.is_some() => return Ok(false),
+
+            // releaseStream, FCPublish, FCUnpublish etc. don't need an answer
+            _ => (),
+        }
+
+        Ok(true)
+    }
+}
+
+/// Data messages of the publishers are `@setDataFrame` followed by the FLV
+/// script data (e.g. `onMetaData` and its array)
+fn strip_set_data_frame(payload: Bytes) -> Bytes {
+    let mut rest = payload.clone();
+
+    match Value::decode(&mut rest) {
+        Ok(Value::String(name)) if name == "@setDataFrame" => rest,
+        _ => payload,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{pin::pin, time::Duration};
+
+    use bytes::Bytes;
+    use flowly_core::{EncodedFrame, Fourcc, Frame, FrameFlags, FrameSource, Packet};
+    use flowly_service::{Context, Service};
+    use futures::StreamExt;
+
+    use super::RtmpServer;
+    use crate::{
+        rtmp::RtmpClient,
+        test_fixtures::{PPS, SPS},
+    };
+
+    #[tokio::test]
+    async fn test_rtmp_server() {
+        let mut annexb = Vec::new();
+        for nal in [SPS, PPS, &[0x65, 0x88, 0x80]] {
+            annexb.extend_from_slice(&[0, 0, 0, 1]);
+            annexb.extend_from_slice(nal);
+        }
+
+        let frames = [
+            // dropped, the stream starts with a keyframe
+            Packet::new(
+                (),
+                Fourcc::VIDEO_AVC,
+                FrameFlags::VIDEO_STREAM | FrameFlags::ANNEXB,
+                Bytes::from_static(&[0, 0, 0, 1, 0x41, 0x9A]),
+            ),
+            Packet::new(
+                (),
+                Fourcc::VIDEO_AVC,
+                FrameFlags::VIDEO_STREAM | FrameFlags::KEYFRAME | FrameFlags::ANNEXB,
+                Bytes::from(annexb),
+            )
+            .with_timestamps(1_000_000, 1_080_000),
+            Packet::new(
+                (),
+                Fourcc::AUDIO_AAC,
+                FrameFlags::AUDIO_STREAM,
+                Bytes::from_static(&[0x21, 0x00]),
+            )
+            .with_timestamps(1_020_000, 1_020_000)
+            .with_params(vec![Bytes::from_static(&[0x12, 0x10])]),
+            Packet::new(
+                (),
+                Fourcc::VIDEO_AV1,
+                FrameFlags::VIDEO_STREAM | FrameFlags::KEYFRAME,
+                Bytes::from(vec![0x32; 10_000]),
+            )
+            .with_timestamps(1_040_000, 1_040_000)
+            .with_params(vec![Bytes::from_static(&[0x81, 0x08, 0x0C, 0x00])]),
+        ];
+
+        let cx = Context::new();
+        let mut server = RtmpServer::new();
+        let bound = server.clone();
+
+        let publisher = tokio::spawn(async move {
+            let addr = loop {
+                match bound.local_addr() {
+                    Some(addr) => break addr,
+                    None => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            };
+
+            let cx = Context::new();
+            let mut client = RtmpClient::new(format!("rtmp://{addr}/live/cam?token=1"));
+            let mut sent = Vec::new();
+
+            for frame in frames {
+                sent.push(
+                    pin!(client.handle(frame, &cx))
+                        .next()
+                        .await
+                        .unwrap()
+                        .unwrap(),
+                );
+            }
+
+            sent
+        });
+
+        let mut packets = pin!(server.handle("127.0.0.1:0", &cx));
+        let mut out = Vec::new();
+
+        while out.len() < 3 {
+            out.push(packets.next().await.unwrap().unwrap());
+        }
+
+        let sent = publisher.await.unwrap();
+        assert_eq!(sent[0], 0);
+        assert!(sent[1..].iter().all(|&x| x > 0));
+
+        let video = &out[0];
+        assert_eq!(video.source.name(), "cam");
+        assert!(video.source.url().ends_with("/live/cam"));
+        assert_eq!(video.codec, Fourcc::VIDEO_AVC);
+        assert_eq!((video.dts, video.pts), (0, 80_000));
+        assert_eq!(video.params().collect::<Vec<_>>(), [SPS, PPS]);
+        assert!(video.is_keyframe() && video.is_live() && video.is_multichannel());
+        assert_eq!(&video.data[..], &[0, 0, 0, 3, 0x65, 0x88, 0x80]);
+
+        let audio = &out[1];
+        assert_eq!(audio.codec, Fourcc::AUDIO_AAC);
+        assert_eq!(audio.dts, 20_000);
+        assert_eq!(audio.params, [&[0x12, 0x10][..]]);
+
+        // enhanced RTMP, chunked by the 4096 bytes chunk size
+        let av1 = &out[2];
+        assert_eq!(av1.codec, Fourcc::VIDEO_AV1);
+        assert_eq!(av1.dts, 40_000);
+        assert_eq!(av1.params, [&[0x81, 0x08, 0x0C, 0x00][..]]);
+        assert_eq!(av1.data.len(), 10_000);
+        assert!(av1.is_keyframe());
+    }
+}
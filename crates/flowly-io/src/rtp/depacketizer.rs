@@ -0,0 +1,1035 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flowly_codec::{VideoInfo, aac::SAMPLES_PER_FRAME, av1, h264, h265, nal, opus};
+use flowly_core::{BitReader, DataFrame, Fourcc, FrameFlags, FrameSource, Packet};
+use flowly_service::{Context, Service};
+
+use super::{RtpPacket, Unwrapper};
+use crate::error::Error;
+
+/// Largest forward jump of the sequence number still taken as a loss
+const MAX_DROPOUT: i16 = 3000;
+
+/// Consecutive packets out of the sequence window which resynchronize the stream
+const RESYNC_PACKETS: u32 = 4;
+
+/// AU header layout of the RFC 3640 `mpeg4-generic` payload, from the SDP `fmtp`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AacMode {
+    pub size_length: u8,
+    pub index_length: u8,
+    pub index_delta_length: u8,
+}
+
+impl AacMode {
+    /// `mode=AAC-hbr`
+    pub const HBR: Self = Self {
+        size_length: 13,
+        index_length: 3,
+        index_delta_length: 3,
+    };
+
+    /// `mode=AAC-lbr`
+    pub const LBR: Self = Self {
+        size_length: 6,
+        index_length: 2,
+        index_delta_length: 2,
+    };
+}
+
+impl Default for AacMode {
+    fn default() -> Self {
+        Self::HBR
+    }
+}
+
+/// RTP depacketizer.
+///
+/// Reassembles the payload of a single RTP stream into a [`Packet`] per access
+/// unit: H.264 (RFC 6184, single NAL unit, STAP-A and FU-A), H.265 (RFC 7798,
+/// single NAL unit, AP and FU), AV1 (RTP payload format for AV1), Opus
+/// (RFC 7587) and AAC (RFC 3640 `mpeg4-generic`). H.264/H.265 access units are
+/// yielded in AnnexB with the parameter sets attached to keyframes, the ones
+/// given by [`RtpDepacketizer::with_params`] (`sprop-parameter-sets`) are
+/// replaced by in-band ones. AV1 is yielded in the low overhead format with
+/// `av1C` built from the in-band sequence header. AAC frames carry
+/// `AudioSpecificConfig` passed as params.
+///
+/// RTP timestamps are converted using the clock rate relative to the first
+/// packet (or [`RtpDepacketizer::set_reference`]) and used as both DTS and PTS. Gaps in sequence numbers are reported
+/// as errors and the access units affected by the loss are dropped. Packets
+/// arriving after the gap are held back by [`RtpDepacketizer::with_reorder_window`]
+/// so a late packet can still fill it, without the window (the default, as
+/// needed for TCP) reordered packets count as lost and are dropped along with
+/// duplicated ones. The stream is resynchronized on an SSRC change or after
+/// several consecutive packets out of the sequence window (a restarted
+/// sender), the timestamps continue from the last packet and the resync is
+/// reported as an error. All packets are tagged as live. The state is reset
+/// when the frame source changes, every chunk of the input frame is a single
+/// RTP packet.
+#[derive(Debug, Clone)]
+pub struct RtpDepacketizer<S = ()> {
+    source: S,
+    codec: Fourcc,
+    clock_rate: u32,
+    aac_mode: AacMode,
+
+    params: Vec<Bytes>,
+    dimensions: (u16, u16),
+    bit_depth: u8,
+    av1_seq: Option<av1::SequenceHeader>,
+
+    ssrc: Option<u32>,
+    sequence: Option<u16>,
+    lost: u64,
+    reorder_window: usize,
+
+    /// Packets after a gap in sequence numbers, sorted
+    pending: Vec<RtpPacket>,
+
+    /// Next sequence number and the count of the consecutive rejected packets
+    rejected: Option<(u16, u32)>,
+
+    timestamps: Unwrapper,
+
+    /// Unwrapped RTP timestamp mapped to microseconds and the time of the last packet
+    reference: Option<(i64, i64)>,
+    last: i64,
+
+    /// Access unit in progress: its timestamp, NAL units or OBUs
+    timestamp: Option<i64>,
+    units: Vec<Bytes>,
+    fragment: Option<BytesMut>,
+    corrupted: bool,
+}
+
+impl<S: FrameSource> RtpDepacketizer<S> {
+    /// Every packet is rejected with zero `clock_rate`
+    pub fn new(codec: Fourcc, clock_rate: u32) -> Self {
+        Self {
+            source: S::default(),
+            codec,
+            clock_rate,
+            aac_mode: AacMode::default(),
+            params: Vec::new(),
+            dimensions: (0, 0),
+            bit_depth: 0,
+            av1_seq: None,
+            ssrc: None,
+            sequence: None,
+            lost: 0,
+            reorder_window: 0,
+            pending: Vec::new(),
+            rejected: None,
+            timestamps: Unwrapper::default(),
+            reference: None,
+            last: 0,
+            timestamp: None,
+            units: Vec::new(),
+            fragment: None,
+            corrupted: false,
+        }
+    }
+
+    /// Sets the out-of-band decoding parameters: H.264/H.265 parameter sets,
+    /// `av1C`, AAC `AudioSpecificConfig` or `OpusHead`
+    pub fn with_params(mut self, params: Vec<Bytes>) -> Self {
+        self.set_params(params);
+        self
+    }
+
+    pub fn with_aac_mode(mut self, mode: AacMode) -> Self {
+        self.aac_mode = mode;
+        self
+    }
+
+    /// Number of packets held back after a gap in sequence numbers waiting for
+    /// the missing ones, e.g. for reordering UDP networks
+    pub fn with_reorder_window(mut self, packets: usize) -> Self {
+        self.reorder_window = packets;
+        self
+    }
+
+    #[inline]
+    pub fn codec(&self) -> Fourcc {
+        self.codec
+    }
+
+    /// Number of packets lost so far
+    #[inline]
+    pub fn lost_packets(&self) -> u64 {
+        self.lost
+    }
+
+    /// Maps the RTP timestamp to the time in microseconds, the later packets
+    /// are converted relative to it until the stream is resynchronized. Used
+    /// to align the tracks of a session (RTP-Info, RTCP sender reports)
+    pub fn set_reference(&mut self, rtp_timestamp: u32, micros: i64) {
+        let timestamp = self.timestamps.unwrap(rtp_timestamp);
+        self.reference = Some((timestamp, micros));
+    }
+
+    /// Time of the RTP timestamp in microseconds, `None` before the first
+    /// packet or reference
+    pub fn micros_at(&self, rtp_timestamp: u32) -> Option<i64> {
+        if self.reference.is_none() || self.clock_rate == 0 {
+            return None;
+        }
+
+        Some(self.micros(self.timestamps.extend(rtp_timestamp)))
+    }
+
+    /// Parses the RTP packet and returns all completed frames
+    pub fn push(&mut self, data: Bytes) -> Vec<Result<Packet<S>, Error>> {
+        match RtpPacket::parse(data) {
+            Ok(packet) => self.push_packet(packet),
+            Err(err) => vec![Err(err)],
+        }
+    }
+
+    pub fn push_packet(&mut self, packet: RtpPacket) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        if self.clock_rate == 0 {
+            out.push(Err(Error::InvalidData("rtp: zero clock rate")));
+            return out;
+        }
+
+        if self.ssrc.is_some_and(|x| x != packet.ssrc) {
+            self.resync(&mut out);
+        }
+
+        self.ssrc = Some(packet.ssrc);
+
+        let Some(last) = self.sequence else {
+            self.push_in_order(packet, &mut out);
+            return out;
+        };
+
+        let diff = packet.sequence.wrapping_sub(last) as i16;
+
+        match diff {
+            1 => {
+                self.rejected = None;
+                self.push_in_order(packet, &mut out);
+            }
+
+            2..=MAX_DROPOUT => {
+                self.rejected = None;
+
+                let pos = self
+                    .pending
+                    .partition_point(|x| (x.sequence.wrapping_sub(last) as i16) < diff);
+
+                if self.pending.get(pos).map(|x| x.sequence) != Some(packet.sequence) {
+                    self.pending.insert(pos, packet);
+                }
+
+                // the gap isn't filled in time
+                if self.pending.len() > self.reorder_window {
+                    let packet = self.pending.remove(0);
+                    self.lose(packet.sequence.wrapping_sub(last) - 1, &mut out);
+                    self.push_in_order(packet, &mut out);
+                }
+            }
+
+            // late, duplicated or out of the window
+            _ => {
+                let count = match self.rejected {
+                    Some((next, count)) if next == packet.sequence => count + 1,
+                    _ => 1,
+                };
+
+                self.rejected = Some((packet.sequence.wrapping_add(1), count));
+
+                if count < RESYNC_PACKETS {
+                    return out;
+                }
+
+                self.resync(&mut out);
+                self.push_in_order(packet, &mut out);
+            }
+        }
+
+        while let Some(next) = self.pending.first()
+            && self.sequence.map(|x| x.wrapping_add(1)) == Some(next.sequence)
+        {
+            let packet = self.pending.remove(0);
+            self.push_in_order(packet, &mut out);
+        }
+
+        out
+    }
+
+    /// Returns the access unit waiting for the marker bit
+    pub fn flush(&mut self) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        for packet in std::mem::take(&mut self.pending) {
+            if let Some(last) = self.sequence {
+                self.lose(packet.sequence.wrapping_sub(last).wrapping_sub(1), &mut out);
+            }
+
+            self.push_in_order(packet, &mut out);
+        }
+
+        out.extend(self.flush_access_unit().transpose());
+        out
+    }
+
+    fn lose(&mut self, count: u16, out: &mut Vec<Result<Packet<S>, Error>>) {
+        if count == 0 {
+            return;
+        }
+
+        self.lost += count as u64;
+        self.units.clear();
+        self.fragment = None;
+        self.timestamp = None;
+        self.corrupted = true;
+
+        out.push(Err(Error::InvalidData("rtp: packets lost")));
+    }
+
+    /// Starts over with the next packet keeping the timestamps continuous
+    fn resync(&mut self, out: &mut Vec<Result<Packet<S>, Error>>) {
+        self.sequence = None;
+        self.pending.clear();
+        self.rejected = None;
+        self.timestamps = Unwrapper::default();
+        self.reference = None;
+
+        self.units.clear();
+        self.fragment = None;
+        self.timestamp = None;
+        self.corrupted = false;
+
+        out.push(Err(Error::InvalidData("rtp: stream resynchronized")));
+    }
+
+    fn push_in_order(&mut self, packet: RtpPacket, out: &mut Vec<Result<Packet<S>, Error>>) {
+        self.sequence = Some(packet.sequence);
+
+        let timestamp = self.timestamps.unwrap(packet.timestamp);
+        self.reference.get_or_insert((timestamp, self.last));
+        self.last = self.micros(timestamp);
+
+        let result = match self.codec {
+            Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC | Fourcc::VIDEO_AV1 => {
+                if self.timestamp.is_some_and(|x| x != timestamp) {
+                    out.extend(self.flush_access_unit().transpose());
+                }
+
+                self.timestamp = Some(timestamp);
+
+                let result = match self.codec {
+                    Fourcc::VIDEO_AVC => self.push_h264(packet.payload),
+                    Fourcc::VIDEO_HEVC => self.push_h265(packet.payload),
+                    _ => self.push_av1(packet.payload),
+                };
+
+                if packet.marker {
+                    out.extend(self.flush_access_unit().transpose());
+                }
+
+                result
+            }
+
+            Fourcc::AUDIO_OPUS => self.push_opus(packet.payload, timestamp, out),
+            Fourcc::AUDIO_AAC => self.push_aac(packet, timestamp, out),
+            _ => Err(Error::Unsupported("rtp: payload format")),
+        };
+
+        if let Err(err) = result {
+            self.corrupted = true;
+            out.push(Err(err));
+        }
+    }
+
+    fn push_h264(&mut self, mut payload: Bytes) -> Result<(), Error> {
+        let &[header, ..] = payload.as_ref() else {
+            return Err(Error::InvalidData("rtp: empty payload"));
+        };
+
+        match header & 0x1F {
+            1..=23 => self.units.push(payload),
+
+            h264::NAL_STAP_A => {
+                payload.advance(1);
+                self.units.extend(read_aggregated(payload)?);
+            }
+
+            h264::NAL_FU_A => {
+                let &[_, fu, ..] = payload.as_ref() else {
+                    return Err(Error::InvalidData("rtp: truncated FU-A"));
+                };
+
+                payload.advance(2);
+                self.push_fragment(&[(header & 0xE0) | (fu & 0x1F)], fu, payload);
+            }
+
+            _ => return Err(Error::Unsupported("rtp: H.264 packetization mode")),
+        }
+
+        Ok(())
+    }
+
+    fn push_h265(&mut self, mut payload: Bytes) -> Result<(), Error> {
+        let &[h0, h1, ..] = payload.as_ref() else {
+            return Err(Error::InvalidData("rtp: truncated payload header"));
+        };
+
+        match (h0 >> 1) & 0x3F {
+            h265::NAL_AP => {
+                payload.advance(2);
+                self.units.extend(read_aggregated(payload)?);
+            }
+
+            h265::NAL_FU => {
+                let &[_, _, fu, ..] = payload.as_ref() else {
+                    return Err(Error::InvalidData("rtp: truncated FU"));
+                };
+
+                payload.advance(3);
+                self.push_fragment(&[(h0 & 0x81) | ((fu & 0x3F) << 1), h1], fu, payload);
+            }
+
+            // PACI
+            50 => return Err(Error::Unsupported("rtp: H.265 PACI packets")),
+            _ => self.units.push(payload),
+        }
+
+        Ok(())
+    }
+
+    /// Handles FU-A/FU fragment with the start and end bits in the FU header
+    fn push_fragment(&mut self, header: &[u8], fu: u8, data: Bytes) {
+        let start = fu & 0x80 != 0;
+        let end = fu & 0x40 != 0;
+
+        if start {
+            let mut fragment = BytesMut::with_capacity(header.len() + data.len());
+            fragment.put_slice(header);
+            fragment.put_slice(&data);
+            self.fragment = Some(fragment);
+        } else if let Some(fragment) = &mut self.fragment {
+            fragment.put_slice(&data);
+        } else {
+            // the first fragment is lost
+            self.corrupted = true;
+            return;
+        }
+
+        if end && let Some(fragment) = self.fragment.take() {
+            self.units.push(fragment.freeze());
+        }
+    }
+
+    fn push_av1(&mut self, payload: Bytes) -> Result<(), Error> {
+        let &[aggregation, ..] = payload.as_ref() else {
+            return Err(Error::InvalidData("rtp: empty payload"));
+        };
+
+        let continuation = aggregation & 0x80 != 0;
+        let continues = aggregation & 0x40 != 0;
+        let count = ((aggregation >> 4) & 0x03) as usize;
+
+        if !continuation && self.fragment.take().is_some() {
+            self.corrupted = true;
+        }
+
+        let mut elements = Vec::new();
+        let mut pos = 1;
+
+        while pos < payload.len() {
+            let len = if count == 0 || elements.len() + 1 < count {
+                let (len, size) = av1::read_leb128(&payload[pos..])?;
+                pos += size;
+                len as usize
+            } else {
+                payload.len() - pos
+            };
+
+            if pos + len > payload.len() {
+                return Err(Error::InvalidData("rtp: truncated OBU element"));
+            }
+
+            elements.push(payload.slice(pos..pos + len));
+            pos += len;
+        }
+
+        let last = elements.len().saturating_sub(1);
+
+        for (idx, element) in elements.into_iter().enumerate() {
+            let obu = if idx == 0 && continuation {
+                let Some(mut fragment) = self.fragment.take() else {
+                    self.corrupted = true;
+                    continue;
+                };
+
+                fragment.put_slice(&element);
+                fragment
+            } else {
+                BytesMut::from(element)
+            };
+
+            if idx == last && continues {
+                self.fragment = Some(obu);
+            } else {
+                self.units.push(obu.freeze());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn push_opus(
+        &mut self,
+        payload: Bytes,
+        timestamp: i64,
+        out: &mut Vec<Result<Packet<S>, Error>>,
+    ) -> Result<(), Error> {
+        let samples = opus::packet_samples(&payload)?;
+        let mut packet = self.audio_packet(payload, timestamp);
+        packet.duration = samples as u64 * 1_000_000 / opus::SAMPLE_RATE as u64;
+
+        out.push(Ok(packet));
+        Ok(())
+    }
+
+    fn push_aac(
+        &mut self,
+        packet: RtpPacket,
+        timestamp: i64,
+        out: &mut Vec<Result<Packet<S>, Error>>,
+    ) -> Result<(), Error> {
+        let mode = self.aac_mode;
+        if mode.size_length == 0 {
+            return Err(Error::Unsupported("rtp: AAC without AU sizes"));
+        }
+
+        if mode
+            .size_length
+            .max(mode.index_length)
+            .max(mode.index_delta_length)
+            > 32
+        {
+            return Err(Error::InvalidData(
+                "rtp: AU header field wider than 32 bits",
+            ));
+        }
+
+        let mut payload = packet.payload;
+        if payload.len() < 2 {
+            return Err(Error::InvalidData("rtp: truncated AU headers"));
+        }
+
+        let headers_bits = payload.get_u16() as usize;
+        let headers_len = headers_bits.div_ceil(8);
+        if payload.len() < headers_len {
+            return Err(Error::InvalidData("rtp: truncated AU headers"));
+        }
+
+        let mut reader = BitReader::new(payload.split_to(headers_len));
+        let mut sizes = Vec::new();
+        let mut consumed = 0;
+
+        while consumed < headers_bits {
+            let index_length = if sizes.is_empty() {
+                mode.index_length
+            } else {
+                mode.index_delta_length
+            };
+
+            sizes.push(reader.read_u32(mode.size_length)? as usize);
+            reader.skip_bits(index_length as usize)?;
+            consumed += mode.size_length as usize + index_length as usize;
+        }
+
+        // single fragmented access unit
+        if let [size] = sizes[..]
+            && (payload.len() < size || self.fragment.is_some())
+        {
+            let fragment = self.fragment.get_or_insert_with(BytesMut::new);
+            fragment.put_slice(&payload);
+
+            if fragment.len() >= size || packet.marker {
+                let mut data = self.fragment.take().unwrap_or_default();
+                if data.len() != size {
+                    return Err(Error::InvalidData("rtp: AU size mismatch"));
+                }
+
+                out.push(Ok(self.aac_packet(data.split().freeze(), timestamp)));
+            }
+
+            return Ok(());
+        }
+
+        let frame_ticks = SAMPLES_PER_FRAME as i64;
+
+        for (idx, size) in sizes.into_iter().enumerate() {
+            if payload.len() < size {
+                return Err(Error::InvalidData("rtp: truncated access unit"));
+            }
+
+            let data = payload.split_to(size);
+            out.push(Ok(
+                self.aac_packet(data, timestamp + idx as i64 * frame_ticks)
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn aac_packet(&self, data: Bytes, timestamp: i64) -> Packet<S> {
+        let mut packet = self.audio_packet(data, timestamp);
+        packet.duration = SAMPLES_PER_FRAME as u64 * 1_000_000 / self.clock_rate as u64;
+        packet
+    }
+
+    fn audio_packet(&self, data: Bytes, timestamp: i64) -> Packet<S> {
+        let flags = FrameFlags::AUDIO_STREAM | FrameFlags::KEYFRAME | FrameFlags::LIVE;
+        let pts = self.micros(timestamp);
+
+        Packet::new(self.source.clone(), self.codec, flags, data)
+            .with_timestamps(pts.max(0) as u64, pts)
+            .with_params(self.params.clone())
+    }
+
+    fn flush_access_unit(&mut self) -> Result<Option<Packet<S>>, Error> {
+        let units = std::mem::take(&mut self.units);
+        let corrupted = std::mem::take(&mut self.corrupted);
+        let Some(timestamp) = self.timestamp.take() else {
+            return Ok(None);
+        };
+
+        // the fragment of the unit is lost
+        if units.is_empty() || corrupted || self.fragment.take().is_some() {
+            return Ok(None);
+        }
+
+        let mut flags = FrameFlags::VIDEO_STREAM | FrameFlags::LIVE;
+
+        let (data, keyframe) = match self.codec {
+            Fourcc::VIDEO_AV1 => self.av1_access_unit(&units)?,
+            _ => {
+                flags |= FrameFlags::ANNEXB;
+                self.nal_access_unit(&units)
+            }
+        };
+
+        if keyframe {
+            flags |= FrameFlags::KEYFRAME;
+        }
+
+        let pts = self.micros(timestamp);
+        let mut packet = Packet::new(self.source.clone(), self.codec, flags, data)
+            .with_timestamps(pts.max(0) as u64, pts);
+
+        if keyframe && !self.params.is_empty() {
+            packet = packet.with_params(self.params.clone());
+        }
+
+        packet.dimensions = self.dimensions;
+        packet.bit_depth = self.bit_depth;
+
+        Ok(Some(packet))
+    }
+
+    fn nal_access_unit(&mut self, units: &[Bytes]) -> (Bytes, bool) {
+        let mut buf = BytesMut::with_capacity(units.iter().map(|x| x.len() + 4).sum());
+        let mut params = Vec::new();
+        let mut keyframe = false;
+
+        for nal in units {
+            if self.codec == Fourcc::VIDEO_AVC {
+                match h264::nal_type(nal) {
+                    Some(h264::NAL_IDR) => keyframe = true,
+                    Some(h264::NAL_SPS | h264::NAL_PPS) => params.push(nal.clone()),
+                    _ => (),
+                }
+            } else {
+                match h265::nal_type(nal) {
+                    Some(x) if h265::is_irap(x) => keyframe = true,
+                    Some(h265::NAL_VPS | h265::NAL_SPS | h265::NAL_PPS) => params.push(nal.clone()),
+                    _ => (),
+                }
+            }
+
+            nal::put_annexb(&mut buf, nal);
+        }
+
+        if !params.is_empty() && params != self.params {
+            self.set_params(params);
+        }
+
+        (buf.freeze(), keyframe)
+    }
+
+    fn av1_access_unit(&mut self, units: &[Bytes]) -> Result<(Bytes, bool), Error> {
+        let mut buf = BytesMut::with_capacity(units.iter().map(|x| x.len() + 8).sum());
+
+        for unit in units {
+            let (obu, _) = av1::Obu::parse(unit)?;
+
+            if obu.obu_type != av1::OBU_TEMPORAL_DELIMITER {
+                obu.write(&mut buf);
+            }
+        }
+
+        let data = buf.freeze();
+
+        if let Ok(config) = av1::Av1Config::from_obus(&data) {
+            let config = config.to_bytes();
+
+            if self.params.first() != Some(&config) {
+                self.set_params(vec![config]);
+            }
+        }
+
+        let keyframe = av1::is_keyframe(&data, self.av1_seq.as_ref())?;
+
+        Ok((data, keyframe))
+    }
+
+    fn set_params(&mut self, params: Vec<Bytes>) {
+        if let Ok(info) = VideoInfo::from_params(self.codec, &params) {
+            self.dimensions = info.dimensions();
+            self.bit_depth = info.bit_depth;
+        }
+
+        if self.codec == Fourcc::VIDEO_AV1 {
+            self.av1_seq = params
+                .first()
+                .and_then(|x| av1::sequence_header_of_param(x).ok());
+        }
+
+        self.params = params;
+    }
+
+    fn micros(&self, timestamp: i64) -> i64 {
+        let (base, micros) = self.reference.unwrap_or((timestamp, self.last));
+        micros + (timestamp - base) * 1_000_000 / self.clock_rate as i64
+    }
+
+    fn reset(&mut self, source: S) {
+        let params = std::mem::take(&mut self.params);
+
+        *self = Self::new(self.codec, self.clock_rate)
+            .with_aac_mode(self.aac_mode)
+            .with_reorder_window(self.reorder_window);
+        self.source = source;
+        self.set_params(params);
+    }
+}
+
+/// Splits STAP-A/AP payload (without the packet header) into NAL units
+fn read_aggregated(mut payload: Bytes) -> Result<Vec<Bytes>, Error> {
+    let mut units = Vec::new();
+
+    while payload.has_remaining() {
+        if payload.len() < 2 {
+            return Err(Error::InvalidData("rtp: truncated aggregation packet"));
+        }
+
+        let len = payload.get_u16() as usize;
+        if payload.len() < len {
+            return Err(Error::InvalidData("rtp: truncated aggregation packet"));
+        }
+
+        units.push(payload.split_to(len));
+    }
+
+    Ok(units)
+}
+
+impl<F> Service<F> for RtpDepacketizer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let mut out = Vec::new();
+
+        if frame.source() != &self.source {
+            out.extend(self.flush());
+            self.reset(frame.source().clone());
+        }
+
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use flowly_codec::av1;
+    use flowly_core::{EncodedFrame, Fourcc, Frame, VideoFrame};
+
+    use super::{AacMode, RtpDepacketizer};
+    use crate::{
+        rtp::RtpPacket,
+        test_fixtures::{PPS, SPS},
+    };
+
+    fn rtp(sequence: u16, timestamp: u32, marker: bool, payload: &[u8]) -> Bytes {
+        RtpPacket {
+            marker,
+            payload_type: 96,
+            sequence,
+            timestamp,
+            ssrc: 1,
+            payload: Bytes::copy_from_slice(payload),
+            ..Default::default()
+        }
+        .to_bytes()
+    }
+
+    #[test]
+    fn test_depacketize_h264() {
+        let mut depacketizer = RtpDepacketizer::<()>::new(Fourcc::VIDEO_AVC, 90_000);
+        let mut out = Vec::new();
+
+        // STAP-A with SPS and PPS
+        let mut stap = vec![24];
+        for nal in [SPS, PPS] {
+            stap.put_u16(nal.len() as u16);
+            stap.put_slice(nal);
+        }
+
+        out.extend(depacketizer.push(rtp(65534, 1000, false, &stap)));
+
+        // IDR split into FU-A
+        out.extend(depacketizer.push(rtp(65535, 1000, false, &[0x7C, 0x85, 1, 2])));
+        out.extend(depacketizer.push(rtp(0, 1000, false, &[0x7C, 0x05, 3, 4])));
+        out.extend(depacketizer.push(rtp(1, 1000, true, &[0x7C, 0x45, 5])));
+
+        // single NAL unit, the access unit is finished by the timestamp change
+        out.extend(depacketizer.push(rtp(2, 4000, false, &[0x41, 6, 7])));
+        out.extend(depacketizer.push(rtp(2, 4000, false, &[0x41, 6, 7])));
+
+        // the loss drops the access unit in progress and the next one
+        out.extend(depacketizer.push(rtp(4, 7000, false, &[0x7C, 0x45, 8])));
+        out.extend(depacketizer.push(rtp(5, 10000, true, &[0x41, 9])));
+
+        assert_eq!(out.len(), 3);
+        assert!(out[1].is_err());
+        assert_eq!(depacketizer.lost_packets(), 1);
+
+        let packets: Vec<_> = out.into_iter().filter_map(Result::ok).collect();
+        let idr = &packets[0];
+
+        let mut expected = BytesMut::new();
+        for nal in [SPS, PPS, &[0x65, 1, 2, 3, 4, 5]] {
+            expected.put_slice(&[0, 0, 0, 1]);
+            expected.put_slice(nal);
+        }
+
+        assert_eq!(idr.data, expected);
+        assert!(idr.is_keyframe() && idr.is_live());
+        assert_eq!(idr.params().collect::<Vec<_>>(), [SPS, PPS]);
+        assert_eq!(idr.dimensions(), (1920, 1080));
+        assert_eq!(idr.timestamp(), 0);
+
+        assert_eq!(packets[1].data, &[0, 0, 0, 1, 0x41, 9][..]);
+        assert_eq!(packets[1].timestamp(), 100_000);
+        assert!(!packets[1].is_keyframe());
+    }
+
+    #[test]
+    fn test_depacketize_h265() {
+        let mut depacketizer = RtpDepacketizer::<()>::new(Fourcc::VIDEO_HEVC, 90_000);
+        let mut out = Vec::new();
+
+        // AP with AUD and prefix SEI
+        let ap = [
+            0x60, 0x01, 0x00, 0x03, 0x46, 0x01, 0x10, 0x00, 0x03, 0x4E, 0x01, 0x05,
+        ];
+        out.extend(depacketizer.push(rtp(10, 0, false, &ap)));
+
+        // IDR_W_RADL split into FU
+        out.extend(depacketizer.push(rtp(11, 0, false, &[0x62, 0x01, 0x93, 0xAA])));
+        out.extend(depacketizer.push(rtp(12, 0, true, &[0x62, 0x01, 0x53, 0xBB])));
+
+        let packet = out.pop().unwrap().unwrap();
+        assert!(out.is_empty());
+        assert!(packet.is_keyframe());
+
+        assert_eq!(
+            packet.data,
+            &[
+                0, 0, 0, 1, 0x46, 0x01, 0x10, 0, 0, 0, 1, 0x4E, 0x01, 0x05, 0, 0, 0, 1, 0x26, 0x01,
+                0xAA, 0xBB
+            ][..]
+        );
+    }
+
+    #[test]
+    fn test_depacketize_av1() {
+        let mut depacketizer = RtpDepacketizer::<()>::new(Fourcc::VIDEO_AV1, 90_000);
+        let mut out = Vec::new();
+
+        // OBUs without the size field, the tile group is split into two packets
+        let metadata = [av1::OBU_METADATA << 3, 1, 2];
+        let tile_group = [av1::OBU_TILE_GROUP << 3, 3, 4, 5, 6];
+
+        let mut first = vec![0x60];
+        first.push(metadata.len() as u8);
+        first.extend_from_slice(&metadata);
+        first.extend_from_slice(&tile_group[..3]);
+
+        let mut second = vec![0x90];
+        second.extend_from_slice(&tile_group[3..]);
+
+        out.extend(depacketizer.push(rtp(0, 0, false, &first)));
+        out.extend(depacketizer.push(rtp(1, 0, true, &second)));
+
+        let packet = out.pop().unwrap().unwrap();
+        assert!(out.is_empty());
+        assert!(!packet.is_keyframe());
+
+        assert_eq!(
+            packet.data,
+            &[0x2A, 2, 1, 2, 0x22, 4, 3, 4, 5, 6][..],
+            "OBUs are written with the size field"
+        );
+    }
+
+    #[test]
+    fn test_depacketize_audio() {
+        let mut depacketizer = RtpDepacketizer::<()>::new(Fourcc::AUDIO_OPUS, 48_000);
+
+        // 20 ms CELT frame
+        let out = depacketizer.push(rtp(0, 960, false, &[0xF8, 1, 2]));
+        let packet = out[0].as_ref().unwrap();
+        assert_eq!((packet.timestamp(), packet.duration), (0, 20_000));
+        assert!(packet.is_audio() && packet.is_keyframe());
+
+        let asc = Bytes::from_static(&[0x11, 0x90]);
+        let mut depacketizer = RtpDepacketizer::<()>::new(Fourcc::AUDIO_AAC, 48_000)
+            .with_aac_mode(AacMode::HBR)
+            .with_params(vec![asc.clone()]);
+
+        // two AUs of 3 and 2 bytes
+        let mut payload = vec![0x00, 0x20];
+        payload.put_u16(3 << 3);
+        payload.put_u16(2 << 3);
+        payload.extend_from_slice(&[1, 1, 1, 2, 2]);
+
+        let out = depacketizer.push(rtp(0, 0, true, &payload));
+        let summary: Vec<_> = out
+            .iter()
+            .map(|x| x.as_ref().unwrap())
+            .map(|x| (x.timestamp(), x.data.len(), x.params[0].clone()))
+            .collect();
+
+        assert_eq!(summary, [(0, 3, asc.clone()), (21_333, 2, asc)]);
+
+        // fragmented AU of 6 bytes
+        let mut out = depacketizer.push(rtp(1, 2048, false, &[0x00, 0x10, 0x00, 0x30, 1, 2, 3]));
+        out.extend(depacketizer.push(rtp(2, 2048, true, &[0x00, 0x10, 0x00, 0x30, 4, 5, 6])));
+
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].as_ref().unwrap().data, &[1, 2, 3, 4, 5, 6][..]);
+    }
+
+    #[test]
+    fn test_depacketize_reorder_resync() {
+        let mut depacketizer =
+            RtpDepacketizer::<()>::new(Fourcc::AUDIO_OPUS, 48_000).with_reorder_window(2);
+
+        // 20 ms CELT frames
+        let mut push = |ssrc: u32, sequence: u16, timestamp: u32| {
+            let packet = RtpPacket {
+                payload_type: 111,
+                sequence,
+                timestamp,
+                ssrc,
+                payload: Bytes::from_static(&[0xF8, 1]),
+                ..Default::default()
+            };
+
+            depacketizer
+                .push_packet(packet)
+                .into_iter()
+                .map(|x| x.map(|x| x.timestamp()).ok())
+                .collect::<Vec<_>>()
+        };
+
+        // the late packet fills the gap, the duplicate is dropped
+        assert_eq!(push(1, 0, 0), [Some(0)]);
+        assert_eq!(push(1, 2, 1920), []);
+        assert_eq!(push(1, 1, 960), [Some(20_000), Some(40_000)]);
+        assert_eq!(push(1, 1, 960), []);
+
+        // the window overflows and the missing packet is lost
+        assert_eq!(push(1, 4, 3840), []);
+        assert_eq!(push(1, 5, 4800), []);
+        assert_eq!(
+            push(1, 6, 5760),
+            [None, Some(80_000), Some(100_000), Some(120_000)]
+        );
+
+        // new SSRC continues from the last timestamp
+        assert_eq!(push(2, 1000, 7), [None, Some(120_000)]);
+        assert_eq!(push(2, 1001, 967), [Some(140_000)]);
+
+        // the sender restarts with lower sequence numbers
+        assert_eq!(push(2, 10, 100), []);
+        assert_eq!(push(2, 11, 1060), []);
+        assert_eq!(push(2, 12, 2020), []);
+        assert_eq!(push(2, 13, 2980), [None, Some(140_000)]);
+        assert_eq!(push(2, 14, 3940), [Some(160_000)]);
+    }
+
+    #[test]
+    fn test_depacketize_malformed() {
+        let mut sequence = 0;
+        let mut errors = |depacketizer: &mut RtpDepacketizer, payload: &[u8]| {
+            sequence += 1;
+            depacketizer
+                .push(rtp(sequence, 0, true, payload))
+                .iter()
+                .filter(|x| x.is_err())
+                .count()
+        };
+
+        let mut depacketizer = RtpDepacketizer::new(Fourcc::AUDIO_OPUS, 0);
+        assert_eq!(errors(&mut depacketizer, &[0xF8, 1]), 1);
+        assert_eq!(depacketizer.micros_at(0), None);
+
+        // AU header fields wider than the 32 bit reader
+        let mode = AacMode {
+            size_length: 200,
+            index_length: 100,
+            index_delta_length: 100,
+        };
+
+        let mut depacketizer = RtpDepacketizer::new(Fourcc::AUDIO_AAC, 48_000).with_aac_mode(mode);
+        assert_eq!(errors(&mut depacketizer, &[0x01, 0x2C, 0xFF, 0xFF]), 1);
+
+        let mut depacketizer = RtpDepacketizer::new(Fourcc::AUDIO_AAC, 48_000);
+        assert_eq!(errors(&mut depacketizer, &[0x00]), 1);
+        assert_eq!(errors(&mut depacketizer, &[0x00, 0x20, 0x00]), 1);
+        assert_eq!(errors(&mut depacketizer, &[0x00, 0x10, 0x00, 0x30, 1]), 1);
+
+        let mut depacketizer = RtpDepacketizer::new(Fourcc::VIDEO_AVC, 90_000);
+        assert_eq!(errors(&mut depacketizer, &[]), 1);
+        assert_eq!(errors(&mut depacketizer, &[24, 0x00, 0x10, 0x67]), 1);
+        assert_eq!(errors(&mut depacketizer, &[28]), 1);
+
+        let mut depacketizer = RtpDepacketizer::new(Fourcc::VIDEO_HEVC, 90_000);
+        assert_eq!(errors(&mut depacketizer, &[0x62]), 1);
+        assert_eq!(errors(&mut depacketizer, &[0x62, 0x01]), 1);
+
+        assert!(
+            RtpDepacketizer::<()>::new(Fourcc::AUDIO_OPUS, 48_000)
+                .push(Bytes::from_static(&[0x40; 12]))[0]
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,304 @@
+mod depacketizer;
+mod packetizer;
+
+pub use depacketizer::{AacMode, RtpDepacketizer};
+pub use packetizer::{DEFAULT_MTU, DEFAULT_PAYLOAD_TYPE, MIN_MTU, RtpPacketizer};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly_core::{Fourcc, Reader, ReaderExt, WriterExt};
+
+use crate::error::Error;
+
+pub const RTP_VERSION: u8 = 2;
+pub const HEADER_SIZE: usize = 12;
+
+/// RTCP packet type of the sender report
+pub const RTCP_SR: u8 = 200;
+
+/// Clock rate of the video payload formats
+pub const VIDEO_CLOCK_RATE: u32 = 90_000;
+
+/// Clock rate of Opus regardless of the actual sample rate (RFC 7587 4.1)
+pub const OPUS_CLOCK_RATE: u32 = 48_000;
+
+/// RTP packet (RFC 3550 5.1)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RtpPacket {
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    pub csrc: Vec<u32>,
+
+    /// Header extension profile and data (RFC 3550 5.3.1)
+    pub extension: Option<(u16, Bytes)>,
+    pub payload: Bytes,
+}
+
+impl RtpPacket {
+    /// Parses the packet, padding is stripped from the payload
+    pub fn parse(mut data: Bytes) -> Result<Self, Error> {
+        if data.len() < HEADER_SIZE {
+            return Err(Error::InvalidData("rtp: packet too short"));
+        }
+
+        let (version, padding, has_extension, csrc_count) = data.read_u8p4::<2, 1, 1, 4>()?;
+        let (marker, payload_type) = data.read_u8p2::<1, 7>()?;
+
+        if version != RTP_VERSION {
+            return Err(Error::InvalidData("rtp: invalid version"));
+        }
+
+        let sequence = data.read_u16()?;
+        let timestamp = data.read_u32()?;
+        let ssrc = data.read_u32()?;
+
+        let csrc = (0..csrc_count)
+            .map(|_| data.read_u32())
+            .collect::<Result<_, _>>()?;
+
+        let extension = if has_extension != 0 {
+            let profile = data.read_u16()?;
+            let len = data.read_u16()? as usize * 4;
+
+            if data.len() < len {
+                return Err(Error::InvalidData("rtp: truncated header extension"));
+            }
+
+            Some((profile, data.split_to(len)))
+        } else {
+            None
+        };
+
+        if padding != 0 {
+            let len = data.last().copied().unwrap_or(0) as usize;
+            if len == 0 || len > data.len() {
+                return Err(Error::InvalidData("rtp: invalid padding"));
+            }
+
+            data.truncate(data.len() - len);
+        }
+
+        Ok(Self {
+            marker: marker != 0,
+            payload_type,
+            sequence,
+            timestamp,
+            ssrc,
+            csrc,
+            extension,
+            payload: data,
+        })
+    }
+
+    /// Writes the packet without padding
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        buf.put_u8p4::<2, 1, 1, 4>(
+            RTP_VERSION,
+            0,
+            self.extension.is_some() as u8,
+            self.csrc.len() as u8,
+        );
+        buf.put_u8p2::<1, 7>(self.marker as u8, self.payload_type);
+        buf.put_u16(self.sequence);
+        buf.put_u32(self.timestamp);
+        buf.put_u32(self.ssrc);
+
+        for &csrc in &self.csrc {
+            buf.put_u32(csrc);
+        }
+
+        if let Some((profile, data)) = &self.extension {
+            buf.put_u16(*profile);
+            buf.put_u16(data.len().div_ceil(4) as u16);
+            buf.put_slice(data);
+            buf.put_bytes(0, data.len().next_multiple_of(4) - data.len());
+        }
+
+        buf.put_slice(&self.payload);
+    }
+
+    /// Size of the packet written by [`RtpPacket::write`]
+    pub fn size(&self) -> usize {
+        HEADER_SIZE
+            + self.csrc.len() * 4
+            + self
+                .extension
+                .as_ref()
+                .map_or(0, |(_, data)| 4 + data.len().next_multiple_of(4))
+            + self.payload.len()
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(self.size());
+        self.write(&mut buf);
+        buf.freeze()
+    }
+}
+
+/// Sender info of the RTCP sender report (RFC 3550 6.4.1)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SenderReport {
+    pub ssrc: u32,
+
+    /// Wallclock time, seconds since 1900 in 32.32 fixed point
+    pub ntp_timestamp: u64,
+
+    /// RTP timestamp of the same instant
+    pub rtp_timestamp: u32,
+    pub packet_count: u32,
+    pub octet_count: u32,
+}
+
+impl SenderReport {
+    /// Finds the sender report in the compound RTCP packet
+    pub fn find(mut data: &[u8]) -> Option<Self> {
+        while data.len() >= 4 {
+            let len = (u16::from_be_bytes([data[2], data[3]]) as usize + 1) * 4;
+            if data[0] >> 6 != RTP_VERSION || data.len() < len {
+                return None;
+            }
+
+            let (packet, rest) = data.split_at(len);
+
+            if packet[1] == RTCP_SR {
+                let mut body = &packet[4..];
+
+                return Some(Self {
+                    ssrc: body.read_u32().ok()?,
+                    ntp_timestamp: body.read_u64().ok()?,
+                    rtp_timestamp: body.read_u32().ok()?,
+                    packet_count: body.read_u32().ok()?,
+                    octet_count: body.read_u32().ok()?,
+                });
+            }
+
+            data = rest;
+        }
+
+        None
+    }
+
+    /// Writes the report without reception report blocks
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        buf.put_u8(RTP_VERSION << 6);
+        buf.put_u8(RTCP_SR);
+        buf.put_u16(6);
+        buf.put_u32(self.ssrc);
+        buf.put_u64(self.ntp_timestamp);
+        buf.put_u32(self.rtp_timestamp);
+        buf.put_u32(self.packet_count);
+        buf.put_u32(self.octet_count);
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(28);
+        self.write(&mut buf);
+        buf.freeze()
+    }
+
+    /// Wallclock time in microseconds
+    pub fn ntp_micros(&self) -> i64 {
+        let secs = self.ntp_timestamp >> 32;
+        let fraction = ((self.ntp_timestamp & 0xFFFF_FFFF) * 1_000_000) >> 32;
+
+        (secs * 1_000_000 + fraction) as i64
+    }
+}
+
+/// Default clock rate of the payload format of `codec`
+pub fn clock_rate(codec: Fourcc) -> Option<u32> {
+    match codec {
+        Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC | Fourcc::VIDEO_AV1 => Some(VIDEO_CLOCK_RATE),
+        Fourcc::AUDIO_OPUS => Some(OPUS_CLOCK_RATE),
+        _ => None,
+    }
+}
+
+/// Extends 32-bit RTP timestamps over the wrap around
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Unwrapper {
+    last: Option<(u32, i64)>,
+}
+
+impl Unwrapper {
+    /// Returns the value extended to be the closest one to the previous value
+    pub fn unwrap(&mut self, value: u32) -> i64 {
+        let extended = self.extend(value);
+
+        self.last = Some((value, extended));
+        extended
+    }
+
+    /// Same as [`Unwrapper::unwrap`] without updating the previous value
+    pub fn extend(&self, value: u32) -> i64 {
+        match self.last {
+            Some((last, extended)) => extended + value.wrapping_sub(last) as i32 as i64,
+            None => value as i64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{RtpPacket, SenderReport, Unwrapper};
+
+    #[test]
+    fn test_rtp_packet() {
+        let packet = RtpPacket {
+            marker: true,
+            payload_type: 96,
+            sequence: 0xFFFF,
+            timestamp: 0x1234_5678,
+            ssrc: 0xDEAD_BEEF,
+            csrc: vec![1, 2],
+            extension: Some((0xBEDE, Bytes::from_static(&[0x10, 0xAA, 0, 0]))),
+            payload: Bytes::from_static(b"payload"),
+        };
+
+        let data = packet.to_bytes();
+        assert_eq!(data.len(), packet.size());
+        assert_eq!(&data[..2], &[0x92, 0xE0]);
+        assert_eq!(RtpPacket::parse(data.clone()).unwrap(), packet);
+
+        // padding bit with 3 bytes of padding
+        let mut padded = data.to_vec();
+        padded[0] |= 0x20;
+        padded.extend_from_slice(&[0, 0, 3]);
+        assert_eq!(RtpPacket::parse(padded.into()).unwrap(), packet);
+
+        assert!(RtpPacket::parse(data.slice(..20)).is_err());
+        assert!(RtpPacket::parse(Bytes::from_static(&[0x40; 12])).is_err());
+
+        let mut unwrapper = Unwrapper::default();
+        assert_eq!(unwrapper.unwrap(u32::MAX - 10), (u32::MAX - 10) as i64);
+        assert_eq!(unwrapper.unwrap(5), u32::MAX as i64 + 6);
+        assert_eq!(unwrapper.unwrap(u32::MAX), u32::MAX as i64);
+    }
+
+    #[test]
+    fn test_sender_report() {
+        let report = SenderReport {
+            ssrc: 0xDEAD_BEEF,
+            ntp_timestamp: (3_900_000_000 << 32) | 0x8000_0000,
+            rtp_timestamp: 90_000,
+            packet_count: 10,
+            octet_count: 12_000,
+        };
+
+        let data = report.to_bytes();
+        assert_eq!(&data[..4], &[0x80, 200, 0, 6]);
+        assert_eq!(report.ntp_micros(), 3_900_000_000_500_000);
+
+        // compound packet with an empty receiver report first
+        let mut compound = vec![0x80, 201, 0, 1, 0, 0, 0, 1];
+        compound.extend_from_slice(&data);
+        assert_eq!(SenderReport::find(&compound), Some(report));
+
+        assert_eq!(SenderReport::find(&compound[..8]), None);
+        assert_eq!(SenderReport::find(&data[..20]), None);
+    }
+}
@@ -0,0 +1,585 @@
+use std::hash::{BuildHasher, RandomState};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly_codec::{BitstreamConverter, av1, h264, h265, nal::AnnexBIter};
+use flowly_core::{EncodedFrame, Fourcc, Frame, Multichannel, Packet, VideoFrame};
+use flowly_service::{Context, Service};
+
+use super::{HEADER_SIZE, RtpPacket};
+use crate::error::Error;
+
+/// Default payload type, the first dynamic one
+pub const DEFAULT_PAYLOAD_TYPE: u8 = 96;
+
+/// Default packet size, leaves room for tunnelling headers within Ethernet MTU
+pub const DEFAULT_MTU: usize = 1200;
+
+/// Smallest accepted packet size
+pub const MIN_MTU: usize = HEADER_SIZE + 64;
+
+/// RTP packetizer.
+///
+/// The reverse of [`RtpDepacketizer`](super::RtpDepacketizer): splits frames
+/// into RTP packets of at most [`RtpPacketizer::with_mtu`] bytes. Small H.264
+/// and H.265 NAL units are aggregated into STAP-A/AP packets, large ones are
+/// fragmented into FU-A/FU packets, the parameter sets are sent in-band in
+/// front of keyframes. AV1 OBUs are packed into elements without the size
+/// field and split over packets when needed. Opus packets are sent as is, AAC
+/// frames in the `AAC-hbr` mode of RFC 3640 one per packet.
+///
+/// The marker bit is set on the last packet of each video or AAC frame.
+/// Presentation timestamps are converted to the clock rate and shifted by the
+/// random (unless set) initial RTP timestamp, sequence numbers continue across
+/// frames and SSRC is kept for the stream lifetime.
+/// The service yields each packet as a separate chunk.
+#[derive(Debug, Clone)]
+pub struct RtpPacketizer {
+    codec: Fourcc,
+    clock_rate: u32,
+    payload_type: u8,
+    ssrc: u32,
+    mtu: usize,
+    sequence: u16,
+    timestamp_offset: u32,
+}
+
+impl RtpPacketizer {
+    pub fn new(codec: Fourcc, clock_rate: u32) -> Self {
+        assert!(clock_rate > 0, "RTP clock rate must be positive");
+
+        Self {
+            codec,
+            clock_rate,
+            payload_type: DEFAULT_PAYLOAD_TYPE,
+            ssrc: random_u32(),
+            mtu: DEFAULT_MTU,
+            sequence: random_u32() as u16,
+            timestamp_offset: random_u32(),
+        }
+    }
+
+    pub fn with_payload_type(mut self, payload_type: u8) -> Self {
+        self.payload_type = payload_type & 0x7F;
+        self
+    }
+
+    pub fn with_ssrc(mut self, ssrc: u32) -> Self {
+        self.ssrc = ssrc;
+        self
+    }
+
+    /// Maximum size of RTP packets including the header, at least [`MIN_MTU`]
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        assert!(mtu >= MIN_MTU, "RTP MTU is too small");
+
+        self.mtu = mtu;
+        self
+    }
+
+    /// Sets the sequence number of the next packet and the RTP timestamp of zero time
+    pub fn with_initial(mut self, sequence: u16, timestamp: u32) -> Self {
+        self.sequence = sequence;
+        self.timestamp_offset = timestamp;
+        self
+    }
+
+    #[inline]
+    pub fn codec(&self) -> Fourcc {
+        self.codec
+    }
+
+    #[inline]
+    pub fn clock_rate(&self) -> u32 {
+        self.clock_rate
+    }
+
+    #[inline]
+    pub fn payload_type(&self) -> u8 {
+        self.payload_type
+    }
+
+    #[inline]
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    /// Sequence number of the next packet
+    #[inline]
+    pub fn sequence(&self) -> u16 {
+        self.sequence
+    }
+
+    /// Converts the timestamp in microseconds into the RTP timestamp
+    pub fn rtp_timestamp(&self, micros: i64) -> u32 {
+        let ticks = (micros as i128 * self.clock_rate as i128).div_euclid(1_000_000);
+        self.timestamp_offset.wrapping_add(ticks as u32)
+    }
+
+    /// Splits the frame into RTP packets
+    pub fn push<F: EncodedFrame + Multichannel + VideoFrame>(
+        &mut self,
+        frame: F,
+    ) -> Result<Vec<RtpPacket>, Error> {
+        if frame.codec() != self.codec {
+            return Err(Error::InvalidData(
+                "rtp: codec does not match the packetizer",
+            ));
+        }
+
+        let timestamp = self.rtp_timestamp(frame.pts());
+        let max = self.mtu - HEADER_SIZE;
+
+        let payloads = match self.codec {
+            Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC => {
+                let packet = BitstreamConverter::annexb().convert(frame)?;
+                let nals = AnnexBIter::new(&packet.data).map(|x| packet.data.slice_ref(x));
+
+                packetize_nals(self.codec, nals, max)
+            }
+
+            Fourcc::VIDEO_AV1 => {
+                let packet = BitstreamConverter::annexb().convert(frame)?;
+                let data = av1::annexb_to_section5(&packet.data)?;
+
+                packetize_av1(&data, packet.is_keyframe(), max)?
+            }
+
+            Fourcc::AUDIO_OPUS => {
+                let packet = Packet::from_frame(frame);
+                if packet.data.len() > max {
+                    return Err(Error::InvalidData("rtp: Opus packet exceeds MTU"));
+                }
+
+                vec![packet.data]
+            }
+
+            Fourcc::AUDIO_AAC => packetize_aac(&Packet::from_frame(frame).data, max)?,
+            _ => return Err(Error::Unsupported("rtp: payload format")),
+        };
+
+        let last = payloads.len().saturating_sub(1);
+
+        let packets = payloads
+            .into_iter()
+            .enumerate()
+            .map(|(idx, payload)| {
+                let packet = RtpPacket {
+                    marker: self.codec != Fourcc::AUDIO_OPUS && idx == last,
+                    payload_type: self.payload_type,
+                    sequence: self.sequence,
+                    timestamp,
+                    ssrc: self.ssrc,
+                    payload,
+                    ..Default::default()
+                };
+
+                self.sequence = self.sequence.wrapping_add(1);
+                packet
+            })
+            .collect();
+
+        Ok(packets)
+    }
+}
+
+/// Random value for the SSRC and initial sequence number and timestamp
+fn random_u32() -> u32 {
+    RandomState::new().hash_one(std::time::SystemTime::now()) as u32
+}
+
+/// Aggregates or fragments H.264/H.265 NAL units into payloads of at most `max` bytes
+fn packetize_nals(codec: Fourcc, nals: impl Iterator<Item = Bytes>, max: usize) -> Vec<Bytes> {
+    let hevc = codec == Fourcc::VIDEO_HEVC;
+    let header_size = if hevc { 2 } else { 1 };
+
+    let mut out = Vec::new();
+    let mut pending: Vec<Bytes> = Vec::new();
+    let mut pending_size = header_size;
+
+    for nal in nals {
+        if nal.len() < header_size {
+            continue;
+        }
+
+        if pending_size + 2 + nal.len() > max && !pending.is_empty() {
+            out.push(aggregate(hevc, std::mem::take(&mut pending)));
+            pending_size = header_size;
+        }
+
+        if nal.len() > max {
+            fragment(hevc, &nal, max, &mut out);
+        } else {
+            pending_size += 2 + nal.len();
+            pending.push(nal);
+        }
+    }
+
+    if !pending.is_empty() {
+        out.push(aggregate(hevc, pending));
+    }
+
+    out
+}
+
+/// Builds the STAP-A/AP packet, a single NAL unit is sent as is
+fn aggregate(hevc: bool, nals: Vec<Bytes>) -> Bytes {
+    if let [nal] = &nals[..] {
+        return nal.clone();
+    }
+
+    let mut buf = BytesMut::with_capacity(2 + nals.iter().map(|x| x.len() + 2).sum::<usize>());
+
+    if hevc {
+        // forbidden bit is OR-ed, layer and temporal ids are the lowest ones
+        let forbidden = nals.iter().fold(0, |acc, x| acc | (x[0] & 0x80));
+        let layer = nals.iter().map(|x| h265_layer_id(x)).min().unwrap_or(0);
+        let tid = nals.iter().map(|x| x[1] & 0x07).min().unwrap_or(1);
+
+        buf.put_u8(forbidden | (h265::NAL_AP << 1) | (layer >> 5));
+        buf.put_u8(((layer & 0x1F) << 3) | tid);
+    } else {
+        // forbidden bit is OR-ed, NRI is the highest one
+        let forbidden = nals.iter().fold(0, |acc, x| acc | (x[0] & 0x80));
+        let nri = nals.iter().map(|x| x[0] & 0x60).max().unwrap_or(0);
+
+        buf.put_u8(forbidden | nri | h264::NAL_STAP_A);
+    }
+
+    for nal in nals {
+        buf.put_u16(nal.len() as u16);
+        buf.put_slice(&nal);
+    }
+
+    buf.freeze()
+}
+
+#[inline]
+fn h265_layer_id(nal: &[u8]) -> u8 {
+    ((nal[0] & 0x01) << 5) | (nal[1] >> 3)
+}
+
+/// Splits the NAL unit into FU-A/FU packets
+fn fragment(hevc: bool, nal: &[u8], max: usize, out: &mut Vec<Bytes>) {
+    let (header, nal_type, data) = if hevc {
+        let header = vec![(nal[0] & 0x81) | (h265::NAL_FU << 1), nal[1]];
+        (header, (nal[0] >> 1) & 0x3F, &nal[2..])
+    } else {
+        let header = vec![(nal[0] & 0xE0) | h264::NAL_FU_A];
+        (header, nal[0] & 0x1F, &nal[1..])
+    };
+
+    let chunk_size = max - header.len() - 1;
+    let count = data.len().div_ceil(chunk_size);
+
+    for (idx, chunk) in data.chunks(chunk_size).enumerate() {
+        let mut fu = nal_type;
+        if idx == 0 {
+            fu |= 0x80;
+        }
+
+        if idx + 1 == count {
+            fu |= 0x40;
+        }
+
+        let mut buf = BytesMut::with_capacity(header.len() + 1 + chunk.len());
+        buf.put_slice(&header);
+        buf.put_u8(fu);
+        buf.put_slice(chunk);
+        out.push(buf.freeze());
+    }
+}
+
+/// Packs OBUs of the temporal unit (low overhead format) into the AV1 payloads
+fn packetize_av1(data: &[u8], keyframe: bool, max: usize) -> Result<Vec<Bytes>, Error> {
+    let mut out = Vec::new();
+    let mut buf = BytesMut::with_capacity(max);
+    let mut aggregation = 0u8;
+    let mut new_sequence = false;
+
+    buf.put_u8(0);
+
+    for obu in av1::ObuIter::new(data) {
+        let obu = obu?;
+
+        match obu.obu_type {
+            av1::OBU_TEMPORAL_DELIMITER | av1::OBU_TILE_LIST => continue,
+            av1::OBU_SEQUENCE_HEADER => new_sequence |= keyframe,
+            _ => (),
+        }
+
+        // OBU without the size field
+        let mut element = BytesMut::with_capacity(2 + obu.payload.len());
+        element.put_u8((obu.obu_type << 3) | if obu.extension.is_some() { 0x04 } else { 0 });
+        if let Some(extension) = obu.extension {
+            element.put_u8(extension);
+        }
+
+        element.put_slice(obu.payload);
+        let mut element = element.freeze();
+
+        loop {
+            let space = max - buf.len();
+            let needed = av1::leb128_size(element.len() as u64) + element.len();
+
+            if needed <= space {
+                av1::put_leb128(&mut buf, element.len() as u64);
+                buf.put_slice(&element);
+                break;
+            }
+
+            // the fragment has to carry at least a byte of the OBU
+            let prefix = av1::leb128_size(space as u64);
+            if space > prefix {
+                let part = element.split_to(space - prefix);
+                av1::put_leb128(&mut buf, part.len() as u64);
+                buf.put_slice(&part);
+                aggregation |= 0x40;
+            }
+
+            finish_av1_payload(&mut buf, aggregation, &mut out);
+            buf.put_u8(0);
+
+            // the next payload continues the fragmented OBU
+            aggregation = if aggregation & 0x40 != 0 { 0x80 } else { 0 };
+        }
+    }
+
+    if buf.len() > 1 {
+        finish_av1_payload(&mut buf, aggregation, &mut out);
+    }
+
+    if new_sequence && let Some(first) = out.first_mut() {
+        let mut payload = BytesMut::from(std::mem::take(first));
+        payload[0] |= 0x08;
+        *first = payload.freeze();
+    }
+
+    Ok(out)
+}
+
+fn finish_av1_payload(buf: &mut BytesMut, aggregation: u8, out: &mut Vec<Bytes>) {
+    buf[0] = aggregation;
+    out.push(buf.split().freeze());
+}
+
+/// Builds `AAC-hbr` payloads with a single AU header, fragmenting large frames
+fn packetize_aac(data: &[u8], max: usize) -> Result<Vec<Bytes>, Error> {
+    // 13-bit AU-size
+    if data.len() >= 1 << 13 {
+        return Err(Error::InvalidData("rtp: AAC frame exceeds the AU size"));
+    }
+
+    let chunk_size = max - 4;
+
+    Ok(data
+        .chunks(chunk_size)
+        .map(|chunk| {
+            let mut buf = BytesMut::with_capacity(4 + chunk.len());
+            buf.put_u16(16);
+            buf.put_u16((data.len() as u16) << 3);
+            buf.put_slice(chunk);
+            buf.freeze()
+        })
+        .collect())
+}
+
+impl<F: EncodedFrame + Multichannel + VideoFrame> Service<F> for RtpPacketizer {
+    type Out = Result<Bytes, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let out: Vec<_> = match self.push(frame) {
+            Ok(packets) => packets.iter().map(|x| Ok(x.to_bytes())).collect(),
+            Err(err) => vec![Err(err)],
+        };
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use flowly_codec::av1;
+    use flowly_core::{EncodedFrame, Fourcc, FrameFlags, Packet};
+
+    use super::RtpPacketizer;
+    use crate::{
+        error::Error,
+        rtp::RtpDepacketizer,
+        test_fixtures::{PPS, SPS},
+    };
+
+    fn annexb(nals: &[&[u8]]) -> Bytes {
+        let mut buf = BytesMut::new();
+        for nal in nals {
+            buf.put_slice(&[0, 0, 0, 1]);
+            buf.put_slice(nal);
+        }
+
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_packetize_h264() {
+        let mut packetizer = RtpPacketizer::new(Fourcc::VIDEO_AVC, 90_000)
+            .with_mtu(200)
+            .with_initial(65535, 1000);
+
+        let mut idr = vec![0x65];
+        idr.extend((0..500).map(|x| x as u8));
+
+        let flags = FrameFlags::VIDEO_STREAM | FrameFlags::KEYFRAME | FrameFlags::ANNEXB;
+        let keyframe = Packet::new((), Fourcc::VIDEO_AVC, flags, annexb(&[&idr]))
+            .with_timestamps(0, 0)
+            .with_params(vec![Bytes::from_static(SPS), Bytes::from_static(PPS)]);
+
+        let flags = FrameFlags::VIDEO_STREAM | FrameFlags::ANNEXB;
+        let frame = Packet::new((), Fourcc::VIDEO_AVC, flags, annexb(&[&[0x41, 1, 2]]))
+            .with_timestamps(40_000, 40_000);
+
+        let mut packets = packetizer.push(keyframe.clone()).unwrap();
+        packets.extend(packetizer.push(frame.clone()).unwrap());
+
+        // STAP-A, 3 FU-A and the single NAL unit
+        let summary: Vec<_> = packets
+            .iter()
+            .map(|x| (x.sequence, x.timestamp, x.marker, x.payload[0] & 0x1F))
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (65535, 1000, false, 24),
+                (0, 1000, false, 28),
+                (1, 1000, false, 28),
+                (2, 1000, true, 28),
+                (3, 4600, true, 1)
+            ]
+        );
+
+        assert!(packets.iter().all(|x| x.size() <= 200));
+        assert_eq!(packetizer.sequence(), 4);
+
+        let mut depacketizer = RtpDepacketizer::<()>::new(Fourcc::VIDEO_AVC, 90_000);
+        let out: Vec<_> = packets
+            .into_iter()
+            .flat_map(|x| depacketizer.push_packet(x))
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(out[0].data, annexb(&[SPS, PPS, &idr]));
+        assert_eq!(out[0].params().collect::<Vec<_>>(), [SPS, PPS]);
+        assert_eq!(out[1].data, frame.data);
+        assert_eq!(out[1].pts, 40_000);
+    }
+
+    #[test]
+    fn test_packetize_h265() {
+        let mut packetizer = RtpPacketizer::new(Fourcc::VIDEO_HEVC, 90_000).with_mtu(100);
+
+        let mut idr = vec![0x26, 0x01];
+        idr.extend((0..200).map(|x| x as u8));
+
+        let flags = FrameFlags::VIDEO_STREAM | FrameFlags::KEYFRAME | FrameFlags::ANNEXB;
+        let data = annexb(&[&[0x46, 0x01, 0x10], &[0x4E, 0x01, 0x05], &idr]);
+        let frame = Packet::new((), Fourcc::VIDEO_HEVC, flags, data.clone());
+
+        let packets = packetizer.push(frame).unwrap();
+        let types: Vec<_> = packets.iter().map(|x| (x.payload[0] >> 1) & 0x3F).collect();
+        assert_eq!(types, [48, 49, 49, 49]);
+
+        let mut depacketizer = RtpDepacketizer::<()>::new(Fourcc::VIDEO_HEVC, 90_000);
+        let out: Vec<_> = packets
+            .into_iter()
+            .flat_map(|x| depacketizer.push_packet(x))
+            .collect();
+
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].as_ref().unwrap().data, data);
+    }
+
+    #[test]
+    fn test_packetize_av1() {
+        let mut packetizer = RtpPacketizer::new(Fourcc::VIDEO_AV1, 90_000).with_mtu(100);
+
+        let tile_group: Vec<u8> = (0..150).collect();
+
+        let mut data = BytesMut::new();
+        for (obu_type, payload) in [
+            (av1::OBU_TEMPORAL_DELIMITER, &[][..]),
+            (av1::OBU_METADATA, &[1, 2][..]),
+            (av1::OBU_TILE_GROUP, &tile_group[..]),
+        ] {
+            let obu = av1::Obu {
+                obu_type,
+                extension: None,
+                payload,
+            };
+
+            obu.write(&mut data);
+        }
+
+        let frame = Packet::new(
+            (),
+            Fourcc::VIDEO_AV1,
+            FrameFlags::VIDEO_STREAM,
+            data.freeze(),
+        );
+        let packets = packetizer.push(frame.clone()).unwrap();
+
+        let aggregation: Vec<_> = packets.iter().map(|x| x.payload[0]).collect();
+        assert_eq!(aggregation, [0x40, 0x80]);
+        assert!(packets.iter().all(|x| x.size() <= 100));
+
+        let mut depacketizer = RtpDepacketizer::<()>::new(Fourcc::VIDEO_AV1, 90_000);
+        let out: Vec<_> = packets
+            .into_iter()
+            .flat_map(|x| depacketizer.push_packet(x))
+            .collect();
+
+        // the temporal delimiter is not transmitted
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].as_ref().unwrap().data, frame.data.slice(2..));
+    }
+
+    #[test]
+    fn test_packetize_aac() {
+        let mut packetizer = RtpPacketizer::new(Fourcc::AUDIO_AAC, 48_000).with_mtu(100);
+        let mut depacketizer = RtpDepacketizer::<()>::new(Fourcc::AUDIO_AAC, 48_000);
+
+        for len in [50usize, 200] {
+            let data: Bytes = (0..len).map(|x| x as u8).collect();
+            let frame = Packet::new(
+                (),
+                Fourcc::AUDIO_AAC,
+                FrameFlags::AUDIO_STREAM,
+                data.clone(),
+            );
+
+            let packets = packetizer.push(frame).unwrap();
+            assert_eq!(packets.len(), len.div_ceil(84));
+            assert!(packets.last().unwrap().marker);
+            assert_eq!(packets.iter().filter(|x| x.marker).count(), 1);
+
+            let out: Vec<_> = packets
+                .into_iter()
+                .flat_map(|x| depacketizer.push_packet(x))
+                .collect();
+
+            assert_eq!(out.len(), 1);
+            assert_eq!(out[0].as_ref().unwrap().data, data);
+        }
+
+        // AU-size is 13 bits
+        let frame = Packet::new(
+            (),
+            Fourcc::AUDIO_AAC,
+            FrameFlags::AUDIO_STREAM,
+            Bytes::from(vec![0; 8192]),
+        );
+        assert!(matches!(
+            packetizer.push(frame),
+            Err(Error::InvalidData("rtp: AAC frame exceeds the AU size"))
+        ));
+    }
+}
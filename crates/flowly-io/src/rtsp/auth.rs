@@ -0,0 +1,332 @@
+use std::hash::{BuildHasher, RandomState};
+
+use crate::error::Error;
+
+/// Credentials with the challenge of the last `401 Unauthorized` response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authenticator {
+    username: String,
+    password: String,
+    challenge: Option<Challenge>,
+    nonce_count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Challenge {
+    Basic,
+    Digest {
+        realm: String,
+        nonce: String,
+        opaque: Option<String>,
+        qop_auth: bool,
+    },
+}
+
+impl Authenticator {
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+            challenge: None,
+            nonce_count: 0,
+        }
+    }
+
+    /// Picks the strongest supported scheme of the `WWW-Authenticate` headers
+    pub fn set_challenge<'a>(
+        &mut self,
+        headers: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), Error> {
+        let mut basic = false;
+
+        for header in headers {
+            let (scheme, params) = header.trim().split_once(' ').unwrap_or((header, ""));
+
+            if scheme.eq_ignore_ascii_case("basic") {
+                basic = true;
+            } else if scheme.eq_ignore_ascii_case("digest") {
+                let params = parse_params(params);
+                let param = |name: &str| {
+                    params
+                        .iter()
+                        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                        .map(|(_, value)| value.clone())
+                };
+
+                if param("algorithm").is_some_and(|x| !x.eq_ignore_ascii_case("md5")) {
+                    continue;
+                }
+
+                let (Some(realm), Some(nonce)) = (param("realm"), param("nonce")) else {
+                    return Err(Error::InvalidData("rtsp: invalid digest challenge"));
+                };
+
+                self.challenge = Some(Challenge::Digest {
+                    realm,
+                    nonce,
+                    opaque: param("opaque"),
+                    qop_auth: param("qop")
+                        .is_some_and(|x| x.split(',').any(|x| x.trim() == "auth")),
+                });
+                self.nonce_count = 0;
+
+                return Ok(());
+            }
+        }
+
+        if !basic {
+            return Err(Error::Unsupported("rtsp: authentication scheme"));
+        }
+
+        self.challenge = Some(Challenge::Basic);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn has_challenge(&self) -> bool {
+        self.challenge.is_some()
+    }
+
+    /// Value of the `Authorization` header for the request, if challenged
+    pub fn authorization(&mut self, method: &str, uri: &str) -> Option<String> {
+        match self.challenge.as_ref()? {
+            Challenge::Basic => {
+                let credentials = format!("{}:{}", self.username, self.password);
+                Some(format!("Basic {}", base64_encode(credentials.as_bytes())))
+            }
+
+            Challenge::Digest {
+                realm,
+                nonce,
+                opaque,
+                qop_auth,
+            } => {
+                let ha1 = md5_hex(format!("{}:{realm}:{}", self.username, self.password));
+                let ha2 = md5_hex(format!("{method}:{uri}"));
+
+                let mut header = format!(
+                    "Digest username=\"{}\", realm=\"{realm}\", nonce=\"{nonce}\", uri=\"{uri}\"",
+                    self.username
+                );
+
+                if *qop_auth {
+                    self.nonce_count += 1;
+                    let nc = format!("{:08x}", self.nonce_count);
+                    let cnonce = format!(
+                        "{:016x}",
+                        RandomState::new().hash_one(std::time::SystemTime::now())
+                    );
+                    let response = md5_hex(format!("{ha1}:{nonce}:{nc}:{cnonce}:auth:{ha2}"));
+
+                    header += &format!(
+                        ", qop=auth, nc={nc}, cnonce=\"{cnonce}\", response=\"{response}\""
+                    );
+                } else {
+                    let response = md5_hex(format!("{ha1}:{nonce}:{ha2}"));
+                    header += &format!(", response=\"{response}\"");
+                }
+
+                if let Some(opaque) = opaque {
+                    header += &format!(", opaque=\"{opaque}\"");
+                }
+
+                Some(header)
+            }
+        }
+    }
+}
+
+/// Splits comma separated `key=value` parameters, values may be quoted
+fn parse_params(params: &str) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let mut rest = params.trim();
+
+    while let Some((key, tail)) = rest.split_once('=') {
+        let tail = tail.trim_start();
+
+        let (value, tail) = if let Some(quoted) = tail.strip_prefix('"') {
+            quoted.split_once('"').unwrap_or((quoted, ""))
+        } else {
+            tail.split_once(',').unwrap_or((tail, ""))
+        };
+
+        out.push((key.trim().to_string(), value.trim().to_string()));
+        rest = tail.trim_start_matches([',', ' ']);
+    }
+
+    out
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with padding
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let block = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let value = u32::from_be_bytes([0, block[0], block[1], block[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(value >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+/// Decodes base64 ignoring the padding
+pub fn base64_decode(data: &str) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::with_capacity(data.len() / 4 * 3);
+    let mut value = 0u32;
+    let mut bits = 0;
+
+    for byte in data.trim().trim_end_matches('=').bytes() {
+        let sextet = BASE64_ALPHABET
+            .iter()
+            .position(|&x| x == byte)
+            .ok_or(Error::InvalidData("base64: invalid character"))?;
+
+        value = (value << 6) | sextet as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            out.push((value >> bits) as u8);
+        }
+    }
+
+    Ok(out)
+}
+
+/// MD5 digest (RFC 1321) as lowercase hex, as used by the digest authentication
+pub fn md5_hex(data: impl AsRef<[u8]>) -> String {
+    md5(data.as_ref())
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect()
+}
+
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+    let constants: [u32; 64] =
+        std::array::from_fn(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32);
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+
+    message.extend_from_slice(&(data.len() as u64).wrapping_mul(8).to_le_bytes());
+
+    for block in message.chunks(64) {
+        let words: [u32; 16] = std::array::from_fn(|i| {
+            u32::from_le_bytes(block[i * 4..i * 4 + 4].try_into().unwrap())
+        });
+
+        let [mut a, mut b, mut c, mut d] = state;
+
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[(i / 16) * 4 + i % 4]);
+
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    let mut out = [0; 16];
+    for (chunk, word) in out.chunks_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Authenticator, base64_decode, base64_encode, md5_hex};
+
+    #[test]
+    fn test_auth() {
+        assert_eq!(md5_hex(""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(
+            md5_hex("The quick brown fox jumps over the lazy dog"),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+        assert_eq!(md5_hex([b'a'; 56]), "3b0c8ac703f828b04c6c197006d17218");
+
+        assert_eq!(base64_encode(b"user:pass"), "dXNlcjpwYXNz");
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(base64_decode("YWI=").unwrap(), b"ab");
+        assert_eq!(base64_decode("Z0LAHg").unwrap(), [0x67, 0x42, 0xC0, 0x1E]);
+
+        let mut auth = Authenticator::new("user", "pass");
+        assert_eq!(auth.authorization("OPTIONS", "rtsp://host/"), None);
+
+        auth.set_challenge(["Basic realm=\"cam\""]).unwrap();
+        assert_eq!(
+            auth.authorization("OPTIONS", "rtsp://host/").unwrap(),
+            "Basic dXNlcjpwYXNz"
+        );
+
+        // RFC 2617 3.5 example credentials without qop
+        let mut auth = Authenticator::new("Mufasa", "Circle Of Life");
+        auth.set_challenge([
+            "Basic realm=\"x\"",
+            "Digest realm=\"testrealm@host.com\", nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\"",
+        ])
+        .unwrap();
+
+        let header = auth.authorization("GET", "/dir/index.html").unwrap();
+        assert!(header.starts_with("Digest username=\"Mufasa\""));
+        assert!(header.ends_with("response=\"670fd8c2df070c60b045671b8b24ff02\""));
+
+        // qop=auth, the client nonce is random
+        auth.set_challenge(["Digest realm=\"cam\", nonce=\"abc\", qop=\"auth\""])
+            .unwrap();
+
+        let cnonce = |header: String| {
+            let (_, rest) = header.split_once("cnonce=\"").unwrap();
+            rest.split('"').next().unwrap().to_string()
+        };
+
+        let first = auth.authorization("DESCRIBE", "rtsp://host/").unwrap();
+        assert!(first.contains("nc=00000001"));
+
+        let second = auth.authorization("DESCRIBE", "rtsp://host/").unwrap();
+        assert!(second.contains("nc=00000002"));
+        assert_ne!(cnonce(first), cnonce(second));
+    }
+}
@@ -0,0 +1,421 @@
+use std::fmt::Write as _;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::error::Error;
+
+pub const RTSP_VERSION: &str = "RTSP/1.0";
+
+/// Upper limit of the start line and headers size
+const MAX_HEADERS_SIZE: usize = 64 * 1024;
+
+/// Upper limit of the body size (`Content-Length`)
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// RTSP request (RFC 2326 6)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Bytes,
+}
+
+impl Request {
+    pub fn new(method: impl Into<String>, uri: impl Into<String>) -> Self {
+        Self {
+            method: method.into(),
+            uri: uri.into(),
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Value of the first header with the name (case insensitive)
+    #[inline]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    #[inline]
+    pub fn cseq(&self) -> Option<u32> {
+        self.header("CSeq")?.trim().parse().ok()
+    }
+
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        let mut head = format!("{} {} {RTSP_VERSION}\r\n", self.method, self.uri);
+        write_headers(&mut head, &self.headers, &self.body);
+
+        buf.put_slice(head.as_bytes());
+        buf.put_slice(&self.body);
+    }
+}
+
+/// RTSP response (RFC 2326 7)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Bytes,
+}
+
+impl Response {
+    pub fn new(status: u16, reason: impl Into<String>) -> Self {
+        Self {
+            status,
+            reason: reason.into(),
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_body(mut self, body: Bytes) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// Value of the first header with the name (case insensitive)
+    #[inline]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Values of all headers with the name
+    pub fn headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[inline]
+    pub fn cseq(&self) -> Option<u32> {
+        self.header("CSeq")?.trim().parse().ok()
+    }
+
+    #[inline]
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        let mut head = format!("{RTSP_VERSION} {} {}\r\n", self.status, self.reason);
+        write_headers(&mut head, &self.headers, &self.body);
+
+        buf.put_slice(head.as_bytes());
+        buf.put_slice(&self.body);
+    }
+}
+
+/// Message of the RTSP connection, RTP/RTCP packets may be interleaved with
+/// requests and responses (RFC 2326 10.12)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Request(Request),
+    Response(Response),
+    Interleaved(u8, Bytes),
+}
+
+impl Message {
+    /// Takes the complete message from the beginning of `buf`,
+    /// `None` if more data is needed
+    pub fn parse(buf: &mut BytesMut) -> Result<Option<Self>, Error> {
+        if buf.first() == Some(&b'$') {
+            let &[_, channel, hi, lo, ..] = buf.as_ref() else {
+                return Ok(None);
+            };
+
+            let len = u16::from_be_bytes([hi, lo]) as usize;
+            if buf.len() < 4 + len {
+                return Ok(None);
+            }
+
+            buf.advance(4);
+            return Ok(Some(Self::Interleaved(channel, buf.split_to(len).freeze())));
+        }
+
+        // empty lines between messages are allowed
+        let skip = buf
+            .iter()
+            .take_while(|x| matches!(x, b'\r' | b'\n'))
+            .count();
+        buf.advance(skip);
+
+        let Some(end) = buf.windows(4).position(|x| x == b"\r\n\r\n") else {
+            if buf.len() > MAX_HEADERS_SIZE {
+                return Err(Error::InvalidData("rtsp: headers too long"));
+            }
+
+            return Ok(None);
+        };
+
+        let head = std::str::from_utf8(&buf[..end])
+            .map_err(|_| Error::InvalidData("rtsp: non UTF-8 headers"))?;
+
+        let mut lines = head.split("\r\n");
+        let start = lines.next().unwrap_or_default();
+
+        let mut headers = Vec::new();
+        for line in lines {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(Error::InvalidData("rtsp: invalid header"))?;
+
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let body_len = match find_header(&headers, "Content-Length") {
+            Some(len) => len
+                .parse::<usize>()
+                .map_err(|_| Error::InvalidData("rtsp: invalid Content-Length"))?,
+            None => 0,
+        };
+
+        let size = (body_len <= MAX_BODY_SIZE)
+            .then(|| (end + 4).checked_add(body_len))
+            .flatten()
+            .ok_or(Error::InvalidData("rtsp: body too long"))?;
+
+        if buf.len() < size {
+            return Ok(None);
+        }
+
+        let mut parts = start.splitn(3, ' ');
+        let (first, second, third) = (
+            parts.next().unwrap_or_default(),
+            parts.next().unwrap_or_default(),
+            parts.next().unwrap_or_default(),
+        );
+
+        let message = if first.starts_with("RTSP/") {
+            Self::Response(Response {
+                status: second
+                    .parse()
+                    .map_err(|_| Error::InvalidData("rtsp: invalid status code"))?,
+                reason: third.to_string(),
+                headers,
+                body: Bytes::new(),
+            })
+        } else if third.starts_with("RTSP/") {
+            Self::Request(Request {
+                method: first.to_string(),
+                uri: second.to_string(),
+                headers,
+                body: Bytes::new(),
+            })
+        } else {
+            return Err(Error::InvalidData("rtsp: invalid start line"));
+        };
+
+        buf.advance(end + 4);
+        let body = buf.split_to(body_len).freeze();
+
+        Ok(Some(match message {
+            Self::Request(request) => Self::Request(Request { body, ..request }),
+            Self::Response(response) => Self::Response(Response { body, ..response }),
+            message => message,
+        }))
+    }
+}
+
+/// Writes the interleaved packet frame
+pub fn put_interleaved<B: BufMut>(buf: &mut B, channel: u8, data: &[u8]) {
+    buf.put_u8(b'$');
+    buf.put_u8(channel);
+    buf.put_u16(data.len() as u16);
+    buf.put_slice(data);
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn write_headers(head: &mut String, headers: &[(String, String)], body: &[u8]) {
+    for (name, value) in headers {
+        let _ = write!(head, "{name}: {value}\r\n");
+    }
+
+    if !body.is_empty() && find_header(headers, "Content-Length").is_none() {
+        let _ = write!(head, "Content-Length: {}\r\n", body.len());
+    }
+
+    head.push_str("\r\n");
+}
+
+/// `Transport` header (RFC 2326 12.39), only the parameters used for unicast RTP
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Transport {
+    pub tcp: bool,
+    pub interleaved: Option<(u8, u8)>,
+    pub client_port: Option<(u16, u16)>,
+    pub server_port: Option<(u16, u16)>,
+    pub ssrc: Option<u32>,
+}
+
+impl Transport {
+    /// Parses the first transport spec of the header
+    pub fn parse(header: &str) -> Result<Self, Error> {
+        let spec = header.split(',').next().unwrap_or_default();
+        let mut params = spec.split(';');
+
+        let protocol = params.next().unwrap_or_default().trim();
+        let tcp = match protocol.to_ascii_uppercase().as_str() {
+            "RTP/AVP" | "RTP/AVP/UDP" => false,
+            "RTP/AVP/TCP" => true,
+            _ => return Err(Error::Unsupported("rtsp: transport protocol")),
+        };
+
+        let mut transport = Self {
+            tcp,
+            ..Default::default()
+        };
+
+        for param in params {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+
+            match name.trim().to_ascii_lowercase().as_str() {
+                "interleaved" => transport.interleaved = parse_range(value),
+                "client_port" => transport.client_port = parse_range(value),
+                "server_port" => transport.server_port = parse_range(value),
+                "ssrc" => transport.ssrc = u32::from_str_radix(value.trim(), 16).ok(),
+                _ => (),
+            }
+        }
+
+        Ok(transport)
+    }
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.tcp {
+            f.write_str("RTP/AVP/TCP;unicast")?;
+        } else {
+            f.write_str("RTP/AVP;unicast")?;
+        }
+
+        if let Some((a, b)) = self.interleaved {
+            write!(f, ";interleaved={a}-{b}")?;
+        }
+
+        if let Some((a, b)) = self.client_port {
+            write!(f, ";client_port={a}-{b}")?;
+        }
+
+        if let Some((a, b)) = self.server_port {
+            write!(f, ";server_port={a}-{b}")?;
+        }
+
+        if let Some(ssrc) = self.ssrc {
+            write!(f, ";ssrc={ssrc:08X}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses `a-b` or a single value `a` as `(a, a + 1)`
+fn parse_range<T: std::str::FromStr + Copy + TryFrom<u32>>(value: &str) -> Option<(T, T)>
+where
+    u32: From<T>,
+{
+    match value.trim().split_once('-') {
+        Some((a, b)) => Some((a.trim().parse().ok()?, b.trim().parse().ok()?)),
+        None => {
+            let a: T = value.trim().parse().ok()?;
+            Some((a, T::try_from(u32::from(a) + 1).ok()?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+
+    use super::{Message, Request, Response, Transport, put_interleaved};
+
+    #[test]
+    fn test_rtsp_messages() {
+        let request = Request::new("DESCRIBE", "rtsp://host/stream")
+            .with_header("CSeq", "2")
+            .with_header("Accept", "application/sdp");
+
+        let response = Response::new(200, "OK")
+            .with_header("CSeq", "2")
+            .with_body(Bytes::from_static(b"v=0\r\n"));
+
+        let mut buf = BytesMut::new();
+        request.write(&mut buf);
+        put_interleaved(&mut buf, 1, b"rtp");
+        response.write(&mut buf);
+
+        let data = buf.split().freeze();
+        let mut out = Vec::new();
+
+        // byte by byte to check the incomplete messages
+        for byte in data {
+            buf.extend_from_slice(&[byte]);
+
+            while let Some(message) = Message::parse(&mut buf).unwrap() {
+                out.push(message);
+            }
+        }
+
+        let Message::Response(parsed) = &out[2] else {
+            panic!("response expected");
+        };
+
+        assert_eq!(parsed.header("content-length"), Some("5"));
+        assert_eq!(parsed.body, response.body);
+        assert_eq!(parsed.cseq(), Some(2));
+
+        assert_eq!(out[0], Message::Request(request));
+        assert_eq!(out[1], Message::Interleaved(1, Bytes::from_static(b"rtp")));
+
+        let transport = Transport::parse(
+            "RTP/AVP;unicast;client_port=5000-5001;server_port=6000;ssrc=0000ABCD",
+        )
+        .unwrap();
+
+        assert!(!transport.tcp);
+        assert_eq!(transport.client_port, Some((5000, 5001)));
+        assert_eq!(transport.server_port, Some((6000, 6001)));
+        assert_eq!(transport.ssrc, Some(0xABCD));
+
+        let transport = Transport::parse("RTP/AVP/TCP;unicast;interleaved=2-3").unwrap();
+        assert_eq!(transport.interleaved, Some((2, 3)));
+        assert_eq!(transport.to_string(), "RTP/AVP/TCP;unicast;interleaved=2-3");
+    }
+
+    #[test]
+    fn test_rtsp_messages_malformed() {
+        let parse = |data: &[u8]| Message::parse(&mut BytesMut::from(data));
+
+        assert!(parse(b"RTSP/1.0 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\n").is_err());
+        assert!(parse(b"RTSP/1.0 200 OK\r\nContent-Length: 1048577\r\n\r\n").is_err());
+        assert!(parse(b"RTSP/1.0 200 OK\r\nContent-Length: -1\r\n\r\n").is_err());
+        assert!(parse(b"RTSP/1.0 OK OK\r\n\r\n").is_err());
+        assert!(parse(b"GARBAGE\r\nno colon\r\n\r\n").is_err());
+        assert!(parse(&[b'a'; 64 * 1024 + 1]).is_err());
+
+        // the body isn't received yet
+        assert!(matches!(
+            parse(b"RTSP/1.0 200 OK\r\nContent-Length: 10\r\n\r\nv=0"),
+            Ok(None)
+        ));
+    }
+}
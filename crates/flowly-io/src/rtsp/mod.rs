@@ -0,0 +1,77 @@
+mod auth;
+mod client;
+mod message;
+mod sdp;
+mod server;
+
+pub use auth::Authenticator;
+pub use client::{DEFAULT_PORT, RtspClient, RtspSource, RtspTransport};
+pub use message::{Message, RTSP_VERSION, Request, Response, Transport, put_interleaved};
+pub use sdp::{Media, SessionDescription, Track};
+pub use server::RtspServer;
+
+use std::io;
+
+use bytes::BytesMut;
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpStream, UdpSocket},
+};
+
+use crate::error::Error;
+
+/// Reads the next message of the connection, cancel safe
+async fn read_message(stream: &mut TcpStream, buf: &mut BytesMut) -> Result<Message, Error> {
+    loop {
+        if let Some(message) = Message::parse(buf)? {
+            return Ok(message);
+        }
+
+        if stream.read_buf(buf).await? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+    }
+}
+
+fn timed_out() -> Error {
+    io::Error::from(io::ErrorKind::TimedOut).into()
+}
+
+/// Binds the RTP socket on an even port and the RTCP one on the next port
+async fn bind_port_pair() -> Result<(UdpSocket, UdpSocket), Error> {
+    for _ in 0..16 {
+        let rtp = UdpSocket::bind("0.0.0.0:0").await?;
+        let port = rtp.local_addr()?.port();
+
+        if port % 2 != 0 || port == u16::MAX {
+            continue;
+        }
+
+        if let Ok(rtcp) = UdpSocket::bind(("0.0.0.0", port + 1)).await {
+            return Ok((rtp, rtcp));
+        }
+    }
+
+    // any pair is better than none
+    Ok((
+        UdpSocket::bind("0.0.0.0:0").await?,
+        UdpSocket::bind("0.0.0.0:0").await?,
+    ))
+}
+
+/// Receives a datagram from any of the sockets, returns the socket index and
+/// the datagram size
+async fn recv_any(sockets: &[UdpSocket], buf: &mut [u8]) -> io::Result<(usize, usize)> {
+    std::future::poll_fn(|cx| {
+        for (idx, socket) in sockets.iter().enumerate() {
+            let mut read = tokio::io::ReadBuf::new(buf);
+
+            if let std::task::Poll::Ready(res) = socket.poll_recv_from(cx, &mut read) {
+                return std::task::Poll::Ready(res.map(|_| (idx, read.filled().len())));
+            }
+        }
+
+        std::task::Poll::Pending
+    })
+    .await
+}
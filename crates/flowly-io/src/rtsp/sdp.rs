@@ -0,0 +1,563 @@
+use std::fmt::Write as _;
+
+use bytes::Bytes;
+use flowly_codec::{aac::AudioSpecificConfig, h265, opus::OpusHead};
+use flowly_core::Fourcc;
+
+use super::auth::{base64_decode, base64_encode};
+use crate::{
+    error::Error,
+    rtp::{AacMode, OPUS_CLOCK_RATE, VIDEO_CLOCK_RATE},
+};
+
+/// Largest AU header field length of `mpeg4-generic`
+const MAX_AU_FIELD_LENGTH: u8 = 16;
+
+/// Session description (RFC 8866), only the parts needed to set up RTP streams
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SessionDescription {
+    pub name: String,
+
+    /// Session level attributes
+    pub attributes: Vec<(String, String)>,
+    pub media: Vec<Media>,
+}
+
+/// Media description (`m=` line and its attributes)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Media {
+    /// `video`, `audio` or `application`
+    pub kind: String,
+    pub port: u16,
+    pub protocol: String,
+    pub formats: Vec<u8>,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl SessionDescription {
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let mut sdp = Self::default();
+
+        for line in data.lines() {
+            let Some((kind, value)) = line.trim().split_once('=') else {
+                continue;
+            };
+
+            match kind {
+                "s" => sdp.name = value.to_string(),
+                "m" => sdp.media.push(Media::parse_line(value)?),
+                "a" => {
+                    let (name, value) = value.split_once(':').unwrap_or((value, ""));
+                    let attribute = (name.to_string(), value.trim().to_string());
+
+                    match sdp.media.last_mut() {
+                        Some(media) => media.attributes.push(attribute),
+                        None => sdp.attributes.push(attribute),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(sdp)
+    }
+
+    /// Session level attribute value
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        find_attribute(&self.attributes, name)
+    }
+
+    #[inline]
+    pub fn control(&self) -> Option<&str> {
+        self.attribute("control")
+    }
+}
+
+impl std::fmt::Display for SessionDescription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = if self.name.is_empty() {
+            "-"
+        } else {
+            &self.name
+        };
+
+        write!(f, "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns={name}\r\n")?;
+        write!(f, "c=IN IP4 0.0.0.0\r\nt=0 0\r\n")?;
+        write_attributes(f, &self.attributes)?;
+
+        for media in &self.media {
+            write!(f, "m={} {} {}", media.kind, media.port, media.protocol)?;
+
+            for format in &media.formats {
+                write!(f, " {format}")?;
+            }
+
+            f.write_str("\r\n")?;
+            write_attributes(f, &media.attributes)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_attributes(
+    f: &mut std::fmt::Formatter<'_>,
+    attributes: &[(String, String)],
+) -> std::fmt::Result {
+    for (name, value) in attributes {
+        if value.is_empty() {
+            write!(f, "a={name}\r\n")?;
+        } else {
+            write!(f, "a={name}:{value}\r\n")?;
+        }
+    }
+
+    Ok(())
+}
+
+impl Media {
+    fn parse_line(value: &str) -> Result<Self, Error> {
+        let mut parts = value.split_ascii_whitespace();
+        let kind = parts.next().unwrap_or_default().to_string();
+
+        let port = parts
+            .next()
+            .and_then(|x| x.split('/').next())
+            .and_then(|x| x.parse().ok())
+            .ok_or(Error::InvalidData("sdp: invalid media line"))?;
+
+        let protocol = parts.next().unwrap_or_default().to_string();
+        let formats = parts.filter_map(|x| x.parse().ok()).collect();
+
+        Ok(Self {
+            kind,
+            port,
+            protocol,
+            formats,
+            attributes: Vec::new(),
+        })
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        find_attribute(&self.attributes, name)
+    }
+
+    #[inline]
+    pub fn control(&self) -> Option<&str> {
+        self.attribute("control")
+    }
+
+    /// Value of the attribute for the payload type, e.g. `a=rtpmap:96 <value>`
+    pub fn format_attribute(&self, name: &str, payload_type: u8) -> Option<&str> {
+        self.attributes
+            .iter()
+            .filter(|(key, _)| key == name)
+            .find_map(|(_, value)| {
+                let (pt, value) = value.split_once(' ').unwrap_or((value, ""));
+                (pt.parse() == Ok(payload_type)).then_some(value.trim())
+            })
+    }
+
+    /// `fmtp` parameters of the payload type
+    pub fn fmtp(&self, payload_type: u8) -> Vec<(&str, &str)> {
+        self.format_attribute("fmtp", payload_type)
+            .into_iter()
+            .flat_map(|x| x.split(';'))
+            .filter_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                Some((key.trim(), value.trim()))
+            })
+            .collect()
+    }
+}
+
+fn find_attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// RTP stream set up from the media description
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Track {
+    pub codec: Fourcc,
+    pub payload_type: u8,
+    pub clock_rate: u32,
+
+    /// Number of audio channels, 0 for video
+    pub channels: u16,
+
+    /// Parameter sets, `AudioSpecificConfig` or `OpusHead`
+    pub params: Vec<Bytes>,
+    pub aac_mode: AacMode,
+
+    /// `control` attribute (relative or absolute URL)
+    pub control: Option<String>,
+}
+
+impl Track {
+    /// Track of the frames with the codec and parameters, the clock rate and
+    /// channels of audio are taken from the parameters
+    pub fn new(codec: Fourcc, payload_type: u8, params: Vec<Bytes>) -> Result<Self, Error> {
+        let (clock_rate, channels) = match codec {
+            Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC | Fourcc::VIDEO_AV1 => (VIDEO_CLOCK_RATE, 0),
+            Fourcc::AUDIO_OPUS => {
+                let channels = params
+                    .first()
+                    .and_then(|x| OpusHead::parse(x).ok())
+                    .map_or(2, |x| x.channels as u16);
+
+                (OPUS_CLOCK_RATE, channels)
+            }
+
+            Fourcc::AUDIO_AAC => {
+                let asc = params.first().ok_or(flowly_codec::Error::MissingParams(
+                    "aac: AudioSpecificConfig",
+                ))?;
+
+                let asc = AudioSpecificConfig::parse(asc)?;
+                (asc.sample_rate, asc.channels().max(1))
+            }
+
+            _ => return Err(Error::Unsupported("sdp: payload format")),
+        };
+
+        Ok(Self {
+            codec,
+            payload_type,
+            clock_rate,
+            channels,
+            params,
+            aac_mode: AacMode::default(),
+            control: None,
+        })
+    }
+
+    /// Builds the track of the first format of the media
+    pub fn from_media(media: &Media) -> Result<Self, Error> {
+        let &payload_type = media
+            .formats
+            .first()
+            .ok_or(Error::InvalidData("sdp: media without formats"))?;
+
+        let rtpmap = media.format_attribute("rtpmap", payload_type);
+
+        // static payload types don't need `rtpmap`
+        let (encoding, clock_rate, channels) = match rtpmap {
+            Some(rtpmap) => {
+                let mut parts = rtpmap.split('/');
+                let encoding = parts.next().unwrap_or_default().to_ascii_uppercase();
+                let clock_rate = parts.next().and_then(|x| x.parse().ok()).filter(|&x| x > 0);
+                let channels = parts.next().and_then(|x| x.parse().ok()).unwrap_or(1);
+
+                (
+                    encoding,
+                    clock_rate.ok_or(Error::InvalidData("sdp: invalid rtpmap"))?,
+                    channels,
+                )
+            }
+            None => return Err(Error::Unsupported("sdp: static payload type")),
+        };
+
+        let fmtp = media.fmtp(payload_type);
+        let param = |name: &str| {
+            fmtp.iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| *value)
+        };
+
+        let mut track = Self {
+            codec: Fourcc::default(),
+            payload_type,
+            clock_rate,
+            channels,
+            params: Vec::new(),
+            aac_mode: AacMode::default(),
+            control: media.control().map(str::to_string),
+        };
+
+        match encoding.as_str() {
+            "H264" => {
+                track.codec = Fourcc::VIDEO_AVC;
+
+                if let Some(sets) = param("sprop-parameter-sets") {
+                    for set in sets.split(',').filter(|x| !x.is_empty()) {
+                        track.params.push(base64_decode(set)?.into());
+                    }
+                }
+            }
+
+            "H265" => {
+                track.codec = Fourcc::VIDEO_HEVC;
+
+                for name in ["sprop-vps", "sprop-sps", "sprop-pps"] {
+                    for set in param(name).into_iter().flat_map(|x| x.split(',')) {
+                        track.params.push(base64_decode(set)?.into());
+                    }
+                }
+            }
+
+            "AV1" => track.codec = Fourcc::VIDEO_AV1,
+
+            "OPUS" => {
+                track.codec = Fourcc::AUDIO_OPUS;
+
+                // the rtpmap always says 2 channels (RFC 7587 7)
+                if param("sprop-stereo") == Some("0") {
+                    track.channels = 1;
+                }
+
+                let head = OpusHead {
+                    channels: track.channels.clamp(1, 255) as u8,
+                    coupled_count: (track.channels > 1) as u8,
+                    ..Default::default()
+                };
+
+                track.params.push(head.to_bytes());
+            }
+
+            "MPEG4-GENERIC" => {
+                track.codec = Fourcc::AUDIO_AAC;
+
+                if let Some(mode) = param("mode")
+                    && !mode.to_ascii_uppercase().starts_with("AAC")
+                {
+                    return Err(Error::Unsupported("sdp: mpeg4-generic mode"));
+                }
+
+                // AU header fields fit in 16 bits (RFC 3640 3.2.1)
+                let length = |name: &str, default: u8| {
+                    param(name).map_or(Ok(default), |x| {
+                        x.parse()
+                            .ok()
+                            .filter(|&x| x <= MAX_AU_FIELD_LENGTH)
+                            .ok_or(Error::InvalidData("sdp: invalid AU header length"))
+                    })
+                };
+
+                track.aac_mode = AacMode {
+                    size_length: length("sizelength", 0)?,
+                    index_length: length("indexlength", 0)?,
+                    index_delta_length: length("indexdeltalength", 0)?,
+                };
+
+                if let Some(config) = param("config") {
+                    track.params.push(hex_decode(config)?.into());
+                }
+            }
+
+            _ => return Err(Error::Unsupported("sdp: payload format")),
+        }
+
+        if track.is_video() {
+            track.channels = 0;
+        }
+
+        Ok(track)
+    }
+
+    /// Media description of the track as sent by [`RtspServer`](super::RtspServer)
+    pub fn to_media(&self) -> Result<Media, Error> {
+        let pt = self.payload_type;
+        let mut fmtp = String::new();
+
+        let (kind, rtpmap) = match self.codec {
+            Fourcc::VIDEO_AVC => {
+                fmtp.push_str("packetization-mode=1");
+
+                if let Some(sps) = self
+                    .params
+                    .iter()
+                    .find(|x| x.first().map(|x| x & 0x1F) == Some(7))
+                    && sps.len() >= 4
+                {
+                    let _ = write!(fmtp, ";profile-level-id={}", hex_encode(&sps[1..4]));
+                }
+
+                if !self.params.is_empty() {
+                    let sets: Vec<_> = self.params.iter().map(|x| base64_encode(x)).collect();
+                    let _ = write!(fmtp, ";sprop-parameter-sets={}", sets.join(","));
+                }
+
+                ("video", format!("{pt} H264/{}", self.clock_rate))
+            }
+
+            Fourcc::VIDEO_HEVC => {
+                let names = [
+                    (h265::NAL_VPS, "sprop-vps"),
+                    (h265::NAL_SPS, "sprop-sps"),
+                    (h265::NAL_PPS, "sprop-pps"),
+                ];
+
+                for (nal_type, name) in names {
+                    let sets: Vec<_> = self
+                        .params
+                        .iter()
+                        .filter(|x| h265::nal_type(x) == Some(nal_type))
+                        .map(|x| base64_encode(x))
+                        .collect();
+
+                    if !sets.is_empty() {
+                        let sep = if fmtp.is_empty() { "" } else { ";" };
+                        let _ = write!(fmtp, "{sep}{name}={}", sets.join(","));
+                    }
+                }
+
+                ("video", format!("{pt} H265/{}", self.clock_rate))
+            }
+
+            Fourcc::VIDEO_AV1 => ("video", format!("{pt} AV1/{}", self.clock_rate)),
+
+            Fourcc::AUDIO_OPUS => {
+                let _ = write!(fmtp, "sprop-stereo={}", (self.channels > 1) as u8);
+                ("audio", format!("{pt} opus/{}/2", self.clock_rate))
+            }
+
+            Fourcc::AUDIO_AAC => {
+                let mode = self.aac_mode;
+                let _ = write!(
+                    fmtp,
+                    "streamtype=5;profile-level-id=1;mode={};sizelength={};indexlength={};indexdeltalength={}",
+                    if mode == AacMode::LBR {
+                        "AAC-lbr"
+                    } else {
+                        "AAC-hbr"
+                    },
+                    mode.size_length,
+                    mode.index_length,
+                    mode.index_delta_length,
+                );
+
+                if let Some(config) = self.params.first() {
+                    let _ = write!(fmtp, ";config={}", hex_encode(config));
+                }
+
+                let rtpmap = format!("{pt} MPEG4-GENERIC/{}/{}", self.clock_rate, self.channels);
+                ("audio", rtpmap)
+            }
+
+            _ => return Err(Error::Unsupported("sdp: payload format")),
+        };
+
+        let mut attributes = vec![(String::from("rtpmap"), rtpmap)];
+        if !fmtp.is_empty() {
+            attributes.push((String::from("fmtp"), format!("{pt} {fmtp}")));
+        }
+
+        if let Some(control) = &self.control {
+            attributes.push((String::from("control"), control.clone()));
+        }
+
+        Ok(Media {
+            kind: kind.to_string(),
+            port: 0,
+            protocol: String::from("RTP/AVP"),
+            formats: vec![pt],
+            attributes,
+        })
+    }
+
+    #[inline]
+    pub fn is_video(&self) -> bool {
+        matches!(
+            self.codec,
+            Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC | Fourcc::VIDEO_AV1
+        )
+    }
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|x| format!("{x:02X}")).collect()
+}
+
+fn hex_decode(data: &str) -> Result<Vec<u8>, Error> {
+    if !data.len().is_multiple_of(2) {
+        return Err(Error::InvalidData("sdp: invalid hex string"));
+    }
+
+    (0..data.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&data[i..i + 2], 16)
+                .map_err(|_| Error::InvalidData("sdp: invalid hex string"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use flowly_core::Fourcc;
+
+    use super::{SessionDescription, Track};
+    use crate::rtp::AacMode;
+
+    const SDP: &str = "v=0\r\n\
+        o=- 1 1 IN IP4 127.0.0.1\r\n\
+        s=Camera\r\n\
+        t=0 0\r\n\
+        a=control:*\r\n\
+        m=video 0 RTP/AVP 96\r\n\
+        a=rtpmap:96 H264/90000\r\n\
+        a=fmtp:96 packetization-mode=1;profile-level-id=42C01E;sprop-parameter-sets=Z0LAHg==,aM4G4g==\r\n\
+        a=control:trackID=1\r\n\
+        m=audio 0 RTP/AVP 97\r\n\
+        a=rtpmap:97 MPEG4-GENERIC/44100/2\r\n\
+        a=fmtp:97 streamtype=5; profile-level-id=15; mode=AAC-hbr; config=1210; sizelength=13; indexlength=3; indexdeltalength=3\r\n\
+        a=control:trackID=2\r\n\
+        m=application 0 RTP/AVP 107\r\n\
+        a=rtpmap:107 vnd.onvif.metadata/90000\r\n";
+
+    #[test]
+    fn test_sdp_tracks() {
+        let sdp = SessionDescription::parse(SDP).unwrap();
+        assert_eq!(sdp.name, "Camera");
+        assert_eq!(sdp.control(), Some("*"));
+        assert_eq!(sdp.media.len(), 3);
+
+        let video = Track::from_media(&sdp.media[0]).unwrap();
+        assert_eq!((video.codec, video.clock_rate), (Fourcc::VIDEO_AVC, 90_000));
+        assert_eq!(
+            video.params,
+            [&[0x67, 0x42, 0xC0, 0x1E][..], &[0x68, 0xCE, 0x06, 0xE2]]
+        );
+        assert_eq!(video.control.as_deref(), Some("trackID=1"));
+
+        let audio = Track::from_media(&sdp.media[1]).unwrap();
+        assert_eq!((audio.codec, audio.clock_rate), (Fourcc::AUDIO_AAC, 44_100));
+        assert_eq!(audio.channels, 2);
+        assert_eq!(audio.aac_mode, AacMode::HBR);
+        assert_eq!(audio.params, [&[0x12, 0x10][..]]);
+
+        assert!(Track::from_media(&sdp.media[2]).is_err());
+
+        // generated description gives back the same tracks
+        let generated = SessionDescription {
+            media: vec![video.to_media().unwrap(), audio.to_media().unwrap()],
+            ..Default::default()
+        };
+
+        let parsed = SessionDescription::parse(&generated.to_string()).unwrap();
+        assert_eq!(parsed.media, generated.media);
+        assert_eq!(Track::from_media(&parsed.media[0]).unwrap(), video);
+        assert_eq!(Track::from_media(&parsed.media[1]).unwrap(), audio);
+    }
+
+    #[test]
+    fn test_sdp_tracks_malformed() {
+        let track = |attributes: &str| {
+            let sdp = format!("v=0\r\ns=-\r\nt=0 0\r\nm=audio 0 RTP/AVP 96\r\n{attributes}");
+            Track::from_media(&SessionDescription::parse(&sdp).unwrap().media[0])
+        };
+
+        assert!(track("a=rtpmap:96 H264/0\r\n").is_err());
+        assert!(track("a=rtpmap:96 H264\r\n").is_err());
+        assert!(track("a=rtpmap:96 H264/90000\r\na=fmtp:96 sprop-parameter-sets=!!\r\n").is_err());
+
+        let aac = "a=rtpmap:96 mpeg4-generic/48000/2\r\na=fmtp:96 mode=AAC-hbr;";
+        assert!(track(&format!("{aac}sizelength=13;indexlength=3\r\n")).is_ok());
+        assert!(track(&format!("{aac}sizelength=200;indexlength=100\r\n")).is_err());
+        assert!(track(&format!("{aac}sizelength=13;indexdeltalength=17\r\n")).is_err());
+        assert!(track(&format!("{aac}sizelength=-1\r\n")).is_err());
+    }
+}
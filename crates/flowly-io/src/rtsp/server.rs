@@ -0,0 +1,778 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use flowly_core::{EncodedFrame, Multichannel, VideoFrame};
+use flowly_service::{Context, Service};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{mpsc, watch},
+    time::Instant,
+};
+
+use super::{
+    bind_port_pair,
+    message::{Message, Request, Response, Transport, put_interleaved},
+    read_message, recv_any,
+    sdp::{SessionDescription, Track},
+};
+use crate::{
+    error::Error,
+    rtp::{DEFAULT_MTU, DEFAULT_PAYLOAD_TYPE, MIN_MTU, RtpPacket, RtpPacketizer},
+};
+
+/// Frames queued for a viewer on top of the cached GOP
+const QUEUE_SIZE: usize = 256;
+
+/// RTSP server sink.
+///
+/// Serves the frames under the mount path (`rtsp://host:port/<mount>`) to any
+/// number of viewers, with RTP interleaved into the RTSP connection or over UDP.
+/// Tracks are keyed by codec (H.264/H.265, AV1, Opus and AAC) in the order of
+/// appearance, SDP is built from the codec and `params()` of their frames.
+/// Frames are packetized once and the RTP packets are shared by all viewers.
+///
+/// Packets since the last video keyframe are cached, so new viewers start on
+/// the latest keyframe. Viewers of a video track that fall behind skip frames
+/// up to the next keyframe, audio only viewers just lose the frames that don't
+/// fit into the queue. The listener is bound on the first frame and stops with the
+/// context abort. Yields the number of viewers the frame was sent to.
+#[derive(Debug, Clone)]
+pub struct RtspServer {
+    address: String,
+    mount: String,
+    mtu: usize,
+    gop_limit: usize,
+    session_timeout: Duration,
+    shared: Arc<Mutex<Shared>>,
+    started: bool,
+}
+
+impl RtspServer {
+    pub fn new(address: impl Into<String>, mount: impl Into<String>) -> Self {
+        let mount = mount.into();
+
+        Self {
+            address: address.into(),
+            mount: format!("/{}", mount.trim_matches('/')),
+            mtu: DEFAULT_MTU,
+            gop_limit: 16 * 1024 * 1024,
+            session_timeout: Duration::from_secs(60),
+            shared: Arc::new(Mutex::new(Shared::default())),
+            started: false,
+        }
+    }
+
+    /// Max size of the RTP packets, at least [`MIN_MTU`]
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        assert!(mtu >= MIN_MTU, "RTP MTU is too small");
+
+        self.mtu = mtu;
+        self
+    }
+
+    /// Max size of the cached GOP in bytes, viewers wait for the next
+    /// keyframe when the GOP doesn't fit
+    pub fn with_gop_limit(mut self, limit: usize) -> Self {
+        self.gop_limit = limit;
+        self
+    }
+
+    /// Session timeout announced to the viewers, UDP sessions without
+    /// requests or RTCP for that long are closed
+    pub fn with_session_timeout(mut self, timeout: Duration) -> Self {
+        self.session_timeout = timeout;
+        self
+    }
+
+    /// Address of the listener once it is bound
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.shared.lock().unwrap().local_addr
+    }
+
+    async fn start(&self, cx: &Context) -> Result<(), Error> {
+        let listener = TcpListener::bind(&self.address).await?;
+        self.shared.lock().unwrap().local_addr = Some(listener.local_addr()?);
+
+        let shared = self.shared.clone();
+        let mount = self.mount.clone();
+        let timeout = self.session_timeout;
+        let mut abort = cx.abort_recv.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = tokio::select! {
+                    _ = abort.changed() => break,
+                    res = listener.accept() => match res {
+                        Ok(res) => res,
+                        Err(_) => continue,
+                    },
+                };
+
+                let _ = stream.set_nodelay(true);
+                let conn = ServerConnection {
+                    shared: shared.clone(),
+                    mount: mount.clone(),
+                    session_timeout: timeout,
+                    stream,
+                    peer,
+                    buf: BytesMut::new(),
+                    session: None,
+                    outputs: Vec::new(),
+                    rtcp_sockets: Vec::new(),
+                    frames: None,
+                };
+
+                tokio::spawn(conn.run(abort.clone()));
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Packetizes the frame and sends it to the viewers
+    fn publish<F: EncodedFrame + Multichannel + VideoFrame>(
+        &self,
+        frame: F,
+    ) -> Result<usize, Error> {
+        let codec = frame.codec();
+        let keyframe = frame.is_keyframe();
+        let params: Vec<_> = frame
+            .params()
+            .map(|x| Bytes::copy_from_slice(x.as_ref()))
+            .collect();
+
+        let mut shared = self.shared.lock().unwrap();
+
+        let idx = match shared.tracks.iter().position(|x| x.track.codec == codec) {
+            Some(idx) => idx,
+            None => {
+                let idx = shared.tracks.len();
+                let mut track =
+                    Track::new(codec, DEFAULT_PAYLOAD_TYPE + idx as u8, params.clone())?;
+                track.control = Some(format!("trackID={idx}"));
+
+                let packetizer = RtpPacketizer::new(codec, track.clock_rate)
+                    .with_payload_type(track.payload_type)
+                    .with_mtu(self.mtu);
+
+                shared.tracks.push(ServerTrack { track, packetizer });
+                idx
+            }
+        };
+
+        let track = &mut shared.tracks[idx];
+        if !params.is_empty() {
+            track.track.params = params;
+        }
+
+        let packets = track.packetizer.push(frame)?;
+        let frame = Arc::new(RtpFrame {
+            track: idx,
+            keyframe: keyframe && track.track.is_video(),
+            packets: packets.iter().map(RtpPacket::to_bytes).collect(),
+        });
+
+        shared.cache(&frame, self.gop_limit);
+        Ok(shared.broadcast(&frame))
+    }
+}
+
+impl<F: EncodedFrame + Multichannel + VideoFrame> Service<F> for RtspServer {
+    type Out = Result<usize, Error>;
+
+    fn handle(&mut self, frame: F, cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        async_stream::stream! {
+            if !self.started {
+                if let Err(err) = self.start(cx).await {
+                    yield Err(err);
+                    return;
+                }
+
+                self.started = true;
+            }
+
+            yield self.publish(frame);
+        }
+    }
+}
+
+type FrameReceiver = mpsc::Receiver<Arc<RtpFrame>>;
+
+/// RTP packets of a frame
+#[derive(Debug)]
+struct RtpFrame {
+    track: usize,
+    keyframe: bool,
+    packets: Vec<Bytes>,
+}
+
+#[derive(Debug)]
+struct ServerTrack {
+    track: Track,
+    packetizer: RtpPacketizer,
+}
+
+#[derive(Debug)]
+struct Viewer {
+    tracks: Vec<usize>,
+    tx: mpsc::Sender<Arc<RtpFrame>>,
+
+    /// One of the tracks is video, otherwise the viewer never waits
+    has_video: bool,
+
+    /// Frames are skipped up to the next keyframe
+    waiting: bool,
+}
+
+/// State shared by the sink and the connections
+#[derive(Debug, Default)]
+struct Shared {
+    local_addr: Option<SocketAddr>,
+    tracks: Vec<ServerTrack>,
+    viewers: Vec<Viewer>,
+
+    /// Frames since the last video keyframe, if it fits the limit
+    gop: Vec<Arc<RtpFrame>>,
+    gop_size: usize,
+    caching: bool,
+}
+
+impl Shared {
+    fn has_video(&self, tracks: &[usize]) -> bool {
+        tracks
+            .iter()
+            .any(|&idx| self.tracks.get(idx).is_some_and(|x| x.track.is_video()))
+    }
+
+    fn cache(&mut self, frame: &Arc<RtpFrame>, limit: usize) {
+        if frame.keyframe {
+            self.gop.clear();
+            self.gop_size = 0;
+            self.caching = true;
+        }
+
+        if !self.caching {
+            return;
+        }
+
+        let size: usize = frame.packets.iter().map(Bytes::len).sum();
+        if self.gop_size + size > limit {
+            self.gop.clear();
+            self.gop_size = 0;
+            self.caching = false;
+        } else {
+            self.gop_size += size;
+            self.gop.push(frame.clone());
+        }
+    }
+
+    fn broadcast(&mut self, frame: &Arc<RtpFrame>) -> usize {
+        let mut count = 0;
+
+        self.viewers.retain_mut(|viewer| {
+            if !viewer.tracks.contains(&frame.track) {
+                return !viewer.tx.is_closed();
+            }
+
+            if viewer.waiting {
+                if !frame.keyframe {
+                    return !viewer.tx.is_closed();
+                }
+
+                viewer.waiting = false;
+            }
+
+            match viewer.tx.try_send(frame.clone()) {
+                Ok(()) => {
+                    count += 1;
+                    true
+                }
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    viewer.waiting = viewer.has_video;
+                    true
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            }
+        });
+
+        count
+    }
+
+    /// Registers the viewer of the tracks, the cached GOP is queued first.
+    /// Returns the frames receiver with the sequence number and RTP timestamp
+    /// of the first packet of the tracks found in the GOP
+    fn play(&mut self, tracks: Vec<usize>) -> (FrameReceiver, Vec<(usize, u16, u32)>) {
+        let (tx, rx) = mpsc::channel(self.gop.len() + QUEUE_SIZE);
+        let mut rtp_info: Vec<(usize, u16, u32)> = Vec::new();
+
+        for frame in self.gop.iter().filter(|x| tracks.contains(&x.track)) {
+            if let Some(packet) = frame.packets.first()
+                && !rtp_info.iter().any(|x| x.0 == frame.track)
+            {
+                let sequence = u16::from_be_bytes([packet[2], packet[3]]);
+                let timestamp = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+                rtp_info.push((frame.track, sequence, timestamp));
+            }
+
+            let _ = tx.try_send(frame.clone());
+        }
+
+        let has_video = self.has_video(&tracks);
+
+        self.viewers.push(Viewer {
+            waiting: self.gop.is_empty() && has_video,
+            has_video,
+            tracks,
+            tx,
+        });
+
+        (rx, rtp_info)
+    }
+}
+
+/// Destination of the RTP packets of a track
+#[derive(Debug)]
+enum Output {
+    Interleaved(u8),
+    Udp(UdpSocket, SocketAddr),
+}
+
+/// RTSP connection of a viewer
+struct ServerConnection {
+    shared: Arc<Mutex<Shared>>,
+    mount: String,
+    session_timeout: Duration,
+    stream: TcpStream,
+    peer: SocketAddr,
+    buf: BytesMut,
+    session: Option<String>,
+
+    /// Set up tracks
+    outputs: Vec<(usize, Output)>,
+    rtcp_sockets: Vec<UdpSocket>,
+    frames: Option<FrameReceiver>,
+}
+
+enum Event {
+    Request(Request),
+    Frame(Arc<RtpFrame>),
+    Activity,
+    Close,
+}
+
+impl ServerConnection {
+    async fn run(mut self, mut abort: watch::Receiver<bool>) {
+        let mut rtcp_buf = vec![0u8; 2048];
+        let mut last_activity = Instant::now();
+
+        loop {
+            let udp = self.frames.is_some() && !self.rtcp_sockets.is_empty();
+
+            let event = tokio::select! {
+                _ = abort.changed() => Event::Close,
+                res = read_message(&mut self.stream, &mut self.buf) => match res {
+                    Ok(Message::Request(request)) => Event::Request(request),
+                    Ok(_) => Event::Activity,
+                    Err(_) => Event::Close,
+                },
+                frame = next_frame(&mut self.frames) => match frame {
+                    Some(frame) => Event::Frame(frame),
+                    None => Event::Close,
+                },
+                _ = recv_any(&self.rtcp_sockets, &mut rtcp_buf) => Event::Activity,
+                _ = tokio::time::sleep_until(last_activity + self.session_timeout), if udp => {
+                    Event::Close
+                }
+            };
+
+            match event {
+                Event::Close => break,
+                Event::Activity => last_activity = Instant::now(),
+                Event::Frame(frame) => {
+                    if self.send(&frame).await.is_err() {
+                        break;
+                    }
+                }
+
+                Event::Request(request) => {
+                    last_activity = Instant::now();
+
+                    let teardown = request.method == "TEARDOWN";
+                    let mut response = self.respond(&request).await;
+
+                    if let Some(cseq) = request.header("CSeq") {
+                        response = response.with_header("CSeq", cseq);
+                    }
+
+                    if let Some(session) = &self.session {
+                        let timeout = self.session_timeout.as_secs();
+                        response =
+                            response.with_header("Session", format!("{session};timeout={timeout}"));
+                    }
+
+                    let mut buf = BytesMut::new();
+                    response.with_header("Server", "flowly").write(&mut buf);
+
+                    if self.stream.write_all(&buf).await.is_err() || teardown {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn send(&mut self, frame: &RtpFrame) -> Result<(), Error> {
+        for (_, output) in self.outputs.iter().filter(|x| x.0 == frame.track) {
+            match output {
+                Output::Interleaved(channel) => {
+                    let mut buf = BytesMut::new();
+                    for packet in &frame.packets {
+                        put_interleaved(&mut buf, *channel, packet);
+                    }
+
+                    self.stream.write_all(&buf).await?;
+                }
+
+                Output::Udp(socket, addr) => {
+                    for packet in &frame.packets {
+                        // delivery over UDP is not guaranteed anyway
+                        let _ = socket.send_to(packet, addr).await;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn respond(&mut self, request: &Request) -> Response {
+        if request.method == "OPTIONS" {
+            return Response::new(200, "OK").with_header(
+                "Public",
+                "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN, GET_PARAMETER",
+            );
+        }
+
+        let Some(path) = relative_path(&request.uri, &self.mount) else {
+            return status(404);
+        };
+
+        if request.method != "DESCRIBE"
+            && let Some(session) = request.header("Session")
+            && self.session.as_deref() != session.split(';').next().map(str::trim)
+        {
+            return status(454);
+        }
+
+        match request.method.as_str() {
+            "DESCRIBE" => {
+                let shared = self.shared.lock().unwrap();
+                if shared.tracks.is_empty() {
+                    return status(503);
+                }
+
+                let mut sdp = SessionDescription {
+                    name: self.mount.clone(),
+                    attributes: vec![(String::from("control"), String::from("*"))],
+                    media: Vec::new(),
+                };
+
+                for track in &shared.tracks {
+                    match track.track.to_media() {
+                        Ok(media) => sdp.media.push(media),
+                        Err(_) => return status(500),
+                    }
+                }
+
+                Response::new(200, "OK")
+                    .with_header(
+                        "Content-Base",
+                        format!("{}/", request.uri.trim_end_matches('/')),
+                    )
+                    .with_header("Content-Type", "application/sdp")
+                    .with_body(Bytes::from(sdp.to_string()))
+            }
+
+            "SETUP" => self.setup(request, path).await,
+
+            "PLAY" => {
+                if self.outputs.is_empty() {
+                    return status(455);
+                }
+
+                let mut response = Response::new(200, "OK").with_header("Range", "npt=0.000-");
+
+                if self.frames.is_none() {
+                    let tracks = self.outputs.iter().map(|x| x.0).collect();
+                    let (frames, rtp_info) = self.shared.lock().unwrap().play(tracks);
+                    self.frames = Some(frames);
+
+                    if !rtp_info.is_empty() {
+                        let base = request.uri.trim_end_matches('/');
+                        let rtp_info: Vec<_> = rtp_info
+                            .into_iter()
+                            .map(|(idx, seq, rtptime)| {
+                                format!("url={base}/trackID={idx};seq={seq};rtptime={rtptime}")
+                            })
+                            .collect();
+
+                        response = response.with_header("RTP-Info", rtp_info.join(","));
+                    }
+                }
+
+                response
+            }
+
+            "TEARDOWN" | "GET_PARAMETER" | "SET_PARAMETER" => Response::new(200, "OK"),
+            _ => status(501),
+        }
+    }
+
+    async fn setup(&mut self, request: &Request, path: &str) -> Response {
+        if self.frames.is_some() {
+            return status(455);
+        }
+
+        let (idx, ssrc) = {
+            let shared = self.shared.lock().unwrap();
+            let idx = match path.strip_prefix("trackID=") {
+                Some(idx) => idx.parse().ok(),
+                None if path.is_empty() && shared.tracks.len() == 1 => Some(0),
+                None => None,
+            };
+
+            match idx.and_then(|idx| Some((idx, shared.tracks.get(idx)?.packetizer.ssrc()))) {
+                Some(track) => track,
+                None => return status(404),
+            }
+        };
+
+        let Some(mut transport) = request
+            .header("Transport")
+            .and_then(|x| Transport::parse(x).ok())
+        else {
+            return status(461);
+        };
+
+        let output = if transport.tcp {
+            let channels = *transport
+                .interleaved
+                .get_or_insert((idx as u8 * 2, idx as u8 * 2 + 1));
+
+            Output::Interleaved(channels.0)
+        } else {
+            let Some((rtp_port, _)) = transport.client_port else {
+                return status(461);
+            };
+
+            let Ok((rtp, rtcp)) = bind_port_pair().await else {
+                return status(500);
+            };
+
+            let (Ok(rtp_addr), Ok(rtcp_addr)) = (rtp.local_addr(), rtcp.local_addr()) else {
+                return status(500);
+            };
+
+            transport.server_port = Some((rtp_addr.port(), rtcp_addr.port()));
+            self.rtcp_sockets.push(rtcp);
+
+            Output::Udp(rtp, SocketAddr::new(self.peer.ip(), rtp_port))
+        };
+
+        transport.ssrc = Some(ssrc);
+        self.outputs.retain(|x| x.0 != idx);
+        self.outputs.push((idx, output));
+        self.session.get_or_insert_with(session_id);
+
+        Response::new(200, "OK").with_header("Transport", transport.to_string())
+    }
+}
+
+async fn next_frame(frames: &mut Option<FrameReceiver>) -> Option<Arc<RtpFrame>> {
+    match frames {
+        Some(frames) => frames.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Path of the url relative to the mount, `None` if it is outside the mount
+fn relative_path<'a>(uri: &'a str, mount: &str) -> Option<&'a str> {
+    let path = match uri.find("://") {
+        Some(pos) => {
+            let rest = &uri[pos + 3..];
+            rest.find('/').map_or("", |x| &rest[x..])
+        }
+        None => uri,
+    };
+
+    let path = path.split('?').next().unwrap_or_default();
+    let rest = path.strip_prefix(mount.trim_end_matches('/'))?;
+
+    match rest {
+        "" | "/" => Some(""),
+        rest => rest.strip_prefix('/'),
+    }
+}
+
+fn session_id() -> String {
+    format!(
+        "{:016X}",
+        RandomState::new().hash_one(std::time::SystemTime::now())
+    )
+}
+
+fn status(code: u16) -> Response {
+    let reason = match code {
+        404 => "Not Found",
+        454 => "Session Not Found",
+        455 => "Method Not Valid in This State",
+        461 => "Unsupported Transport",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+
+    Response::new(code, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use bytes::{BufMut, BytesMut};
+    use flowly_core::{EncodedFrame, Fourcc, Frame, FrameFlags, Packet};
+    use flowly_service::{Context, Service};
+    use futures::StreamExt;
+
+    use super::{RtpFrame, RtspServer, ServerTrack, Shared, relative_path};
+    use crate::{
+        rtp::{MIN_MTU, RtpPacketizer},
+        rtsp::{RtspClient, RtspTransport, Track},
+        test_fixtures::{PPS, SPS},
+    };
+
+    fn frame(idx: u64) -> Packet {
+        let keyframe = idx.is_multiple_of(3);
+
+        let mut data = BytesMut::new();
+        data.put_slice(&[0, 0, 0, 1, if keyframe { 0x65 } else { 0x41 }]);
+        data.put_bytes(idx as u8, 2000);
+
+        let mut flags = FrameFlags::VIDEO_STREAM | FrameFlags::ANNEXB;
+        if keyframe {
+            flags |= FrameFlags::KEYFRAME;
+        }
+
+        let packet = Packet::new((), Fourcc::VIDEO_AVC, flags, data.freeze())
+            .with_timestamps(idx * 40_000, idx as i64 * 40_000);
+
+        if keyframe {
+            packet.with_params(vec![SPS.into(), PPS.into()])
+        } else {
+            packet
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rtsp_server() {
+        let cx = Context::new();
+        let mut server = RtspServer::new("127.0.0.1:0", "live/");
+
+        let publish = async |server: &mut RtspServer, idx| {
+            pin!(server.handle(frame(idx), &cx))
+                .next()
+                .await
+                .unwrap()
+                .unwrap()
+        };
+
+        assert_eq!(publish(&mut server, 0).await, 0);
+        assert_eq!(publish(&mut server, 1).await, 0);
+
+        let url = format!("rtsp://{}/live", server.local_addr().unwrap());
+
+        let mut tcp_client = RtspClient::new().with_reconnect(None);
+        let mut udp_client = RtspClient::new()
+            .with_transport(RtspTransport::Udp)
+            .with_reconnect(None);
+
+        let mut tcp = pin!(tcp_client.handle(url.clone(), &cx));
+        let mut udp = pin!(udp_client.handle(url, &cx));
+
+        // the cached GOP first
+        for stream in [&mut tcp, &mut udp] {
+            let keyframe = stream.next().await.unwrap().unwrap();
+            assert!(keyframe.is_keyframe());
+            assert_eq!(keyframe.params().collect::<Vec<_>>(), [SPS, PPS]);
+            assert_eq!(keyframe.dimensions, (1920, 1080));
+
+            let frame = stream.next().await.unwrap().unwrap();
+            assert_eq!(frame.pts, 40_000);
+        }
+
+        assert_eq!(publish(&mut server, 2).await, 2);
+        assert_eq!(publish(&mut server, 3).await, 2);
+
+        for stream in [&mut tcp, &mut udp] {
+            let frame = stream.next().await.unwrap().unwrap();
+            assert_eq!((frame.pts, &frame.data), (80_000, &self::frame(2).data));
+        }
+
+        let _ = cx.abort.send(true);
+        assert!(tcp.next().await.is_none());
+
+        assert_eq!(
+            relative_path("rtsp://host:554/live/trackID=1", "/live"),
+            Some("trackID=1")
+        );
+        assert_eq!(relative_path("rtsp://host/live/", "/live"), Some(""));
+        assert_eq!(relative_path("rtsp://host/live2", "/live"), None);
+        assert_eq!(relative_path("rtsp://host/any", "/"), Some("any"));
+    }
+
+    #[test]
+    fn test_audio_viewer() {
+        let mut shared = Shared::default();
+        for (codec, params) in [
+            (Fourcc::VIDEO_AVC, vec![SPS.into(), PPS.into()]),
+            (Fourcc::AUDIO_AAC, vec![vec![0x11, 0x90].into()]),
+        ] {
+            let track = Track::new(codec, 96, params).unwrap();
+            let packetizer = RtpPacketizer::new(codec, track.clock_rate);
+            shared.tracks.push(ServerTrack { track, packetizer });
+        }
+
+        let audio = || {
+            std::sync::Arc::new(RtpFrame {
+                track: 1,
+                keyframe: false,
+                packets: Vec::new(),
+            })
+        };
+
+        // nothing is cached, only the video viewer waits for a keyframe
+        let (mut video_rx, _) = shared.play(vec![0, 1]);
+        let (mut audio_rx, _) = shared.play(vec![1]);
+        assert_eq!(shared.broadcast(&audio()), 1);
+
+        // the queue overflows
+        while shared.broadcast(&audio()) > 0 {}
+        assert_eq!(shared.viewers[1].tx.capacity(), 0);
+
+        audio_rx.try_recv().unwrap();
+        assert_eq!(shared.broadcast(&audio()), 1);
+        assert!(video_rx.try_recv().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "RTP MTU is too small")]
+    fn test_rtsp_server_small_mtu() {
+        let _ = RtspServer::new("127.0.0.1:0", "live").with_mtu(MIN_MTU - 1);
+    }
+}
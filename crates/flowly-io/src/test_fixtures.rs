@@ -0,0 +1,10 @@
+//! Bitstream samples shared by the tests
+
+/// x264 1920x1080 High profile level 4.0 H.264 SPS, 25 fps
+pub const SPS: &[u8] = &[
+    0x67, 0x64, 0x00, 0x28, 0xAC, 0xD9, 0x40, 0x78, 0x02, 0x27, 0xE5, 0xC0, 0x44, 0x00, 0x00, 0x03,
+    0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0xC8, 0x3C, 0x60, 0xC6, 0x58,
+];
+
+/// H.264 PPS of the [`SPS`] stream
+pub const PPS: &[u8] = &[0x68, 0xEB, 0xE3, 0xCB, 0x22, 0xC0];
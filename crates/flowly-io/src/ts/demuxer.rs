@@ -0,0 +1,902 @@
+use std::collections::HashMap;
+
+use bytes::{Buf, Bytes, BytesMut};
+use flowly_codec::{
+    VideoInfo,
+    aac::{AdtsHeader, SAMPLES_PER_FRAME},
+    h264, h265,
+    nal::AnnexBIter,
+};
+use flowly_core::{Chunked, DataFrame, Fourcc, FrameFlags, FrameSource, Packet, Reader, ReaderExt};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+/// Elementary stream announced by PMT
+#[derive(Debug, Clone)]
+struct Stream {
+    pid: u16,
+    codec: Fourcc,
+    track: u32,
+    continuity_counter: Option<u8>,
+    pes: Option<BytesMut>,
+    random_access: bool,
+    params: Vec<Bytes>,
+    dimensions: (u16, u16),
+    bit_depth: u8,
+
+    /// Unwrapped DTS and PTS following the last PES (90 kHz), used for the PES
+    /// without timestamps
+    timestamps: Option<(u64, u64)>,
+}
+
+impl Stream {
+    fn new(pid: u16, codec: Fourcc, track: u32) -> Self {
+        Self {
+            pid,
+            codec,
+            track,
+            continuity_counter: None,
+            pes: None,
+            random_access: false,
+            params: Vec::new(),
+            dimensions: (0, 0),
+            bit_depth: 8,
+            timestamps: None,
+        }
+    }
+
+    #[inline]
+    fn is_video(&self) -> bool {
+        matches!(
+            self.codec,
+            Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC | Fourcc::VIDEO_AV1
+        )
+    }
+
+    /// Length of the PES packet if it is signalled in the PES header
+    fn pes_size(&self) -> Option<usize> {
+        let pes = self.pes.as_ref()?;
+        let len = u16::from_be_bytes([*pes.get(4)?, *pes.get(5)?]) as usize;
+
+        (len > 0).then_some(len + 6)
+    }
+}
+
+/// MPEG-TS demuxer.
+///
+/// Accepts arbitrary chunks of the transport stream, resynchronizes on the
+/// 0x47 sync byte, follows PAT/PMT of the first program and reassembles PES
+/// packets of the known elementary streams. Every elementary stream maps to
+/// its own track (in PMT order), so all packets carry the `MULTICHANNEL` flag.
+///
+/// PES packets with the unbounded length (usually video) are emitted when
+/// the next PES of the same stream starts, use [`TsDemuxer::flush`] to get
+/// the pending ones at the end of the stream. PES damaged by the lost TS
+/// packets (continuity counter gap) are dropped.
+///
+/// Video is yielded as AnnexB with in-band parameter sets copied into the
+/// packet params of every keyframe. AAC PES are split into access units with
+/// the ADTS headers stripped and `AudioSpecificConfig` attached as params (as
+/// [`crate::adts::AdtsDemuxer`] does), the access units after the first one
+/// are timed by 1024 samples. Every stream keeps its own timestamps for the
+/// 33-bit wraparound, all of them are rebased to the first DTS of the program.
+#[derive(Debug, Clone)]
+pub struct TsDemuxer<S = ()> {
+    source: S,
+    buf: Chunked<Bytes>,
+    sections: HashMap<u16, BytesMut>,
+    pmt_pid: Option<u16>,
+    pmt_version: Option<u8>,
+    streams: Vec<Stream>,
+    synced: bool,
+    base: Option<u64>,
+
+    /// Last DTS of the program, the first timestamp of a stream is unwrapped against it
+    clock: u64,
+}
+
+impl<S: FrameSource> TsDemuxer<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            buf: Chunked::new(),
+            sections: HashMap::new(),
+            pmt_pid: None,
+            pmt_version: None,
+            streams: Vec::new(),
+            synced: false,
+            base: None,
+            clock: 0,
+        }
+    }
+
+    /// Codecs of the elementary streams indexed by track
+    pub fn tracks(&self) -> impl Iterator<Item = Fourcc> + '_ {
+        self.streams.iter().map(|s| s.codec)
+    }
+
+    /// Feeds the chunk of the stream and returns all completed packets
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        self.buf.put(chunk);
+
+        while self.sync() {
+            let packet = self.buf.copy_to_bytes(PACKET_SIZE);
+
+            if let Err(err) = self.parse_packet(packet, &mut out) {
+                out.push(Err(err));
+            }
+        }
+
+        out
+    }
+
+    /// Emits PES packets which are still waiting for the next payload unit start
+    pub fn flush(&mut self) -> Vec<Result<Packet<S>, Error>> {
+        let mut out = Vec::new();
+
+        for idx in 0..self.streams.len() {
+            self.finish_pes(idx, &mut out);
+        }
+
+        out
+    }
+
+    /// Skips bytes until the complete packet starting with the sync byte is buffered
+    fn sync(&mut self) -> bool {
+        loop {
+            if self.buf.remaining() < PACKET_SIZE {
+                return false;
+            }
+
+            if self.buf.chunk()[0] == SYNC_BYTE {
+                if self.synced {
+                    return true;
+                }
+
+                // out of sync the position is confirmed by the sync byte of the next packet
+                let mut next = [0u8];
+                match self.buf.peek(PACKET_SIZE, &mut next) {
+                    0 => return false,
+                    _ if next[0] == SYNC_BYTE => {
+                        self.synced = true;
+                        return true;
+                    }
+                    _ => (),
+                }
+            }
+
+            self.synced = false;
+            self.buf.advance(1);
+        }
+    }
+
+    fn parse_packet(
+        &mut self,
+        mut data: Bytes,
+        out: &mut Vec<Result<Packet<S>, Error>>,
+    ) -> Result<(), Error> {
+        data.advance(1);
+
+        let header = TsHeader::read(&mut data)?;
+        if header.transport_error || header.pid == PID_NULL {
+            return Ok(());
+        }
+
+        let mut discontinuity = false;
+        let mut random_access = false;
+
+        if header.has_adaptation {
+            let len = data.read_u8()? as usize;
+            if len > data.len() {
+                return Err(Error::InvalidData("adaptation field length"));
+            }
+
+            let mut adaptation = data.split_to(len);
+            if len > 0 {
+                let flags = adaptation.read_u8()?;
+                discontinuity = flags & 0x80 != 0;
+                random_access = flags & 0x40 != 0;
+            }
+        }
+
+        if !header.has_payload {
+            return Ok(());
+        }
+
+        if header.pid == PID_PAT || Some(header.pid) == self.pmt_pid {
+            return self.push_section(&header, data);
+        }
+
+        let Some(idx) = self.streams.iter().position(|s| s.pid == header.pid) else {
+            return Ok(());
+        };
+
+        let stream = &mut self.streams[idx];
+        let cc = header.continuity_counter;
+
+        if let Some(last) = stream.continuity_counter
+            && !discontinuity
+        {
+            if cc == last {
+                // duplicate packet
+                return Ok(());
+            }
+
+            if cc != (last + 1) & 0x0F {
+                stream.pes = None;
+            }
+        }
+
+        stream.continuity_counter = Some(cc);
+
+        if header.payload_unit_start {
+            self.finish_pes(idx, out);
+
+            let stream = &mut self.streams[idx];
+            stream.pes = Some(BytesMut::from(&data[..]));
+            stream.random_access = random_access;
+        } else if let Some(pes) = &mut stream.pes {
+            pes.extend_from_slice(&data);
+        }
+
+        let stream = &self.streams[idx];
+        if let Some(size) = stream.pes_size()
+            && stream.pes.as_ref().is_some_and(|x| x.len() >= size)
+        {
+            self.finish_pes(idx, out);
+        }
+
+        Ok(())
+    }
+
+    fn push_section(&mut self, header: &TsHeader, mut data: Bytes) -> Result<(), Error> {
+        if header.payload_unit_start {
+            let pointer = data.read_u8()? as usize;
+            if pointer > data.len() {
+                return Err(Error::InvalidData("PSI pointer field"));
+            }
+
+            // the tail of the previous section goes before the pointed one
+            let tail = data.split_to(pointer);
+            if let Some(section) = self.sections.get_mut(&header.pid) {
+                section.extend_from_slice(&tail);
+                self.try_parse_section(header.pid)?;
+            }
+
+            self.sections.insert(header.pid, BytesMut::new());
+        }
+
+        let Some(section) = self.sections.get_mut(&header.pid) else {
+            return Ok(());
+        };
+
+        section.extend_from_slice(&data);
+        self.try_parse_section(header.pid)
+    }
+
+    fn try_parse_section(&mut self, pid: u16) -> Result<(), Error> {
+        loop {
+            let Some(section) = self.sections.get_mut(&pid) else {
+                return Ok(());
+            };
+
+            // stuffing bytes till the end of the packet
+            if section.first().is_none_or(|&x| x == 0xFF) {
+                section.clear();
+                return Ok(());
+            }
+
+            if section.len() < 3 {
+                return Ok(());
+            }
+
+            let len = 3 + (u16::from_be_bytes([section[1], section[2]]) & 0x0FFF) as usize;
+            if section.len() < len {
+                return Ok(());
+            }
+
+            let data = section.split_to(len).freeze();
+            self.parse_section(pid, data)?;
+        }
+    }
+
+    fn parse_section(&mut self, pid: u16, mut data: Bytes) -> Result<(), Error> {
+        if data.len() < 12 {
+            return Err(Error::InvalidData("PSI section is too short"));
+        }
+
+        if crc32(&data) != 0 {
+            return Err(Error::InvalidData("PSI section CRC mismatch"));
+        }
+
+        data.truncate(data.len() - 4);
+
+        let table_id = data.read_u8()?;
+        let _ = data.read_u16()?;
+        let _id = data.read_u16()?;
+        let (_, version, current) = data.read_u8p3::<2, 5, 1>()?;
+        let _section_number = data.read_u8()?;
+        let _last_section_number = data.read_u8()?;
+
+        if current == 0 {
+            return Ok(());
+        }
+
+        match table_id {
+            TABLE_ID_PAT if pid == PID_PAT => {
+                while data.len() >= 4 {
+                    let program = data.read_u16()?;
+                    let (_, pmt_pid) = data.read_u16p2::<3, 13>()?;
+
+                    // program 0 points to the network information table
+                    if program == 0 {
+                        continue;
+                    }
+
+                    if self.pmt_pid != Some(pmt_pid) {
+                        if let Some(old) = self.pmt_pid {
+                            self.sections.remove(&old);
+                        }
+
+                        self.pmt_pid = Some(pmt_pid);
+                        self.pmt_version = None;
+                    }
+
+                    break;
+                }
+            }
+
+            TABLE_ID_PMT if Some(pid) == self.pmt_pid => {
+                if self.pmt_version == Some(version) {
+                    return Ok(());
+                }
+
+                let (_, _pcr_pid) = data.read_u16p2::<3, 13>()?;
+                let (_, info_len) = data.read_u16p2::<4, 12>()?;
+                data.advance((info_len as usize).min(data.len()));
+
+                let mut streams = Vec::new();
+
+                while data.len() >= 5 {
+                    let stream_type = data.read_u8()?;
+                    let (_, es_pid) = data.read_u16p2::<3, 13>()?;
+                    let (_, es_info_len) = data.read_u16p2::<4, 12>()?;
+
+                    if es_info_len as usize > data.len() {
+                        return Err(Error::InvalidData("PMT ES info length"));
+                    }
+
+                    let descriptors = data.split_to(es_info_len as usize);
+                    let Some(codec) = stream_codec(stream_type, &descriptors) else {
+                        continue;
+                    };
+
+                    let track = streams.len() as u32;
+
+                    // keep the state of the streams which survived the PMT update
+                    let stream = match self
+                        .streams
+                        .iter()
+                        .position(|s| s.pid == es_pid && s.codec == codec)
+                    {
+                        Some(idx) => Stream {
+                            track,
+                            ..self.streams.swap_remove(idx)
+                        },
+                        None => Stream::new(es_pid, codec, track),
+                    };
+
+                    streams.push(stream);
+                }
+
+                self.streams = streams;
+                self.pmt_version = Some(version);
+            }
+
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn finish_pes(&mut self, idx: usize, out: &mut Vec<Result<Packet<S>, Error>>) {
+        let Some(pes) = self.streams[idx].pes.take() else {
+            return;
+        };
+
+        if let Err(err) = self.parse_pes(idx, pes.freeze(), out) {
+            out.push(Err(err));
+        }
+    }
+
+    fn parse_pes(
+        &mut self,
+        idx: usize,
+        mut data: Bytes,
+        out: &mut Vec<Result<Packet<S>, Error>>,
+    ) -> Result<(), Error> {
+        if data.read_u24()? != 0x000001 {
+            return Err(Error::InvalidData("PES start code"));
+        }
+
+        let stream_id = data.read_u8()?;
+        let len = data.read_u16()? as usize;
+
+        if len > 0 {
+            if len > data.len() {
+                return Err(Error::InvalidData("PES packet length"));
+            }
+
+            data.truncate(len);
+        }
+
+        // padding_stream and private_stream_2 have no PES header
+        if matches!(stream_id, 0xBE | 0xBF) {
+            return Ok(());
+        }
+
+        let _flags = data.read_u8()?;
+        let (pts_dts, _) = data.read_u8p2::<2, 6>()?;
+        let header_len = data.read_u8()? as usize;
+
+        if header_len > data.len() {
+            return Err(Error::InvalidData("PES header length"));
+        }
+
+        let mut header = data.split_to(header_len);
+        let (last_dts, last_pts) = self.streams[idx]
+            .timestamps
+            .unwrap_or((self.clock, self.clock));
+
+        let (pts, dts) = match pts_dts {
+            0b10 => {
+                let pts = read_timestamp(&mut header)?;
+                (pts, pts)
+            }
+            0b11 => (read_timestamp(&mut header)?, read_timestamp(&mut header)?),
+
+            // continuation of the previous access unit
+            _ => (last_pts & TIMESTAMP_MASK, last_dts & TIMESTAMP_MASK),
+        };
+
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let dts = unwrap_timestamp(last_dts, dts);
+        let pts = unwrap_timestamp(dts, pts);
+        self.clock = dts;
+        self.streams[idx].timestamps = Some((dts, pts));
+
+        let base = *self.base.get_or_insert(dts);
+        let dts_us = (dts.saturating_sub(base) * 100) / 9;
+        let pts_us = ((pts as i64 - base as i64) * 100) / 9;
+
+        if self.streams[idx].codec == Fourcc::AUDIO_AAC {
+            return self.split_adts(idx, (dts, pts), data, out);
+        }
+
+        let stream = &mut self.streams[idx];
+        let mut flags = FrameFlags::MULTICHANNEL;
+        let mut params = Vec::new();
+
+        if stream.is_video() {
+            flags |= FrameFlags::VIDEO_STREAM;
+
+            let mut keyframe = stream.random_access;
+
+            if stream.codec != Fourcc::VIDEO_AV1 {
+                flags |= FrameFlags::ANNEXB;
+
+                for nal in AnnexBIter::new(&data) {
+                    match stream.codec {
+                        Fourcc::VIDEO_AVC => match h264::nal_type(nal) {
+                            Some(h264::NAL_IDR) => keyframe = true,
+                            Some(h264::NAL_SPS | h264::NAL_PPS) => params.push(data.slice_ref(nal)),
+                            _ => (),
+                        },
+                        _ => match h265::nal_type(nal) {
+                            Some(x) if h265::is_irap(x) => keyframe = true,
+                            Some(h265::NAL_VPS | h265::NAL_SPS | h265::NAL_PPS) => {
+                                params.push(data.slice_ref(nal))
+                            }
+                            _ => (),
+                        },
+                    }
+                }
+            }
+
+            if !params.is_empty() && params != stream.params {
+                if let Ok(info) = VideoInfo::from_params(stream.codec, &params) {
+                    stream.dimensions = info.dimensions();
+                    stream.bit_depth = info.bit_depth;
+                }
+
+                stream.params = params;
+            }
+
+            if keyframe {
+                flags |= FrameFlags::KEYFRAME;
+            }
+        } else {
+            flags |= FrameFlags::AUDIO_STREAM | FrameFlags::KEYFRAME;
+        }
+
+        let mut packet = Packet::new(self.source.clone(), stream.codec, flags, data)
+            .with_timestamps(dts_us, pts_us)
+            .with_track(stream.track);
+
+        if flags.contains(FrameFlags::KEYFRAME) && !stream.params.is_empty() {
+            packet = packet.with_params(stream.params.clone());
+        }
+
+        packet.dimensions = stream.dimensions;
+        packet.bit_depth = stream.bit_depth;
+
+        out.push(Ok(packet));
+
+        Ok(())
+    }
+
+    /// Yields the ADTS frames of the AAC PES as separate access units
+    fn split_adts(
+        &mut self,
+        idx: usize,
+        (mut dts, mut pts): (u64, u64),
+        mut data: Bytes,
+        out: &mut Vec<Result<Packet<S>, Error>>,
+    ) -> Result<(), Error> {
+        let base = self.base.unwrap_or(dts);
+
+        while !data.is_empty() {
+            let header = AdtsHeader::parse(&data)?;
+            if header.frame_length as usize > data.len() {
+                return Err(Error::InvalidData("ts: truncated ADTS frame"));
+            }
+
+            let frame = data.split_to(header.frame_length as usize);
+
+            if header.raw_blocks > 1 {
+                return Err(Error::Unsupported("adts: multiple raw data blocks"));
+            }
+
+            if header
+                .crc
+                .is_some_and(|crc| crc != AdtsHeader::compute_crc(&frame))
+            {
+                return Err(Error::InvalidData("adts: CRC mismatch"));
+            }
+
+            let stream = &mut self.streams[idx];
+            let asc = header.to_asc().to_bytes();
+            if stream.params.first() != Some(&asc) {
+                stream.params = vec![asc];
+            }
+
+            let rate = header.sample_rate() as u64;
+            let duration = SAMPLES_PER_FRAME as u64 * 90_000 / rate;
+
+            let mut packet = Packet::new(
+                self.source.clone(),
+                Fourcc::AUDIO_AAC,
+                FrameFlags::MULTICHANNEL | FrameFlags::AUDIO_STREAM | FrameFlags::KEYFRAME,
+                frame.slice(header.size()..),
+            )
+            .with_timestamps(
+                (dts.saturating_sub(base) * 100) / 9,
+                ((pts as i64 - base as i64) * 100) / 9,
+            )
+            .with_track(stream.track)
+            .with_params(stream.params.clone());
+
+            packet.duration = SAMPLES_PER_FRAME as u64 * 1_000_000 / rate;
+            out.push(Ok(packet));
+
+            dts += duration;
+            pts += duration;
+            stream.timestamps = Some((dts, pts));
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self, source: S) {
+        *self = Self::new();
+        self.source = source;
+    }
+}
+
+impl<S: FrameSource> Default for TsDemuxer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F> Service<F> for TsDemuxer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<Packet<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let mut out = Vec::new();
+
+        if frame.source() != &self.source {
+            out.extend(self.flush());
+            self.reset(frame.source().clone());
+        }
+
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use flowly_codec::aac::{AdtsHeader, AudioSpecificConfig};
+    use flowly_core::{EncodedFrame, Fourcc, Frame, Multichannel, VideoFrame};
+
+    use super::TsDemuxer;
+    use crate::{
+        error::Error,
+        test_fixtures::{PPS, SPS},
+        ts::*,
+    };
+
+    const ASC: &[u8] = &[0x11, 0x90];
+
+    fn packets(pid: u16, cc: &mut u8, mut payload: &[u8], out: &mut BytesMut) {
+        let mut start = true;
+
+        while !payload.is_empty() {
+            let len = payload.len().min(PACKET_SIZE - 4);
+            let stuffing = PACKET_SIZE - 4 - len;
+
+            TsHeader {
+                payload_unit_start: start,
+                pid,
+                has_adaptation: stuffing > 0,
+                has_payload: true,
+                continuity_counter: *cc,
+                ..Default::default()
+            }
+            .write(out);
+
+            if stuffing > 0 {
+                out.put_u8(stuffing as u8 - 1);
+                if stuffing > 1 {
+                    out.put_u8(0);
+                    out.put_bytes(0xFF, stuffing - 2);
+                }
+            }
+
+            out.put_slice(&payload[..len]);
+            payload = &payload[len..];
+            start = false;
+            *cc = (*cc + 1) & 0x0F;
+        }
+    }
+
+    fn section(table_id: u8, id: u16, body: &[u8]) -> Vec<u8> {
+        let mut data = vec![0];
+        data.put_u8(table_id);
+        data.put_u16(0xB000 | (body.len() as u16 + 9));
+        data.put_u16(id);
+        data.put_u8(0xC1);
+        data.put_u16(0);
+        data.put_slice(body);
+        let crc = crc32(&data[1..]);
+        data.put_u32(crc);
+        data
+    }
+
+    fn pes(stream_id: u8, bounded: bool, pts: u64, dts: Option<u64>, payload: &[u8]) -> Vec<u8> {
+        let header_len = if dts.is_some() { 10 } else { 5 };
+        let mut data = vec![0, 0, 1, stream_id];
+        data.put_u16(if bounded {
+            (3 + header_len + payload.len()) as u16
+        } else {
+            0
+        });
+        data.put_u8(0x80);
+        data.put_u8(if dts.is_some() { 0xC0 } else { 0x80 });
+        data.put_u8(header_len as u8);
+        put_timestamp(&mut data, if dts.is_some() { 3 } else { 2 }, pts);
+        if let Some(dts) = dts {
+            put_timestamp(&mut data, 1, dts);
+        }
+        data.put_slice(payload);
+        data
+    }
+
+    #[test]
+    fn test_ts_demux() {
+        let mut ts = BytesMut::new();
+        let (mut cc_pat, mut cc_pmt, mut cc_video, mut cc_audio) = (0, 0, 0, 0);
+
+        // garbage before the first packet
+        ts.put_slice(&[0x47, 0x00, 0x12]);
+
+        packets(
+            PID_PAT,
+            &mut cc_pat,
+            &section(TABLE_ID_PAT, 1, &[0, 1, 0xF0, 0x00]),
+            &mut ts,
+        );
+        let pmt = [
+            0xE1,
+            0x00,
+            0xF0,
+            0x00, //
+            STREAM_TYPE_AVC,
+            0xE1,
+            0x00,
+            0xF0,
+            0x00, //
+            STREAM_TYPE_AAC_ADTS,
+            0xE1,
+            0x01,
+            0xF0,
+            0x00,
+        ];
+        packets(
+            0x1000,
+            &mut cc_pmt,
+            &section(TABLE_ID_PMT, 1, &pmt),
+            &mut ts,
+        );
+
+        // keyframe spanning several TS packets, the 33-bit clock wraps around
+        let start = TIMESTAMP_MASK - 90_000 * 2 / 100;
+        let mut idr = Vec::new();
+        for nal in [SPS, PPS, &[0x65; 400][..]] {
+            idr.extend_from_slice(&[0, 0, 0, 1]);
+            idr.extend_from_slice(nal);
+        }
+
+        packets(
+            0x100,
+            &mut cc_video,
+            &pes(0xE0, false, start + 3600, Some(start), &idr),
+            &mut ts,
+        );
+        // two ADTS frames in the single PES
+        let asc = AudioSpecificConfig::parse(ASC).unwrap();
+        let mut adts = BytesMut::new();
+        for payload in [&[1, 2, 3][..], &[4, 5]] {
+            AdtsHeader::from_asc(&asc, payload.len())
+                .unwrap()
+                .write(&mut adts);
+            adts.put_slice(payload);
+        }
+
+        packets(
+            0x101,
+            &mut cc_audio,
+            &pes(0xC0, true, start, None, &adts),
+            &mut ts,
+        );
+
+        // the second frame gets lost in the middle
+        let mut lost = BytesMut::new();
+        let p_frame = [&[0, 0, 0, 1, 0x41][..], &[0x9A; 300]].concat();
+        packets(
+            0x100,
+            &mut cc_video,
+            &pes(0xE0, false, start + 7200, Some(start + 3600), &p_frame),
+            &mut lost,
+        );
+        ts.put_slice(&lost[..PACKET_SIZE]);
+
+        packets(
+            0x100,
+            &mut cc_video,
+            &pes(0xE0, false, start + 10800, Some(start + 7200), &p_frame),
+            &mut ts,
+        );
+
+        let mut demuxer = TsDemuxer::<()>::new();
+        let mut out = Vec::new();
+        for chunk in ts.freeze().chunks(7) {
+            out.extend(demuxer.push(Bytes::copy_from_slice(chunk)));
+        }
+        out.extend(demuxer.flush());
+
+        let out: Vec<_> = out.into_iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            demuxer.tracks().collect::<Vec<_>>(),
+            [Fourcc::VIDEO_AVC, Fourcc::AUDIO_AAC]
+        );
+        assert_eq!(out.len(), 4);
+
+        // bounded audio PES is emitted before the video one waiting for the next start
+        let audio = &out[0];
+        assert_eq!(audio.codec(), Fourcc::AUDIO_AAC);
+        assert_eq!(audio.track(), 1);
+        assert!(audio.is_audio() && audio.is_multichannel());
+        assert_eq!((audio.dts(), audio.pts()), (0, 0));
+        assert_eq!(audio.params, [ASC]);
+        assert_eq!(&audio.data[..], &[1, 2, 3]);
+
+        let audio = &out[1];
+        assert_eq!((audio.dts(), audio.duration), (21_333, 21_333));
+        assert_eq!(audio.params, [ASC]);
+        assert_eq!(&audio.data[..], &[4, 5]);
+
+        let video = &out[2];
+        assert_eq!(video.codec(), Fourcc::VIDEO_AVC);
+        assert_eq!(video.track(), 0);
+        assert!(video.is_keyframe() && video.is_multichannel());
+        assert_eq!((video.dts(), video.pts()), (0, 40_000));
+        assert_eq!(video.params().collect::<Vec<_>>(), [SPS, PPS]);
+        assert_eq!(video.dimensions(), (1920, 1080));
+        assert_eq!(video.data, Bytes::from(idr));
+
+        // the damaged frame is dropped, the next one is wrapped around
+        let video = &out[3];
+        assert!(!video.is_keyframe());
+        assert_eq!((video.dts(), video.pts()), (80_000, 120_000));
+        assert_eq!(video.data, Bytes::from(p_frame));
+    }
+
+    #[test]
+    fn test_ts_demux_malformed() {
+        let mut ts = BytesMut::new();
+        let (mut cc_pat, mut cc_pmt, mut cc_video) = (0, 0, 0);
+
+        let mut pat = section(TABLE_ID_PAT, 1, &[0, 1, 0xF0, 0x00]);
+        *pat.last_mut().unwrap() ^= 1;
+        packets(PID_PAT, &mut cc_pat, &pat, &mut ts);
+
+        // null packet confirming the sync
+        packets(0x1FFF, &mut 0, &[0xFF; PACKET_SIZE - 4], &mut ts);
+
+        let res = TsDemuxer::<()>::new().push(ts.split().freeze());
+        assert!(matches!(
+            res[..],
+            [Err(Error::InvalidData("PSI section CRC mismatch"))]
+        ));
+
+        // PES without the start code
+        let pmt = [
+            0xE1,
+            0x00,
+            0xF0,
+            0x00,
+            STREAM_TYPE_AVC,
+            0xE1,
+            0x00,
+            0xF0,
+            0x00,
+        ];
+        packets(
+            PID_PAT,
+            &mut cc_pat,
+            &section(TABLE_ID_PAT, 1, &[0, 1, 0xF0, 0x00]),
+            &mut ts,
+        );
+        packets(
+            0x1000,
+            &mut cc_pmt,
+            &section(TABLE_ID_PMT, 1, &pmt),
+            &mut ts,
+        );
+        packets(0x100, &mut cc_video, &[0, 0, 2, 0xE0, 0, 0], &mut ts);
+
+        let mut demuxer = TsDemuxer::<()>::new();
+        let mut res = demuxer.push(ts.freeze());
+        res.extend(demuxer.flush());
+        assert!(matches!(
+            res[..],
+            [Err(Error::InvalidData("PES start code"))]
+        ));
+    }
+}
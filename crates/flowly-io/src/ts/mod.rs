@@ -0,0 +1,187 @@
+mod demuxer;
+mod muxer;
+
+pub use demuxer::TsDemuxer;
+pub use muxer::TsMuxer;
+
+use bytes::{Buf, BufMut};
+use flowly_core::{Fourcc, Reader, ReaderExt, WriterExt};
+
+use crate::error::Error;
+
+pub const PACKET_SIZE: usize = 188;
+pub const SYNC_BYTE: u8 = 0x47;
+
+pub const PID_PAT: u16 = 0x0000;
+pub const PID_NULL: u16 = 0x1FFF;
+
+/// PIDs assigned by the muxer
+pub const PID_PMT: u16 = 0x1000;
+pub const PID_FIRST_STREAM: u16 = 0x0100;
+
+pub const STREAM_ID_PRIVATE_1: u8 = 0xBD;
+pub const STREAM_ID_AUDIO: u8 = 0xC0;
+pub const STREAM_ID_VIDEO: u8 = 0xE0;
+
+pub const TABLE_ID_PAT: u8 = 0x00;
+pub const TABLE_ID_PMT: u8 = 0x02;
+
+pub const STREAM_TYPE_MP3: u8 = 0x03;
+pub const STREAM_TYPE_MPEG2_AUDIO: u8 = 0x04;
+pub const STREAM_TYPE_PRIVATE: u8 = 0x06;
+pub const STREAM_TYPE_AAC_ADTS: u8 = 0x0F;
+pub const STREAM_TYPE_AVC: u8 = 0x1B;
+pub const STREAM_TYPE_HEVC: u8 = 0x24;
+pub const STREAM_TYPE_AC3: u8 = 0x81;
+pub const STREAM_TYPE_EAC3: u8 = 0x87;
+
+pub const DESCRIPTOR_REGISTRATION: u8 = 0x05;
+pub const DESCRIPTOR_AC3: u8 = 0x6A;
+pub const DESCRIPTOR_EAC3: u8 = 0x7A;
+pub const DESCRIPTOR_EXTENSION: u8 = 0x7F;
+
+/// 90 kHz clock of PTS/DTS
+pub const CLOCK_RATE: u64 = 90_000;
+
+/// PTS/DTS wrap around at 33 bits
+pub const TIMESTAMP_MASK: u64 = (1 << 33) - 1;
+
+/// TS packet header (ISO/IEC 13818-1 2.4.3.2)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TsHeader {
+    pub transport_error: bool,
+    pub payload_unit_start: bool,
+    pub pid: u16,
+    pub scrambling: u8,
+    pub has_adaptation: bool,
+    pub has_payload: bool,
+    pub continuity_counter: u8,
+}
+
+impl TsHeader {
+    /// Reads the header following the sync byte
+    pub fn read<B: Buf>(buf: &mut B) -> Result<Self, Error> {
+        let (transport_error, payload_unit_start, _priority, pid) =
+            buf.read_u16p4::<1, 1, 1, 13>()?;
+        let (scrambling, has_adaptation, has_payload, continuity_counter) =
+            buf.read_u8p4::<2, 1, 1, 4>()?;
+
+        Ok(Self {
+            transport_error: transport_error != 0,
+            payload_unit_start: payload_unit_start != 0,
+            pid,
+            scrambling,
+            has_adaptation: has_adaptation != 0,
+            has_payload: has_payload != 0,
+            continuity_counter,
+        })
+    }
+
+    /// Writes the header including the sync byte
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        buf.put_u8(SYNC_BYTE);
+        buf.put_u16p4::<1, 1, 1, 13>(
+            self.transport_error as u16,
+            self.payload_unit_start as u16,
+            0,
+            self.pid,
+        );
+        buf.put_u8p4::<2, 1, 1, 4>(
+            self.scrambling,
+            self.has_adaptation as u8,
+            self.has_payload as u8,
+            self.continuity_counter,
+        );
+    }
+}
+
+/// Reads 33-bit PTS/DTS of the PES header
+pub fn read_timestamp<B: Buf>(buf: &mut B) -> Result<u64, Error> {
+    let hi = buf.read_u8()? as u64;
+    let mid = buf.read_u16()? as u64;
+    let lo = buf.read_u16()? as u64;
+
+    Ok(((hi >> 1) & 0x07) << 30 | (mid >> 1) << 15 | lo >> 1)
+}
+
+/// Writes 33-bit PTS/DTS with the 4-bit `prefix` and marker bits
+pub fn put_timestamp<B: BufMut>(buf: &mut B, prefix: u8, ts: u64) {
+    let ts = ts & TIMESTAMP_MASK;
+
+    buf.put_u8(prefix << 4 | ((ts >> 30) as u8 & 0x07) << 1 | 1);
+    buf.put_u16(((ts >> 15) as u16 & 0x7FFF) << 1 | 1);
+    buf.put_u16((ts as u16 & 0x7FFF) << 1 | 1);
+}
+
+/// Extends the wrapped 33-bit timestamp to be the closest one to `last`
+pub fn unwrap_timestamp(last: u64, ts: u64) -> u64 {
+    const PERIOD: u64 = TIMESTAMP_MASK + 1;
+
+    let base = last & !TIMESTAMP_MASK;
+    let candidates = [
+        base.checked_sub(PERIOD).map(|x| x + ts),
+        Some(base + ts),
+        Some(base + PERIOD + ts),
+    ];
+
+    candidates
+        .into_iter()
+        .flatten()
+        .min_by_key(|x| x.abs_diff(last))
+        .unwrap_or(ts)
+}
+
+/// CRC32/MPEG-2 of the PSI sections
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// Codec of the elementary stream from PMT `stream_type` and ES descriptors
+pub fn stream_codec(stream_type: u8, mut descriptors: &[u8]) -> Option<Fourcc> {
+    match stream_type {
+        STREAM_TYPE_AVC => return Some(Fourcc::VIDEO_AVC),
+        STREAM_TYPE_HEVC => return Some(Fourcc::VIDEO_HEVC),
+        STREAM_TYPE_AAC_ADTS => return Some(Fourcc::AUDIO_AAC),
+        STREAM_TYPE_MP3 | STREAM_TYPE_MPEG2_AUDIO => return Some(Fourcc::AUDIO_MP3),
+        STREAM_TYPE_AC3 => return Some(Fourcc::AUDIO_AC3),
+        STREAM_TYPE_EAC3 => return Some(Fourcc::AUDIO_EC3),
+        STREAM_TYPE_PRIVATE => (),
+        _ => return None,
+    }
+
+    while descriptors.len() >= 2 {
+        let tag = descriptors[0];
+        let len = (descriptors[1] as usize).min(descriptors.len() - 2);
+        let body = &descriptors[2..2 + len];
+
+        match (tag, body) {
+            (DESCRIPTOR_REGISTRATION, [b'O', b'p', b'u', b's', ..]) => {
+                return Some(Fourcc::AUDIO_OPUS);
+            }
+            (DESCRIPTOR_REGISTRATION, [b'A', b'V', b'0', b'1', ..]) => {
+                return Some(Fourcc::VIDEO_AV1);
+            }
+            (DESCRIPTOR_AC3, _) => return Some(Fourcc::AUDIO_AC3),
+            (DESCRIPTOR_EAC3, _) => return Some(Fourcc::AUDIO_EC3),
+            _ => (),
+        }
+
+        descriptors = &descriptors[2 + len..];
+    }
+
+    None
+}
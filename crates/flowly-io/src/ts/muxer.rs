@@ -0,0 +1,551 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly_codec::{
+    BitstreamConverter,
+    aac::{AdtsHeader, AudioSpecificConfig},
+    h264, h265,
+    nal::AnnexBIter,
+};
+use flowly_core::{EncodedFrame, Fourcc, Frame, Multichannel, Packet, VideoFrame};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+/// Distance between PCR and DTS in 90 kHz ticks (700 ms of decoder buffering),
+/// the first DTS starts from it so PCR starts from zero
+const PCR_DELAY: u64 = 63_000;
+
+const AUD_H264: &[u8] = &[0x09, 0xF0];
+const AUD_H265: &[u8] = &[0x46, 0x01, 0x50];
+
+/// Elementary stream listed in PMT
+#[derive(Debug, Clone)]
+struct Stream {
+    codec: Fourcc,
+    pid: u16,
+    stream_id: u8,
+    stream_type: u8,
+    descriptors: Vec<u8>,
+    continuity_counter: u8,
+}
+
+impl Stream {
+    #[inline]
+    fn is_video(&self) -> bool {
+        self.stream_id & 0xF0 == STREAM_ID_VIDEO
+    }
+}
+
+/// MPEG-TS muxer.
+///
+/// Accepts frames of any [`EncodedFrame`] stream (H.264/H.265, AAC, Opus and MP3)
+/// and yields a chunk of 188-byte TS packets per frame, ready to be written to
+/// a file, an HLS segment or a UDP socket. Elementary streams are keyed by codec
+/// and get PIDs in the order of appearance (pre-declare them with
+/// [`TsMuxer::with_stream`] to have the complete PMT from the start).
+///
+/// PAT/PMT are written in front of every video keyframe and at least every
+/// `psi_interval`. PCR goes with every PES of the first video stream (or of the
+/// first stream when there is no video). H.264/H.265 are converted to AnnexB with
+/// access unit delimiters, raw AAC gets ADTS headers built from `params()`.
+/// Timestamps are shifted so the first frame starts at zero.
+#[derive(Debug, Clone)]
+pub struct TsMuxer {
+    streams: Vec<Stream>,
+    pmt_version: u8,
+    pat_cc: u8,
+    pmt_cc: u8,
+    psi_interval: u64,
+    psi_dts: Option<u64>,
+    psi_changed: bool,
+    start_dts: Option<u64>,
+    converter: BitstreamConverter,
+}
+
+impl TsMuxer {
+    pub fn new() -> Self {
+        Self {
+            streams: Vec::new(),
+            pmt_version: 0,
+            pat_cc: 0,
+            pmt_cc: 0,
+            psi_interval: 100_000,
+            psi_dts: None,
+            psi_changed: true,
+            start_dts: None,
+            converter: BitstreamConverter::annexb(),
+        }
+    }
+
+    /// Declares the elementary stream before the first frame, unsupported codecs are ignored
+    pub fn with_stream(mut self, codec: Fourcc) -> Self {
+        let _ = self.add_stream(codec, None);
+        self
+    }
+
+    /// Sets max interval between PAT/PMT in microseconds (100 ms by default)
+    pub fn with_psi_interval(mut self, interval: u64) -> Self {
+        self.psi_interval = interval;
+        self
+    }
+
+    /// Encodes the frame into TS packets (prefixed with PAT/PMT when they are due)
+    pub fn push<F: EncodedFrame + Multichannel + VideoFrame>(
+        &mut self,
+        frame: F,
+    ) -> Result<Bytes, Error> {
+        let codec = frame.codec();
+
+        let idx = match self.streams.iter().position(|s| s.codec == codec) {
+            Some(idx) => idx,
+            None => self.add_stream(codec, frame.params().next().map(AsRef::as_ref))?,
+        };
+
+        let packet = match codec {
+            Fourcc::VIDEO_AVC | Fourcc::VIDEO_HEVC => {
+                let mut packet = self.converter.convert(frame)?;
+                packet.data = with_aud(codec, packet.data);
+                packet
+            }
+            _ => Packet::from_frame(frame),
+        };
+
+        let payload = match codec {
+            Fourcc::AUDIO_AAC if !is_adts(&packet.data) => {
+                let asc = packet
+                    .params
+                    .first()
+                    .ok_or(flowly_codec::Error::MissingParams(
+                        "aac: AudioSpecificConfig",
+                    ))?;
+
+                let mut buf = BytesMut::with_capacity(7 + packet.data.len());
+                put_adts_header(&mut buf, asc, packet.data.len())?;
+                buf.put_slice(&packet.data);
+                buf.freeze()
+            }
+
+            Fourcc::AUDIO_OPUS => {
+                let mut buf = BytesMut::with_capacity(packet.data.len() + 8);
+                put_opus_control_header(&mut buf, packet.data.len());
+                buf.put_slice(&packet.data);
+                buf.freeze()
+            }
+
+            _ => packet.data.clone(),
+        };
+
+        let start = *self.start_dts.get_or_insert(packet.dts);
+        let dts = packet.dts.saturating_sub(start) * 9 / 100 + PCR_DELAY;
+        let pts = ((packet.pts - start as i64) * 9 / 100 + PCR_DELAY as i64).max(0) as u64;
+
+        let stream = &self.streams[idx];
+        let is_pcr = stream.pid == self.pcr_pid();
+        let keyframe = stream.is_video() && packet.is_keyframe();
+
+        let mut buf = BytesMut::with_capacity((payload.len() / 184 + 4) * PACKET_SIZE);
+
+        if self.psi_changed
+            || keyframe
+            || self
+                .psi_dts
+                .is_none_or(|x| packet.dts.abs_diff(x) >= self.psi_interval)
+        {
+            self.put_psi(&mut buf);
+            self.psi_dts = Some(packet.dts);
+        }
+
+        let mut pes = BytesMut::with_capacity(19 + payload.len());
+        let stream = &self.streams[idx];
+        put_pes_header(&mut pes, stream, pts, dts, payload.len());
+        pes.put_slice(&payload);
+
+        let pcr = is_pcr.then(|| dts - PCR_DELAY);
+        self.put_packets(&mut buf, idx, &pes, pcr, keyframe);
+
+        Ok(buf.freeze())
+    }
+
+    fn add_stream(&mut self, codec: Fourcc, config: Option<&[u8]>) -> Result<usize, Error> {
+        let videos = self.streams.iter().filter(|s| s.is_video()).count() as u8;
+        let audios = self.streams.len() as u8 - videos;
+
+        let (stream_type, stream_id, descriptors) = match codec {
+            Fourcc::VIDEO_AVC => (STREAM_TYPE_AVC, STREAM_ID_VIDEO + videos, vec![]),
+            Fourcc::VIDEO_HEVC => (STREAM_TYPE_HEVC, STREAM_ID_VIDEO + videos, vec![]),
+            Fourcc::AUDIO_AAC => (STREAM_TYPE_AAC_ADTS, STREAM_ID_AUDIO + audios, vec![]),
+            Fourcc::AUDIO_MP3 => (STREAM_TYPE_MP3, STREAM_ID_AUDIO + audios, vec![]),
+            Fourcc::AUDIO_OPUS => {
+                // OpusHead carries the channel count at offset 9
+                let channels = config
+                    .filter(|x| x.starts_with(b"OpusHead"))
+                    .and_then(|x| x.get(9).copied())
+                    .unwrap_or(2);
+
+                let mut descriptors = vec![DESCRIPTOR_REGISTRATION, 4];
+                descriptors.extend_from_slice(b"Opus");
+                descriptors.extend_from_slice(&[DESCRIPTOR_EXTENSION, 2, 0x80, channels]);
+
+                (STREAM_TYPE_PRIVATE, STREAM_ID_PRIVATE_1, descriptors)
+            }
+            codec => return Err(flowly_codec::Error::UnsupportedCodec(codec).into()),
+        };
+
+        self.streams.push(Stream {
+            codec,
+            pid: PID_FIRST_STREAM + self.streams.len() as u16,
+            stream_id,
+            stream_type,
+            descriptors,
+            continuity_counter: 0,
+        });
+
+        if !self.psi_changed {
+            self.pmt_version = (self.pmt_version + 1) & 0x1F;
+        }
+
+        self.psi_changed = true;
+
+        Ok(self.streams.len() - 1)
+    }
+
+    fn pcr_pid(&self) -> u16 {
+        self.streams
+            .iter()
+            .find(|s| s.is_video())
+            .or(self.streams.first())
+            .map(|s| s.pid)
+            .unwrap_or(PID_NULL)
+    }
+
+    fn put_psi(&mut self, buf: &mut BytesMut) {
+        let mut pat = Vec::with_capacity(4);
+        pat.put_u16(1);
+        pat.put_u16(0xE000 | PID_PMT);
+
+        put_section(buf, PID_PAT, &mut self.pat_cc, TABLE_ID_PAT, 1, 0, &pat);
+
+        let mut pmt = Vec::with_capacity(4 + self.streams.len() * 5);
+        pmt.put_u16(0xE000 | self.pcr_pid());
+        pmt.put_u16(0xF000);
+
+        for stream in &self.streams {
+            pmt.put_u8(stream.stream_type);
+            pmt.put_u16(0xE000 | stream.pid);
+            pmt.put_u16(0xF000 | stream.descriptors.len() as u16);
+            pmt.put_slice(&stream.descriptors);
+        }
+
+        let version = self.pmt_version;
+        put_section(
+            buf,
+            PID_PMT,
+            &mut self.pmt_cc,
+            TABLE_ID_PMT,
+            1,
+            version,
+            &pmt,
+        );
+
+        self.psi_changed = false;
+    }
+
+    /// Splits PES into TS packets, the last one is padded with adaptation field stuffing
+    fn put_packets(
+        &mut self,
+        buf: &mut BytesMut,
+        idx: usize,
+        mut data: &[u8],
+        pcr: Option<u64>,
+        random_access: bool,
+    ) {
+        let stream = &mut self.streams[idx];
+        let mut first = true;
+
+        while !data.is_empty() {
+            let mut adaptation = Vec::new();
+
+            if first && (pcr.is_some() || random_access) {
+                adaptation.put_u8((random_access as u8) << 6 | (pcr.is_some() as u8) << 4);
+
+                if let Some(pcr) = pcr {
+                    put_pcr(&mut adaptation, pcr);
+                }
+            }
+
+            let room = PACKET_SIZE
+                - 4
+                - if adaptation.is_empty() {
+                    0
+                } else {
+                    adaptation.len() + 1
+                };
+            let len = room.min(data.len());
+            let free = PACKET_SIZE - 4 - len;
+
+            if free > 0 {
+                if adaptation.is_empty() && free > 1 {
+                    adaptation.put_u8(0);
+                }
+
+                adaptation.resize(free - 1, 0xFF);
+            }
+
+            TsHeader {
+                payload_unit_start: first,
+                pid: stream.pid,
+                has_adaptation: free > 0,
+                has_payload: true,
+                continuity_counter: stream.continuity_counter,
+                ..Default::default()
+            }
+            .write(buf);
+
+            if free > 0 {
+                buf.put_u8(adaptation.len() as u8);
+                buf.put_slice(&adaptation);
+            }
+
+            buf.put_slice(&data[..len]);
+
+            data = &data[len..];
+            first = false;
+            stream.continuity_counter = (stream.continuity_counter + 1) & 0x0F;
+        }
+    }
+}
+
+impl Default for TsMuxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Single packet PSI section with CRC and 0xFF stuffing
+fn put_section(
+    buf: &mut BytesMut,
+    pid: u16,
+    cc: &mut u8,
+    table_id: u8,
+    id: u16,
+    version: u8,
+    body: &[u8],
+) {
+    TsHeader {
+        payload_unit_start: true,
+        pid,
+        has_payload: true,
+        continuity_counter: *cc,
+        ..Default::default()
+    }
+    .write(buf);
+
+    *cc = (*cc + 1) & 0x0F;
+
+    let mut section = Vec::with_capacity(12 + body.len());
+    section.put_u8(table_id);
+    section.put_u16(0xB000 | (body.len() as u16 + 9));
+    section.put_u16(id);
+    section.put_u8(0xC1 | (version & 0x1F) << 1);
+    section.put_u8(0);
+    section.put_u8(0);
+    section.put_slice(body);
+    section.put_u32(crc32(&section));
+
+    // pointer field
+    buf.put_u8(0);
+    buf.put_slice(&section);
+    buf.put_bytes(0xFF, PACKET_SIZE - 5 - section.len());
+}
+
+fn put_pes_header(buf: &mut BytesMut, stream: &Stream, pts: u64, dts: u64, len: usize) {
+    let has_dts = pts != dts;
+    let header_len = if has_dts { 10 } else { 5 };
+
+    // unbounded PES packet length is allowed for video only
+    let pes_len = 3 + header_len + len;
+    let pes_len = if pes_len > u16::MAX as usize && stream.is_video() {
+        0
+    } else {
+        pes_len.min(u16::MAX as usize) as u16
+    };
+
+    buf.put_slice(&[0, 0, 1, stream.stream_id]);
+    buf.put_u16(pes_len);
+
+    // marker bits, data_alignment_indicator for video
+    buf.put_u8(if stream.is_video() { 0x84 } else { 0x80 });
+    buf.put_u8(if has_dts { 0xC0 } else { 0x80 });
+    buf.put_u8(header_len as u8);
+
+    if has_dts {
+        put_timestamp(buf, 0b0011, pts);
+        put_timestamp(buf, 0b0001, dts);
+    } else {
+        put_timestamp(buf, 0b0010, pts);
+    }
+}
+
+/// 33-bit base and 9-bit extension of the 27 MHz program clock
+fn put_pcr(buf: &mut Vec<u8>, pcr: u64) {
+    let base = pcr & TIMESTAMP_MASK;
+
+    buf.put_u32((base >> 1) as u32);
+    buf.put_u8(((base & 1) as u8) << 7 | 0x7E);
+    buf.put_u8(0);
+}
+
+/// Prepends access unit delimiter unless the frame already starts with it
+fn with_aud(codec: Fourcc, data: Bytes) -> Bytes {
+    let (aud, first) = match codec {
+        Fourcc::VIDEO_AVC => (
+            AUD_H264,
+            AnnexBIter::new(&data).next().and_then(h264::nal_type),
+        ),
+        _ => (
+            AUD_H265,
+            AnnexBIter::new(&data).next().and_then(h265::nal_type),
+        ),
+    };
+
+    if matches!(first, Some(h264::NAL_AUD) if codec == Fourcc::VIDEO_AVC)
+        || matches!(first, Some(h265::NAL_AUD) if codec == Fourcc::VIDEO_HEVC)
+    {
+        return data;
+    }
+
+    let mut buf = BytesMut::with_capacity(data.len() + 8);
+    flowly_codec::nal::put_annexb(&mut buf, aud);
+    buf.put_slice(&data);
+    buf.freeze()
+}
+
+#[inline]
+fn is_adts(data: &[u8]) -> bool {
+    matches!(data, [0xFF, b, ..] if b & 0xF6 == 0xF0)
+}
+
+/// ADTS header of the raw AAC frame described by `AudioSpecificConfig`
+fn put_adts_header(buf: &mut BytesMut, asc: &[u8], len: usize) -> Result<(), Error> {
+    let asc = AudioSpecificConfig::parse(asc)?;
+    AdtsHeader::from_asc(&asc, len)?.write(buf);
+
+    Ok(())
+}
+
+/// `opus_control_header` of the Opus access unit (ETSI TS 102 366 Annex B)
+fn put_opus_control_header(buf: &mut BytesMut, len: usize) {
+    // prefix 0x3FF and no trim or extension flags
+    buf.put_u16(0x7FE0);
+    buf.put_bytes(0xFF, len / 255);
+    buf.put_u8((len % 255) as u8);
+}
+
+impl<F: EncodedFrame + Multichannel + VideoFrame> Service<F> for TsMuxer {
+    type Out = Result<Bytes, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        futures::stream::once(futures::future::ready(self.push(frame)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use flowly_core::{EncodedFrame, Fourcc, Frame, FrameFlags, Multichannel, Packet, VideoFrame};
+
+    use super::TsMuxer;
+    use crate::{
+        test_fixtures::{PPS, SPS},
+        ts::*,
+    };
+
+    const ASC: &[u8] = &[0x12, 0x10];
+
+    #[test]
+    fn test_ts_mux_demux() {
+        let mut muxer = TsMuxer::new()
+            .with_stream(Fourcc::VIDEO_AVC)
+            .with_stream(Fourcc::AUDIO_AAC);
+
+        let mut frames = Vec::new();
+        for i in 0..3u64 {
+            let keyframe = i == 0;
+            let flags = if keyframe {
+                FrameFlags::KEYFRAME | FrameFlags::VIDEO_STREAM
+            } else {
+                FrameFlags::VIDEO_STREAM
+            };
+
+            // length prefixed input, parameter sets go in-band in front of the keyframe
+            let mut data = vec![0, 0, 1, 0];
+            data.push(if keyframe { 0x65 } else { 0x41 });
+            data.resize(4 + 256, i as u8 + 1);
+
+            let video = Packet::new((), Fourcc::VIDEO_AVC, flags, Bytes::from(data))
+                .with_timestamps(
+                    1_000_000 + i * 40_000,
+                    1_000_000 + i as i64 * 40_000 + 80_000,
+                )
+                .with_params(vec![Bytes::from_static(SPS), Bytes::from_static(PPS)]);
+
+            let audio = Packet::new(
+                (),
+                Fourcc::AUDIO_AAC,
+                FrameFlags::KEYFRAME | FrameFlags::AUDIO_STREAM,
+                Bytes::from(vec![0x21; 20]),
+            )
+            .with_timestamps(1_000_000 + i * 40_000, 1_000_000 + i as i64 * 40_000)
+            .with_params(vec![Bytes::from_static(ASC)]);
+
+            frames.push(video);
+            frames.push(audio);
+        }
+
+        let mut ts = Vec::new();
+        for frame in frames {
+            let chunk = muxer.push(frame).unwrap();
+            assert_eq!(chunk.len() % PACKET_SIZE, 0);
+            ts.extend_from_slice(&chunk);
+        }
+
+        // PAT/PMT go first, PCR goes with the video
+        assert_eq!(&ts[..4], &[SYNC_BYTE, 0x40, 0x00, 0x10]);
+        let video = &ts[2 * PACKET_SIZE..3 * PACKET_SIZE];
+        assert_eq!(video[1] & 0x1F, (PID_FIRST_STREAM >> 8) as u8);
+        assert_eq!(video[5] & 0x50, 0x50);
+
+        let mut demuxer = TsDemuxer::<()>::new();
+        let mut out = demuxer.push(Bytes::from(ts));
+        out.extend(demuxer.flush());
+
+        let out: Vec<_> = out.into_iter().collect::<Result<_, _>>().unwrap();
+        let (video, audio): (Vec<_>, Vec<_>) = out.into_iter().partition(|x| x.is_video());
+
+        assert_eq!(video.len(), 3);
+        assert_eq!(audio.len(), 3);
+
+        assert!(video[0].is_keyframe() && video[0].is_multichannel());
+        assert_eq!(video[0].params().collect::<Vec<_>>(), [SPS, PPS]);
+        assert_eq!(video[0].dimensions(), (1920, 1080));
+        assert!(!video[1].is_keyframe());
+
+        for (i, frame) in video.iter().enumerate() {
+            let i = i as u64;
+            assert_eq!(frame.track(), 0);
+            assert_eq!(
+                (frame.dts(), frame.pts()),
+                (i * 40_000, (i * 40_000 + 80_000) as i64)
+            );
+            assert!(frame.data.starts_with(&[0, 0, 0, 1, 0x09, 0xF0]));
+            assert!(frame.data.ends_with(&[i as u8 + 1; 16]));
+        }
+
+        for (i, frame) in audio.iter().enumerate() {
+            assert_eq!(frame.track(), 1);
+            assert_eq!(frame.dts(), i as u64 * 40_000);
+            assert_eq!(frame.params, [ASC]);
+            assert_eq!(&frame.data[..], &[0x21; 20]);
+        }
+    }
+}
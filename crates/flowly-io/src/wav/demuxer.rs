@@ -0,0 +1,283 @@
+use bytes::{Buf, Bytes};
+use flowly_core::{Chunked, DataFrame, Fourcc, FrameFlags, FrameSource, RawAudioFrame};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Header,
+    ChunkHeader,
+    Format(usize),
+
+    /// Remaining bytes of the data chunk, `None` for the unknown size
+    Data(Option<u64>),
+    Failed,
+}
+
+/// RIFF/WAVE demuxer.
+///
+/// Accepts arbitrary chunks of the WAV byte stream and yields [`RawAudioFrame`]s
+/// of `chunk_samples` samples per channel (the last frame of the data chunk may
+/// be shorter). Samples are kept interleaved as stored; integer PCM, IEEE float,
+/// A-law and µ-law are supported, including `WAVE_FORMAT_EXTENSIBLE`. Timestamps
+/// count from the first sample. A data chunk size of 0 or `0xFFFFFFFF` (streaming
+/// writers) means the data lasts until the end of the stream, call [`WavDemuxer::flush`]
+/// to get the remainder. The state is reset when the frame source changes.
+#[derive(Debug, Clone)]
+pub struct WavDemuxer<S = ()> {
+    source: S,
+    state: State,
+    skip: usize,
+    buf: Chunked<Bytes>,
+    chunk_samples: usize,
+    format: Option<Format>,
+    codec: Fourcc,
+
+    /// Samples per channel emitted so far
+    position: u64,
+    padding: usize,
+}
+
+impl<S: FrameSource> WavDemuxer<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            state: State::Header,
+            skip: 0,
+            buf: Chunked::new(),
+            chunk_samples: 1024,
+            format: None,
+            codec: Fourcc::default(),
+            position: 0,
+            padding: 0,
+        }
+    }
+
+    /// Number of samples per channel in the produced frames
+    pub fn with_chunk_samples(mut self, samples: usize) -> Self {
+        self.chunk_samples = samples.max(1);
+        self
+    }
+
+    /// Stream format, available once received
+    #[inline]
+    pub fn format(&self) -> Option<&Format> {
+        self.format.as_ref()
+    }
+
+    /// Feeds the chunk of the stream and returns all completed frames
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<RawAudioFrame<S>, Error>> {
+        let mut out = Vec::new();
+
+        self.buf.put(chunk);
+
+        loop {
+            if self.skip > 0 {
+                let len = self.skip.min(self.buf.remaining());
+                self.buf.advance(len);
+                self.skip -= len;
+
+                if self.skip > 0 {
+                    break;
+                }
+            }
+
+            match self.state {
+                State::Header => {
+                    if self.buf.remaining() < RIFF_HEADER_SIZE {
+                        break;
+                    }
+
+                    let riff = self.buf.copy_to_bytes(RIFF_HEADER_SIZE);
+                    if &riff[0..4] != RIFF || &riff[8..12] != WAVE {
+                        out.push(Err(Error::InvalidData("wav: invalid signature")));
+                        self.state = State::Failed;
+                    } else {
+                        self.state = State::ChunkHeader;
+                    }
+                }
+
+                State::ChunkHeader => {
+                    if self.buf.remaining() < CHUNK_HEADER_SIZE {
+                        break;
+                    }
+
+                    let mut id = [0u8; 4];
+                    self.buf.copy_to_slice(&mut id);
+                    let size = self.buf.get_u32_le();
+
+                    self.state = match &id {
+                        CHUNK_FMT => State::Format(size as usize),
+                        CHUNK_DATA if self.format.is_none() => {
+                            out.push(Err(Error::InvalidData("wav: data before format")));
+                            State::Failed
+                        }
+                        CHUNK_DATA if size == 0 || size == UNKNOWN_SIZE => State::Data(None),
+                        CHUNK_DATA => {
+                            self.padding = (size & 1) as usize;
+                            State::Data(Some(size as u64))
+                        }
+                        _ => {
+                            // chunks are padded to the even size
+                            self.skip = size as usize + (size & 1) as usize;
+                            State::ChunkHeader
+                        }
+                    };
+                }
+
+                State::Format(size) => {
+                    if self.buf.remaining() < size + (size & 1) {
+                        break;
+                    }
+
+                    let mut data = self.buf.copy_to_bytes(size);
+                    self.buf.advance(size & 1);
+
+                    let format = Format::read(&mut data).and_then(|format| {
+                        let codec = format
+                            .codec()
+                            .ok_or(Error::Unsupported("wav: unsupported sample format"))?;
+
+                        Ok((format, codec))
+                    });
+
+                    self.state = match format {
+                        Ok((format, codec)) => {
+                            self.format = Some(format);
+                            self.codec = codec;
+                            State::ChunkHeader
+                        }
+                        Err(err) => {
+                            out.push(Err(err));
+                            State::Failed
+                        }
+                    };
+                }
+
+                State::Data(remaining) => {
+                    let Some(format) = self.format else {
+                        break;
+                    };
+
+                    let chunk_size = self.chunk_samples * format.block_align as usize;
+                    let available = self.buf.remaining() as u64;
+
+                    match remaining {
+                        Some(remaining) if remaining <= chunk_size as u64 => {
+                            if available < remaining {
+                                break;
+                            }
+
+                            if remaining > 0 {
+                                let data = self.buf.copy_to_bytes(remaining as usize);
+                                out.push(Ok(self.frame(data)));
+                            }
+
+                            self.skip = self.padding;
+                            self.state = State::ChunkHeader;
+                        }
+                        _ if available >= chunk_size as u64 => {
+                            let data = self.buf.copy_to_bytes(chunk_size);
+                            out.push(Ok(self.frame(data)));
+
+                            self.state = State::Data(remaining.map(|x| x - chunk_size as u64));
+                        }
+                        _ => break,
+                    }
+                }
+
+                State::Failed => {
+                    let len = self.buf.remaining();
+                    self.buf.advance(len);
+                    break;
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Returns the remaining samples of the data chunk with the unknown size
+    pub fn flush(&mut self) -> Option<RawAudioFrame<S>> {
+        let State::Data(_) = self.state else {
+            return None;
+        };
+
+        let block_align = self.format?.block_align as usize;
+        let size = self.buf.remaining() / block_align * block_align;
+
+        if size == 0 {
+            return None;
+        }
+
+        let data = self.buf.copy_to_bytes(size);
+        Some(self.frame(data))
+    }
+
+    fn frame(&mut self, data: Bytes) -> RawAudioFrame<S> {
+        let format = self.format.unwrap_or(Format {
+            tag: WAVE_FORMAT_PCM,
+            channels: 1,
+            sample_rate: 1,
+            block_align: 1,
+            bits_per_sample: 8,
+            extensible: None,
+        });
+
+        let samples = (data.len() / format.block_align as usize) as u64;
+        let rate = format.sample_rate as u64;
+
+        let mut frame = RawAudioFrame::new(
+            self.source.clone(),
+            self.codec,
+            FrameFlags::AUDIO_STREAM | FrameFlags::KEYFRAME,
+            data,
+        )
+        .with_timestamp(self.position * 1_000_000 / rate)
+        .with_layout(
+            format.sample_rate,
+            format.channels,
+            (format.block_align / format.channels * 8) as u8,
+        );
+
+        frame.duration = (self.position + samples) * 1_000_000 / rate - frame.timestamp;
+        self.position += samples;
+
+        frame
+    }
+
+    fn reset(&mut self, source: S) {
+        *self = Self::new().with_chunk_samples(self.chunk_samples);
+        self.source = source;
+    }
+}
+
+impl<S: FrameSource> Default for WavDemuxer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F> Service<F> for WavDemuxer<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<RawAudioFrame<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let mut out = Vec::new();
+
+        if frame.source() != &self.source {
+            out.extend(self.flush().map(Ok));
+            self.reset(frame.source().clone());
+        }
+
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
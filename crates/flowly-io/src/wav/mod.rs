@@ -0,0 +1,141 @@
+mod demuxer;
+mod writer;
+
+pub use demuxer::WavDemuxer;
+pub use writer::WavWriter;
+
+use bytes::{Buf, BufMut};
+use flowly_core::Fourcc;
+
+use crate::error::Error;
+
+pub const RIFF: &[u8; 4] = b"RIFF";
+pub const WAVE: &[u8; 4] = b"WAVE";
+pub const CHUNK_FMT: &[u8; 4] = b"fmt ";
+pub const CHUNK_DATA: &[u8; 4] = b"data";
+
+/// Size of `RIFF` header including the `WAVE` form type
+pub const RIFF_HEADER_SIZE: usize = 12;
+pub const CHUNK_HEADER_SIZE: usize = 8;
+
+/// Chunk size of the stream with unknown length
+pub const UNKNOWN_SIZE: u32 = u32::MAX;
+
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+pub const WAVE_FORMAT_ALAW: u16 = 0x0006;
+pub const WAVE_FORMAT_MULAW: u16 = 0x0007;
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Tail of the `KSDATAFORMAT_SUBTYPE_*` GUIDs, the first two bytes are the format tag
+pub const SUBTYPE_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// `fmt ` chunk payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    /// Format tag, the subformat one for `WAVE_FORMAT_EXTENSIBLE`
+    pub tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+
+    /// `dwChannelMask` and `wValidBitsPerSample` of `WAVE_FORMAT_EXTENSIBLE`
+    pub extensible: Option<(u32, u16)>,
+}
+
+impl Format {
+    pub fn read<B: Buf>(buf: &mut B) -> Result<Self, Error> {
+        let mut tag = buf.try_get_u16_le()?;
+        let channels = buf.try_get_u16_le()?;
+        let sample_rate = buf.try_get_u32_le()?;
+        let _byte_rate = buf.try_get_u32_le()?;
+        let block_align = buf.try_get_u16_le()?;
+        let bits_per_sample = buf.try_get_u16_le()?;
+        let mut extensible = None;
+
+        if tag == WAVE_FORMAT_EXTENSIBLE {
+            if buf.try_get_u16_le()? < 22 {
+                return Err(Error::InvalidData("wav: invalid extensible format"));
+            }
+
+            let valid_bits = buf.try_get_u16_le()?;
+            let channel_mask = buf.try_get_u32_le()?;
+            tag = buf.try_get_u16_le()?;
+            buf.advance(SUBTYPE_GUID_TAIL.len().min(buf.remaining()));
+
+            extensible = Some((channel_mask, valid_bits));
+        }
+
+        if channels == 0 || sample_rate == 0 || block_align == 0 {
+            return Err(Error::InvalidData("wav: invalid format"));
+        }
+
+        Ok(Self {
+            tag,
+            channels,
+            sample_rate,
+            block_align,
+            bits_per_sample,
+            extensible,
+        })
+    }
+
+    pub fn write<B: BufMut>(&self, buf: &mut B) {
+        let tag = if self.extensible.is_some() {
+            WAVE_FORMAT_EXTENSIBLE
+        } else {
+            self.tag
+        };
+
+        buf.put_u16_le(tag);
+        buf.put_u16_le(self.channels);
+        buf.put_u32_le(self.sample_rate);
+        buf.put_u32_le(self.sample_rate * self.block_align as u32);
+        buf.put_u16_le(self.block_align);
+        buf.put_u16_le(self.bits_per_sample);
+
+        if let Some((channel_mask, valid_bits)) = self.extensible {
+            buf.put_u16_le(22);
+            buf.put_u16_le(valid_bits);
+            buf.put_u32_le(channel_mask);
+            buf.put_u16_le(self.tag);
+            buf.put_slice(&SUBTYPE_GUID_TAIL);
+        } else if self.tag != WAVE_FORMAT_PCM {
+            buf.put_u16_le(0);
+        }
+    }
+
+    /// Size of the written chunk payload
+    pub fn size(&self) -> usize {
+        match (self.extensible, self.tag) {
+            (Some(_), _) => 40,
+            (None, WAVE_FORMAT_PCM) => 16,
+            (None, _) => 18,
+        }
+    }
+
+    /// Sample format of the stream
+    pub fn codec(&self) -> Option<Fourcc> {
+        match (self.tag, self.bits_per_sample) {
+            (WAVE_FORMAT_PCM, 8 | 16 | 24 | 32) => Some(Fourcc::AUDIO_PCM),
+            (WAVE_FORMAT_IEEE_FLOAT, 32 | 64) => Some(Fourcc::AUDIO_PCM_FLOAT),
+            (WAVE_FORMAT_ALAW, 8) => Some(Fourcc::AUDIO_PCM_ALAW),
+            (WAVE_FORMAT_MULAW, 8) => Some(Fourcc::AUDIO_PCM_ULAW),
+            _ => None,
+        }
+    }
+}
+
+/// Format tag of the sample format
+pub fn format_tag(codec: Fourcc) -> Option<u16> {
+    match codec {
+        Fourcc::AUDIO_PCM => Some(WAVE_FORMAT_PCM),
+        Fourcc::AUDIO_PCM_FLOAT => Some(WAVE_FORMAT_IEEE_FLOAT),
+        Fourcc::AUDIO_PCM_ALAW => Some(WAVE_FORMAT_ALAW),
+        Fourcc::AUDIO_PCM_ULAW => Some(WAVE_FORMAT_MULAW),
+        _ => None,
+    }
+}
@@ -0,0 +1,236 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use flowly_core::{AudioFrame, concat_chunks};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+/// Streaming RIFF/WAVE writer.
+///
+/// Accepts uncompressed [`AudioFrame`]s (e.g. [`RawAudioFrame`](flowly_core::RawAudioFrame))
+/// and yields the WAV byte stream: the header first, then the interleaved samples
+/// of every frame as is. The format is taken from the first frame, multichannel
+/// and high resolution PCM use `WAVE_FORMAT_EXTENSIBLE`. Sizes are unknown while
+/// streaming, so the header carries `0xFFFFFFFF` until [`WavWriter::finalize`]
+/// rewrites it in the seekable output.
+#[derive(Debug, Clone, Default)]
+pub struct WavWriter {
+    format: Option<Format>,
+    data_size: u64,
+}
+
+impl WavWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the frame samples (prefixed with the header on the first call)
+    pub fn push<F: AudioFrame>(&mut self, frame: F) -> Result<Vec<Bytes>, Error> {
+        let codec = frame.codec();
+        let tag = format_tag(codec).ok_or(flowly_codec::Error::UnsupportedCodec(codec))?;
+        let sample_size = frame.sample_size() as u16;
+        let channels = frame.channels();
+
+        let mut out = Vec::with_capacity(2);
+
+        match self.format {
+            Some(format) => {
+                if format.tag != tag
+                    || format.channels != channels
+                    || format.sample_rate != frame.sample_rate()
+                    || format.bits_per_sample != sample_size
+                {
+                    return Err(Error::InvalidData("wav: frame format has changed"));
+                }
+            }
+
+            None => {
+                if channels == 0 || frame.sample_rate() == 0 || !sample_size.is_multiple_of(8) {
+                    return Err(Error::InvalidData("wav: invalid frame format"));
+                }
+
+                let is_pcm = matches!(tag, WAVE_FORMAT_PCM | WAVE_FORMAT_IEEE_FLOAT);
+                let extensible = (is_pcm && (channels > 2 || sample_size > 16)).then(|| {
+                    let mask = if channels < 32 {
+                        (1 << channels) - 1
+                    } else {
+                        0
+                    };
+                    (mask, sample_size)
+                });
+
+                let format = Format {
+                    tag,
+                    channels,
+                    sample_rate: frame.sample_rate(),
+                    block_align: channels * sample_size / 8,
+                    bits_per_sample: sample_size,
+                    extensible,
+                };
+
+                if format.codec() != Some(codec) {
+                    return Err(Error::Unsupported("wav: unsupported sample size"));
+                }
+
+                self.format = Some(format);
+                out.push(self.header(None));
+            }
+        }
+
+        let data = concat_chunks(frame.into_chunks());
+        self.data_size += data.len() as u64;
+        out.push(data);
+
+        Ok(out)
+    }
+
+    /// Patches the header sizes (and pads the data chunk) of the written output
+    ///
+    /// `out` must contain the whole stream produced by this writer, starting at offset 0.
+    pub fn finalize<W: Write + Seek>(&mut self, out: &mut W) -> Result<(), Error> {
+        if self.format.is_none() {
+            return Ok(());
+        }
+
+        if self.data_size & 1 != 0 {
+            out.seek(SeekFrom::End(0))?;
+            out.write_all(&[0])?;
+        }
+
+        out.seek(SeekFrom::Start(0))?;
+        out.write_all(&self.header(Some(self.data_size)))?;
+        out.flush()?;
+
+        Ok(())
+    }
+
+    fn header(&self, data_size: Option<u64>) -> Bytes {
+        let format = self.format.unwrap_or(Format {
+            tag: WAVE_FORMAT_PCM,
+            channels: 1,
+            sample_rate: 1,
+            block_align: 1,
+            bits_per_sample: 8,
+            extensible: None,
+        });
+
+        let fmt_size = format.size();
+        let riff_size = data_size.map(|size| {
+            (RIFF_HEADER_SIZE - 8 + CHUNK_HEADER_SIZE * 2 + fmt_size) as u64 + size + (size & 1)
+        });
+
+        let size = |x: Option<u64>| {
+            x.and_then(|x| u32::try_from(x).ok())
+                .unwrap_or(UNKNOWN_SIZE)
+        };
+
+        let mut buf = BytesMut::with_capacity(RIFF_HEADER_SIZE + CHUNK_HEADER_SIZE * 2 + fmt_size);
+        buf.put_slice(RIFF);
+        buf.put_u32_le(size(riff_size));
+        buf.put_slice(WAVE);
+
+        buf.put_slice(CHUNK_FMT);
+        buf.put_u32_le(fmt_size as u32);
+        format.write(&mut buf);
+
+        buf.put_slice(CHUNK_DATA);
+        buf.put_u32_le(size(data_size));
+
+        buf.freeze()
+    }
+}
+
+impl<F: AudioFrame> Service<F> for WavWriter {
+    type Out = Result<Bytes, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let out: Vec<_> = match self.push(frame) {
+            Ok(chunks) => chunks.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        };
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use bytes::Bytes;
+    use flowly_core::{AudioFrame, Fourcc, Frame, FrameFlags, RawAudioFrame};
+
+    use super::WavWriter;
+    use crate::wav::{WAVE_FORMAT_IEEE_FLOAT, WavDemuxer};
+
+    #[test]
+    fn test_wav_roundtrip() {
+        let samples: Vec<u8> = (0..10 * 3 * 4).map(|x| x as u8).collect();
+        let mut writer = WavWriter::new();
+        let mut out = Cursor::new(Vec::new());
+
+        for part in [&samples[..48], &samples[48..]] {
+            let frame = RawAudioFrame::new(
+                (),
+                Fourcc::AUDIO_PCM_FLOAT,
+                FrameFlags::AUDIO_STREAM,
+                Bytes::copy_from_slice(part),
+            )
+            .with_layout(48_000, 3, 32);
+
+            for chunk in writer.push(frame).unwrap() {
+                out.write_all(&chunk).unwrap();
+            }
+        }
+
+        writer.finalize(&mut out).unwrap();
+        let file = Bytes::from(out.into_inner());
+
+        // extensible header: 12 + 8 + 40 + 8
+        assert_eq!(file.len(), 68 + samples.len());
+        assert_eq!(&file[4..8], &(60 + samples.len() as u32).to_le_bytes());
+        assert_eq!(&file[64..68], &(samples.len() as u32).to_le_bytes());
+
+        let mut demuxer = WavDemuxer::<()>::new().with_chunk_samples(4);
+        let mut frames = Vec::new();
+        for chunk in file.chunks(7) {
+            frames.extend(demuxer.push(Bytes::copy_from_slice(chunk)));
+        }
+
+        let frames: Vec<_> = frames.into_iter().collect::<Result<_, _>>().unwrap();
+        let summary: Vec<_> = frames
+            .iter()
+            .map(|x| (x.timestamp(), x.samples(), x.channels(), x.sample_size()))
+            .collect();
+
+        assert_eq!(summary, [(0, 4, 3, 32), (83, 4, 3, 32), (166, 2, 3, 32)]);
+        assert_eq!(frames[0].codec(), Fourcc::AUDIO_PCM_FLOAT);
+        assert_eq!(demuxer.format().unwrap().tag, WAVE_FORMAT_IEEE_FLOAT);
+        assert_eq!(
+            frames
+                .iter()
+                .flat_map(|x| x.data.to_vec())
+                .collect::<Vec<_>>(),
+            samples
+        );
+    }
+
+    #[test]
+    fn test_wav_demux_malformed() {
+        let mut data_first = b"RIFF\0\0\0\0WAVE".to_vec();
+        data_first.extend_from_slice(b"data\x04\0\0\0\0\0\0\0");
+
+        for file in [
+            b"RIFX\0\0\0\0WAVE".to_vec(),
+            b"RIFF\0\0\0\0AVI ".to_vec(),
+            data_first,
+        ] {
+            let mut demuxer = WavDemuxer::<()>::new();
+            let out = demuxer.push(Bytes::from(file));
+
+            assert!(matches!(out[..], [Err(_)]));
+        }
+    }
+}
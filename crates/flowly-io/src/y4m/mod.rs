@@ -0,0 +1,235 @@
+mod reader;
+mod writer;
+
+pub use reader::Y4mReader;
+pub use writer::Y4mWriter;
+
+use bytes::Bytes;
+use flowly_core::{Chunked, Fourcc};
+
+use crate::error::Error;
+
+pub const SIGNATURE: &str = "YUV4MPEG2";
+pub const FRAME_MAGIC: &str = "FRAME";
+
+/// Header lines longer than that are rejected
+pub const MAX_LINE_SIZE: usize = 4096;
+
+/// YUV4MPEG2 stream header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub width: u16,
+    pub height: u16,
+
+    /// Frames per second as `num:den`
+    pub frame_rate: (u32, u32),
+    pub interlacing: Option<char>,
+    pub aspect_ratio: Option<(u32, u32)>,
+
+    /// `C` tag, `420jpeg` if not present
+    pub colorspace: String,
+
+    /// `X` tags without the prefix
+    pub extensions: Vec<String>,
+}
+
+impl Header {
+    /// Parses the header line (without the trailing newline)
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        let mut tokens = line.split(' ').filter(|x| !x.is_empty());
+
+        if tokens.next() != Some(SIGNATURE) {
+            return Err(Error::InvalidData("y4m: invalid signature"));
+        }
+
+        let mut header = Self::default();
+        let (mut width, mut height) = (None, None);
+
+        for token in tokens {
+            // tags are single characters, possibly multi-byte in a broken header
+            let tag_len = token.chars().next().map_or(0, char::len_utf8);
+            let (tag, value) = token.split_at(tag_len);
+
+            match tag {
+                "W" => width = value.parse().ok(),
+                "H" => height = value.parse().ok(),
+                "F" => header.frame_rate = parse_ratio(value)?,
+                "I" => header.interlacing = value.chars().next(),
+                "A" => header.aspect_ratio = Some(parse_ratio(value)?),
+                "C" => header.colorspace = value.to_string(),
+                "X" => header.extensions.push(value.to_string()),
+                _ => (),
+            }
+        }
+
+        let (Some(width), Some(height)) = (width, height) else {
+            return Err(Error::InvalidData("y4m: invalid frame size"));
+        };
+
+        if header.frame_rate.0 == 0 || header.frame_rate.1 == 0 {
+            return Err(Error::InvalidData("y4m: invalid frame rate"));
+        }
+
+        header.width = width;
+        header.height = height;
+        header.format()?;
+
+        Ok(header)
+    }
+
+    /// Header line including the trailing newline
+    pub fn to_line(&self) -> String {
+        let mut line = format!(
+            "{SIGNATURE} W{} H{} F{}:{}",
+            self.width, self.height, self.frame_rate.0, self.frame_rate.1
+        );
+
+        if let Some(interlacing) = self.interlacing {
+            line.push_str(&format!(" I{interlacing}"));
+        }
+
+        if let Some((num, den)) = self.aspect_ratio {
+            line.push_str(&format!(" A{num}:{den}"));
+        }
+
+        line.push_str(&format!(" C{}", self.colorspace));
+
+        for ext in &self.extensions {
+            line.push_str(&format!(" X{ext}"));
+        }
+
+        line.push('\n');
+        line
+    }
+
+    /// Pixel format and bit depth of the `C` tag
+    pub fn format(&self) -> Result<(Fourcc, u8), Error> {
+        let (layout, bit_depth) = match self.colorspace.as_str() {
+            "420jpeg" | "420paldv" | "420mpeg2" => ("420", Some(8)),
+            colorspace => match colorspace.split_once('p') {
+                Some((layout, depth)) => (layout, depth.parse().ok()),
+                None => (colorspace, Some(8)),
+            },
+        };
+
+        let format = match layout {
+            "420" => Fourcc::PIXEL_FORMAT_YUV420,
+            "422" => Fourcc::PIXEL_FORMAT_YUV422,
+            "444" => Fourcc::PIXEL_FORMAT_YUV444,
+            "411" => Fourcc::PIXEL_FORMAT_YUV411,
+            "mono" => Fourcc::PIXEL_FORMAT_R8,
+            "mono10" => return Ok((Fourcc::PIXEL_FORMAT_R10, 10)),
+            "mono12" => return Ok((Fourcc::PIXEL_FORMAT_R12, 12)),
+            "mono16" => return Ok((Fourcc::PIXEL_FORMAT_R16, 16)),
+            _ => return Err(Error::Unsupported("y4m: unsupported colorspace")),
+        };
+
+        match bit_depth {
+            Some(depth @ 8..=16) => Ok((format, depth)),
+            _ => Err(Error::Unsupported("y4m: unsupported bit depth")),
+        }
+    }
+
+    /// Sizes of the frame planes in bytes
+    pub fn plane_sizes(&self) -> Result<Vec<usize>, Error> {
+        let (format, bit_depth) = self.format()?;
+        let (width, height) = (self.width as usize, self.height as usize);
+        let sample = if bit_depth > 8 { 2 } else { 1 };
+        let luma = width * height * sample;
+
+        let (sx, sy) = match format {
+            Fourcc::PIXEL_FORMAT_YUV420 => (2, 2),
+            Fourcc::PIXEL_FORMAT_YUV422 => (2, 1),
+            Fourcc::PIXEL_FORMAT_YUV444 => (1, 1),
+            Fourcc::PIXEL_FORMAT_YUV411 => (4, 1),
+            _ => return Ok(vec![luma]),
+        };
+
+        let chroma = width.div_ceil(sx) * height.div_ceil(sy) * sample;
+
+        Ok(vec![luma, chroma, chroma])
+    }
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            frame_rate: (25, 1),
+            interlacing: None,
+            aspect_ratio: None,
+            colorspace: "420jpeg".into(),
+            extensions: Vec::new(),
+        }
+    }
+}
+
+/// `C` tag of the pixel format
+pub fn colorspace(format: Fourcc, bit_depth: u8) -> Option<String> {
+    let layout = match format {
+        Fourcc::PIXEL_FORMAT_YUV420 if bit_depth <= 8 => return Some("420jpeg".into()),
+        Fourcc::PIXEL_FORMAT_YUV420 => "420",
+        Fourcc::PIXEL_FORMAT_YUV422 => "422",
+        Fourcc::PIXEL_FORMAT_YUV444 => "444",
+        Fourcc::PIXEL_FORMAT_YUV411 => "411",
+        Fourcc::PIXEL_FORMAT_R8 => return Some("mono".into()),
+        Fourcc::PIXEL_FORMAT_R10 => return Some("mono10".into()),
+        Fourcc::PIXEL_FORMAT_R12 => return Some("mono12".into()),
+        Fourcc::PIXEL_FORMAT_R16 => return Some("mono16".into()),
+        _ => return None,
+    };
+
+    Some(if bit_depth > 8 {
+        format!("{layout}p{bit_depth}")
+    } else {
+        layout.into()
+    })
+}
+
+fn parse_ratio(value: &str) -> Result<(u32, u32), Error> {
+    value
+        .split_once(':')
+        .and_then(|(num, den)| Some((num.parse().ok()?, den.parse().ok()?)))
+        .ok_or(Error::InvalidData("y4m: invalid ratio"))
+}
+
+/// Position of the first newline in the buffer
+fn find_newline(buf: &Chunked<Bytes>) -> Option<usize> {
+    let mut offset = 0;
+
+    for chunk in buf.iter() {
+        if let Some(pos) = chunk.iter().position(|&x| x == b'\n') {
+            return Some(offset + pos);
+        }
+
+        offset += chunk.len();
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Header;
+
+    #[test]
+    fn test_y4m_header_malformed() {
+        let header = Header::parse("YUV4MPEG2 W2 H2 F30:1 Xé Éx").unwrap();
+        assert_eq!((header.width, header.height), (2, 2));
+        assert_eq!(header.extensions, ["é"]);
+
+        for line in [
+            "YUV4MPEG W2 H2",
+            "YUV4MPEG2 W2",
+            "YUV4MPEG2 W70000 H2",
+            "YUV4MPEG2 W2 H2 F30:0",
+            "YUV4MPEG2 W2 H2 F30",
+            "YUV4MPEG2 W2 H2 Aé",
+            "YUV4MPEG2 W2 H2 C420p99",
+            "YUV4MPEG2 W2 H2 Cbgr",
+        ] {
+            assert!(Header::parse(line).is_err(), "{line}");
+        }
+    }
+}
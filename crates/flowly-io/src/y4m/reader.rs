@@ -0,0 +1,196 @@
+use bytes::{Buf, Bytes};
+use flowly_core::{Chunked, DataFrame, Fourcc, FrameFlags, FrameSource, RawFrame};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Header,
+    FrameHeader,
+    FrameData,
+    Failed,
+}
+
+/// YUV4MPEG2 reader.
+///
+/// Accepts arbitrary chunks of the Y4M byte stream and yields an uncompressed
+/// [`RawFrame`] per frame, one chunk per plane. The pixel format and bit depth
+/// come from the `C` tag, timestamps are derived from the frame number and the
+/// `F` frame rate. Frame parameters are ignored.
+/// The state is reset when the frame source changes.
+#[derive(Debug, Clone)]
+pub struct Y4mReader<S = ()> {
+    source: S,
+    state: State,
+    buf: Chunked<Bytes>,
+    header: Option<Header>,
+    format: (Fourcc, u8),
+    planes: Vec<usize>,
+    frame_count: u64,
+}
+
+impl<S: FrameSource> Y4mReader<S> {
+    pub fn new() -> Self {
+        Self {
+            source: S::default(),
+            state: State::Header,
+            buf: Chunked::new(),
+            header: None,
+            format: (Fourcc::default(), 8),
+            planes: Vec::new(),
+            frame_count: 0,
+        }
+    }
+
+    /// Stream header, available once received
+    #[inline]
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    /// Feeds the chunk of the stream and returns all completed frames
+    pub fn push(&mut self, chunk: Bytes) -> Vec<Result<RawFrame<S>, Error>> {
+        let mut out = Vec::new();
+
+        self.buf.put(chunk);
+
+        loop {
+            match self.state {
+                State::Header | State::FrameHeader => {
+                    let line = match self.read_line() {
+                        Some(Ok(line)) => line,
+                        Some(Err(err)) => {
+                            out.push(Err(err));
+                            self.state = State::Failed;
+                            continue;
+                        }
+                        None => break,
+                    };
+
+                    let res = if self.state == State::Header {
+                        self.parse_header(&line).map(|_| State::FrameHeader)
+                    } else if line.starts_with(FRAME_MAGIC) {
+                        Ok(State::FrameData)
+                    } else {
+                        Err(Error::InvalidData("y4m: invalid frame header"))
+                    };
+
+                    self.state = match res {
+                        Ok(state) => state,
+                        Err(err) => {
+                            out.push(Err(err));
+                            State::Failed
+                        }
+                    };
+                }
+
+                State::FrameData => {
+                    if self.buf.remaining() < self.planes.iter().sum() {
+                        break;
+                    }
+
+                    let planes = self
+                        .planes
+                        .iter()
+                        .map(|&len| self.buf.copy_to_bytes(len))
+                        .collect();
+
+                    out.push(Ok(self.frame(planes)));
+                    self.state = State::FrameHeader;
+                }
+
+                State::Failed => {
+                    let len = self.buf.remaining();
+                    self.buf.advance(len);
+                    break;
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Takes the line without the trailing newline, `None` if more data needed
+    fn read_line(&mut self) -> Option<Result<String, Error>> {
+        let Some(pos) = find_newline(&self.buf) else {
+            if self.buf.remaining() > MAX_LINE_SIZE {
+                return Some(Err(Error::InvalidData("y4m: header line is too long")));
+            }
+
+            return None;
+        };
+
+        let line = self.buf.copy_to_bytes(pos + 1);
+
+        Some(
+            String::from_utf8(line[..pos].to_vec())
+                .map_err(|_| Error::InvalidData("y4m: invalid header line")),
+        )
+    }
+
+    fn parse_header(&mut self, line: &str) -> Result<(), Error> {
+        let header = Header::parse(line)?;
+
+        self.format = header.format()?;
+        self.planes = header.plane_sizes()?;
+        self.header = Some(header);
+        self.frame_count = 0;
+
+        Ok(())
+    }
+
+    fn frame(&mut self, planes: Vec<Bytes>) -> RawFrame<S> {
+        let (num, den) = self.header.as_ref().map_or((25, 1), |x| x.frame_rate);
+        let (format, bit_depth) = self.format;
+        let (width, height) = self.header.as_ref().map_or((0, 0), |x| (x.width, x.height));
+
+        let timestamp = self.frame_count * den as u64 * 1_000_000 / num as u64;
+        self.frame_count += 1;
+
+        let mut frame = RawFrame::new(
+            self.source.clone(),
+            format,
+            FrameFlags::VIDEO_STREAM | FrameFlags::KEYFRAME,
+            planes,
+        )
+        .with_timestamp(timestamp)
+        .with_dimensions(width, height)
+        .with_bit_depth(bit_depth);
+
+        frame.duration = den as u64 * 1_000_000 / num as u64;
+        frame
+    }
+
+    fn reset(&mut self, source: S) {
+        *self = Self::new();
+        self.source = source;
+    }
+}
+
+impl<S: FrameSource> Default for Y4mReader<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F> Service<F> for Y4mReader<F::Source>
+where
+    F: DataFrame<Chunk = Bytes>,
+{
+    type Out = Result<RawFrame<F::Source>, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        if frame.source() != &self.source {
+            self.reset(frame.source().clone());
+        }
+
+        let mut out = Vec::new();
+        for chunk in frame.into_chunks() {
+            out.extend(self.push(chunk));
+        }
+
+        futures::stream::iter(out)
+    }
+}
@@ -0,0 +1,171 @@
+use bytes::Bytes;
+use flowly_core::{MemBlock, VideoFrame};
+use flowly_service::{Context, Service};
+
+use super::*;
+use crate::error::Error;
+
+/// YUV4MPEG2 writer.
+///
+/// Accepts uncompressed planar [`VideoFrame`]s (e.g. [`RawFrame`](flowly_core::RawFrame))
+/// and yields the Y4M byte stream: the header line first, then the `FRAME` line
+/// followed by the frame planes as is. The header is taken from [`Y4mWriter::with_header`]
+/// when given, otherwise it is built from the format, bit depth and dimensions of
+/// the first frame. Frames must match the header layout.
+#[derive(Debug, Clone)]
+pub struct Y4mWriter {
+    header: Option<Header>,
+    frame_rate: (u32, u32),
+    frame_size: usize,
+    header_written: bool,
+}
+
+impl Y4mWriter {
+    pub fn new() -> Self {
+        Self {
+            header: None,
+            frame_rate: (25, 1),
+            frame_size: 0,
+            header_written: false,
+        }
+    }
+
+    /// Uses the header as is instead of building it from the first frame
+    pub fn with_header(mut self, header: Header) -> Self {
+        self.header = Some(header);
+        self
+    }
+
+    /// Sets the frame rate of the built header as `num:den` frames per second
+    pub fn with_frame_rate(mut self, num: u32, den: u32) -> Self {
+        self.frame_rate = (num.max(1), den.max(1));
+        self
+    }
+
+    /// Writes the frame (prefixed with the stream header on the first call)
+    pub fn push<F: VideoFrame>(&mut self, frame: F) -> Result<Vec<Bytes>, Error> {
+        if frame.is_encoded() {
+            return Err(Error::Unsupported("y4m: encoded frames are not supported"));
+        }
+
+        let mut out = Vec::new();
+
+        if !self.header_written {
+            let header = match self.header.take() {
+                Some(header) => header,
+                None => {
+                    let (width, height) = frame.dimensions();
+                    let colorspace = colorspace(frame.codec(), frame.bit_depth())
+                        .ok_or(flowly_codec::Error::UnsupportedCodec(frame.codec()))?;
+
+                    Header {
+                        width,
+                        height,
+                        frame_rate: self.frame_rate,
+                        colorspace,
+                        ..Default::default()
+                    }
+                }
+            };
+
+            self.frame_size = header.plane_sizes()?.iter().sum();
+            out.push(Bytes::from(header.to_line()));
+            self.header = Some(header);
+            self.header_written = true;
+        }
+
+        let planes: Vec<_> = frame.into_chunks().map(MemBlock::into_cpu_bytes).collect();
+        if planes.iter().map(Bytes::len).sum::<usize>() != self.frame_size {
+            return Err(Error::InvalidData(
+                "y4m: frame size does not match the header",
+            ));
+        }
+
+        out.push(Bytes::from_static(b"FRAME\n"));
+        out.extend(planes);
+
+        Ok(out)
+    }
+}
+
+impl Default for Y4mWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: VideoFrame> Service<F> for Y4mWriter {
+    type Out = Result<Bytes, Error>;
+
+    fn handle(&mut self, frame: F, _cx: &Context) -> impl futures::Stream<Item = Self::Out> + Send {
+        let out: Vec<_> = match self.push(frame) {
+            Ok(chunks) => chunks.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        };
+
+        futures::stream::iter(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use flowly_core::{DataFrame, Fourcc, Frame, FrameFlags, RawFrame, VideoFrame};
+
+    use super::Y4mWriter;
+    use crate::y4m::Y4mReader;
+
+    #[test]
+    fn test_y4m_roundtrip() {
+        let mut file = BytesMut::new();
+        file.put_slice(b"YUV4MPEG2 W5 H3 F30000:1001 Ip A1:1 C420p10 XYSCSS=420P10\n");
+        for i in 0..2u8 {
+            file.put_slice(b"FRAME\n");
+            file.put_slice(&[i; 5 * 3 * 2]);
+            file.put_slice(&[0x80 | i; 3 * 2 * 2 * 2]);
+        }
+        let file = file.freeze();
+
+        let mut reader = Y4mReader::<()>::new();
+        let mut frames = Vec::new();
+        for chunk in file.chunks(17) {
+            frames.extend(reader.push(Bytes::copy_from_slice(chunk)));
+        }
+
+        let frames: Vec<_> = frames.into_iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(frames.len(), 2);
+
+        let frame = &frames[1];
+        assert_eq!(frame.codec(), Fourcc::PIXEL_FORMAT_YUV420);
+        assert_eq!(frame.dimensions(), (5, 3));
+        assert_eq!(frame.bit_depth(), 10);
+        assert_eq!(frame.timestamp(), 33_366);
+        assert!(!frame.is_encoded() && frame.is_keyframe());
+
+        let planes: Vec<_> = frame.chunks().map(|x| x.len()).collect();
+        assert_eq!(planes, [30, 12, 12]);
+
+        let mut writer = Y4mWriter::new().with_header(reader.header().unwrap().clone());
+        let mut out = BytesMut::new();
+        for frame in frames {
+            for chunk in writer.push(frame).unwrap() {
+                out.put_slice(&chunk);
+            }
+        }
+
+        assert_eq!(out.freeze(), file);
+
+        // header built from the frame
+        let mut writer = Y4mWriter::new().with_frame_rate(30, 1);
+        let frame = RawFrame::new(
+            (),
+            Fourcc::PIXEL_FORMAT_R8,
+            FrameFlags::VIDEO_STREAM,
+            vec![Bytes::from_static(&[1, 2, 3, 4])],
+        )
+        .with_dimensions(2, 2);
+
+        let out = writer.push(frame).unwrap();
+        assert_eq!(&out[0][..], b"YUV4MPEG2 W2 H2 F30:1 Cmono\n");
+    }
+}
@@ -1,3 +1,4 @@
+pub use flowly_codec as codec;
 pub use flowly_core::*;
 pub use flowly_io as io;
 pub use flowly_service::*;